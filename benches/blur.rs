@@ -0,0 +1,35 @@
+//! `mode: blur` on a 6000 by 4000 canvas, blurred at full size against the
+//! default `blurDownscale`.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use image_bg_extender::image::{DynamicImage, Rgba};
+use image_bg_extender::style::{Background, DEFAULT_BLUR_DOWNSCALE};
+use image_bg_extender::{extend_image, test_util, Options};
+
+fn blur(c: &mut Criterion) {
+    // Landscape, so the canvas grows to 6000 by 4000 for 3:2
+    let source = DynamicImage::ImageRgba8(test_util::checkerboard(
+        6000,
+        2000,
+        50,
+        Rgba([200, 40, 40, 255]),
+        Rgba([40, 40, 200, 255]),
+    ));
+    let mut group = c.benchmark_group("blur 6000x4000");
+    group.sample_size(10);
+    for downscale in [1.0, DEFAULT_BLUR_DOWNSCALE] {
+        let mut options = Options::default();
+        options.style.background = Background::Blur;
+        options.style.blur_sigma = Some(20.0);
+        options.style.blur_downscale = Some(downscale);
+        group.bench_with_input(
+            BenchmarkId::new("blurDownscale", downscale),
+            &options,
+            |b, options| b.iter(|| extend_image(&source, (3, 2), options).unwrap()),
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, blur);
+criterion_main!(benches);
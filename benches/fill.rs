@@ -0,0 +1,43 @@
+//! The `split` background on an 8K canvas, with the colors given and with
+//! them sampled from the edges, most of which is averaging the strips.
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use image_bg_extender::color::Color;
+use image_bg_extender::image::{DynamicImage, Rgba};
+use image_bg_extender::style::Length;
+use image_bg_extender::{extend_image, test_util, Options};
+
+fn split(c: &mut Criterion) {
+    // Half the height of the canvas, so the bands are the other half
+    let source = DynamicImage::ImageRgba8(test_util::horizontal_gradient(
+        7680,
+        2160,
+        Rgba([255, 0, 0, 255]),
+        Rgba([0, 0, 255, 255]),
+    ));
+    let mut group = c.benchmark_group("split 7680x4320");
+    group.sample_size(10);
+
+    let mut given = Options::default();
+    given.style.first_color = Some(Color(Rgba([20, 40, 60, 255])));
+    given.style.second_color = Some(Color(Rgba([200, 180, 160, 255])));
+    group.bench_function("given colors", |b| {
+        b.iter(|| extend_image(&source, (16, 9), &given).unwrap())
+    });
+
+    let sampled = Options::default();
+    group.bench_function("sampled colors", |b| {
+        b.iter(|| extend_image(&source, (16, 9), &sampled).unwrap())
+    });
+
+    // Every pixel of the source is averaged into both edge colors
+    let mut whole = Options::default();
+    whole.style.edge_sample = Some(Length::Percent(100.0));
+    group.bench_function("whole image sampled", |b| {
+        b.iter(|| extend_image(&source, (16, 9), &whole).unwrap())
+    });
+    group.finish();
+}
+
+criterion_group!(benches, split);
+criterion_main!(benches);
@@ -0,0 +1,20 @@
+fn main() {
+    #[cfg(feature = "ffi")]
+    generate_header();
+}
+
+/// Generates the C header from the `ffi` module into `OUT_DIR`, leaving the
+/// tree alone. `include/image_bg_extender.h` is the committed copy, which
+/// `make -C ffi header` brings up to date and `tests/ffi.rs` checks.
+#[cfg(feature = "ffi")]
+fn generate_header() {
+    let crate_dir = std::env::var("CARGO_MANIFEST_DIR").unwrap();
+    let out_dir = std::env::var("OUT_DIR").unwrap();
+    println!("cargo:rerun-if-changed=src/ffi.rs");
+    println!("cargo:rerun-if-changed=cbindgen.toml");
+    let header = std::path::Path::new(&out_dir).join("image_bg_extender.h");
+    cbindgen::generate(&crate_dir)
+        .expect("failed to generate C bindings")
+        .write_to_file(&header);
+    println!("cargo:rustc-env=IBE_GENERATED_HEADER={}", header.display());
+}
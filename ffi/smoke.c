@@ -0,0 +1,150 @@
+/*
+ * Smoke test for the C interface. Build and run with `make -C ffi test`,
+ * which `tests/ffi.rs` does too.
+ *
+ * Usage: smoke <destination PNG>
+ *
+ * The source is a gradient drawn here and written next to the destination
+ * as a BMP, so the test needs no fixture.
+ */
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+#include "image_bg_extender.h"
+
+#define WIDTH 64
+#define HEIGHT 32
+/* 24-bit rows are padded to a multiple of 4 bytes */
+#define ROW ((WIDTH * 3 + 3) & ~3)
+#define BMP_LEN (54 + ROW * HEIGHT)
+
+static int check(const char *what, IbeErrorCode code, const IbeError *err) {
+    if (code != IBE_ERROR_CODE_OK) {
+        fprintf(stderr, "%s failed (%d): %s\n", what, (int)code, err->message);
+        return 1;
+    }
+    return 0;
+}
+
+static void le16(uint8_t *at, uint16_t value) {
+    at[0] = value & 0xff;
+    at[1] = value >> 8;
+}
+
+static void le32(uint8_t *at, uint32_t value) {
+    le16(at, value & 0xffff);
+    le16(at + 2, value >> 16);
+}
+
+/* A red to blue gradient, bottom row first as BMP stores it */
+static void draw_bmp(uint8_t *bmp) {
+    memset(bmp, 0, BMP_LEN);
+    bmp[0] = 'B';
+    bmp[1] = 'M';
+    le32(bmp + 2, BMP_LEN);
+    le32(bmp + 10, 54);
+    le32(bmp + 14, 40);
+    le32(bmp + 18, WIDTH);
+    le32(bmp + 22, HEIGHT);
+    le16(bmp + 26, 1);
+    le16(bmp + 28, 24);
+    le32(bmp + 34, ROW * HEIGHT);
+    for (int y = 0; y < HEIGHT; y++) {
+        uint8_t *row = bmp + 54 + y * ROW;
+        for (int x = 0; x < WIDTH; x++) {
+            uint8_t blue = (uint8_t)(x * 255 / (WIDTH - 1));
+            row[x * 3] = blue;
+            row[x * 3 + 1] = 0;
+            row[x * 3 + 2] = 255 - blue;
+        }
+    }
+}
+
+static uint32_t be32(const uint8_t *at) {
+    return (uint32_t)at[0] << 24 | (uint32_t)at[1] << 16 | (uint32_t)at[2] << 8 | at[3];
+}
+
+/* Checks that `data` is a PNG of `width` by `height`, from its IHDR */
+static int check_png(const char *what, const uint8_t *data, size_t len, uint32_t width,
+                     uint32_t height) {
+    if (len < 24 || memcmp(data, "\x89PNG\r\n\x1a\n", 8) != 0 || memcmp(data + 12, "IHDR", 4) != 0) {
+        fprintf(stderr, "%s is not a PNG\n", what);
+        return 1;
+    }
+    if (be32(data + 16) != width || be32(data + 20) != height) {
+        fprintf(stderr, "%s is %ux%u, expected %ux%u\n", what, be32(data + 16), be32(data + 20),
+                width, height);
+        return 1;
+    }
+    return 0;
+}
+
+int main(int argc, char **argv) {
+    if (argc != 2) {
+        fprintf(stderr, "usage: %s <destination>\n", argv[0]);
+        return 2;
+    }
+    const char *destination = argv[1];
+
+    static uint8_t bmp[BMP_LEN];
+    draw_bmp(bmp);
+    size_t source_len = strlen(destination) + sizeof(".source.bmp");
+    char *source = malloc(source_len);
+    snprintf(source, source_len, "%s.source.bmp", destination);
+    FILE *f = fopen(source, "wb");
+    if (f == NULL || fwrite(bmp, 1, BMP_LEN, f) != BMP_LEN || fclose(f) != 0) {
+        perror(source);
+        return 1;
+    }
+
+    IbeError err;
+    IbeOptions options = {IBE_FORMAT_AUTO};
+    if (check("ibe_extend_file", ibe_extend_file(source, destination, 16, 9, &options, &err),
+              &err)) {
+        return 1;
+    }
+    static uint8_t written[1024 * 1024];
+    f = fopen(destination, "rb");
+    if (f == NULL) {
+        perror(destination);
+        return 1;
+    }
+    size_t written_len = fread(written, 1, sizeof(written), f);
+    fclose(f);
+    if (check_png("ibe_extend_file output", written, written_len, 64, 36)) {
+        return 1;
+    }
+    printf("ibe_extend_file wrote %zu bytes\n", written_len);
+
+    IbeBuffer out = {NULL, 0};
+    options.output_format = IBE_FORMAT_PNG;
+    if (check("ibe_extend_bytes", ibe_extend_bytes(bmp, BMP_LEN, 1, 1, &options, &out, &err),
+              &err)) {
+        return 1;
+    }
+    if (check_png("ibe_extend_bytes output", out.data, out.len, 64, 64)) {
+        return 1;
+    }
+    printf("ibe_extend_bytes produced %zu bytes\n", out.len);
+    ibe_buffer_free(&out);
+
+    /* Errors must come back through the out-parameter, never as a crash */
+    IbeErrorCode code = ibe_extend_file(source, destination, 0, 9, NULL, &err);
+    if (code == IBE_ERROR_CODE_OK) {
+        fprintf(stderr, "zero aspect ratio unexpectedly succeeded\n");
+        return 1;
+    }
+    printf("zero aspect ratio rejected (%d): %s\n", (int)code, err.message);
+
+    code = ibe_extend_file(NULL, destination, 16, 9, NULL, &err);
+    if (code != IBE_ERROR_CODE_INVALID_ARGUMENT) {
+        fprintf(stderr, "null source not rejected\n");
+        return 1;
+    }
+
+    remove(source);
+    free(source);
+    printf("ok\n");
+    return 0;
+}
@@ -0,0 +1,159 @@
+#ifndef IMAGE_BG_EXTENDER_H
+#define IMAGE_BG_EXTENDER_H
+
+/* Generated by cbindgen from src/ffi.rs, do not edit. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+/**
+ * Canvases above 250 megapixels are most likely a mistyped ratio.
+ */
+#define DEFAULT_MAX_OUTPUT_PIXELS 250000000
+
+/**
+ * A tenth of a photo is usually sky or floor that nobody misses.
+ */
+#define DEFAULT_CROP_THRESHOLD 0.1
+
+/**
+ * Small enough that the crop goes unnoticed.
+ */
+#define DEFAULT_MAX_ZOOM 0.05
+
+#define DEFAULT_CELL 160
+
+#define DEFAULT_MAX_DIMENSION 4096
+
+/**
+ * Bumped whenever a field of `Analysis` changes or goes away. Added fields
+ * keep the version.
+ */
+#define ANALYSIS_VERSION 1
+
+/**
+ * Delay before the first retry, doubled for every one after it.
+ */
+#define DEFAULT_BACKOFF_MS 200
+
+#define DEFAULT_BLUR_DOWNSCALE 4.0
+
+#define DEFAULT_PALETTE_SIZE 3
+
+/**
+ * Size of `IbeError::message`, including the terminating NUL.
+ */
+#define IBE_MESSAGE_CAPACITY 256
+
+/**
+ * Largest accepted request body.
+ */
+#define MAX_UPLOAD_BYTES ((64 * 1024) * 1024)
+
+/**
+ * Status of an FFI call. `IBE_ERROR_CODE_OK` is always zero.
+ *
+ * `Image` is a source in a format that is not supported, `Corrupt` one whose
+ * data is damaged or cut short. `InvalidArgument` covers settings that
+ * cannot work as well as bad pointers.
+ */
+typedef enum IbeErrorCode {
+  IBE_ERROR_CODE_OK = 0,
+  IBE_ERROR_CODE_INVALID_ARGUMENT = 1,
+  IBE_ERROR_CODE_IO = 2,
+  IBE_ERROR_CODE_IMAGE = 3,
+  IBE_ERROR_CODE_PANIC = 4,
+  IBE_ERROR_CODE_OTHER = 5,
+  IBE_ERROR_CODE_CORRUPT = 6,
+  IBE_ERROR_CODE_LIMITS = 7,
+  IBE_ERROR_CODE_TIMEOUT = 8,
+} IbeErrorCode;
+
+/**
+ * Output encoding. `IBE_FORMAT_AUTO` keeps the destination extension for files
+ * and the input format for buffers.
+ */
+typedef enum IbeFormat {
+  IBE_FORMAT_AUTO = 0,
+  IBE_FORMAT_PNG = 1,
+  IBE_FORMAT_JPEG = 2,
+  IBE_FORMAT_GIF = 3,
+  IBE_FORMAT_BMP = 4,
+  IBE_FORMAT_TIFF = 5,
+} IbeFormat;
+
+/**
+ * Optional settings; a null pointer means all defaults.
+ */
+typedef struct IbeOptions {
+  enum IbeFormat output_format;
+} IbeOptions;
+
+/**
+ * Filled in by every call when non-null; `message` is NUL-terminated.
+ */
+typedef struct IbeError {
+  enum IbeErrorCode code;
+  char message[IBE_MESSAGE_CAPACITY];
+} IbeError;
+
+/**
+ * Encoded image owned by the library. Release with `ibe_buffer_free`.
+ */
+typedef struct IbeBuffer {
+  uint8_t *data;
+  size_t len;
+} IbeBuffer;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * Extends the image at `src` to the `aw`:`ah` aspect ratio and writes it to `dest`.
+ *
+ * # Safety
+ *
+ * `src` and `dest` must be NUL-terminated strings. `options` and `err` may be
+ * null, otherwise they must point to valid structs.
+ */
+enum IbeErrorCode ibe_extend_file(const char *src,
+                                  const char *dest,
+                                  uint32_t aw,
+                                  uint32_t ah,
+                                  const struct IbeOptions *options,
+                                  struct IbeError *err);
+
+/**
+ * Extends the encoded image in `data` and stores the encoded result in `out`.
+ *
+ * # Safety
+ *
+ * `data` must point to `len` readable bytes and `out` to a writable
+ * `IbeBuffer`. `options` and `err` may be null.
+ */
+enum IbeErrorCode ibe_extend_bytes(const uint8_t *data,
+                                   size_t len,
+                                   uint32_t aw,
+                                   uint32_t ah,
+                                   const struct IbeOptions *options,
+                                   struct IbeBuffer *out,
+                                   struct IbeError *err);
+
+/**
+ * Releases a buffer returned by `ibe_extend_bytes`. Safe to call twice.
+ *
+ * # Safety
+ *
+ * `buffer` must be null or point to an `IbeBuffer` filled by this library.
+ */
+void ibe_buffer_free(struct IbeBuffer *buffer);
+
+#ifdef __cplusplus
+} // extern "C"
+#endif // __cplusplus
+
+#endif /* IMAGE_BG_EXTENDER_H */
@@ -0,0 +1,73 @@
+"""Smoke test for the Python bindings.
+
+Run with `maturin develop && pytest python/tests`.
+"""
+import struct
+import zlib
+
+import pytest
+
+import image_bg_extender as ibe
+
+
+def png(width, height, rgb=(200, 30, 30), row=None):
+    def chunk(kind, data):
+        body = kind + data
+        return struct.pack(">I", len(data)) + body + struct.pack(">I", zlib.crc32(body))
+
+    row = b"\x00" + (row or bytes(rgb) * width)
+    return (
+        b"\x89PNG\r\n\x1a\n"
+        + chunk(b"IHDR", struct.pack(">IIBBBBB", width, height, 8, 2, 0, 0, 0))
+        + chunk(b"IDAT", zlib.compress(row * height))
+        + chunk(b"IEND", b"")
+    )
+
+
+def png_size(data):
+    return struct.unpack(">II", data[16:24])
+
+
+def test_extend_bytes():
+    out = ibe.extend_bytes(png(30, 20), "1:1", format="png")
+    assert png_size(out) == (30, 30)
+
+
+def test_extend_bytes_takes_every_option():
+    # Stripes, for the quality to show in the size of a JPEG
+    source = png(30, 20, row=bytes((x * 97) % 256 for x in range(30 * 3)))
+    low = ibe.extend_bytes(source, "1:1", format="jpeg", quality=5)
+    high = ibe.extend_bytes(source, "1:1", format="jpeg", quality=95)
+    assert low[:2] == b"\xff\xd8"
+    assert len(low) < len(high)
+    assert png_size(ibe.extend_bytes(source, "1:1", first_frame_only=True)) == (30, 30)
+    with pytest.raises(TypeError):
+        ibe.extend_bytes(source, "1:1", qualty=5)
+
+
+def test_extend_file(tmp_path):
+    src = tmp_path / "src.png"
+    dest = tmp_path / "dest.png"
+    src.write_bytes(png(30, 20))
+    ibe.extend_file(str(src), str(dest), (1, 1))
+    assert png_size(dest.read_bytes()) == (30, 30)
+
+
+def test_errors_map_to_exception_types(tmp_path):
+    with pytest.raises(ibe.SourceIoError):
+        ibe.extend_file(str(tmp_path / "missing.png"), str(tmp_path / "out.png"), (1, 1))
+    with pytest.raises(ibe.ImageError):
+        ibe.extend_bytes(b"not an image", (1, 1))
+    with pytest.raises(ValueError):
+        ibe.extend_bytes(png(2, 2), (0, 1))
+
+
+def test_corrupt_images_are_image_errors():
+    # The signature and header only, cut off before the image data
+    data = png(30, 20)[:33]
+    with pytest.raises(ibe.CorruptImageError):
+        ibe.extend_bytes(data, (1, 1))
+    with pytest.raises(ibe.ImageError):
+        ibe.extend_bytes(data, (1, 1))
+    with pytest.raises(ibe.CorruptImageError):
+        ibe.extend_bytes(b"", (1, 1))
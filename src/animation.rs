@@ -0,0 +1,592 @@
+//! Frame-by-frame extension of animated GIFs, and of animated WebPs with the
+//! `animated-webp` feature, into GIF, APNG or WebP animations.
+//!
+//! Frames are decoded, extended and encoded one at a time so memory use does
+//! not grow with the length of the animation.
+
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read};
+use std::time::Instant;
+
+use image::codecs::gif::GifDecoder;
+use image::error::{
+    EncodingError, ImageFormatHint, ParameterError, ParameterErrorKind, UnsupportedError,
+    UnsupportedErrorKind,
+};
+use image::{AnimationDecoder, DynamicImage, Frame, GenericImageView, ImageError, ImageFormat};
+
+use crate::atomic::{self, TempFile};
+use crate::error::{BoxError, Context, CorruptSource, ExtendError, Stage};
+use crate::report::Timings;
+use crate::{
+    check_dimensions, check_source_pixels, crop_for, fill_background, inset, layout_for,
+    normalise_image, place, render, AnimationColors, Crop, ImageReport, Layout, Mode, Options,
+    OutputFormat,
+};
+use crate::{saliency, timeout, watermark};
+
+pub(crate) enum GifOutcome {
+    Animated(Box<ImageReport>),
+    /// The animation has a single frame and goes through the regular
+    /// still-image path.
+    Still(DynamicImage),
+}
+
+/// The decoded frames of an animated source, with what else is read from it.
+struct Animation<'a> {
+    /// Format of the source, which it is copied as when it needs no change.
+    format: OutputFormat,
+    frames: Box<dyn Iterator<Item = Result<Frame, ImageError>> + 'a>,
+    /// How many times the animation plays, zero meaning forever.
+    plays: u32,
+    /// Counts the frames once more, for APNG output, which needs the number
+    /// up front.
+    count_frames: Box<dyn Fn() -> Result<u32, BoxError> + 'a>,
+}
+
+/// Reads how many times the animation plays from the NETSCAPE2.0 extension,
+/// which the decoder does not expose. Zero means forever.
+///
+/// Walks the blocks preceding the first image descriptor; GIFs without the
+/// extension play once.
+fn read_plays<R: Read>(mut r: R) -> io::Result<u32> {
+    fn byte<R: Read>(r: &mut R) -> io::Result<u8> {
+        let mut buf = [0];
+        r.read_exact(&mut buf)?;
+        Ok(buf[0])
+    }
+    fn skip<R: Read>(r: &mut R, len: u64) -> io::Result<()> {
+        io::copy(&mut r.take(len), &mut io::sink()).map(|_| ())
+    }
+
+    let mut header = [0; 13];
+    r.read_exact(&mut header)?;
+    if header[10] & 0x80 != 0 {
+        skip(&mut r, 3 << ((header[10] & 0x07) + 1))?;
+    }
+
+    // Extension introducer; anything else is the first frame or the trailer
+    while byte(&mut r)? == 0x21 {
+        let label = byte(&mut r)?;
+        let mut first_block = Vec::new();
+        loop {
+            let len = byte(&mut r)?;
+            if len == 0 {
+                break;
+            }
+            if first_block.is_empty() {
+                first_block.resize(len as usize, 0);
+                r.read_exact(&mut first_block)?;
+            } else {
+                skip(&mut r, len as u64)?;
+            }
+            if label == 0xFF && first_block.as_slice() == &b"NETSCAPE2.0"[..] {
+                let mut data = [0; 4];
+                r.read_exact(&mut data)?;
+                if data[0] == 3 && data[1] == 1 {
+                    let repeats = u16::from_le_bytes([data[2], data[3]]);
+                    return Ok(if repeats == 0 { 0 } else { repeats as u32 + 1 });
+                }
+            }
+        }
+    }
+    Ok(1)
+}
+
+fn encoding_error(e: gif::EncodingError) -> ImageError {
+    ImageError::Encoding(EncodingError::new(ImageFormat::Gif.into(), e))
+}
+
+fn parameter_error(kind: ParameterErrorKind) -> ImageError {
+    ImageError::Parameter(ParameterError::from_kind(kind))
+}
+
+fn unsupported(format: ImageFormatHint, feature: String) -> ImageError {
+    ImageError::Unsupported(UnsupportedError::from_format_and_kind(
+        format,
+        UnsupportedErrorKind::GenericFeature(feature),
+    ))
+}
+
+fn delay_ms(frame: &Frame) -> u32 {
+    let (numerator, denominator) = frame.delay().numer_denom_ms();
+    numerator / denominator.max(1)
+}
+
+/// Receives the extended frames in order.
+trait FrameSink {
+    fn write_frame(&mut self, canvas: image::RgbaImage, delay_ms: u32) -> Result<(), BoxError>;
+
+    fn finish(self: Box<Self>) -> Result<(), BoxError>;
+}
+
+struct GifSink {
+    encoder: gif::Encoder<BufWriter<File>>,
+    width: u16,
+    height: u16,
+}
+
+impl GifSink {
+    fn create(dest: &str, (width, height): (u32, u32), plays: u32) -> Result<Self, ImageError> {
+        // GIF dimensions are 16-bit
+        let too_large = || parameter_error(ParameterErrorKind::DimensionMismatch);
+        let width = u16::try_from(width).map_err(|_| too_large())?;
+        let height = u16::try_from(height).map_err(|_| too_large())?;
+        let mut encoder =
+            gif::Encoder::new(BufWriter::new(File::create(dest)?), width, height, &[])
+                .map_err(encoding_error)?;
+        let repeat = match plays {
+            0 => gif::Repeat::Infinite,
+            plays => gif::Repeat::Finite(u16::try_from(plays - 1).unwrap_or(u16::MAX)),
+        };
+        encoder.set_repeat(repeat).map_err(encoding_error)?;
+        Ok(GifSink {
+            encoder,
+            width,
+            height,
+        })
+    }
+}
+
+impl FrameSink for GifSink {
+    fn write_frame(&mut self, mut canvas: image::RgbaImage, delay_ms: u32) -> Result<(), BoxError> {
+        let mut frame = gif::Frame::from_rgba_speed(self.width, self.height, &mut canvas, 10);
+        // GIF delays are in hundredths of a second
+        frame.delay = u16::try_from(delay_ms / 10).unwrap_or(u16::MAX);
+        self.encoder.write_frame(&frame).map_err(encoding_error)?;
+        Ok(())
+    }
+
+    fn finish(self: Box<Self>) -> Result<(), BoxError> {
+        Ok(())
+    }
+}
+
+#[cfg(feature = "apng")]
+struct ApngSink {
+    writer: png::Writer<BufWriter<File>>,
+}
+
+#[cfg(feature = "apng")]
+impl ApngSink {
+    fn create(
+        dest: &str,
+        (width, height): (u32, u32),
+        frames: u32,
+        plays: u32,
+    ) -> Result<Self, BoxError> {
+        let mut encoder = png::Encoder::new(BufWriter::new(File::create(dest)?), width, height);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.set_animated(frames, plays)?;
+        Ok(ApngSink {
+            writer: encoder.write_header()?,
+        })
+    }
+}
+
+#[cfg(feature = "apng")]
+impl FrameSink for ApngSink {
+    fn write_frame(&mut self, canvas: image::RgbaImage, delay_ms: u32) -> Result<(), BoxError> {
+        let delay = u16::try_from(delay_ms).unwrap_or(u16::MAX);
+        self.writer.set_frame_delay(delay, 1000)?;
+        self.writer.write_image_data(&canvas)?;
+        Ok(())
+    }
+
+    fn finish(self: Box<Self>) -> Result<(), BoxError> {
+        self.writer.finish()?;
+        Ok(())
+    }
+}
+
+#[cfg(feature = "animated-webp")]
+struct WebpSink {
+    encoder: webp_animation::Encoder,
+    dest: String,
+    timestamp: i32,
+}
+
+#[cfg(feature = "animated-webp")]
+impl WebpSink {
+    fn create(
+        dest: &str,
+        dimensions: (u32, u32),
+        plays: u32,
+        quality: Option<u8>,
+    ) -> Result<Self, BoxError> {
+        let encoding_config = quality.map(|quality| webp_animation::EncodingConfig {
+            encoding_type: webp_animation::EncodingType::new_lossy(),
+            quality: quality.min(100) as f32,
+            ..Default::default()
+        });
+        let options = webp_animation::EncoderOptions {
+            anim_params: webp_animation::AnimParams {
+                loop_count: plays as i32,
+            },
+            encoding_config,
+            ..Default::default()
+        };
+        Ok(WebpSink {
+            encoder: webp_animation::Encoder::new_with_options(dimensions, options)
+                .map_err(|e| format!("{:?}", e))?,
+            dest: dest.to_string(),
+            timestamp: 0,
+        })
+    }
+}
+
+#[cfg(feature = "animated-webp")]
+impl FrameSink for WebpSink {
+    fn write_frame(&mut self, canvas: image::RgbaImage, delay_ms: u32) -> Result<(), BoxError> {
+        self.encoder
+            .add_frame(&canvas, self.timestamp)
+            .map_err(|e| format!("{:?}", e))?;
+        self.timestamp += delay_ms as i32;
+        Ok(())
+    }
+
+    fn finish(self: Box<Self>) -> Result<(), BoxError> {
+        let data = self
+            .encoder
+            .finalize(self.timestamp)
+            .map_err(|e| format!("{:?}", e))?;
+        std::fs::write(&self.dest, &*data)?;
+        Ok(())
+    }
+}
+
+#[cfg_attr(
+    not(all(feature = "apng", feature = "animated-webp")),
+    allow(unused_variables)
+)]
+fn create_sink(
+    count_frames: &dyn Fn() -> Result<u32, BoxError>,
+    dest: &str,
+    format: OutputFormat,
+    dimensions: (u32, u32),
+    plays: u32,
+    options: &Options,
+) -> Result<Box<dyn FrameSink>, BoxError> {
+    match format {
+        OutputFormat::Gif => Ok(Box::new(GifSink::create(dest, dimensions, plays)?)),
+        #[cfg(feature = "apng")]
+        OutputFormat::Png | OutputFormat::Apng => Ok(Box::new(ApngSink::create(
+            dest,
+            dimensions,
+            count_frames()?,
+            plays,
+        )?)),
+        #[cfg(feature = "animated-webp")]
+        OutputFormat::Webp => Ok(Box::new(WebpSink::create(
+            dest,
+            dimensions,
+            plays,
+            options.quality,
+        )?)),
+        format => Err(unsupported(
+            format.hint(),
+            format!(
+                "animated {:?} output (enable the `apng` or `animated-webp` feature, or set firstFrameOnly)",
+                format
+            ),
+        )
+        .into()),
+    }
+}
+
+pub(crate) fn extend_gif(
+    src: &str,
+    dest: &str,
+    aspect_ratio: (u32, u32),
+    format: Option<OutputFormat>,
+    options: &Options,
+) -> Result<GifOutcome, ExtendError> {
+    let started = Instant::now();
+    let file = File::open(src).context(Stage::Read, src)?;
+    let frames = GifDecoder::new(BufReader::new(file))
+        .map_err(|e| CorruptSource::classify_file(src, e))
+        .context(Stage::Decode, src)?
+        .into_frames();
+    let plays = File::open(src)
+        .and_then(|file| read_plays(BufReader::new(file)))
+        .context(Stage::Decode, src)?;
+    let animation = Animation {
+        format: OutputFormat::Gif,
+        frames: Box::new(frames),
+        plays,
+        count_frames: Box::new(|| {
+            let frames = GifDecoder::new(BufReader::new(File::open(src)?))?.into_frames();
+            Ok(frames.count() as u32)
+        }),
+    };
+    extend_animation(src, dest, aspect_ratio, format, options, animation, started)
+}
+
+/// Whether the WebP at `src` is animated, as the flags of its extended
+/// header say. Plain WebPs have no such header.
+#[cfg(feature = "animated-webp")]
+pub(crate) fn is_animated_webp(src: &str) -> bool {
+    let mut header = [0; 21];
+    File::open(src)
+        .and_then(|mut file| file.read_exact(&mut header))
+        .is_ok_and(|()| &header[12..16] == b"VP8X" && header[20] & 0x02 != 0)
+}
+
+/// The loop count in the ANIM chunk of a WebP, which the decoder does not
+/// expose. Zero means forever, as it does for `plays`.
+#[cfg(feature = "animated-webp")]
+fn webp_plays(data: &[u8]) -> u32 {
+    let mut at = 12;
+    while let Some(chunk) = data.get(at..at + 8) {
+        let len = u32::from_le_bytes([chunk[4], chunk[5], chunk[6], chunk[7]]) as usize;
+        if &chunk[..4] == b"ANIM" {
+            return data
+                .get(at + 12..at + 14)
+                .map_or(0, |count| u16::from_le_bytes([count[0], count[1]]) as u32);
+        }
+        // Chunks are padded to an even length
+        at += 8 + len + (len & 1);
+    }
+    0
+}
+
+#[cfg(feature = "animated-webp")]
+pub(crate) fn extend_webp(
+    src: &str,
+    dest: &str,
+    aspect_ratio: (u32, u32),
+    format: Option<OutputFormat>,
+    options: &Options,
+) -> Result<GifOutcome, ExtendError> {
+    let started = Instant::now();
+    let data = std::fs::read(src).context(Stage::Read, src)?;
+    let decode = || {
+        webp_animation::Decoder::new(&data).map_err(|e| {
+            ImageError::Decoding(image::error::DecodingError::new(
+                ImageFormat::WebP.into(),
+                format!("{:?}", e),
+            ))
+        })
+    };
+    let decoder = decode()
+        .map_err(|e| CorruptSource::classify_file(src, e))
+        .context(Stage::Decode, src)?;
+    // Timestamps are where each frame ends
+    let mut ended = 0;
+    let frames = decoder.into_iter().map(move |frame| {
+        let delay = (frame.timestamp() - ended).max(0) as u32;
+        ended = frame.timestamp();
+        let (width, height) = frame.dimensions();
+        let buffer = image::RgbaImage::from_raw(width, height, frame.data().to_vec())
+            .ok_or_else(|| parameter_error(ParameterErrorKind::DimensionMismatch))?;
+        Ok(Frame::from_parts(
+            buffer,
+            0,
+            0,
+            image::Delay::from_numer_denom_ms(delay, 1),
+        ))
+    });
+    let animation = Animation {
+        format: OutputFormat::Webp,
+        frames: Box::new(frames),
+        plays: webp_plays(&data),
+        count_frames: Box::new(|| Ok(decode()?.into_iter().count() as u32)),
+    };
+    extend_animation(src, dest, aspect_ratio, format, options, animation, started)
+}
+
+fn extend_animation(
+    src: &str,
+    dest: &str,
+    aspect_ratio: (u32, u32),
+    format: Option<OutputFormat>,
+    options: &Options,
+    animation: Animation,
+    started: Instant,
+) -> Result<GifOutcome, ExtendError> {
+    let Animation {
+        format: source_format,
+        mut frames,
+        plays,
+        count_frames,
+    } = animation;
+
+    let first = match frames.next() {
+        Some(frame) => frame
+            .map_err(|e| CorruptSource::classify_file(src, e))
+            .context(Stage::Decode, src)?,
+        None => {
+            return Err(parameter_error(ParameterErrorKind::NoMoreData)).context(Stage::Decode, src)
+        }
+    };
+    check_dimensions(first.buffer().dimensions(), Some(src)).context(Stage::Decode, src)?;
+    check_source_pixels(first.buffer().dimensions(), options).context(Stage::Decode, src)?;
+    let second = match frames.next() {
+        Some(frame) => frame
+            .map_err(|e| CorruptSource::classify_file(src, e))
+            .context(Stage::Decode, src)?,
+        None => {
+            return Ok(GifOutcome::Still(DynamicImage::ImageRgba8(
+                first.into_buffer(),
+            )))
+        }
+    };
+    let first_image = DynamicImage::ImageRgba8(first.buffer().clone());
+    if options.first_frame_only {
+        return Ok(GifOutcome::Still(first_image));
+    }
+    let first_image = options.orient(first_image);
+
+    let format = match format {
+        Some(format) if format.is_animated() => format,
+        Some(format) => {
+            return Err(unsupported(
+                format.hint(),
+                format!(
+                    "animated source written as still {:?} (set firstFrameOnly to keep only the first frame)",
+                    format
+                ),
+            ))
+            .context(Stage::Write, dest)
+        }
+        None => {
+            return Err(unsupported(
+                source_format.hint(),
+                "unknown destination format".into(),
+            ))
+                .context(Stage::Write, dest)
+        }
+    };
+
+    let crop = match options.mode_for(first_image.dimensions(), aspect_ratio) {
+        Mode::Crop => Some(
+            crop_for(first_image.dimensions(), aspect_ratio, options)
+                .context(Stage::Composite, src)?,
+        ),
+        Mode::Extend | Mode::ExtendOrCrop | Mode::ZoomToFill => None,
+        Mode::SeamCarve => {
+            return Err(unsupported(
+                source_format.hint(),
+                "seamCarve mode on an animated source (set firstFrameOnly to carve only the first frame)"
+                    .into(),
+            ))
+            .context(Stage::Composite, src)
+        }
+    };
+    let layout = match crop {
+        Some(crop) => crop.map(|crop| Layout::unchanged(crop.size())),
+        None => layout_for(first_image.dimensions(), aspect_ratio, options)
+            .context(Stage::Composite, src)?,
+    };
+    let layout = match layout {
+        Some(layout) => layout,
+        None if format == source_format && options.max_size(first_image.dimensions()).is_none() => {
+            // Already in place when the destination is the source
+            if !atomic::same_file(src, dest).unwrap_or(false) {
+                atomic::write(dest, |temp| {
+                    std::fs::copy(src, temp).context(Stage::Write, dest)
+                })?;
+            }
+            let mut report = ImageReport::unchanged(first_image.dimensions());
+            report.frames = Some(2 + frames.count() as u32);
+            return Ok(GifOutcome::Animated(Box::new(report)));
+        }
+        // Re-encode the frames as they are
+        None => Layout::unchanged(first_image.dimensions()),
+    };
+    // Placed by the first frame, so the image holds still
+    let layout = saliency::settle(&first_image, layout, &options.style);
+    // Every frame is cut the same, with no background
+    let crop = crop.map(|crop| crop.unwrap_or_else(|| Crop::whole(first_image.dimensions())));
+
+    let mut timings = Timings::default();
+    let output = TempFile::new(dest);
+    let mut sink = Timings::measure(&mut timings.write_ms, || {
+        create_sink(
+            &*count_frames,
+            output.path(),
+            format,
+            options.max_size(layout.canvas).unwrap_or(layout.canvas),
+            plays,
+            options,
+        )
+    })
+    .context(Stage::Write, dest)?;
+
+    let extended = Timings::measure(&mut timings.composite_ms, || {
+        match &crop {
+            Some(crop) => crop.apply(&first_image, &options.style),
+            None => render(&first_image, &layout, None, &options.style),
+        }
+        .map(|extended| extended.shrunk(options))
+    })
+    .context(Stage::Composite, src)?;
+    let mut report = extended.report();
+    // With fixed colors the background is filled once and reused for every frame
+    let background = match options.animation_colors {
+        _ if crop.is_some() => None,
+        AnimationColors::FirstFrame => Some(
+            fill_background(
+                &normalise_image(&first_image, layout.overflow),
+                &layout,
+                (extended.colors[0], extended.colors[1]),
+                &options.style,
+            )
+            .context(Stage::Composite, src)?,
+        ),
+        AnimationColors::PerFrame => None,
+    };
+
+    Timings::measure(&mut timings.write_ms, || {
+        sink.write_frame(extended.image, delay_ms(&first))
+    })
+    .context(Stage::Write, dest)?;
+    let mut count = 1;
+    for frame in std::iter::once(Ok(second)).chain(frames) {
+        timeout::checkpoint(Stage::Decode, src)?;
+        let frame = frame
+            .map_err(|e| CorruptSource::classify_file(src, e))
+            .context(Stage::Decode, src)?;
+        let delay = delay_ms(&frame);
+        let image = options.orient(DynamicImage::ImageRgba8(frame.into_buffer()));
+        let canvas = Timings::measure(&mut timings.composite_ms, || match (&crop, &background) {
+            (Some(crop), _) => crop
+                .apply(&image, &options.style)
+                .map(|extended| extended.image),
+            (None, Some(background)) => {
+                let mut canvas = background.clone();
+                place(
+                    &mut canvas,
+                    &inset(
+                        normalise_image(&image, layout.overflow),
+                        &layout,
+                        &options.style,
+                    ),
+                    &layout,
+                    &options.style,
+                )?;
+                watermark::apply(&mut canvas, Some(&layout), &options.style)?;
+                Ok(canvas)
+            }
+            (None, None) => {
+                render(&image, &layout, None, &options.style).map(|extended| extended.image)
+            }
+        })
+        .context(Stage::Composite, src)?;
+        let canvas = Timings::measure(&mut timings.composite_ms, || options.shrink(canvas));
+        Timings::measure(&mut timings.write_ms, || sink.write_frame(canvas, delay))
+            .context(Stage::Write, dest)?;
+        count += 1;
+    }
+    Timings::measure(&mut timings.write_ms, || sink.finish()).context(Stage::Write, dest)?;
+    output.commit()?;
+
+    // Frames are decoded lazily, in between the other stages
+    timings.decode_ms =
+        started.elapsed().as_secs_f64() * 1000.0 - timings.composite_ms - timings.write_ms;
+    report.timings = timings;
+    report.frames = Some(count);
+    Ok(GifOutcome::Animated(Box::new(report)))
+}
@@ -0,0 +1,301 @@
+//! Sources read from, and destinations written into, ZIP archives, named as
+//! `bundle.zip!photos/a.jpg`, through the zip crate when the `zip` feature is
+//! enabled.
+//!
+//! Entries are read into memory and decoded from there, so nothing is
+//! extracted to disk. Such paths are recognised in either case, so builds
+//! without the feature can say what is missing instead of failing on a file
+//! that does not exist.
+
+use std::error::Error;
+use std::fmt;
+use std::path::Path;
+
+use crate::error::{BoxError, ExtendError};
+#[cfg(not(feature = "zip"))]
+use crate::format::FormatNotCompiled;
+use crate::ImageInfo;
+
+/// The archive and the path of the entry in it that `path` names, if it
+/// names one.
+pub fn split(path: &str) -> Option<(&str, &str)> {
+    // Lowercasing ASCII keeps the byte offsets of the original
+    let at = path.to_ascii_lowercase().find(".zip!")? + ".zip".len();
+    Some((&path[..at], &path[at + 1..]))
+}
+
+/// An entry that could not be read from or written into its archive.
+#[derive(Debug)]
+pub struct ArchiveError {
+    pub archive: String,
+    pub entry: String,
+    source: BoxError,
+}
+
+impl ArchiveError {
+    fn new(path: &str, source: impl Into<BoxError>) -> Self {
+        let (archive, entry) = split(path).unwrap_or((path, ""));
+        ArchiveError {
+            archive: archive.to_string(),
+            entry: entry.to_string(),
+            source: source.into(),
+        }
+    }
+}
+
+impl fmt::Display for ArchiveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} in {}: {}", self.entry, self.archive, self.source)
+    }
+}
+
+impl Error for ArchiveError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&*self.source)
+    }
+}
+
+#[cfg(not(feature = "zip"))]
+fn not_compiled() -> FormatNotCompiled {
+    FormatNotCompiled {
+        format: "ZIP",
+        feature: "zip",
+    }
+}
+
+#[cfg(feature = "zip")]
+fn open(archive: &str) -> Result<zip::ZipArchive<std::io::BufReader<std::fs::File>>, BoxError> {
+    let file = std::fs::File::open(archive)?;
+    Ok(zip::ZipArchive::new(std::io::BufReader::new(file))?)
+}
+
+/// Reads the whole entry `path` names.
+#[cfg(feature = "zip")]
+pub(crate) fn read(path: &str) -> Result<Vec<u8>, BoxError> {
+    use std::io::Read;
+
+    let (archive, entry) = split(path).ok_or("not in an archive")?;
+    let read = || -> Result<Vec<u8>, BoxError> {
+        let mut archive = open(archive)?;
+        let mut file = archive.by_name(entry)?;
+        let mut data = Vec::with_capacity(file.size() as usize);
+        file.read_to_end(&mut data)?;
+        Ok(data)
+    };
+    read().map_err(|e| ArchiveError::new(path, e).into())
+}
+
+#[cfg(not(feature = "zip"))]
+pub(crate) fn read(_path: &str) -> Result<Vec<u8>, BoxError> {
+    Err(not_compiled().into())
+}
+
+/// Checks that the entry `path` names exists, without reading it.
+#[cfg(feature = "zip")]
+pub(crate) fn check(path: &str) -> Result<(), BoxError> {
+    let (archive, entry) = split(path).ok_or("not in an archive")?;
+    let check = || -> Result<(), BoxError> {
+        open(archive)?.by_name(entry)?;
+        Ok(())
+    };
+    check().map_err(|e| ArchiveError::new(path, e).into())
+}
+
+#[cfg(not(feature = "zip"))]
+pub(crate) fn check(_path: &str) -> Result<(), BoxError> {
+    Err(not_compiled().into())
+}
+
+/// Whether `name` matches `pattern`, in which `*` stands for any part of a
+/// name within a single directory.
+fn matches(pattern: &[u8], name: &[u8]) -> bool {
+    match pattern.split_first() {
+        None => name.is_empty(),
+        Some((b'*', rest)) => (0..=name.len())
+            .take_while(|&taken| taken == 0 || name[taken - 1] != b'/')
+            .any(|taken| matches(rest, &name[taken..])),
+        Some((c, rest)) => name
+            .split_first()
+            .is_some_and(|(first, name)| first == c && matches(rest, name)),
+    }
+}
+
+/// Whether the name of an entry tells of an image format, which entries of
+/// a wildcard, and files of a pattern, are picked by.
+pub(crate) fn is_image(entry: &str) -> bool {
+    image::ImageFormat::from_path(entry).is_ok()
+        || Path::new(entry)
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .is_some_and(|ext| ["heic", "heif"].contains(&ext.to_ascii_lowercase().as_str()))
+}
+
+#[cfg(feature = "zip")]
+fn entries(archive: &str) -> Result<Vec<String>, BoxError> {
+    let mut names: Vec<String> = open(archive)?.file_names().map(str::to_string).collect();
+    names.sort();
+    Ok(names)
+}
+
+#[cfg(not(feature = "zip"))]
+fn entries(_archive: &str) -> Result<Vec<String>, BoxError> {
+    Err(not_compiled().into())
+}
+
+/// Entries of the job list made from a wildcard source, here or by
+/// `glob::expand`.
+pub struct Expanded {
+    /// One for each image the wildcard matches.
+    pub entries: Vec<ImageInfo>,
+    /// Other files it matches, as paths into the archive or on disk.
+    pub skipped: Vec<String>,
+}
+
+/// The entries a wildcard source of `info`, as `bundle.zip!photos/*`,
+/// stands for. `None` when the source is no such wildcard.
+///
+/// The destination of `info`, and that of each of its `outputs`, is taken as
+/// a directory, or a directory in an archive, each image keeping its path
+/// below the directory of the wildcard.
+/// The archive is opened under `base` when its path is relative.
+pub fn expand(info: &ImageInfo, base: Option<&Path>) -> Option<Result<Expanded, ArchiveError>> {
+    let (archive, pattern) = split(info.source()).filter(|(_, entry)| entry.contains('*'))?;
+    let resolved = match base {
+        Some(base) => base.join(archive),
+        None => Path::new(archive).to_path_buf(),
+    };
+    let names = match entries(&resolved.to_string_lossy()) {
+        Ok(names) => names,
+        Err(e) => return Some(Err(ArchiveError::new(info.source(), e))),
+    };
+    let dir = &pattern[..pattern[..pattern.find('*')?]
+        .rfind('/')
+        .map_or(0, |at| at + 1)];
+    let mut entries = Vec::new();
+    let mut skipped = Vec::new();
+    for name in names {
+        // Directories have entries of their own in most archives
+        if name.ends_with('/') || !matches(pattern.as_bytes(), name.as_bytes()) {
+            continue;
+        }
+        let source = format!("{}!{}", archive, name);
+        if !is_image(&name) {
+            skipped.push(source);
+            continue;
+        }
+        let relative = &name[dir.len()..];
+        let below = |dest: &str| {
+            if dest.is_empty() {
+                relative.to_string()
+            } else if dest.ends_with('/') || dest.ends_with('!') {
+                format!("{}{}", dest, relative)
+            } else {
+                format!("{}/{}", dest, relative)
+            }
+        };
+        let mut entry = info.clone();
+        entry.source = source;
+        entry.destination = below(info.destination());
+        for output in &mut entry.outputs {
+            output.destination = below(&output.destination);
+        }
+        entries.push(entry);
+    }
+    Some(Ok(Expanded { entries, skipped }))
+}
+
+#[cfg(feature = "zip")]
+mod writers {
+    use std::collections::HashMap;
+    use std::fs::File;
+    use std::io::Write;
+    use std::sync::{Mutex, MutexGuard, OnceLock};
+
+    use zip::write::FileOptions;
+    use zip::{CompressionMethod, ZipWriter};
+
+    use super::{split, ArchiveError};
+    use crate::atomic::TempFile;
+    use crate::error::{BoxError, Context, ExtendError, Stage};
+
+    /// An archive being written, which replaces its path once finished.
+    struct Writer {
+        zip: ZipWriter<File>,
+        temp: TempFile,
+    }
+
+    static WRITERS: OnceLock<Mutex<HashMap<String, Writer>>> = OnceLock::new();
+
+    fn writers() -> MutexGuard<'static, HashMap<String, Writer>> {
+        WRITERS
+            .get_or_init(Mutex::default)
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub(crate) fn add(dest: &str, file: &str) -> Result<(), ExtendError> {
+        let (archive, entry) = split(dest)
+            .ok_or("not in an archive")
+            .context(Stage::Write, dest)?;
+        let data = std::fs::read(file).context(Stage::Write, dest)?;
+        let mut writers = writers();
+        let add = |writers: &mut HashMap<String, Writer>| -> Result<(), BoxError> {
+            if !writers.contains_key(archive) {
+                // Not tracked by timeouts, as later entries still write into it
+                let temp = TempFile::detached(archive);
+                let file = File::create(temp.path())?;
+                let zip = ZipWriter::new(file);
+                writers.insert(archive.to_string(), Writer { zip, temp });
+            }
+            let writer = writers.get_mut(archive).expect("inserted above");
+            // Images are compressed already
+            let options = FileOptions::default().compression_method(CompressionMethod::Stored);
+            writer.zip.start_file(entry, options)?;
+            writer.zip.write_all(&data)?;
+            Ok(())
+        };
+        add(&mut writers)
+            .map_err(|e| ExtendError::new(Stage::Write, Some(dest), ArchiveError::new(dest, e)))
+    }
+
+    pub(crate) fn finish() -> Result<Vec<String>, ExtendError> {
+        let finished: Vec<(String, Writer)> = writers().drain().collect();
+        let mut archives = Vec::new();
+        for (archive, mut writer) in finished {
+            writer.zip.finish().context(Stage::Write, &archive)?;
+            writer.temp.commit()?;
+            archives.push(archive);
+        }
+        archives.sort();
+        Ok(archives)
+    }
+}
+
+/// Adds the file at `file` to the archive that `dest` names an entry of.
+/// The archive is started anew the first time it is written to.
+#[cfg(feature = "zip")]
+pub(crate) fn add(dest: &str, file: &str) -> Result<(), ExtendError> {
+    writers::add(dest, file)
+}
+
+#[cfg(not(feature = "zip"))]
+pub(crate) fn add(dest: &str, _file: &str) -> Result<(), ExtendError> {
+    Err(ExtendError::new(
+        crate::error::Stage::Write,
+        Some(dest),
+        not_compiled(),
+    ))
+}
+
+/// Writes out every archive that destinations were added to and returns
+/// their paths. An archive can only be read once this was called, after the
+/// last entry writing into it.
+#[cfg(feature = "zip")]
+pub fn finish() -> Result<Vec<String>, ExtendError> {
+    writers::finish()
+}
+
+#[cfg(not(feature = "zip"))]
+pub fn finish() -> Result<Vec<String>, ExtendError> {
+    Ok(Vec::new())
+}
@@ -0,0 +1,230 @@
+//! Writes that never leave a half-written destination behind.
+//!
+//! Output goes to a temporary file next to the destination, which is renamed
+//! over it only once everything was written. This also makes it safe for the
+//! destination to be the source.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::error::{Context, ExtendError, Stage};
+use crate::{archive, storage, timeout};
+
+static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Hidden sibling of `dest` that keeps its extension, so formats inferred
+/// from the path stay the same.
+fn temp_path(dest: &str) -> String {
+    let dest = Path::new(dest);
+    let unique = format!(
+        "ibe-{}-{}",
+        std::process::id(),
+        COUNTER.fetch_add(1, Ordering::Relaxed)
+    );
+    let stem = dest
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .unwrap_or("");
+    let name = match dest.extension().and_then(|ext| ext.to_str()) {
+        Some(ext) => format!(".{}.{}.{}", stem, unique, ext),
+        None => format!(".{}.{}", stem, unique),
+    };
+    dest.with_file_name(name).to_string_lossy().into_owned()
+}
+
+/// Output file that only replaces its destination on `commit`; dropping it
+/// beforehand removes whatever was written.
+///
+/// Destinations in an archive are written next to it, and added to it on
+/// `commit`. Remote destinations are written to the temporary directory and
+/// uploaded on `commit`.
+pub(crate) struct TempFile {
+    temp: String,
+    dest: String,
+    committed: bool,
+}
+
+impl TempFile {
+    pub(crate) fn new(dest: &str) -> Self {
+        let temp = Self::detached(dest);
+        timeout::track(&temp.temp);
+        temp
+    }
+
+    /// Left alone when the worker writing it is abandoned.
+    pub(crate) fn detached(dest: &str) -> Self {
+        if let Some((archive, entry)) = archive::split(dest) {
+            let beside =
+                Path::new(archive).with_file_name(Path::new(entry).file_name().unwrap_or_default());
+            return TempFile {
+                temp: temp_path(&beside.to_string_lossy()),
+                dest: dest.to_string(),
+                committed: false,
+            };
+        }
+        if storage::is_remote(dest) {
+            let name = Path::new(storage::name(dest))
+                .file_name()
+                .unwrap_or_default();
+            return TempFile {
+                temp: temp_path(&std::env::temp_dir().join(name).to_string_lossy()),
+                dest: dest.to_string(),
+                committed: false,
+            };
+        }
+        // Replace what a symlink points to rather than the link itself
+        let is_link = fs::symlink_metadata(dest).is_ok_and(|meta| meta.file_type().is_symlink());
+        let dest = match fs::canonicalize(dest) {
+            Ok(target) if is_link => target.to_string_lossy().into_owned(),
+            _ => dest.to_string(),
+        };
+        TempFile {
+            temp: temp_path(&dest),
+            dest,
+            committed: false,
+        }
+    }
+
+    /// Where the output has to be written.
+    pub(crate) fn path(&self) -> &str {
+        &self.temp
+    }
+
+    pub(crate) fn commit(mut self) -> Result<(), ExtendError> {
+        timeout::checkpoint(Stage::Write, &self.dest)?;
+        // Dropping removes the temporary file once it is in the archive
+        if archive::split(&self.dest).is_some() {
+            return archive::add(&self.dest, &self.temp);
+        }
+        storage::sink(&self.dest)
+            .put(&self.dest, Path::new(&self.temp))
+            .context(Stage::Write, &self.dest)?;
+        // An uploaded file is still there to be removed
+        self.committed = !storage::is_remote(&self.dest);
+        Ok(())
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl TempFile {
+    /// `commit` of a destination on this machine, renamed into place with
+    /// `tokio::fs`.
+    pub(crate) async fn commit_async(mut self) -> Result<(), ExtendError> {
+        tokio::fs::rename(&self.temp, &self.dest)
+            .await
+            .context(Stage::Write, &self.dest)?;
+        self.committed = true;
+        Ok(())
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        if !self.committed {
+            let _ = fs::remove_file(&self.temp);
+        }
+    }
+}
+
+/// Runs `write` against a temporary path and moves the result to `dest` once
+/// it succeeded.
+pub(crate) fn write<T>(
+    dest: &str,
+    write: impl FnOnce(&str) -> Result<T, ExtendError>,
+) -> Result<T, ExtendError> {
+    let temp = TempFile::new(dest);
+    let value = write(temp.path())?;
+    temp.commit()?;
+    Ok(value)
+}
+
+/// Resolves `path` even when it does not exist yet, as long as its directory does.
+pub(crate) fn resolve(path: &str) -> io::Result<PathBuf> {
+    let path = Path::new(path);
+    match fs::canonicalize(path) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => match (path.parent(), path.file_name()) {
+            (Some(parent), Some(name)) => {
+                let parent = if parent.as_os_str().is_empty() {
+                    Path::new(".")
+                } else {
+                    parent
+                };
+                Ok(fs::canonicalize(parent)?.join(name))
+            }
+            _ => Err(e),
+        },
+        resolved => resolved,
+    }
+}
+
+/// Whether both paths name the same file, following symlinks and `.` or `..`
+/// components.
+pub(crate) fn same_file(src: &str, dest: &str) -> io::Result<bool> {
+    Ok(resolve(src)? == resolve(dest)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn touch(path: &Path) -> String {
+        fs::write(path, b"source").unwrap();
+        path.to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn same_file_sees_through_dot_components() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = touch(&dir.path().join("a.png"));
+        let dotted = dir.path().join(".").join("sub").join("..").join("a.png");
+        fs::create_dir(dir.path().join("sub")).unwrap();
+        assert!(same_file(&source, dotted.to_str().unwrap()).unwrap());
+        let other = dir.path().join("b.png");
+        assert!(!same_file(&source, other.to_str().unwrap()).unwrap());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn same_file_follows_symlinks() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = touch(&dir.path().join("a.png"));
+        let link = dir.path().join("link.png");
+        std::os::unix::fs::symlink(&source, &link).unwrap();
+        assert!(same_file(&source, link.to_str().unwrap()).unwrap());
+        assert!(same_file(link.to_str().unwrap(), &source).unwrap());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn writes_through_a_symlink_to_its_target() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = touch(&dir.path().join("a.png"));
+        let link = dir.path().join("link.png");
+        std::os::unix::fs::symlink(&target, &link).unwrap();
+        write(link.to_str().unwrap(), |temp| {
+            fs::write(temp, b"written").context(Stage::Write, temp)
+        })
+        .unwrap();
+        assert!(fs::symlink_metadata(&link)
+            .unwrap()
+            .file_type()
+            .is_symlink());
+        assert_eq!(fs::read(&target).unwrap(), b"written");
+    }
+
+    #[test]
+    fn a_failed_write_leaves_the_destination_alone() {
+        let dir = tempfile::tempdir().unwrap();
+        let dest = touch(&dir.path().join("a.png"));
+        let result: Result<(), ExtendError> = write(&dest, |temp| {
+            fs::write(temp, b"half").context(Stage::Write, temp)?;
+            Err(ExtendError::new(Stage::Write, Some(temp), "encoder failed"))
+        });
+        assert!(result.is_err());
+        assert_eq!(fs::read(&dest).unwrap(), b"source");
+        // No temporary file is left next to it
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+}
@@ -0,0 +1,52 @@
+//! Work on a single large canvas split into bands of rows.
+//!
+//! With the `parallel` feature the bands run on as many threads as there
+//! are permits of the process stage that no other entry is using, so a few
+//! huge images make use of the `--jobs` budget without going over it. Every
+//! row is worked out the same way whichever band it falls in, so the result
+//! does not depend on how the canvas was split.
+
+/// Canvases smaller than this, in bytes, are not worth spreading over threads.
+#[cfg(feature = "parallel")]
+const MIN_PARALLEL_BYTES: usize = 4 << 20;
+
+/// Bands per thread, so threads that get through theirs early take on more.
+#[cfg(feature = "parallel")]
+const BANDS_PER_THREAD: usize = 4;
+
+/// Calls `work` with the index of the first row of each band and the rows of
+/// the band, `row_len` channels each, until all of `data` is done.
+#[cfg_attr(not(feature = "parallel"), allow(unused_variables))]
+pub(crate) fn rows<T, F>(data: &mut [T], row_len: usize, work: F)
+where
+    T: Send,
+    F: Fn(usize, &mut [T]) + Send + Sync,
+{
+    #[cfg(feature = "parallel")]
+    if row_len > 0 && std::mem::size_of_val(data) >= MIN_PARALLEL_BYTES {
+        use rayon::prelude::*;
+
+        // Handed back once the bands are done
+        let spare = crate::stages::acquire_spare(crate::error::Stage::Composite);
+        let threads = spare.len() + 1;
+        let pool = if spare.is_empty() {
+            None
+        } else {
+            rayon::ThreadPoolBuilder::new()
+                .num_threads(threads)
+                .build()
+                .ok()
+        };
+        if let Some(pool) = pool {
+            let rows = data.len() / row_len;
+            let band_rows = rows.div_ceil(threads * BANDS_PER_THREAD).max(1);
+            pool.install(|| {
+                data.par_chunks_mut(band_rows * row_len)
+                    .enumerate()
+                    .for_each(|(band, rows)| work(band * band_rows, rows))
+            });
+            return;
+        }
+    }
+    work(0, data)
+}
@@ -0,0 +1,192 @@
+//! Checks across the entries of a job list before any of them is processed.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Component, Path, PathBuf};
+
+use crate::atomic;
+use crate::incremental::Settings;
+use crate::ImageInfo;
+
+/// Entries left to process after folding duplicates together.
+pub struct Batch {
+    pub entries: Vec<ImageInfo>,
+    /// Entries replaced by a later one for the same destination under `last_wins`.
+    pub superseded: Vec<ImageInfo>,
+}
+
+/// Different entries writing the same destination.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Conflict {
+    pub destination: String,
+    /// Sources of the conflicting entries, in job list order.
+    pub sources: Vec<String>,
+    /// Positions of the conflicting entries in the job list, counted from 0.
+    pub entries: Vec<usize>,
+}
+
+impl fmt::Display for Conflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} entries write \"{}\": {}",
+            self.sources.len(),
+            self.destination,
+            self.sources.join(", ")
+        )
+    }
+}
+
+/// Resolves `path` on disk where possible, and lexically otherwise, so that
+/// `./out/a.png` and `out/a.png` compare equal.
+fn normalize(path: &str) -> PathBuf {
+    atomic::resolve(path).unwrap_or_else(|_| {
+        PathBuf::from(path)
+            .components()
+            .filter(|component| *component != Component::CurDir)
+            .collect()
+    })
+}
+
+/// Folds identical entries into one and looks for entries that would
+/// overwrite each other's destination.
+///
+/// Conflicting entries are an error unless `last_wins` is set, in which case
+/// only the last entry for each destination is kept.
+pub fn dedupe(info_list: Vec<ImageInfo>, last_wins: bool) -> Result<Batch, Vec<Conflict>> {
+    // Entry writing each destination, by index in `entries`
+    let mut by_destination: HashMap<PathBuf, usize> = HashMap::new();
+    let mut entries: Vec<ImageInfo> = Vec::new();
+    // Position in the job list of each of `entries`
+    let mut positions: Vec<usize> = Vec::new();
+    let mut superseded = Vec::new();
+    let mut conflicts: Vec<Conflict> = Vec::new();
+
+    for (position, info) in info_list.into_iter().enumerate() {
+        // Entries with variants write several files, any of which can clash
+        let outputs: Vec<PathBuf> = info.outputs().iter().map(|dest| normalize(dest)).collect();
+        let index = match outputs.iter().find_map(|dest| by_destination.get(dest)) {
+            Some(&index) => index,
+            None => {
+                for dest in outputs {
+                    by_destination.insert(dest, entries.len());
+                }
+                entries.push(info);
+                positions.push(position);
+                continue;
+            }
+        };
+        let earlier = &mut entries[index];
+        if normalize(&earlier.source) == normalize(&info.source)
+            && Settings::new(earlier) == Settings::new(&info).with_source(&earlier.source)
+        {
+            earlier.duplicates += 1;
+        } else if last_wins {
+            superseded.push(std::mem::replace(earlier, info));
+            positions[index] = position;
+            for dest in outputs {
+                by_destination.insert(dest, index);
+            }
+        } else {
+            match conflicts
+                .iter_mut()
+                .find(|conflict| conflict.destination == earlier.destination)
+            {
+                Some(conflict) => {
+                    conflict.sources.push(info.source);
+                    conflict.entries.push(position);
+                }
+                None => conflicts.push(Conflict {
+                    destination: earlier.destination.clone(),
+                    sources: vec![earlier.source.clone(), info.source],
+                    entries: vec![positions[index], position],
+                }),
+            }
+        }
+    }
+
+    if conflicts.is_empty() {
+        Ok(Batch {
+            entries,
+            superseded,
+        })
+    } else {
+        Err(conflicts)
+    }
+}
+
+/// Points every entry at `dir`, keeping the file name of its source, or its
+/// whole relative path under `preserve_tree`. Destinations already in the
+/// job list are replaced. The `outputs` of an entry go into the directory it
+/// would be written to, each keeping its own file name.
+///
+/// Different sources that end up at the same path are returned as
+/// conflicts, whatever `last_wins` would make of them later, as flattening
+/// them into one directory is never what was meant.
+pub fn map_to_dir(
+    info_list: &mut [ImageInfo],
+    dir: &Path,
+    preserve_tree: bool,
+) -> Result<(), Vec<Conflict>> {
+    for info in info_list.iter_mut() {
+        let source = Path::new(&info.source);
+        let dest = if preserve_tree {
+            // Roots and `..` are dropped so that nothing lands outside `dir`
+            let relative: PathBuf = source
+                .components()
+                .filter(|component| matches!(component, Component::Normal(_)))
+                .collect();
+            dir.join(relative)
+        } else {
+            dir.join(source.file_name().unwrap_or(source.as_os_str()))
+        };
+        let parent = dest.parent().unwrap_or(dir);
+        for output in &mut info.outputs {
+            let name = Path::new(&output.destination)
+                .file_name()
+                .unwrap_or_default()
+                .to_os_string();
+            output.destination = parent.join(name).to_string_lossy().into_owned();
+        }
+        info.destination = dest.to_string_lossy().into_owned();
+    }
+
+    let mut by_destination: HashMap<PathBuf, usize> = HashMap::new();
+    let mut conflicts: Vec<Conflict> = Vec::new();
+    for (position, info) in info_list.iter().enumerate() {
+        for destination in info.outputs() {
+            let dest = normalize(&destination);
+            let earlier = match by_destination.get(&dest) {
+                Some(&earlier) => earlier,
+                None => {
+                    by_destination.insert(dest, position);
+                    continue;
+                }
+            };
+            let earlier_info = &info_list[earlier];
+            // The same source listed twice is left for `dedupe` to fold
+            if normalize(&earlier_info.source) == normalize(&info.source) {
+                continue;
+            }
+            match conflicts
+                .iter_mut()
+                .find(|conflict| conflict.destination == destination)
+            {
+                Some(conflict) => {
+                    conflict.sources.push(info.source.clone());
+                    conflict.entries.push(position);
+                }
+                None => conflicts.push(Conflict {
+                    destination,
+                    sources: vec![earlier_info.source.clone(), info.source.clone()],
+                    entries: vec![earlier, position],
+                }),
+            }
+        }
+    }
+    if conflicts.is_empty() {
+        Ok(())
+    } else {
+        Err(conflicts)
+    }
+}
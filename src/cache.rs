@@ -0,0 +1,145 @@
+//! Cache of finished entries keyed by content rather than timestamps.
+//!
+//! The key of an entry hashes the source bytes together with the settings
+//! that affect the output. An entry is fresh when the cache has its key and
+//! the destination still hashes to what was written back then, so re-synced
+//! files with new timestamps are not processed again.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::sync::{Mutex, MutexGuard};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::atomic;
+use crate::error::{Context, ExtendError, Stage};
+use crate::incremental::Settings;
+use crate::ImageInfo;
+
+/// Bumped whenever the key or the file layout changes, which discards old caches.
+const VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+struct Record {
+    destination: String,
+    output_hash: String,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct CacheFile {
+    version: u32,
+    entries: HashMap<String, Record>,
+}
+
+/// Cache file that can be shared between threads processing entries.
+pub struct Cache {
+    path: String,
+    entries: Mutex<HashMap<String, Record>>,
+}
+
+fn hex(digest: &[u8]) -> String {
+    digest.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Hashes every file written for `dest`, which are several for `{page}`
+/// destinations.
+fn output_hash(dest: &str) -> io::Result<String> {
+    #[cfg(feature = "tiff")]
+    {
+        use crate::pages::PAGE_PLACEHOLDER;
+        if dest.contains(PAGE_PLACEHOLDER) {
+            let mut hasher = Sha256::new();
+            let mut page = 1;
+            loop {
+                match fs::read(dest.replace(PAGE_PLACEHOLDER, &page.to_string())) {
+                    Ok(data) => hasher.update(Sha256::digest(&data)),
+                    Err(e) if e.kind() == io::ErrorKind::NotFound && page > 1 => break,
+                    Err(e) => return Err(e),
+                }
+                page += 1;
+            }
+            return Ok(hex(&hasher.finalize()));
+        }
+    }
+    Ok(hex(&Sha256::digest(fs::read(dest)?)))
+}
+
+/// Hashes the outputs of `info`: its destination, or every variant or
+/// output.
+fn outputs_hash(info: &ImageInfo) -> io::Result<String> {
+    if info.variants().is_empty() && info.outputs.is_empty() {
+        return output_hash(&info.destination);
+    }
+    let mut hasher = Sha256::new();
+    for dest in info.outputs() {
+        hasher.update(output_hash(&dest)?.as_bytes());
+    }
+    Ok(hex(&hasher.finalize()))
+}
+
+impl Cache {
+    /// Loads the cache at `path`. A missing, unreadable or outdated cache
+    /// starts out empty and is rebuilt.
+    pub fn open(path: &str) -> Self {
+        let entries = fs::read(path)
+            .ok()
+            .and_then(|data| serde_json::from_slice::<CacheFile>(&data).ok())
+            .filter(|file| file.version == VERSION)
+            .map(|file| file.entries)
+            .unwrap_or_default();
+        Cache {
+            path: path.to_string(),
+            entries: Mutex::new(entries),
+        }
+    }
+
+    fn entries(&self) -> MutexGuard<'_, HashMap<String, Record>> {
+        self.entries.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Hashes the source of `info` together with its settings and destination.
+    pub fn key(&self, info: &ImageInfo) -> Result<String, ExtendError> {
+        let source = crate::read_source(&info.source)?;
+        let settings =
+            serde_json::to_vec(&Settings::new(info)).context(Stage::Read, &info.source)?;
+        let mut hasher = Sha256::new();
+        hasher.update(Sha256::digest(&source));
+        hasher.update(&settings);
+        hasher.update(info.destination.as_bytes());
+        Ok(hex(&hasher.finalize()))
+    }
+
+    /// Whether the destination of `info` is still what was written for `key`.
+    pub fn is_fresh(&self, info: &ImageInfo, key: &str) -> bool {
+        let record = match self.entries().get(key) {
+            Some(record) if record.destination == info.destination => record.clone(),
+            _ => return false,
+        };
+        outputs_hash(info).is_ok_and(|hash| hash == record.output_hash)
+    }
+
+    /// Remembers the destination of `info`, which was just written for `key`.
+    pub fn insert(&self, info: &ImageInfo, key: String) -> Result<(), ExtendError> {
+        let record = Record {
+            destination: info.destination.clone(),
+            output_hash: outputs_hash(info).context(Stage::Write, &info.destination)?,
+        };
+        self.entries().insert(key, record);
+        Ok(())
+    }
+
+    /// Writes the cache back, replacing the previous file atomically.
+    pub fn save(&self) -> Result<(), ExtendError> {
+        let file = CacheFile {
+            version: VERSION,
+            entries: self.entries().clone(),
+        };
+        let data = serde_json::to_vec(&file).context(Stage::Write, &self.path)?;
+        atomic::write(&self.path, |temp| {
+            fs::write(temp, &data).context(Stage::Write, &self.path)
+        })
+    }
+}
@@ -0,0 +1,208 @@
+//! Captions rendered into the extended area, when the `text` feature is enabled.
+
+#[cfg(not(feature = "text"))]
+pub(crate) fn draw(
+    _canvas: &mut image::RgbaImage,
+    _layout: &crate::Layout,
+    _caption: &crate::style::Caption,
+) -> Result<(), crate::error::BoxError> {
+    Err(crate::format::FormatNotCompiled {
+        format: "Caption",
+        feature: "text",
+    }
+    .into())
+}
+
+#[cfg(feature = "text")]
+pub(crate) use rendering::{draw, draw_in, load_font};
+
+#[cfg(feature = "text")]
+mod rendering {
+    use std::collections::HashMap;
+    use std::fmt;
+    use std::sync::{Mutex, OnceLock};
+
+    use ab_glyph::{point, Font, FontArc, PxScale, ScaleFont};
+    use image::{Pixel, RgbaImage};
+
+    use crate::error::BoxError;
+    use crate::style::{Align, Caption};
+    use crate::Layout;
+
+    /// Font sizes below this are unreadable, so shrinking stops here and the
+    /// rest of the caption is cut off instead.
+    const MIN_SIZE: f32 = 6.0;
+
+    /// The font of a caption could not be read or parsed.
+    #[derive(Debug)]
+    struct FontError {
+        path: String,
+        inner: BoxError,
+    }
+
+    impl fmt::Display for FontError {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "cannot load font {}: {}", self.path, self.inner)
+        }
+    }
+
+    impl std::error::Error for FontError {}
+
+    /// Fonts are kept for the rest of the process, like watermarks.
+    static FONTS: OnceLock<Mutex<HashMap<String, FontArc>>> = OnceLock::new();
+
+    /// Loads the font at `path` so later captions in it cannot fail to.
+    pub(crate) fn load_font(path: &str) -> Result<(), BoxError> {
+        load(path)?;
+        Ok(())
+    }
+
+    fn load(path: &str) -> Result<FontArc, FontError> {
+        let fonts = FONTS.get_or_init(Mutex::default);
+        if let Some(font) = fonts.lock().unwrap_or_else(|e| e.into_inner()).get(path) {
+            return Ok(font.clone());
+        }
+        let error = |inner: BoxError| FontError {
+            path: path.to_string(),
+            inner,
+        };
+        let data = std::fs::read(path).map_err(|e| error(e.into()))?;
+        let font = FontArc::try_from_vec(data).map_err(|e| error(e.into()))?;
+        fonts
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(path.to_string(), font.clone());
+        Ok(font)
+    }
+
+    fn text_width<F: ScaleFont<G>, G: Font>(font: &F, text: &str) -> f32 {
+        let mut width = 0.0;
+        let mut previous = None;
+        for c in text.chars() {
+            let id = font.glyph_id(c);
+            if let Some(previous) = previous {
+                width += font.kern(previous, id);
+            }
+            width += font.h_advance(id);
+            previous = Some(id);
+        }
+        width
+    }
+
+    /// Breaks `text` into lines no wider than `max_width`, at spaces and at
+    /// explicit line breaks. A single word wider than that gets a line of its own.
+    fn wrap<F: ScaleFont<G>, G: Font>(font: &F, text: &str, max_width: f32) -> Vec<String> {
+        let mut lines = Vec::new();
+        for paragraph in text.lines() {
+            let mut line = String::new();
+            for word in paragraph.split_whitespace() {
+                let candidate = if line.is_empty() {
+                    word.to_string()
+                } else {
+                    format!("{} {}", line, word)
+                };
+                if line.is_empty() || text_width(font, &candidate) <= max_width {
+                    line = candidate;
+                } else {
+                    lines.push(std::mem::replace(&mut line, word.to_string()));
+                }
+            }
+            lines.push(line);
+        }
+        lines
+    }
+
+    pub(crate) fn draw(
+        canvas: &mut RgbaImage,
+        layout: &Layout,
+        caption: &Caption,
+    ) -> Result<(), BoxError> {
+        let (canvas_width, canvas_height) = layout.canvas;
+        draw_in(
+            canvas,
+            layout.area(caption.area),
+            canvas_width.min(canvas_height),
+            caption,
+        )
+    }
+
+    /// Draws `caption` into `(x, y, width, height)` of the canvas, with a
+    /// percentage size taken of `base`. Its own area is left aside.
+    pub(crate) fn draw_in(
+        canvas: &mut RgbaImage,
+        (x, y, width, height): (u32, u32, u32, u32),
+        base: u32,
+        caption: &Caption,
+    ) -> Result<(), BoxError> {
+        let font = load(&caption.font)?;
+        // Keep clear of the canvas edges and the image
+        let padding = (width.min(height) as f32 * 0.1).round();
+        let (max_width, max_height) = (width as f32 - 2.0 * padding, height as f32 - 2.0 * padding);
+        if max_width < 1.0 || max_height < MIN_SIZE {
+            return Ok(());
+        }
+
+        let mut size = caption.size.resolve(base) as f32;
+        let (scaled, lines) = loop {
+            let scaled = font.as_scaled(PxScale::from(size.max(MIN_SIZE)));
+            let lines = wrap(&scaled, &caption.text, max_width);
+            let block_height =
+                lines.len() as f32 * (scaled.height() + scaled.line_gap()) - scaled.line_gap();
+            let fits = block_height <= max_height
+                && lines
+                    .iter()
+                    .all(|line| text_width(&scaled, line) <= max_width);
+            if fits || size <= MIN_SIZE {
+                break (scaled, lines);
+            }
+            size *= 0.9;
+        };
+
+        let line_height = scaled.height() + scaled.line_gap();
+        let block_height = lines.len() as f32 * line_height - scaled.line_gap();
+        let top = y as f32 + padding + ((max_height - block_height) / 2.0).max(0.0);
+        // Nothing is drawn outside the area, whatever did not fit
+        let clip = (
+            x as f32 + padding,
+            y as f32 + padding,
+            x as f32 + padding + max_width,
+            y as f32 + padding + max_height,
+        );
+        let color = caption.color.0;
+        for (index, line) in lines.iter().enumerate() {
+            let line_width = text_width(&scaled, line);
+            let mut pen = match caption.align {
+                Align::Left => clip.0,
+                Align::Center => clip.0 + (max_width - line_width) / 2.0,
+                Align::Right => clip.2 - line_width,
+            };
+            let baseline = top + index as f32 * line_height + scaled.ascent();
+            let mut previous = None;
+            for c in line.chars() {
+                let id = scaled.glyph_id(c);
+                if let Some(previous) = previous {
+                    pen += scaled.kern(previous, id);
+                }
+                let glyph = id.with_scale_and_position(scaled.scale(), point(pen, baseline));
+                pen += scaled.h_advance(id);
+                previous = Some(id);
+                let outlined = match font.outline_glyph(glyph) {
+                    Some(outlined) => outlined,
+                    None => continue,
+                };
+                let bounds = outlined.px_bounds();
+                outlined.draw(|gx, gy, coverage| {
+                    let px = bounds.min.x + gx as f32;
+                    let py = bounds.min.y + gy as f32;
+                    if px < clip.0 || py < clip.1 || px >= clip.2 || py >= clip.3 {
+                        return;
+                    }
+                    let mut ink = color;
+                    ink.0[3] = (color.0[3] as f32 * coverage.min(1.0)).round() as u8;
+                    canvas.get_pixel_mut(px as u32, py as u32).blend(&ink);
+                });
+            }
+        }
+        Ok(())
+    }
+}
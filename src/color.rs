@@ -0,0 +1,195 @@
+//! Colors given as hex strings or arrays of channels in the job list.
+
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+/// `#rgb`, `#rrggbb` or `#rrggbbaa`, with or without the `#`, or an array
+/// of red, green, blue and optionally alpha from 0 to 255. Always written
+/// back as hex.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(try_from = "Value", into = "String")]
+pub struct Color(pub image::Rgba<u8>);
+
+/// A color as the job list has it.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Value {
+    Hex(String),
+    Channels(Vec<i64>),
+}
+
+impl FromStr for Color {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let hex = value.strip_prefix('#').unwrap_or(value);
+        let invalid = || format!("invalid color {:?}, expected #rrggbb", value);
+        if !hex.is_ascii() {
+            return Err(invalid());
+        }
+        let channel = |i: usize, len: usize| {
+            u8::from_str_radix(&hex[i * len..(i + 1) * len], 16).map_err(|_| invalid())
+        };
+        let rgba = match hex.len() {
+            3 => {
+                let [r, g, b] = [channel(0, 1)?, channel(1, 1)?, channel(2, 1)?];
+                [r * 17, g * 17, b * 17, 255]
+            }
+            6 => [channel(0, 2)?, channel(1, 2)?, channel(2, 2)?, 255],
+            8 => [
+                channel(0, 2)?,
+                channel(1, 2)?,
+                channel(2, 2)?,
+                channel(3, 2)?,
+            ],
+            _ => return Err(invalid()),
+        };
+        Ok(Color(image::Rgba(rgba)))
+    }
+}
+
+impl TryFrom<Value> for Color {
+    type Error = String;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        let channels = match value {
+            Value::Hex(hex) => return hex.parse(),
+            Value::Channels(channels) => channels,
+        };
+        let invalid = || {
+            format!(
+                "invalid color {:?}, expected [r, g, b] or [r, g, b, a] from 0 to 255",
+                channels
+            )
+        };
+        if channels.len() != 3 && channels.len() != 4 {
+            return Err(invalid());
+        }
+        let mut rgba = [255; 4];
+        for (target, &channel) in rgba.iter_mut().zip(&channels) {
+            *target = u8::try_from(channel).map_err(|_| invalid())?;
+        }
+        Ok(Color(image::Rgba(rgba)))
+    }
+}
+
+impl fmt::Display for Color {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let [r, g, b, a] = self.0 .0;
+        write!(f, "#{:02x}{:02x}{:02x}", r, g, b)?;
+        if a != 255 {
+            write!(f, "{:02x}", a)?;
+        }
+        Ok(())
+    }
+}
+
+impl From<Color> for String {
+    fn from(color: Color) -> Self {
+        color.to_string()
+    }
+}
+
+/// `color` with its red, green and blue scaled by `factor`, keeping the alpha.
+pub(crate) fn scale(color: image::Rgba<u8>, factor: f32) -> image::Rgba<u8> {
+    let [r, g, b, a] = color.0;
+    let scale = |channel: u8| (channel as f32 * factor).round().clamp(0.0, 255.0) as u8;
+    image::Rgba([scale(r), scale(g), scale(b), a])
+}
+
+/// An sRGB channel in linear light, from 0 to 1.
+pub(crate) fn to_linear(channel: u8) -> f32 {
+    let c = channel as f32 / 255.0;
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// The sRGB channel for a linear light value from 0 to 1.
+pub(crate) fn from_linear(value: f32) -> u8 {
+    let v = value.clamp(0.0, 1.0);
+    let c = if v <= 0.0031308 {
+        v * 12.92
+    } else {
+        1.055 * v.powf(1.0 / 2.4) - 0.055
+    };
+    (c * 255.0).round() as u8
+}
+
+/// A color in the Oklab space, where equal steps look equally different.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Oklab {
+    pub(crate) l: f32,
+    pub(crate) a: f32,
+    pub(crate) b: f32,
+}
+
+impl Oklab {
+    pub(crate) fn from_rgb(color: image::Rgba<u8>) -> Self {
+        let [r, g, b] = [
+            to_linear(color.0[0]),
+            to_linear(color.0[1]),
+            to_linear(color.0[2]),
+        ];
+        let l = (0.412_221_46 * r + 0.536_332_55 * g + 0.051_445_995 * b).cbrt();
+        let m = (0.211_903_5 * r + 0.680_699_5 * g + 0.107_396_96 * b).cbrt();
+        let s = (0.088_302_46 * r + 0.281_718_85 * g + 0.629_978_7 * b).cbrt();
+        Oklab {
+            l: 0.210_454_26 * l + 0.793_617_8 * m - 0.004_072_047 * s,
+            a: 1.977_998_5 * l - 2.428_592_2 * m + 0.450_593_7 * s,
+            b: 0.025_904_037 * l + 0.782_771_77 * m - 0.808_675_77 * s,
+        }
+    }
+
+    /// Linear light RGB, which may be out of the sRGB gamut.
+    fn to_linear_rgb(self) -> [f32; 3] {
+        let l = (self.l + 0.396_337_78 * self.a + 0.215_803_76 * self.b).powi(3);
+        let m = (self.l - 0.105_561_346 * self.a - 0.063_854_17 * self.b).powi(3);
+        let s = (self.l - 0.089_484_18 * self.a - 1.291_485_5 * self.b).powi(3);
+        [
+            4.076_741_7 * l - 3.307_711_6 * m + 0.230_969_94 * s,
+            -1.268_438 * l + 2.609_757_4 * m - 0.341_319_38 * s,
+            -0.004_196_086_3 * l - 0.703_418_6 * m + 1.707_614_7 * s,
+        ]
+    }
+
+    /// Back to sRGB with `alpha`. Colors outside the gamut lose chroma until
+    /// they fit, keeping their lightness and hue.
+    pub(crate) fn to_rgb(self, alpha: u8) -> image::Rgba<u8> {
+        let l = self.l.clamp(0.0, 1.0);
+        let in_gamut = |chroma: f32| {
+            let rgb = Oklab {
+                l,
+                a: self.a * chroma,
+                b: self.b * chroma,
+            }
+            .to_linear_rgb();
+            rgb.iter().all(|c| (-1e-4..=1.0 + 1e-4).contains(c))
+        };
+        let mut chroma = 1.0;
+        if !in_gamut(chroma) {
+            let (mut low, mut high) = (0.0, 1.0);
+            for _ in 0..20 {
+                let mid = (low + high) / 2.0;
+                if in_gamut(mid) {
+                    low = mid;
+                } else {
+                    high = mid;
+                }
+            }
+            chroma = low;
+        }
+        let [r, g, b] = Oklab {
+            l,
+            a: self.a * chroma,
+            b: self.b * chroma,
+        }
+        .to_linear_rgb();
+        image::Rgba([from_linear(r), from_linear(g), from_linear(b), alpha])
+    }
+}
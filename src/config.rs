@@ -0,0 +1,96 @@
+//! Defaults shared by the entries of a job list, read from a `--config`
+//! file.
+//!
+//! The file has the fields of an entry, such as `aspectRatio`, `background`,
+//! `outputFormat` or `quality`, which every entry starts from and overrides
+//! field by field. `outDir` is where the entries that leave out their
+//! destination are written, as with `--out-dir`. Files ending in `.toml` are
+//! TOML, read with the `toml` feature, and any others JSON.
+
+use std::path::Path;
+
+use serde_json::{Map, Value};
+
+#[cfg(not(feature = "toml"))]
+use crate::format::FormatNotCompiled;
+
+/// Fields that only make sense for a single entry.
+const PER_ENTRY: &[&str] = &["source", "destination"];
+
+#[derive(Clone, Debug, Default)]
+pub struct Defaults {
+    fields: Map<String, Value>,
+    /// Directory for the entries without a destination, made absolute
+    /// against the working directory.
+    pub out_dir: Option<String>,
+}
+
+#[cfg(feature = "toml")]
+fn parse_toml(text: &str) -> Result<Value, String> {
+    toml::from_str(text).map_err(|e| e.to_string())
+}
+
+#[cfg(not(feature = "toml"))]
+fn parse_toml(_text: &str) -> Result<Value, String> {
+    Err(FormatNotCompiled {
+        format: "TOML",
+        feature: "toml",
+    }
+    .to_string())
+}
+
+impl Defaults {
+    /// Reads the config file at `path`.
+    pub fn read(path: &str) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("Cannot read config {}: {}", path, e))?;
+        let toml = Path::new(path)
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("toml"));
+        let invalid = |e: String| format!("Invalid config {}: {}", path, e);
+        let value = if toml {
+            parse_toml(&text)
+        } else {
+            serde_json::from_str(&text).map_err(|e| e.to_string())
+        }
+        .map_err(invalid)?;
+        match value {
+            Value::Object(fields) => Self::new(fields).map_err(invalid),
+            _ => Err(invalid("expected the fields of an entry".into())),
+        }
+    }
+
+    fn new(mut fields: Map<String, Value>) -> Result<Self, String> {
+        if let Some(field) = PER_ENTRY.iter().find(|field| fields.contains_key(**field)) {
+            return Err(format!("{} is set by each entry", field));
+        }
+        let out_dir = match fields.remove("outDir") {
+            None => None,
+            Some(Value::String(dir)) => Some(
+                std::path::absolute(&dir)
+                    .map_err(|e| format!("outDir {}: {}", dir, e))?
+                    .to_string_lossy()
+                    .into_owned(),
+            ),
+            Some(_) => return Err("outDir must be a path".into()),
+        };
+        Ok(Defaults { fields, out_dir })
+    }
+
+    /// Whether the file set anything.
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty() && self.out_dir.is_none()
+    }
+
+    /// Fills in the fields `entry` leaves out. Entries that are not objects
+    /// are left for parsing to refuse.
+    pub fn apply(&self, entry: &mut Value) {
+        if let Value::Object(entry) = entry {
+            for (field, value) in &self.fields {
+                if !entry.contains_key(field) {
+                    entry.insert(field.clone(), value.clone());
+                }
+            }
+        }
+    }
+}
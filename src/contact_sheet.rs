@@ -0,0 +1,268 @@
+//! Contact sheets pairing the thumbnail of each source with one of its
+//! output, for reviewing how a batch came out.
+//!
+//! Thumbnails are made as each entry finishes and only they are kept, and a
+//! page is written as soon as it is full, so no full-size image is held on to.
+
+use std::io::Cursor;
+use std::path::Path;
+
+use image::io::Reader as ImageReader;
+use image::{imageops, DynamicImage, RgbaImage};
+
+use crate::error::{BoxError, Context, ExtendError, Stage};
+use crate::format::Encoding;
+use crate::metadata::Metadata;
+#[cfg(not(feature = "text"))]
+use crate::FormatNotCompiled;
+use crate::{atomic, encode, heif, read_source, ImageInfo, ImageReport};
+
+/// Space around the pairs and between their two cells, in pixels.
+const GAP: u32 = 8;
+
+const BACKGROUND: image::Rgba<u8> = image::Rgba([32, 32, 32, 255]);
+
+pub const DEFAULT_CELL: u32 = 160;
+pub const DEFAULT_MAX_DIMENSION: u32 = 4096;
+
+/// Layout of a contact sheet.
+#[derive(Clone, Debug)]
+pub struct SheetOptions {
+    /// Side of the square each thumbnail is fitted into.
+    pub cell: u32,
+    /// Longest side of a page. Pairs that do not fit go on to the next page.
+    pub max_dimension: u32,
+    /// Font the file name of each source is written in under its pair,
+    /// with the `text` feature. Pairs go unlabeled without one.
+    pub font: Option<String>,
+}
+
+impl Default for SheetOptions {
+    fn default() -> Self {
+        SheetOptions {
+            cell: DEFAULT_CELL,
+            max_dimension: DEFAULT_MAX_DIMENSION,
+            font: None,
+        }
+    }
+}
+
+/// A source and its output, shrunk to fit a cell.
+pub struct Pair {
+    label: String,
+    before: RgbaImage,
+    after: RgbaImage,
+}
+
+/// The image at `path` fitted into a square of `cell`. Animations and
+/// multi-page files show their first frame or page.
+fn thumbnail(path: &str, cell: u32) -> Result<RgbaImage, ExtendError> {
+    let data = read_source(path)?;
+    let img = if heif::is_heif(&data) {
+        heif::decode(&data)
+    } else {
+        ImageReader::new(Cursor::new(&data[..]))
+            .with_guessed_format()
+            .map_err(BoxError::from)
+            .and_then(|reader| reader.decode().map_err(BoxError::from))
+    }
+    .context(Stage::Decode, path)?;
+    Ok(img.thumbnail(cell, cell).to_rgba8())
+}
+
+impl Pair {
+    /// Thumbnails of the source of `info` and of the first output that
+    /// `report` says was written for it.
+    pub fn new(info: &ImageInfo, report: &ImageReport, cell: u32) -> Result<Pair, ExtendError> {
+        let variant = report
+            .variants
+            .iter()
+            .find(|variant| variant.error.is_none())
+            .map(|variant| &variant.destination);
+        let output = report
+            .outputs
+            .iter()
+            .find(|output| output.error.is_none())
+            .map(|output| &output.destination);
+        let output = variant
+            .or(output)
+            .map_or_else(|| info.destination().to_string(), String::clone);
+        #[cfg(feature = "tiff")]
+        let output = output.replace(crate::pages::PAGE_PLACEHOLDER, "1");
+        let label = Path::new(info.source())
+            .file_name()
+            .map_or_else(|| info.source().into(), |name| name.to_string_lossy())
+            .into_owned();
+        Ok(Pair {
+            label,
+            before: thumbnail(info.source(), cell)?,
+            after: thumbnail(&output, cell)?,
+        })
+    }
+}
+
+/// A contact sheet being filled in, entry by entry.
+pub struct ContactSheet {
+    path: String,
+    options: SheetOptions,
+    pairs: Vec<Pair>,
+    /// Pages written so far.
+    pages: Vec<String>,
+}
+
+impl ContactSheet {
+    /// The first page is written to `path`, and the others next to it with
+    /// their number appended to the file name, as in `sheet-2.png`.
+    ///
+    /// Fails when the font of the labels cannot be loaded.
+    pub fn new(
+        path: &str,
+        options: SheetOptions,
+    ) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        #[cfg(feature = "text")]
+        if let Some(font) = &options.font {
+            crate::caption::load_font(font)?;
+        }
+        #[cfg(not(feature = "text"))]
+        if options.font.is_some() {
+            return Err(FormatNotCompiled {
+                format: "Labels",
+                feature: "text",
+            }
+            .into());
+        }
+        Ok(ContactSheet {
+            path: path.to_string(),
+            options,
+            pairs: Vec::new(),
+            pages: Vec::new(),
+        })
+    }
+
+    fn label_height(&self) -> u32 {
+        if self.options.font.is_some() {
+            (self.options.cell / 8).max(12)
+        } else {
+            0
+        }
+    }
+
+    /// Size of a pair with its label, without the gap around it.
+    fn pair_size(&self) -> (u32, u32) {
+        (
+            2 * self.options.cell + GAP,
+            self.options.cell + self.label_height(),
+        )
+    }
+
+    /// Columns and rows of pairs on a page, at least one of each even when a
+    /// single pair is larger than `max_dimension`.
+    fn grid(&self) -> (u32, u32) {
+        let (width, height) = self.pair_size();
+        let room = self.options.max_dimension.saturating_sub(GAP);
+        (
+            (room / (width + GAP)).max(1),
+            (room / (height + GAP)).max(1),
+        )
+    }
+
+    fn page_path(&self, page: usize) -> String {
+        if page == 1 {
+            return self.path.clone();
+        }
+        let path = Path::new(&self.path);
+        let stem = path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let name = match path.extension() {
+            Some(extension) => format!("{}-{}.{}", stem, page, extension.to_string_lossy()),
+            None => format!("{}-{}", stem, page),
+        };
+        path.with_file_name(name).to_string_lossy().into_owned()
+    }
+
+    /// Adds the next pair, writing out the page once it is full.
+    pub fn push(&mut self, pair: Pair) -> Result<(), ExtendError> {
+        self.pairs.push(pair);
+        let (columns, rows) = self.grid();
+        if self.pairs.len() as u32 >= columns * rows {
+            self.write_page()?;
+        }
+        Ok(())
+    }
+
+    fn write_page(&mut self) -> Result<(), ExtendError> {
+        let pairs = std::mem::take(&mut self.pairs);
+        let cell = self.options.cell;
+        let (pair_width, pair_height) = self.pair_size();
+        let columns = self.grid().0.min(pairs.len() as u32);
+        let rows = (pairs.len() as u32).div_ceil(columns);
+        let mut canvas = RgbaImage::from_pixel(
+            GAP + columns * (pair_width + GAP),
+            GAP + rows * (pair_height + GAP),
+            BACKGROUND,
+        );
+        for (index, pair) in pairs.iter().enumerate() {
+            let index = index as u32;
+            let x = GAP + index % columns * (pair_width + GAP);
+            let y = GAP + index / columns * (pair_height + GAP);
+            for (left, thumbnail) in [(x, &pair.before), (x + cell + GAP, &pair.after)] {
+                imageops::overlay(
+                    &mut canvas,
+                    thumbnail,
+                    left + (cell - thumbnail.width()) / 2,
+                    y + (cell - thumbnail.height()) / 2,
+                );
+            }
+            self.label(&mut canvas, (x, y + cell, pair_width), &pair.label);
+        }
+
+        let path = self.page_path(self.pages.len() + 1);
+        let encoded = encode(
+            DynamicImage::ImageRgba8(canvas),
+            None,
+            Encoding::default(),
+            &path,
+            &Metadata::default(),
+        )?;
+        atomic::write(&path, |temp| {
+            std::fs::write(temp, &encoded).context(Stage::Write, &path)
+        })?;
+        self.pages.push(path);
+        Ok(())
+    }
+
+    #[cfg(feature = "text")]
+    fn label(&self, canvas: &mut RgbaImage, (x, y, width): (u32, u32, u32), label: &str) {
+        use crate::color::Color;
+        use crate::style::{Align, Caption, CaptionArea, Length};
+
+        let font = match &self.options.font {
+            Some(font) => font.clone(),
+            None => return,
+        };
+        let height = self.label_height();
+        let caption = Caption {
+            text: label.to_string(),
+            font,
+            size: Length::Pixels(height),
+            color: Color(image::Rgba([255, 255, 255, 255])),
+            align: Align::Center,
+            area: CaptionArea::After,
+        };
+        // The font was loaded by `new`, and a label is not worth failing over
+        let _ = crate::caption::draw_in(canvas, (x, y, width, height), height, &caption);
+    }
+
+    #[cfg(not(feature = "text"))]
+    fn label(&self, _canvas: &mut RgbaImage, _area: (u32, u32, u32), _label: &str) {}
+
+    /// Writes the last page, if it has any pairs, and returns every page written.
+    pub fn finish(mut self) -> Result<Vec<String>, ExtendError> {
+        if !self.pairs.is_empty() {
+            self.write_page()?;
+        }
+        Ok(self.pages)
+    }
+}
@@ -0,0 +1,183 @@
+//! Physical resolution carried over from the source to the destination: the
+//! `pHYs` chunk of PNG and the JFIF density of JPEG.
+//!
+//! The encoders of `image` write neither, so the values are read from the
+//! encoded source and patched into the encoded output.
+
+use std::convert::TryInto;
+
+use image::ImageFormat;
+
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Unit {
+    Inch,
+    Centimeter,
+    Meter,
+}
+
+/// Pixels per unit along the width and the height.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct Density {
+    pub(crate) x: u32,
+    pub(crate) y: u32,
+    pub(crate) unit: Unit,
+}
+
+impl Density {
+    /// For the image turned as `rotate` turns it.
+    pub(crate) fn oriented(self, options: &crate::Options) -> Self {
+        let (x, y) = options.oriented((self.x, self.y));
+        Density { x, y, ..self }
+    }
+
+    /// Pixels per meter, as `pHYs` has them.
+    fn per_meter(self) -> (u32, u32) {
+        let convert = |value: u32| match self.unit {
+            Unit::Inch => (value as f64 / 0.0254).round() as u32,
+            Unit::Centimeter => value.saturating_mul(100),
+            Unit::Meter => value,
+        };
+        (convert(self.x), convert(self.y))
+    }
+
+    /// The JFIF unit and the densities in it. `pHYs` values go to inches,
+    /// which keeps more of them than centimeters.
+    fn jfif(self) -> (u8, u16, u16) {
+        let clamp = |value: u32| value.min(u16::MAX as u32) as u16;
+        match self.unit {
+            Unit::Inch => (1, clamp(self.x), clamp(self.y)),
+            Unit::Centimeter => (2, clamp(self.x), clamp(self.y)),
+            Unit::Meter => {
+                let convert = |value: u32| clamp((value as f64 * 0.0254).round() as u32);
+                (1, convert(self.x), convert(self.y))
+            }
+        }
+    }
+}
+
+pub(crate) fn be16(data: &[u8], at: usize) -> Option<u16> {
+    Some(u16::from_be_bytes(data.get(at..at + 2)?.try_into().ok()?))
+}
+
+pub(crate) fn be32(data: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(data.get(at..at + 4)?.try_into().ok()?))
+}
+
+/// The density an encoded PNG or JPEG source states, `None` for other
+/// formats and for densities without a unit, which only give the shape of
+/// the pixels.
+pub(crate) fn read(data: &[u8]) -> Option<Density> {
+    let density = if data.starts_with(PNG_SIGNATURE) {
+        read_png(data)
+    } else if data.starts_with(&[0xFF, 0xD8]) {
+        read_jpeg(data)
+    } else {
+        None
+    }?;
+    if density.x == 0 || density.y == 0 {
+        return None;
+    }
+    Some(density)
+}
+
+fn read_png(data: &[u8]) -> Option<Density> {
+    let mut at = PNG_SIGNATURE.len();
+    loop {
+        let len = be32(data, at)? as usize;
+        match data.get(at + 4..at + 8)? {
+            b"pHYs" if data.get(at + 16)? == &1 => {
+                return Some(Density {
+                    x: be32(data, at + 8)?,
+                    y: be32(data, at + 12)?,
+                    unit: Unit::Meter,
+                })
+            }
+            // Ancillary chunks like pHYs come before the image data
+            b"IDAT" | b"IEND" => return None,
+            _ => at += 12 + len,
+        }
+    }
+}
+
+fn read_jpeg(data: &[u8]) -> Option<Density> {
+    let mut at = 2;
+    loop {
+        if data.get(at)? != &0xFF {
+            return None;
+        }
+        let marker = *data.get(at + 1)?;
+        let len = be16(data, at + 2)? as usize;
+        match marker {
+            0xE0 if data.get(at + 4..at + 9)? == b"JFIF\0" => {
+                let unit = match data.get(at + 11)? {
+                    1 => Unit::Inch,
+                    2 => Unit::Centimeter,
+                    _ => return None,
+                };
+                return Some(Density {
+                    x: be16(data, at + 12)? as u32,
+                    y: be16(data, at + 14)? as u32,
+                    unit,
+                });
+            }
+            // Start of scan, past all of the headers
+            0xDA => return None,
+            _ => at += 2 + len,
+        }
+    }
+}
+
+pub(crate) fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+/// Writes `density` into `data`, encoded as `format` by `format::encode`.
+/// Other formats, or data laid out otherwise, are left as they are.
+pub(crate) fn embed(mut data: Vec<u8>, format: ImageFormat, density: Density) -> Vec<u8> {
+    match format {
+        ImageFormat::Png => {
+            // Right after IHDR, which is always 13 bytes long
+            let at = PNG_SIGNATURE.len() + 12 + 13;
+            if data.len() < at || &data[PNG_SIGNATURE.len() + 4..PNG_SIGNATURE.len() + 8] != b"IHDR"
+            {
+                return data;
+            }
+            let (x, y) = density.per_meter();
+            let mut chunk = Vec::with_capacity(21);
+            chunk.extend_from_slice(&9u32.to_be_bytes());
+            chunk.extend_from_slice(b"pHYs");
+            chunk.extend_from_slice(&x.to_be_bytes());
+            chunk.extend_from_slice(&y.to_be_bytes());
+            chunk.push(1);
+            let crc = crc32(&chunk[4..]);
+            chunk.extend_from_slice(&crc.to_be_bytes());
+            data.splice(at..at, chunk);
+        }
+        ImageFormat::Jpeg => {
+            // The encoder starts with a JFIF header of no density
+            if data.get(2..4) != Some(&[0xFF, 0xE0][..]) || data.get(6..11) != Some(&b"JFIF\0"[..])
+            {
+                return data;
+            }
+            let (unit, x, y) = density.jfif();
+            data[13] = unit;
+            data[14..16].copy_from_slice(&x.to_be_bytes());
+            data[16..18].copy_from_slice(&y.to_be_bytes());
+        }
+        _ => {}
+    }
+    data
+}
@@ -0,0 +1,171 @@
+//! Outputs of 16-bit sources in 16 bits a channel.
+//!
+//! The canvas of a 16-bit source is drawn a second time in 16 bits. The
+//! `blur`, `mirror` and `replicate` backgrounds are made from the 16-bit
+//! pixels of the image, and the image is scaled and placed with all its
+//! bits. Backgrounds in sampled colors are widened from the 8-bit canvas,
+//! their colors having 8 bits.
+//!
+//! What is only ever drawn in 8 bits keeps the old way: the finished
+//! canvas is widened, and wherever the image went onto it unscaled and came
+//! out as its own pixel quantized, the pixel of the source is put back.
+//! That is the vignette, shadow, grain, border, caption and watermark,
+//! rounded and feathered corners, the `smear` background, background
+//! adjustments, registered fills, and the `crop` and `seamCarve` modes.
+//! PNG and TIFF write the result as it is, and the other formats in 8 bits.
+
+use image::{ColorType, DynamicImage, GenericImageView, ImageBuffer, Rgba, RgbaImage};
+
+use crate::report::Placement;
+use crate::style::{self, Background, BackgroundAlpha, Style};
+use crate::{padding, Layout};
+
+pub(crate) type Rgba16Image = ImageBuffer<Rgba<u16>, Vec<u16>>;
+
+/// A channel of a canvas, of 8 or 16 bits.
+pub(crate) trait Channel: image::Primitive + Into<f32> + Send + Sync + 'static {
+    /// The value of a full channel.
+    const MAX: f32;
+
+    /// `value`, rounded and clamped to the channel.
+    fn from_f32(value: f32) -> Self;
+
+    /// `img` in RGBA with channels of this size.
+    fn rgba(img: &DynamicImage) -> ImageBuffer<Rgba<Self>, Vec<Self>>;
+}
+
+impl Channel for u8 {
+    const MAX: f32 = 255.0;
+
+    fn from_f32(value: f32) -> Self {
+        value.clamp(0.0, <Self as Channel>::MAX).round() as u8
+    }
+
+    fn rgba(img: &DynamicImage) -> RgbaImage {
+        img.to_rgba8()
+    }
+}
+
+impl Channel for u16 {
+    const MAX: f32 = 65535.0;
+
+    fn from_f32(value: f32) -> Self {
+        value.clamp(0.0, <Self as Channel>::MAX).round() as u16
+    }
+
+    fn rgba(img: &DynamicImage) -> Rgba16Image {
+        img.to_rgba16()
+    }
+}
+
+/// Whether a source of `color` has more than 8 bits a channel.
+pub(crate) fn is_deep(color: ColorType) -> bool {
+    matches!(
+        color,
+        ColorType::L16 | ColorType::La16 | ColorType::Rgb16 | ColorType::Rgba16
+    )
+}
+
+/// Whether everything `style` draws around an image of `layout` can be
+/// drawn in 16 bits.
+fn drawn_deep(style: &Style, layout: &Layout) -> bool {
+    style.fill.is_none()
+        && style.vignette.is_none()
+        && style.shadow.is_none()
+        && style.grain.is_none()
+        && style.border.is_none()
+        && style.caption.is_none()
+        && style.watermark.is_none()
+        && style.corner_radius(layout.image) == 0
+        && style.blend_width(layout.image) == 0
+}
+
+/// The canvas of the normalised 16-bit `img` at `layout`, drawn in 16 bits
+/// a channel, or `None` where `style` draws anything in 8 bits only.
+/// `background` is the 8-bit background the sampled colors were filled in.
+pub(crate) fn render(
+    img: &DynamicImage,
+    layout: &Layout,
+    background: &RgbaImage,
+    style: &Style,
+) -> Option<Rgba16Image> {
+    if !drawn_deep(style, layout) {
+        return None;
+    }
+    let adjusted = style.background_lightness() != 0.0 || style.background_saturation() != 1.0;
+    let unchanged = |pixel| pixel;
+    let mut canvas = match style.background {
+        _ if style.background_color.is_some() => widen_all(background),
+        Background::Blur | Background::Mirror | Background::Replicate | Background::Smear
+            if adjusted =>
+        {
+            return None
+        }
+        Background::Blur => crate::blur_background(img, layout, style, unchanged),
+        Background::Mirror => padding::pad(img, layout, style, padding::reflect, unchanged),
+        Background::Replicate => padding::pad(img, layout, style, padding::clamp, unchanged),
+        Background::Smear => return None,
+        _ => widen_all(background),
+    };
+    // Laid over the fallback color in 8 bits
+    let opaque = style.background_alpha.unwrap_or_default() == BackgroundAlpha::Opaque;
+    if opaque && canvas.pixels().any(|pixel| pixel.0[3] < u16::MAX) {
+        return None;
+    }
+
+    let placed = u16::rgba(&crate::inset(img.clone(), layout, style));
+    let (width, height) = placed.dimensions();
+    let (x, y) = layout.gravity.offset(canvas.dimensions(), (width, height));
+    if x + width > canvas.width() || y + height > canvas.height() {
+        return None;
+    }
+    for (px, py, pixel) in placed.enumerate_pixels() {
+        let under = canvas.get_pixel_mut(x + px, y + py);
+        *under = style::over(*under, *pixel);
+    }
+    Some(canvas)
+}
+
+/// `canvas` in 16 bits a channel, every value as it is.
+fn widen_all(canvas: &RgbaImage) -> Rgba16Image {
+    let (width, height) = canvas.dimensions();
+    Rgba16Image::from_fn(width, height, |x, y| {
+        Rgba(canvas.get_pixel(x, y).0.map(|channel| channel as u16 * 257))
+    })
+}
+
+/// `canvas` in 16 bits a channel, with the pixels of `source` from
+/// `origin` on put back wherever they sit unscaled at `placement` and
+/// unchanged. Only widened when the image was scaled, which `placed` leaves
+/// out.
+pub(crate) fn widen(
+    source: &DynamicImage,
+    canvas: &RgbaImage,
+    placed: Option<(Placement, (u32, u32))>,
+) -> Rgba16Image {
+    let (width, height) = canvas.dimensions();
+    let mut wide = widen_all(canvas);
+    let (source_width, source_height) = source.dimensions();
+    let (placement, origin) = match placed {
+        Some((placement, origin))
+            if origin.0 + placement.width <= source_width
+                && origin.1 + placement.height <= source_height
+                && placement.x + placement.width <= width
+                && placement.y + placement.height <= height =>
+        {
+            (placement, origin)
+        }
+        _ => return wide,
+    };
+    let (narrow, deep) = (source.to_rgba8(), source.to_rgba16());
+    for y in 0..placement.height {
+        for x in 0..placement.width {
+            let (source_x, source_y) = (origin.0 + x, origin.1 + y);
+            let (canvas_x, canvas_y) = (placement.x + x, placement.y + y);
+            if canvas.get_pixel(canvas_x, canvas_y) == narrow.get_pixel(source_x, source_y) {
+                wide.put_pixel(canvas_x, canvas_y, *deep.get_pixel(source_x, source_y));
+            }
+        }
+    }
+    wide
+}
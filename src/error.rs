@@ -0,0 +1,457 @@
+//! Errors that say which file and which step of processing failed.
+
+use std::any::Any;
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+/// Step of processing an entry.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum Stage {
+    /// Opening or reading the source.
+    Read,
+    Decode,
+    /// Extending the decoded image onto its new canvas.
+    Composite,
+    /// Encoding the result or writing the destination.
+    Write,
+}
+
+pub(crate) type BoxError = Box<dyn Error + Send + Sync>;
+
+/// The canvas needed for the aspect ratio is larger than allowed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CanvasTooLarge {
+    pub width: u64,
+    pub height: u64,
+    /// Maximum number of pixels.
+    pub limit: u64,
+}
+
+impl fmt::Display for CanvasTooLarge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "canvas of {}x{} exceeds the limit of {} pixels",
+            self.width, self.height, self.limit
+        )
+    }
+}
+
+impl Error for CanvasTooLarge {}
+
+/// The source has more pixels than `maxSourcePixels` allows, which is
+/// found from its header before it is decoded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceTooLarge {
+    pub width: u32,
+    pub height: u32,
+    /// Maximum number of pixels.
+    pub limit: u64,
+}
+
+impl fmt::Display for SourceTooLarge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "source of {}x{} exceeds the limit of {} pixels",
+            self.width, self.height, self.limit
+        )
+    }
+}
+
+impl Error for SourceTooLarge {}
+
+/// The watermark of an entry could not be read or decoded.
+#[derive(Debug)]
+pub struct WatermarkError {
+    pub path: String,
+    inner: BoxError,
+}
+
+impl WatermarkError {
+    pub(crate) fn new(path: &str, inner: impl Into<BoxError>) -> Self {
+        WatermarkError {
+            path: path.to_string(),
+            inner: inner.into(),
+        }
+    }
+}
+
+impl fmt::Display for WatermarkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "cannot load watermark {}: {}", self.path, self.inner)
+    }
+}
+
+impl Error for WatermarkError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&*self.inner)
+    }
+}
+
+/// The source is not a usable image: its data is damaged or cut short, or it
+/// decodes to no pixels at all.
+#[derive(Debug)]
+pub struct CorruptSource {
+    /// `None` for images processed in memory.
+    pub path: Option<String>,
+    /// Byte at which the data ran out, for sources that are cut short.
+    pub offset: Option<u64>,
+    inner: BoxError,
+}
+
+impl CorruptSource {
+    pub(crate) fn new(path: Option<&str>, offset: Option<u64>, inner: impl Into<BoxError>) -> Self {
+        CorruptSource {
+            path: path.map(str::to_string),
+            offset,
+            inner: inner.into(),
+        }
+    }
+
+    /// `e` as a `CorruptSource` when the decoder rejected the `len` bytes
+    /// of data, rather than their format or the limits.
+    pub(crate) fn classify(path: Option<&str>, len: u64, e: BoxError) -> BoxError {
+        if len == 0 {
+            // Nothing to tell the format by, which is what decoders say
+            return Box::new(CorruptSource::new(path, Some(0), e));
+        }
+        let truncated = match e.downcast_ref::<image::ImageError>() {
+            Some(image::ImageError::Decoding(decoding)) => {
+                let message = decoding.to_string();
+                [
+                    "EOF",
+                    "end of",
+                    "not enough data",
+                    "failed to fill whole buffer",
+                ]
+                .iter()
+                .any(|hint| message.contains(hint))
+            }
+            Some(image::ImageError::IoError(inner))
+                if inner.kind() == io::ErrorKind::UnexpectedEof =>
+            {
+                true
+            }
+            _ => return e,
+        };
+        let offset = if truncated { Some(len) } else { None };
+        Box::new(CorruptSource::new(path, offset, e))
+    }
+
+    /// `classify` for decoders that read the file at `path` on their own.
+    pub(crate) fn classify_file(path: &str, e: impl Into<BoxError>) -> BoxError {
+        let e = e.into();
+        match std::fs::metadata(path) {
+            Ok(meta) => CorruptSource::classify(Some(path), meta.len(), e),
+            Err(_) => e,
+        }
+    }
+}
+
+impl fmt::Display for CorruptSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.offset {
+            Some(offset) => write!(
+                f,
+                "corrupt image, data ends at byte {}: {}",
+                offset, self.inner
+            ),
+            None => write!(f, "corrupt image: {}", self.inner),
+        }
+    }
+}
+
+impl Error for CorruptSource {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&*self.inner)
+    }
+}
+
+/// The entry took longer than its timeout and was abandoned.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TimedOut {
+    pub limit: Duration,
+}
+
+impl fmt::Display for TimedOut {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "timed out after {}s", self.limit.as_secs())
+    }
+}
+
+impl Error for TimedOut {}
+
+/// Processing the entry panicked, on a bug or a decoder tripping over a
+/// malformed file. Only the entry is given up; the rest of the batch carries on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InternalPanic {
+    pub source: String,
+    pub message: String,
+}
+
+impl InternalPanic {
+    pub(crate) fn new(source: &str, payload: &(dyn Any + Send)) -> Self {
+        InternalPanic {
+            source: source.to_string(),
+            message: panic_message(payload),
+        }
+    }
+}
+
+impl fmt::Display for InternalPanic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "internal error, processing panicked: {}", self.message)
+    }
+}
+
+impl Error for InternalPanic {}
+
+/// The message `panic!` was given, as far as it can be told from `payload`.
+pub(crate) fn panic_message(payload: &(dyn Any + Send)) -> String {
+    payload
+        .downcast_ref::<&str>()
+        .map(|s| s.to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "unknown panic".to_string())
+}
+
+#[derive(Debug)]
+pub struct ExtendError {
+    stage: Stage,
+    /// `None` for images processed in memory.
+    path: Option<String>,
+    inner: Box<dyn Error + Send + Sync>,
+    attempts: u32,
+}
+
+impl ExtendError {
+    pub fn new<E: Into<Box<dyn Error + Send + Sync>>>(
+        stage: Stage,
+        path: Option<&str>,
+        inner: E,
+    ) -> Self {
+        ExtendError {
+            stage,
+            path: path.map(str::to_string),
+            inner: inner.into(),
+            attempts: 1,
+        }
+    }
+
+    pub(crate) fn with_attempts(mut self, attempts: u32) -> Self {
+        self.attempts = attempts;
+        self
+    }
+
+    pub fn stage(&self) -> Stage {
+        self.stage
+    }
+
+    pub fn path(&self) -> Option<&str> {
+        self.path.as_deref()
+    }
+
+    /// The underlying error, for telling I/O failures from image failures.
+    pub fn inner(&self) -> &(dyn Error + Send + Sync + 'static) {
+        self.inner.as_ref()
+    }
+
+    /// How often the entry was tried, more than once when transient I/O
+    /// errors were retried.
+    pub fn attempts(&self) -> u32 {
+        self.attempts
+    }
+
+    /// What kind of failure this is, without downcasting `inner`.
+    pub fn kind(&self) -> ErrorKind {
+        let inner = self.inner();
+        if inner.is::<io::Error>() {
+            ErrorKind::Io
+        } else if inner.is::<CorruptSource>() {
+            ErrorKind::Corrupt
+        } else if inner.is::<image::ImageError>() {
+            ErrorKind::Image
+        } else if inner.is::<CanvasTooLarge>() || inner.is::<SourceTooLarge>() {
+            ErrorKind::Limits
+        } else if inner.is::<TimedOut>() {
+            ErrorKind::Timeout
+        } else if inner.is::<InternalPanic>() {
+            ErrorKind::Panic
+        } else if inner.is::<crate::validate::Invalid>() {
+            ErrorKind::Invalid
+        } else {
+            ErrorKind::Other
+        }
+    }
+}
+
+/// What kind of failure an `ExtendError` is. Together with its `Stage` this
+/// tells, say, a failed decode from a failed encode.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum ErrorKind {
+    /// Reading or writing a file failed.
+    Io,
+    /// The source is damaged, cut short or decodes to no pixels.
+    Corrupt,
+    /// `image` could not decode or encode the image, or does not support
+    /// its format.
+    Image,
+    /// The source would exceed `maxSourcePixels`, or the canvas
+    /// `maxOutputPixels`.
+    Limits,
+    /// The entry ran past its `timeout`.
+    Timeout,
+    /// Processing panicked.
+    Panic,
+    /// A field of the entry cannot work, as an aspect ratio with a zero side.
+    Invalid,
+    Other,
+}
+
+impl ErrorKind {
+    /// The name of the kind in JSON results.
+    pub fn name(self) -> &'static str {
+        match self {
+            ErrorKind::Io => "io",
+            ErrorKind::Corrupt => "corrupt",
+            ErrorKind::Image => "image",
+            ErrorKind::Limits => "limits",
+            ErrorKind::Timeout => "timeout",
+            ErrorKind::Panic => "panic",
+            ErrorKind::Invalid => "invalid",
+            ErrorKind::Other => "other",
+        }
+    }
+}
+
+impl fmt::Display for ExtendError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let action = match self.stage {
+            Stage::Read => "failed to read source",
+            Stage::Decode => "failed to decode source",
+            Stage::Composite => "failed to extend",
+            Stage::Write => "failed to write destination",
+        };
+        match &self.path {
+            Some(path) => write!(f, "{} \"{}\": {}", action, path, self.inner)?,
+            None => write!(f, "{}: {}", action, self.inner)?,
+        }
+        if self.attempts > 1 {
+            write!(f, " (after {} attempts)", self.attempts)?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for ExtendError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(self.inner.as_ref())
+    }
+}
+
+/// Attaches the stage and path to the errors of a fallible step.
+pub(crate) trait Context<T> {
+    fn context(self, stage: Stage, path: &str) -> Result<T, ExtendError>;
+
+    /// For images that have no path.
+    fn stage(self, stage: Stage) -> Result<T, ExtendError>;
+}
+
+impl<T, E: Into<Box<dyn Error + Send + Sync>>> Context<T> for Result<T, E> {
+    fn context(self, stage: Stage, path: &str) -> Result<T, ExtendError> {
+        self.map_err(|e| ExtendError::new(stage, Some(path), e))
+    }
+
+    fn stage(self, stage: Stage) -> Result<T, ExtendError> {
+        self.map_err(|e| ExtendError::new(stage, None, e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util;
+    use crate::ImageInfo;
+    use image::{DynamicImage, ImageFormat, Rgba};
+
+    fn entry(source: &str, destination: &str) -> ImageInfo {
+        serde_json::from_value(serde_json::json!({
+            "source": source,
+            "destination": destination,
+            "aspectRatio": "1:1",
+        }))
+        .unwrap()
+    }
+
+    fn gradient(dir: &std::path::Path) -> String {
+        let img = test_util::horizontal_gradient(40, 20, Rgba([255, 0, 0, 255]), Rgba([0; 4]));
+        let path = dir.join("wide.png");
+        let data = test_util::encode(&DynamicImage::ImageRgba8(img), ImageFormat::Png);
+        std::fs::write(&path, data).unwrap();
+        path.to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn display_names_the_stage_and_path() {
+        let e = ExtendError::new(Stage::Decode, Some("photos/a.jpg"), "truncated");
+        assert_eq!(
+            e.to_string(),
+            "failed to decode source \"photos/a.jpg\": truncated"
+        );
+        let e = ExtendError::new(Stage::Write, Some("out/a.png"), "permission denied");
+        assert_eq!(
+            e.to_string(),
+            "failed to write destination \"out/a.png\": permission denied"
+        );
+        let e = ExtendError::new(Stage::Composite, None, "canvas too large");
+        assert_eq!(e.to_string(), "failed to extend: canvas too large");
+    }
+
+    #[test]
+    fn a_missing_source_fails_to_read() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("missing.png");
+        let source = source.to_str().unwrap();
+        let dest = dir.path().join("out.png");
+        let e = crate::compile_image(&entry(source, dest.to_str().unwrap())).unwrap_err();
+        assert_eq!(e.stage(), Stage::Read);
+        assert_eq!(e.path(), Some(source));
+        assert!(e
+            .to_string()
+            .starts_with(&format!("failed to read source \"{}\": ", source)));
+    }
+
+    #[test]
+    fn a_broken_source_fails_to_decode() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("broken.png");
+        std::fs::write(&source, b"\x89PNG\r\n\x1a\nnot really").unwrap();
+        let source = source.to_str().unwrap();
+        let dest = dir.path().join("out.png");
+        let e = crate::compile_image(&entry(source, dest.to_str().unwrap())).unwrap_err();
+        assert_eq!(e.stage(), Stage::Decode);
+        assert!(e
+            .to_string()
+            .starts_with(&format!("failed to decode source \"{}\": ", source)));
+    }
+
+    #[test]
+    fn an_unwritable_destination_fails_to_write() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = gradient(dir.path());
+        let dest = dir.path().join("missing-dir").join("out.png");
+        let dest = dest.to_str().unwrap();
+        let e = crate::compile_image(&entry(&source, dest)).unwrap_err();
+        assert_eq!(e.stage(), Stage::Write);
+        assert!(e
+            .to_string()
+            .starts_with(&format!("failed to write destination \"{}\": ", dest)));
+    }
+}
@@ -0,0 +1,232 @@
+//! C interface to the extender, enabled with the `ffi` feature.
+//!
+//! Every entry point reports failures through an `IbeError` out-parameter and
+//! never lets a panic cross the boundary.
+
+use std::ffi::CStr;
+use std::os::raw::c_char;
+use std::panic::{self, AssertUnwindSafe};
+use std::{ptr, slice};
+
+use crate::error::{panic_message, ErrorKind};
+use crate::{ExtendError, Options, OutputFormat};
+
+/// Size of `IbeError::message`, including the terminating NUL.
+pub const IBE_MESSAGE_CAPACITY: usize = 256;
+
+/// Status of an FFI call. `IBE_ERROR_CODE_OK` is always zero.
+///
+/// `Image` is a source in a format that is not supported, `Corrupt` one whose
+/// data is damaged or cut short. `InvalidArgument` covers settings that
+/// cannot work as well as bad pointers.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum IbeErrorCode {
+    Ok = 0,
+    InvalidArgument = 1,
+    Io = 2,
+    Image = 3,
+    Panic = 4,
+    Other = 5,
+    Corrupt = 6,
+    Limits = 7,
+    Timeout = 8,
+}
+
+/// Filled in by every call when non-null; `message` is NUL-terminated.
+#[repr(C)]
+pub struct IbeError {
+    pub code: IbeErrorCode,
+    pub message: [c_char; IBE_MESSAGE_CAPACITY],
+}
+
+/// Output encoding. `IBE_FORMAT_AUTO` keeps the destination extension for files
+/// and the input format for buffers.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum IbeFormat {
+    Auto = 0,
+    Png = 1,
+    Jpeg = 2,
+    Gif = 3,
+    Bmp = 4,
+    Tiff = 5,
+}
+
+/// Optional settings; a null pointer means all defaults.
+#[repr(C)]
+pub struct IbeOptions {
+    pub output_format: IbeFormat,
+}
+
+/// Encoded image owned by the library. Release with `ibe_buffer_free`.
+#[repr(C)]
+pub struct IbeBuffer {
+    pub data: *mut u8,
+    pub len: usize,
+}
+
+struct FfiError(IbeErrorCode, String);
+
+impl FfiError {
+    fn invalid(message: &str) -> Self {
+        FfiError(IbeErrorCode::InvalidArgument, message.to_string())
+    }
+}
+
+impl From<ExtendError> for FfiError {
+    fn from(e: ExtendError) -> Self {
+        let code = match e.kind() {
+            ErrorKind::Io => IbeErrorCode::Io,
+            ErrorKind::Image => IbeErrorCode::Image,
+            ErrorKind::Corrupt => IbeErrorCode::Corrupt,
+            ErrorKind::Invalid => IbeErrorCode::InvalidArgument,
+            ErrorKind::Limits => IbeErrorCode::Limits,
+            ErrorKind::Timeout => IbeErrorCode::Timeout,
+            ErrorKind::Panic => IbeErrorCode::Panic,
+            ErrorKind::Other => IbeErrorCode::Other,
+        };
+        FfiError(code, e.to_string())
+    }
+}
+
+impl IbeFormat {
+    fn output_format(self) -> Option<OutputFormat> {
+        match self {
+            IbeFormat::Auto => None,
+            IbeFormat::Png => Some(OutputFormat::Png),
+            IbeFormat::Jpeg => Some(OutputFormat::Jpeg),
+            IbeFormat::Gif => Some(OutputFormat::Gif),
+            IbeFormat::Bmp => Some(OutputFormat::Bmp),
+            IbeFormat::Tiff => Some(OutputFormat::Tiff),
+        }
+    }
+}
+
+unsafe fn output_format(options: *const IbeOptions) -> Option<OutputFormat> {
+    options
+        .as_ref()
+        .and_then(|options| options.output_format.output_format())
+}
+
+unsafe fn c_str<'a>(s: *const c_char, name: &str) -> Result<&'a str, FfiError> {
+    if s.is_null() {
+        return Err(FfiError::invalid(&format!("{} is null", name)));
+    }
+    CStr::from_ptr(s)
+        .to_str()
+        .map_err(|_| FfiError::invalid(&format!("{} is not valid UTF-8", name)))
+}
+
+unsafe fn write_error(err: *mut IbeError, code: IbeErrorCode, message: &str) {
+    let err = match err.as_mut() {
+        Some(err) => err,
+        None => return,
+    };
+    err.code = code;
+    // Truncate on a char boundary so the message stays valid UTF-8
+    let mut len = message.len().min(IBE_MESSAGE_CAPACITY - 1);
+    while !message.is_char_boundary(len) {
+        len -= 1;
+    }
+    for (dst, &src) in err.message.iter_mut().zip(&message.as_bytes()[..len]) {
+        *dst = src as c_char;
+    }
+    err.message[len] = 0;
+}
+
+fn guarded<F>(err: *mut IbeError, f: F) -> IbeErrorCode
+where
+    F: FnOnce() -> Result<(), FfiError>,
+{
+    let (code, message) = match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(())) => (IbeErrorCode::Ok, String::new()),
+        Ok(Err(FfiError(code, message))) => (code, message),
+        Err(payload) => (IbeErrorCode::Panic, panic_message(&*payload)),
+    };
+    unsafe { write_error(err, code, &message) };
+    code
+}
+
+/// Extends the image at `src` to the `aw`:`ah` aspect ratio and writes it to `dest`.
+///
+/// # Safety
+///
+/// `src` and `dest` must be NUL-terminated strings. `options` and `err` may be
+/// null, otherwise they must point to valid structs.
+#[no_mangle]
+pub unsafe extern "C" fn ibe_extend_file(
+    src: *const c_char,
+    dest: *const c_char,
+    aw: u32,
+    ah: u32,
+    options: *const IbeOptions,
+    err: *mut IbeError,
+) -> IbeErrorCode {
+    guarded(err, || {
+        let src = c_str(src, "src")?;
+        let dest = c_str(dest, "dest")?;
+        let options = Options {
+            output_format: output_format(options),
+            ..Options::default()
+        };
+        crate::extend_file(src, dest, (aw, ah), &options)?;
+        Ok(())
+    })
+}
+
+/// Extends the encoded image in `data` and stores the encoded result in `out`.
+///
+/// # Safety
+///
+/// `data` must point to `len` readable bytes and `out` to a writable
+/// `IbeBuffer`. `options` and `err` may be null.
+#[no_mangle]
+pub unsafe extern "C" fn ibe_extend_bytes(
+    data: *const u8,
+    len: usize,
+    aw: u32,
+    ah: u32,
+    options: *const IbeOptions,
+    out: *mut IbeBuffer,
+    err: *mut IbeError,
+) -> IbeErrorCode {
+    guarded(err, || {
+        if data.is_null() {
+            return Err(FfiError::invalid("data is null"));
+        }
+        let out = out
+            .as_mut()
+            .ok_or_else(|| FfiError::invalid("out is null"))?;
+        let result = crate::extend_bytes(
+            slice::from_raw_parts(data, len),
+            (aw, ah),
+            output_format(options).and_then(OutputFormat::image_format),
+        )?
+        .into_boxed_slice();
+        out.len = result.len();
+        out.data = Box::into_raw(result) as *mut u8;
+        Ok(())
+    })
+}
+
+/// Releases a buffer returned by `ibe_extend_bytes`. Safe to call twice.
+///
+/// # Safety
+///
+/// `buffer` must be null or point to an `IbeBuffer` filled by this library.
+#[no_mangle]
+pub unsafe extern "C" fn ibe_buffer_free(buffer: *mut IbeBuffer) {
+    let buffer = match buffer.as_mut() {
+        Some(buffer) => buffer,
+        None => return,
+    };
+    if !buffer.data.is_null() {
+        drop(Box::from_raw(ptr::slice_from_raw_parts_mut(
+            buffer.data,
+            buffer.len,
+        )));
+    }
+    buffer.data = ptr::null_mut();
+    buffer.len = 0;
+}
@@ -0,0 +1,183 @@
+//! What paints the canvas before the image is placed on it.
+//!
+//! Every `Background` is a `BackgroundFill`, and library users can add
+//! their own with `register`, which an entry then picks by name with
+//! `fill`. A fill paints the whole canvas; the image, and the vignette,
+//! shadow, grain, border and caption of the style, go on top of it
+//! afterwards, and backgrounds left transparent are laid over the fallback
+//! color with `backgroundAlpha: opaque`.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, OnceLock};
+
+use image::{DynamicImage, Rgba, RgbaImage};
+
+use crate::report::Placement;
+use crate::style::{Background, Style};
+use crate::{padding, palette, smear, Layout, Orientation};
+
+/// Paints the background of a canvas.
+pub trait BackgroundFill: Send + Sync {
+    /// Paints all of `canvas`, which arrives transparent and the size the
+    /// output will be before `maxWidth` and `maxHeight`.
+    fn fill(&self, canvas: &mut RgbaImage, ctx: &FillContext<'_>);
+}
+
+/// What a fill gets to know about the image it paints around.
+pub struct FillContext<'a> {
+    pub(crate) image: &'a DynamicImage,
+    pub(crate) layout: &'a Layout,
+    pub(crate) colors: (Rgba<u8>, Rgba<u8>),
+    pub(crate) style: &'a Style,
+}
+
+impl FillContext<'_> {
+    /// The source as it is placed, cropped for `rounding: down` but not yet
+    /// scaled to the size it takes on the canvas.
+    pub fn source(&self) -> &DynamicImage {
+        self.image
+    }
+
+    /// Where the image goes on the canvas.
+    pub fn placement(&self) -> Placement {
+        let (x, y) = self.layout.offset();
+        Placement {
+            x,
+            y,
+            width: self.layout.image.0,
+            height: self.layout.image.1,
+        }
+    }
+
+    /// Which sides of the image the canvas has room on.
+    pub fn orientation(&self) -> Orientation {
+        self.layout.orientation
+    }
+
+    /// The two colors sampled from the opposite edges the canvas extends,
+    /// adjusted as the style asks and made opaque with `backgroundAlpha:
+    /// opaque`, first the top or left one.
+    pub fn colors(&self) -> (Rgba<u8>, Rgba<u8>) {
+        self.colors
+    }
+
+    /// The style of the entry.
+    pub fn style(&self) -> &Style {
+        self.style
+    }
+}
+
+fn registry() -> &'static Mutex<HashMap<String, Arc<dyn BackgroundFill>>> {
+    static FILLS: OnceLock<Mutex<HashMap<String, Arc<dyn BackgroundFill>>>> = OnceLock::new();
+    FILLS.get_or_init(Default::default)
+}
+
+/// Makes `fill` available to entries as `"fill": name`, in place of any
+/// fill registered under that name before.
+pub fn register(name: &str, fill: impl BackgroundFill + 'static) {
+    registry()
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .insert(name.to_string(), Arc::new(fill));
+}
+
+/// The fill registered as `name`.
+pub(crate) fn registered(name: &str) -> Option<Arc<dyn BackgroundFill>> {
+    registry()
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .get(name)
+        .cloned()
+}
+
+/// Makes a sampled color opaque for `backgroundAlpha: opaque`.
+fn solid(color: Rgba<u8>, opaque: bool) -> Rgba<u8> {
+    match opaque {
+        true => Rgba([color.0[0], color.0[1], color.0[2], 255]),
+        false => color,
+    }
+}
+
+impl BackgroundFill for Background {
+    fn fill(&self, canvas: &mut RgbaImage, ctx: &FillContext<'_>) {
+        let FillContext {
+            image: img,
+            layout,
+            colors: (first_color, second_color),
+            style,
+        } = *ctx;
+        let opaque = style.background_alpha.unwrap_or_default() == crate::BackgroundAlpha::Opaque;
+        *canvas = match self {
+            Background::Complement | Background::Uniform => {
+                crate::create_uniform_background(layout.canvas, first_color)
+            }
+            Background::Split if layout.pads_both_axes() => {
+                // The other two edges give the bands across the split
+                let other = match layout.orientation {
+                    Orientation::Landscape => Orientation::Portrait,
+                    Orientation::Portrait => Orientation::Landscape,
+                };
+                let across = crate::pick_colors(
+                    crate::aggregate_edge_colors(img, other, style).colors,
+                    style,
+                )
+                .0;
+                let sides = match layout.orientation {
+                    Orientation::Landscape => [first_color, second_color, across.0, across.1],
+                    Orientation::Portrait => [across.0, across.1, first_color, second_color],
+                };
+                crate::create_frame_background(layout, sides)
+            }
+            Background::Split => {
+                crate::create_split_background(
+                    canvas,
+                    first_color,
+                    second_color,
+                    layout.orientation,
+                    layout.middle(),
+                );
+                return;
+            }
+            Background::Gradient => {
+                crate::create_gradient_background(
+                    canvas,
+                    first_color,
+                    second_color,
+                    layout.orientation,
+                    style.gradient_angle,
+                );
+                return;
+            }
+            Background::Blur => crate::create_blur_background(img, layout, style),
+            Background::Smear => smear::background(img, layout, style),
+            Background::Mirror => padding::mirror(img, layout, style),
+            Background::Corners => {
+                let corners = crate::corner_colors(img, style).map(|corner| {
+                    let corner = solid(corner, opaque);
+                    style
+                        .guard_contrast(style.adjust_background(corner), &[corner])
+                        .0
+                });
+                crate::create_corner_background(layout, corners)
+            }
+            Background::Replicate => padding::replicate(img, layout, style),
+            Background::Palette => {
+                let colors: Vec<_> = palette::colors(img, style.palette_size(), style)
+                    .into_iter()
+                    .map(|color| {
+                        let color = solid(color, opaque);
+                        style
+                            .guard_contrast(style.adjust_background(color), &[color])
+                            .0
+                    })
+                    .collect();
+                if colors.is_empty() {
+                    let (width, height) = layout.canvas;
+                    RgbaImage::from_pixel(width, height, crate::fallback_color(img, style))
+                } else {
+                    palette::background(layout, &colors, style.palette_gradient.unwrap_or(false))
+                }
+            }
+        };
+    }
+}
@@ -0,0 +1,572 @@
+//! Output formats that can be requested explicitly or inferred from the destination.
+
+use std::borrow::Cow;
+use std::fmt;
+use std::io::Cursor;
+use std::path::Path;
+use std::str::FromStr;
+
+use image::error::ImageFormatHint;
+use image::{DynamicImage, ImageError, ImageFormat, ImageResult};
+use serde::de::{self, Deserializer};
+use serde::{Deserialize, Serialize};
+
+use crate::error::BoxError;
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    Png,
+    /// Animated PNG. Still images are written as plain PNG.
+    Apng,
+    #[serde(alias = "jpg")]
+    Jpeg,
+    Gif,
+    Bmp,
+    Tiff,
+    Webp,
+    Ico,
+    Avif,
+    /// JPEG XL, written lossless.
+    Jxl,
+}
+
+impl OutputFormat {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "apng" => Some(OutputFormat::Apng),
+            "jxl" => Some(OutputFormat::Jxl),
+            name => ImageFormat::from_extension(name).and_then(Self::from_image_format),
+        }
+    }
+
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Option<Self> {
+        path.as_ref()
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .and_then(Self::from_name)
+    }
+
+    fn from_image_format(format: ImageFormat) -> Option<Self> {
+        match format {
+            ImageFormat::Png => Some(OutputFormat::Png),
+            ImageFormat::Jpeg => Some(OutputFormat::Jpeg),
+            ImageFormat::Gif => Some(OutputFormat::Gif),
+            ImageFormat::Bmp => Some(OutputFormat::Bmp),
+            ImageFormat::Tiff => Some(OutputFormat::Tiff),
+            ImageFormat::WebP => Some(OutputFormat::Webp),
+            ImageFormat::Ico => Some(OutputFormat::Ico),
+            ImageFormat::Avif => Some(OutputFormat::Avif),
+            _ => None,
+        }
+    }
+
+    /// Format used when writing a single still image. `None` for JPEG XL,
+    /// which `image` has no format for and `encode_jxl` writes instead.
+    pub fn image_format(self) -> Option<ImageFormat> {
+        Some(match self {
+            OutputFormat::Png | OutputFormat::Apng => ImageFormat::Png,
+            OutputFormat::Jpeg => ImageFormat::Jpeg,
+            OutputFormat::Gif => ImageFormat::Gif,
+            OutputFormat::Bmp => ImageFormat::Bmp,
+            OutputFormat::Tiff => ImageFormat::Tiff,
+            OutputFormat::Webp => ImageFormat::WebP,
+            OutputFormat::Ico => ImageFormat::Ico,
+            OutputFormat::Avif => ImageFormat::Avif,
+            OutputFormat::Jxl => return None,
+        })
+    }
+
+    /// The format as `image` names it in its errors, those of animations.
+    #[cfg_attr(not(feature = "gif"), allow(dead_code))]
+    pub(crate) fn hint(self) -> ImageFormatHint {
+        match self.image_format() {
+            Some(format) => ImageFormatHint::Exact(format),
+            None => ImageFormatHint::Name(self.encoder().format.to_string()),
+        }
+    }
+
+    /// Whether the format can hold more than one frame.
+    pub fn is_animated(self) -> bool {
+        matches!(
+            self,
+            OutputFormat::Gif | OutputFormat::Png | OutputFormat::Apng | OutputFormat::Webp
+        )
+    }
+}
+
+/// Names of `outputFormat` in the job list.
+const OUTPUT_FORMAT_NAMES: &[&str] = &[
+    "png", "apng", "jpeg", "jpg", "gif", "bmp", "tiff", "webp", "ico", "avif", "jxl",
+];
+
+impl<'de> Deserialize<'de> for OutputFormat {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let name = Cow::<str>::deserialize(deserializer)?;
+        Ok(match name.as_ref() {
+            "png" => OutputFormat::Png,
+            "apng" => OutputFormat::Apng,
+            "jpeg" | "jpg" => OutputFormat::Jpeg,
+            "gif" => OutputFormat::Gif,
+            "bmp" => OutputFormat::Bmp,
+            "tiff" => OutputFormat::Tiff,
+            "webp" => OutputFormat::Webp,
+            "ico" => OutputFormat::Ico,
+            "avif" => OutputFormat::Avif,
+            "jxl" => OutputFormat::Jxl,
+            name => return Err(de::Error::unknown_variant(name, OUTPUT_FORMAT_NAMES)),
+        })
+    }
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        OutputFormat::from_name(name).ok_or_else(|| format!("unknown output format {}", name))
+    }
+}
+
+/// A source or destination needs a codec this build was compiled without.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FormatNotCompiled {
+    pub format: &'static str,
+    /// Cargo feature that provides the codec.
+    pub feature: &'static str,
+}
+
+impl fmt::Display for FormatNotCompiled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} support is not compiled in, enable the `{}` feature",
+            self.format, self.feature
+        )
+    }
+}
+
+impl std::error::Error for FormatNotCompiled {}
+
+/// Name of the codec for `format`, the feature that provides it and whether
+/// this build has it. `None` for formats without a feature of their own.
+fn codec(format: ImageFormat) -> Option<(&'static str, &'static str, bool)> {
+    Some(match format {
+        ImageFormat::Png => ("PNG", "png", cfg!(feature = "png")),
+        ImageFormat::Jpeg => ("JPEG", "jpeg", cfg!(feature = "jpeg")),
+        ImageFormat::Gif => ("GIF", "gif", cfg!(feature = "gif")),
+        ImageFormat::WebP => ("WebP", "webp", cfg!(feature = "webp")),
+        ImageFormat::Pnm => ("PNM", "pnm", cfg!(feature = "pnm")),
+        ImageFormat::Tiff => ("TIFF", "tiff", cfg!(feature = "tiff")),
+        ImageFormat::Tga => ("TGA", "tga", cfg!(feature = "tga")),
+        ImageFormat::Dds => ("DDS", "dds", cfg!(feature = "dds")),
+        ImageFormat::Bmp => ("BMP", "bmp", cfg!(feature = "bmp")),
+        ImageFormat::Ico => ("ICO", "ico", cfg!(feature = "ico")),
+        ImageFormat::Hdr => ("HDR", "hdr", cfg!(feature = "hdr")),
+        ImageFormat::Farbfeld => ("farbfeld", "farbfeld", cfg!(feature = "farbfeld")),
+        _ => return None,
+    })
+}
+
+/// Fails when the codec for `format` was left out of this build.
+pub(crate) fn ensure_compiled(format: ImageFormat) -> Result<(), FormatNotCompiled> {
+    let (name, feature, compiled) = match codec(format) {
+        Some(codec) => codec,
+        None => return Ok(()),
+    };
+    if compiled {
+        Ok(())
+    } else {
+        Err(FormatNotCompiled {
+            format: name,
+            feature,
+        })
+    }
+}
+
+/// A format destinations can be written in, whether or not this build has
+/// its encoder.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Encoder {
+    pub format: &'static str,
+    /// `None` for JPEG XL, which `image` has no format for.
+    pub image_format: Option<ImageFormat>,
+    /// Extensions of destinations written in it, lowercase and without the dot.
+    pub extensions: &'static [&'static str],
+    /// Cargo feature that provides the encoder.
+    pub feature: &'static str,
+    pub compiled: bool,
+    /// Whether still images are written, rather than only animations.
+    pub stills: bool,
+}
+
+impl Encoder {
+    pub fn not_compiled(&self) -> FormatNotCompiled {
+        FormatNotCompiled {
+            format: self.format,
+            feature: self.feature,
+        }
+    }
+}
+
+const fn encoder(
+    format: &'static str,
+    image_format: Option<ImageFormat>,
+    extensions: &'static [&'static str],
+    feature: &'static str,
+    compiled: bool,
+) -> Encoder {
+    Encoder {
+        format,
+        image_format,
+        extensions,
+        feature,
+        compiled,
+        stills: true,
+    }
+}
+
+/// Every format destinations can be written in, by what is written for
+/// their extension or `outputFormat`.
+pub const ENCODERS: &[Encoder] = &[
+    encoder(
+        "PNG",
+        Some(ImageFormat::Png),
+        &["png"],
+        "png",
+        cfg!(feature = "png"),
+    ),
+    encoder(
+        "APNG",
+        Some(ImageFormat::Png),
+        &["apng"],
+        "apng",
+        cfg!(feature = "apng"),
+    ),
+    encoder(
+        "JPEG",
+        Some(ImageFormat::Jpeg),
+        &["jpg", "jpeg"],
+        "jpeg",
+        cfg!(feature = "jpeg"),
+    ),
+    encoder(
+        "GIF",
+        Some(ImageFormat::Gif),
+        &["gif"],
+        "gif",
+        cfg!(feature = "gif"),
+    ),
+    encoder(
+        "BMP",
+        Some(ImageFormat::Bmp),
+        &["bmp"],
+        "bmp",
+        cfg!(feature = "bmp"),
+    ),
+    encoder(
+        "TIFF",
+        Some(ImageFormat::Tiff),
+        &["tif", "tiff"],
+        "tiff",
+        cfg!(feature = "tiff"),
+    ),
+    encoder(
+        "ICO",
+        Some(ImageFormat::Ico),
+        &["ico"],
+        "ico",
+        cfg!(feature = "ico"),
+    ),
+    encoder(
+        "AVIF",
+        Some(ImageFormat::Avif),
+        &["avif"],
+        "avif",
+        cfg!(feature = "avif"),
+    ),
+    encoder("JPEG XL", None, &["jxl"], "jxl", cfg!(feature = "jxl")),
+    encoder(
+        "PNM",
+        Some(ImageFormat::Pnm),
+        &["pbm", "pam", "ppm", "pgm"],
+        "pnm",
+        cfg!(feature = "pnm"),
+    ),
+    encoder(
+        "TGA",
+        Some(ImageFormat::Tga),
+        &["tga"],
+        "tga",
+        cfg!(feature = "tga"),
+    ),
+    encoder(
+        "farbfeld",
+        Some(ImageFormat::Farbfeld),
+        &["ff", "farbfeld"],
+        "farbfeld",
+        cfg!(feature = "farbfeld"),
+    ),
+    // `image` has no WebP encoder, only the animation encoder has
+    Encoder {
+        stills: false,
+        ..encoder(
+            "WebP",
+            Some(ImageFormat::WebP),
+            &["webp"],
+            "animated-webp",
+            cfg!(feature = "animated-webp"),
+        )
+    },
+];
+
+impl OutputFormat {
+    /// The entry of `ENCODERS` for the format.
+    pub fn encoder(self) -> &'static Encoder {
+        let extension = match self {
+            OutputFormat::Png => "png",
+            OutputFormat::Apng => "apng",
+            OutputFormat::Jpeg => "jpg",
+            OutputFormat::Gif => "gif",
+            OutputFormat::Bmp => "bmp",
+            OutputFormat::Tiff => "tif",
+            OutputFormat::Webp => "webp",
+            OutputFormat::Ico => "ico",
+            OutputFormat::Avif => "avif",
+            OutputFormat::Jxl => "jxl",
+        };
+        encoder_for_extension(extension).expect("every output format has an encoder")
+    }
+}
+
+/// The encoder for destinations ending in `.extension`, in any case.
+pub fn encoder_for_extension(extension: &str) -> Option<&'static Encoder> {
+    let extension = extension.to_ascii_lowercase();
+    ENCODERS
+        .iter()
+        .find(|encoder| encoder.extensions.contains(&extension.as_str()))
+}
+
+/// Name of a format that is read but never written, as DDS.
+pub(crate) fn read_only(extension: &str) -> Option<&'static str> {
+    let format = ImageFormat::from_extension(extension)?;
+    if ENCODERS
+        .iter()
+        .any(|encoder| encoder.image_format == Some(format))
+    {
+        return None;
+    }
+    Some(codec(format)?.0)
+}
+
+/// Edits that turn one string into the other.
+fn distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, a) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, &b) in b.iter().enumerate() {
+            let replaced = diagonal + usize::from(a != b);
+            diagonal = row[j + 1];
+            row[j + 1] = replaced.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+    row[b.len()]
+}
+
+/// Extensions this build writes still images for that are closest to
+/// `extension`, at most three of them.
+pub fn nearest_extensions(extension: &str) -> Vec<&'static str> {
+    let extension = extension.to_ascii_lowercase();
+    let mut candidates: Vec<(usize, &'static str)> = ENCODERS
+        .iter()
+        .filter(|encoder| encoder.compiled && encoder.stills)
+        .flat_map(|encoder| encoder.extensions.iter())
+        .map(|candidate| (distance(&extension, candidate), *candidate))
+        .collect();
+    // Stable, so ties keep the order of `ENCODERS`
+    candidates.sort_by_key(|&(distance, _)| distance);
+    candidates
+        .into_iter()
+        .take(3)
+        .map(|(_, candidate)| candidate)
+        .collect()
+}
+
+/// Fails when this build cannot write an image in `format`, or only writes
+/// animations in it and `animated` is not set.
+pub(crate) fn ensure_writable(format: ImageFormat, animated: bool) -> Result<(), BoxError> {
+    match ENCODERS
+        .iter()
+        .find(|encoder| encoder.image_format == Some(format))
+    {
+        Some(encoder) => encoder.ensure_writable(animated),
+        None => Err(ImageError::Unsupported(ImageFormatHint::Exact(format).into()).into()),
+    }
+}
+
+impl Encoder {
+    /// `ensure_writable` for the format of this encoder.
+    pub(crate) fn ensure_writable(&self, animated: bool) -> Result<(), BoxError> {
+        if !self.compiled {
+            return Err(self.not_compiled().into());
+        }
+        if !self.stills && !animated {
+            return Err(format!("{} is only written for animated GIF sources", self.format).into());
+        }
+        Ok(())
+    }
+}
+
+/// How hard PNG output is compressed, from fastest to smallest.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum PngCompression {
+    /// What is written when none is set.
+    Fast,
+    Default,
+    Best,
+}
+
+/// Encoder settings of an entry, each used by the formats it applies to.
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct Encoding {
+    /// JPEG and AVIF quality from 0 to 100.
+    #[cfg_attr(not(any(feature = "jpeg", feature = "avif")), allow(dead_code))]
+    pub(crate) quality: Option<u8>,
+    #[cfg_attr(not(feature = "png"), allow(dead_code))]
+    pub(crate) png_compression: Option<PngCompression>,
+    #[cfg_attr(not(feature = "avif"), allow(dead_code))]
+    pub(crate) avif_speed: Option<u8>,
+    #[cfg_attr(not(feature = "jxl"), allow(dead_code))]
+    pub(crate) jxl_effort: Option<u8>,
+}
+
+impl Encoding {
+    pub(crate) fn of(options: &crate::Options) -> Self {
+        Encoding {
+            quality: options.quality,
+            png_compression: options.png_compression,
+            avif_speed: options.avif_speed,
+            jxl_effort: options.jxl_effort,
+        }
+    }
+}
+
+/// `img` in 8 bits a channel, with the channels it has, for the formats
+/// written without 16-bit support.
+fn eight_bit(img: &DynamicImage, format: ImageFormat) -> Cow<'_, DynamicImage> {
+    if matches!(format, ImageFormat::Png | ImageFormat::Tiff) {
+        return Cow::Borrowed(img);
+    }
+    Cow::Owned(match img {
+        DynamicImage::ImageLuma16(_) => DynamicImage::ImageLuma8(img.to_luma8()),
+        DynamicImage::ImageLumaA16(_) => DynamicImage::ImageLumaA8(img.to_luma_alpha8()),
+        DynamicImage::ImageRgb16(_) => DynamicImage::ImageRgb8(img.to_rgb8()),
+        DynamicImage::ImageRgba16(_) => DynamicImage::ImageRgba8(img.to_rgba8()),
+        _ => return Cow::Borrowed(img),
+    })
+}
+
+/// Encodes `img` as `save_with_format` would, but into memory, so that the
+/// destination can be written in a stage of its own. 16-bit images stay
+/// 16-bit in PNG and TIFF.
+#[cfg_attr(not(any(feature = "jpeg", feature = "png")), allow(unused_variables))]
+pub(crate) fn encode(
+    img: &DynamicImage,
+    format: ImageFormat,
+    encoding: Encoding,
+) -> ImageResult<Vec<u8>> {
+    let img = &*eight_bit(img, format);
+    let mut data = Cursor::new(Vec::new());
+    match format {
+        #[cfg(feature = "jpeg")]
+        ImageFormat::Jpeg if encoding.quality.is_some() => {
+            let quality = encoding.quality.unwrap_or_default().min(100);
+            img.write_to(&mut data, image::ImageOutputFormat::Jpeg(quality))?
+        }
+        // `write_to` leaves 16-bit samples in native byte order, where PNG
+        // wants big-endian
+        #[cfg(feature = "png")]
+        ImageFormat::Png
+            if encoding.png_compression.is_some() || crate::depth::is_deep(img.color()) =>
+        {
+            use image::codecs::png::{CompressionType, FilterType, PngEncoder};
+            use image::{GenericImageView, ImageEncoder};
+            let compression = match encoding.png_compression {
+                Some(PngCompression::Default) => CompressionType::Default,
+                Some(PngCompression::Best) => CompressionType::Best,
+                _ => CompressionType::Fast,
+            };
+            let (width, height) = img.dimensions();
+            // The filter `write_to` uses
+            PngEncoder::new_with_quality(&mut data, compression, FilterType::Sub).write_image(
+                img.as_bytes(),
+                width,
+                height,
+                img.color(),
+            )?
+        }
+        // `write_to` has no TIFF support, which needs to seek
+        #[cfg(feature = "tiff")]
+        ImageFormat::Tiff => {
+            use image::GenericImageView;
+            let (width, height) = img.dimensions();
+            image::codecs::tiff::TiffEncoder::new(&mut data).encode(
+                img.as_bytes(),
+                width,
+                height,
+                img.color(),
+            )?
+        }
+        #[cfg(feature = "avif")]
+        ImageFormat::Avif => {
+            return crate::heif::encode_avif(img, encoding.quality, encoding.avif_speed)
+        }
+        format => img.write_to(&mut data, format)?,
+    }
+    Ok(data.into_inner())
+}
+
+/// Encodes `img` as a lossless JPEG XL, in 16 bits a channel for 16-bit
+/// images. `image` has no JPEG XL support, so this is kept apart from
+/// `encode`.
+#[cfg(feature = "jxl")]
+pub(crate) fn encode_jxl(img: &DynamicImage, encoding: Encoding) -> Result<Vec<u8>, BoxError> {
+    use image::{ColorType, GenericImageView};
+    use zune_core::bit_depth::BitDepth;
+    use zune_core::colorspace::ColorSpace;
+    use zune_core::options::EncoderOptions;
+
+    let (img, colorspace, depth) = match img.color() {
+        ColorType::L8 => (Cow::Borrowed(img), ColorSpace::Luma, BitDepth::Eight),
+        ColorType::La8 => (Cow::Borrowed(img), ColorSpace::LumaA, BitDepth::Eight),
+        ColorType::Rgb8 => (Cow::Borrowed(img), ColorSpace::RGB, BitDepth::Eight),
+        ColorType::Rgba8 => (Cow::Borrowed(img), ColorSpace::RGBA, BitDepth::Eight),
+        ColorType::L16 => (Cow::Borrowed(img), ColorSpace::Luma, BitDepth::Sixteen),
+        ColorType::La16 => (Cow::Borrowed(img), ColorSpace::LumaA, BitDepth::Sixteen),
+        ColorType::Rgb16 => (Cow::Borrowed(img), ColorSpace::RGB, BitDepth::Sixteen),
+        ColorType::Rgba16 => (Cow::Borrowed(img), ColorSpace::RGBA, BitDepth::Sixteen),
+        _ => (
+            Cow::Owned(DynamicImage::ImageRgba8(img.to_rgba8())),
+            ColorSpace::RGBA,
+            BitDepth::Eight,
+        ),
+    };
+    let (width, height) = img.dimensions();
+    let mut options = EncoderOptions::new(width as usize, height as usize, colorspace, depth);
+    if let Some(effort) = encoding.jxl_effort {
+        options = options.set_effort(effort);
+    }
+    // 16-bit samples go in native byte order, as `as_bytes` has them
+    zune_jpegxl::JxlSimpleEncoder::new(img.as_bytes(), options)
+        .encode()
+        .map_err(|e| format!("cannot encode JPEG XL: {}", format!("{:?}", e).trim_end()).into())
+}
+
+/// `encode_jxl` of a build without the `jxl` feature, which validation
+/// keeps from being reached.
+#[cfg(not(feature = "jxl"))]
+pub(crate) fn encode_jxl(_img: &DynamicImage, _encoding: Encoding) -> Result<Vec<u8>, BoxError> {
+    Err(OutputFormat::Jxl.encoder().not_compiled().into())
+}
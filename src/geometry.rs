@@ -0,0 +1,128 @@
+//! How big the canvas of an extended image is and where the image sits on
+//! it, for code that lays out pages around outputs before they are made.
+//!
+//! A source is extended to the smallest multiple of the ratio that holds it
+//! along its longer side relative to the ratio, which decides its
+//! `Orientation`, with `rounding: down` cropping up to a pixel less than
+//! the ratio off each side instead of growing the canvas for it. `plan`
+//! takes the padding, inset, `targetSize` and tolerance of an entry into
+//! account as `compile_image` does.
+
+use serde::{Deserialize, Serialize};
+
+use crate::{CanvasTooLarge, Options, Rounding};
+
+/// Which sides of the image the canvas has room on.
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum Orientation {
+    /// Wider than the ratio, with room above and below.
+    Landscape,
+    /// Taller than the ratio, with room to the left and right.
+    Portrait,
+}
+
+/// Where an image ends up on the canvas `plan` lays out.
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct CanvasPlan {
+    pub orientation: Orientation,
+    /// Width and height of the canvas.
+    pub canvas: (u32, u32),
+    /// Size of the image on the canvas, once cropped, scaled and inset.
+    pub image: (u32, u32),
+    /// Top left corner of the image on the canvas.
+    pub offset: (u32, u32),
+    /// Pixels cropped off the width and height of the source with
+    /// `rounding: down`, half on either side.
+    pub overflow: (u32, u32),
+    /// Size of the written output, the canvas scaled down to `maxWidth` and
+    /// `maxHeight`.
+    pub output: (u32, u32),
+}
+
+/// The multiple of `ratio` that `length` is brought to and the pixels
+/// cropped off it.
+fn fit(length: u32, ratio: u32, rounding: Rounding) -> (u32, u32) {
+    let (multiplier, overflow) = (length / ratio, length % ratio);
+    match rounding {
+        Rounding::Up if overflow > 0 => (multiplier + 1, 0),
+        Rounding::Up => (multiplier, 0),
+        // Sides shorter than the ratio are kept whole rather than cropped away
+        Rounding::Down if multiplier == 0 => (1, 0),
+        Rounding::Down => (multiplier, overflow),
+    }
+}
+
+/// Whether an image of `dimensions` is exactly a multiple of `aspect_ratio`
+/// already, and so needs no canvas.
+pub fn fits_exactly((width, height): (u32, u32), aspect_ratio: (u32, u32)) -> bool {
+    width % aspect_ratio.0 == 0
+        && height % aspect_ratio.1 == 0
+        && width / aspect_ratio.0 == height / aspect_ratio.1
+}
+
+/// Which sides an image of `dimensions` is extended on to reach
+/// `aspect_ratio`.
+pub fn orientation(
+    (width, height): (u32, u32),
+    aspect_ratio: (u32, u32),
+    rounding: Rounding,
+) -> Orientation {
+    if fit(width, aspect_ratio.0, rounding).0 > fit(height, aspect_ratio.1, rounding).0 {
+        Orientation::Landscape
+    } else {
+        Orientation::Portrait
+    }
+}
+
+/// Pixels cropped off the width and height of an image of `dimensions` to
+/// make them multiples of `aspect_ratio`, which only `rounding: down` does.
+pub fn overflow(
+    (width, height): (u32, u32),
+    aspect_ratio: (u32, u32),
+    rounding: Rounding,
+) -> (u32, u32) {
+    (
+        fit(width, aspect_ratio.0, rounding).1,
+        fit(height, aspect_ratio.1, rounding).1,
+    )
+}
+
+/// Width and height of the canvas an image of `dimensions` is extended to
+/// for `aspect_ratio`, which may be too large for a `u32`.
+pub fn canvas_dimensions(
+    (width, height): (u32, u32),
+    aspect_ratio: (u32, u32),
+    rounding: Rounding,
+) -> (u64, u64) {
+    let multiplier = match orientation((width, height), aspect_ratio, rounding) {
+        Orientation::Landscape => fit(width, aspect_ratio.0, rounding).0,
+        Orientation::Portrait => fit(height, aspect_ratio.1, rounding).0,
+    } as u64;
+    (
+        aspect_ratio.0 as u64 * multiplier,
+        aspect_ratio.1 as u64 * multiplier,
+    )
+}
+
+/// The canvas `mode: extend` lays an image of `dimensions` out on for
+/// `aspect_ratio` with the settings of `options`.
+///
+/// Returns `None` when the image is written as it is.
+pub fn plan(
+    dimensions: (u32, u32),
+    aspect_ratio: (u32, u32),
+    options: &Options,
+) -> Result<Option<CanvasPlan>, CanvasTooLarge> {
+    Ok(
+        crate::layout_for(dimensions, aspect_ratio, options)?.map(|layout| CanvasPlan {
+            orientation: layout.orientation,
+            canvas: layout.canvas,
+            image: layout.image,
+            offset: layout.offset(),
+            overflow: layout.overflow,
+            output: options.max_size(layout.canvas).unwrap_or(layout.canvas),
+        }),
+    )
+}
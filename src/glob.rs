@@ -0,0 +1,218 @@
+//! Sources naming many files at once, as a pattern like `photos/**/*.jpg`
+//! or a whole directory, expanded into one entry per image before the job
+//! list is processed.
+//!
+//! In patterns `*` stands for any part of a name and `?` for any one
+//! character, neither going past a `/`, and a `**` of its own stands for
+//! any number of directories. A directory stands for `dir/**/*`.
+
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::archive::{self, Expanded};
+use crate::{storage, ImageInfo};
+
+/// Replaced in the destination of a pattern by the file name, and by the
+/// file name without and with only its extension.
+pub const NAME_PLACEHOLDER: &str = "{name}";
+pub const STEM_PLACEHOLDER: &str = "{stem}";
+pub const EXT_PLACEHOLDER: &str = "{ext}";
+
+/// A directory that could not be listed while expanding `pattern`.
+#[derive(Debug)]
+pub struct GlobError {
+    pub pattern: String,
+    pub path: PathBuf,
+    source: io::Error,
+}
+
+impl fmt::Display for GlobError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "cannot list {} for {}: {}",
+            self.path.display(),
+            self.pattern,
+            self.source
+        )
+    }
+}
+
+impl Error for GlobError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&self.source)
+    }
+}
+
+fn is_pattern(path: &str) -> bool {
+    path.contains(['*', '?'])
+}
+
+/// Whether `name` matches the single component `pattern`.
+fn matches(pattern: &[u8], name: &[u8]) -> bool {
+    match pattern.split_first() {
+        None => name.is_empty(),
+        Some((b'*', rest)) => (0..=name.len()).any(|taken| matches(rest, &name[taken..])),
+        Some((b'?', rest)) => !name.is_empty() && matches(rest, &name[1..]),
+        Some((c, rest)) => name
+            .split_first()
+            .is_some_and(|(first, name)| first == c && matches(rest, name)),
+    }
+}
+
+/// Whether the components of a path below the root match the components
+/// of the pattern below it.
+fn matches_path(pattern: &[&str], path: &[&str]) -> bool {
+    match pattern.split_first() {
+        None => path.is_empty(),
+        Some((&"**", rest)) => (0..=path.len()).any(|taken| matches_path(rest, &path[taken..])),
+        Some((component, rest)) => path.split_first().is_some_and(|(first, path)| {
+            matches(component.as_bytes(), first.as_bytes()) && matches_path(rest, path)
+        }),
+    }
+}
+
+/// Every file below `dir` and at most `depth` components deep, as paths
+/// relative to it split into components. Links to directories are not
+/// followed, so a loop cannot be walked into.
+fn walk(
+    dir: &Path,
+    depth: usize,
+    prefix: &mut Vec<String>,
+    files: &mut Vec<Vec<String>>,
+) -> io::Result<()> {
+    let mut entries = fs::read_dir(dir)?.collect::<io::Result<Vec<_>>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+    for entry in entries {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let file_type = entry.file_type()?;
+        prefix.push(name);
+        if file_type.is_dir() {
+            if prefix.len() < depth {
+                walk(&entry.path(), depth, prefix, files)?;
+            }
+        } else if file_type.is_file() || entry.path().is_file() {
+            files.push(prefix.clone());
+        }
+        prefix.pop();
+    }
+    Ok(())
+}
+
+/// `name` appended to `dir` as a path, `dir` being a directory on disk or
+/// in an archive.
+fn join(dir: &str, name: &str) -> String {
+    if dir.is_empty() {
+        name.to_string()
+    } else if dir.ends_with('/') || dir.ends_with('!') {
+        format!("{}{}", dir, name)
+    } else {
+        format!("{}/{}", dir, name)
+    }
+}
+
+/// Where the image at `relative` below the root of a pattern goes, for an
+/// entry destination of `dest`: the same path below `dest` taken as a
+/// directory, or below the directory of `dest` with its file name filled in
+/// when it has placeholders.
+fn destination(dest: &str, relative: &[String]) -> String {
+    let (dirs, name) = relative.split_at(relative.len() - 1);
+    let name = &name[0];
+    let has_placeholders = [NAME_PLACEHOLDER, STEM_PLACEHOLDER, EXT_PLACEHOLDER]
+        .iter()
+        .any(|placeholder| dest.contains(placeholder));
+    let (root, file) = if has_placeholders {
+        let at = dest.rfind(['/', '!']).map_or(0, |at| at + 1);
+        let (stem, ext) = match name.rsplit_once('.') {
+            Some((stem, ext)) if !stem.is_empty() => (stem, ext),
+            _ => (name.as_str(), ""),
+        };
+        let file = dest[at..]
+            .replace(NAME_PLACEHOLDER, name)
+            .replace(STEM_PLACEHOLDER, stem)
+            .replace(EXT_PLACEHOLDER, ext);
+        (&dest[..at], file)
+    } else {
+        (dest, name.clone())
+    };
+    let dir = dirs
+        .iter()
+        .fold(root.to_string(), |dir, part| join(&dir, part));
+    join(&dir, &file)
+}
+
+/// The entries a pattern or directory source of `info` stands for, each
+/// keeping its path below the directories the pattern starts with. `None`
+/// when the source is a single file, remote, or in an archive, which
+/// `archive::expand` handles.
+///
+/// The destination of `info`, and that of each of its `outputs`, is taken
+/// as a directory, unless its file name has `{name}`, `{stem}` or `{ext}` in
+/// it, which are filled in for each image. Relative paths are looked for under `base` when it is given.
+pub fn expand(info: &ImageInfo, base: Option<&Path>) -> Option<Result<Expanded, GlobError>> {
+    let source = info.source();
+    if archive::split(source).is_some() || storage::is_remote(source) {
+        return None;
+    }
+    let on_disk = |path: &str| match base {
+        Some(base) => base.join(path),
+        None => PathBuf::from(path),
+    };
+    let pattern = if is_pattern(source) {
+        source.to_string()
+    } else if on_disk(source).is_dir() {
+        format!("{}/**/*", source.trim_end_matches('/'))
+    } else {
+        return None;
+    };
+
+    let components: Vec<&str> = pattern.split('/').collect();
+    let literal = components
+        .iter()
+        .position(|component| is_pattern(component))
+        .unwrap_or(components.len());
+    let root = match components[..literal].join("/") {
+        root if root.is_empty() && pattern.starts_with('/') => "/".to_string(),
+        root => root,
+    };
+    let below = &components[literal..];
+    let walked = on_disk(if root.is_empty() { "." } else { &root });
+    let depth = if below.contains(&"**") {
+        usize::MAX
+    } else {
+        below.len()
+    };
+    let mut files = Vec::new();
+    if let Err(source) = walk(&walked, depth, &mut Vec::new(), &mut files) {
+        return Some(Err(GlobError {
+            pattern,
+            path: walked,
+            source,
+        }));
+    }
+
+    let mut entries = Vec::new();
+    let mut skipped = Vec::new();
+    for relative in files {
+        let parts: Vec<&str> = relative.iter().map(String::as_str).collect();
+        if !matches_path(below, &parts) {
+            continue;
+        }
+        let path = join(&root, &parts.join("/"));
+        if !archive::is_image(&path) {
+            skipped.push(path);
+            continue;
+        }
+        let mut entry = info.clone();
+        entry.destination = destination(info.destination(), &relative);
+        for output in &mut entry.outputs {
+            output.destination = destination(&output.destination, &relative);
+        }
+        entry.source = path;
+        entries.push(entry);
+    }
+    Some(Ok(Expanded { entries, skipped }))
+}
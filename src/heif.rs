@@ -0,0 +1,196 @@
+//! HEIF/HEIC sources, decoded through libheif when the `heif` feature is enabled.
+//!
+//! Sources are recognised by their `ftyp` brand in either case, so builds
+//! without the feature can say what is missing instead of failing generically.
+
+use std::fs::File;
+use std::io::{self, Read};
+
+use image::DynamicImage;
+
+#[cfg(not(feature = "heif"))]
+use crate::format::FormatNotCompiled;
+
+/// Major brands of HEIF still images and sequences.
+const BRANDS: &[&[u8; 4]] = &[
+    b"heic", b"heix", b"heim", b"heis", b"hevc", b"hevx", b"mif1", b"msf1",
+];
+
+/// Whether `data` starts with an ISO base media `ftyp` box of a HEIF brand.
+pub(crate) fn is_heif(data: &[u8]) -> bool {
+    data.len() >= 12 && &data[4..8] == b"ftyp" && BRANDS.iter().any(|brand| &data[8..12] == *brand)
+}
+
+pub(crate) fn is_heif_file(src: &str) -> io::Result<bool> {
+    let mut header = [0; 12];
+    match File::open(src)?.read_exact(&mut header) {
+        Ok(()) => Ok(is_heif(&header)),
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(not(feature = "heif"))]
+pub(crate) fn dimensions(
+    _data: &[u8],
+) -> Result<(u32, u32), Box<dyn std::error::Error + Send + Sync>> {
+    Err(FormatNotCompiled {
+        format: "HEIC",
+        feature: "heif",
+    }
+    .into())
+}
+
+#[cfg(not(feature = "heif"))]
+pub(crate) fn decode(
+    _data: &[u8],
+) -> Result<DynamicImage, Box<dyn std::error::Error + Send + Sync>> {
+    Err(FormatNotCompiled {
+        format: "HEIC",
+        feature: "heif",
+    }
+    .into())
+}
+
+/// Width and height of the primary image, read without decoding it.
+#[cfg(feature = "heif")]
+pub(crate) fn dimensions(
+    data: &[u8],
+) -> Result<(u32, u32), Box<dyn std::error::Error + Send + Sync>> {
+    use image::error::{DecodingError, ImageFormatHint};
+    use image::ImageError;
+    use libheif_rs::HeifContext;
+
+    let decoding_error = |e: libheif_rs::HeifError| {
+        ImageError::Decoding(DecodingError::new(ImageFormatHint::Name("HEIC".into()), e))
+    };
+    let context = HeifContext::read_from_bytes(data).map_err(decoding_error)?;
+    let handle = context.primary_image_handle().map_err(decoding_error)?;
+    Ok((handle.width(), handle.height()))
+}
+
+/// Decodes the primary image, with the rotation and mirroring stored in the
+/// container already applied.
+#[cfg(feature = "heif")]
+pub(crate) fn decode(
+    data: &[u8],
+) -> Result<DynamicImage, Box<dyn std::error::Error + Send + Sync>> {
+    use image::error::{DecodingError, ImageFormatHint, UnsupportedError, UnsupportedErrorKind};
+    use image::{ImageBuffer, ImageError};
+    use libheif_rs::{ColorSpace, HeifContext, LibHeif, RgbChroma};
+
+    let hint = || ImageFormatHint::Name("HEIC".into());
+    let decoding_error =
+        |e: libheif_rs::HeifError| ImageError::Decoding(DecodingError::new(hint(), e));
+
+    let context = HeifContext::read_from_bytes(data).map_err(decoding_error)?;
+    let handle = context.primary_image_handle().map_err(decoding_error)?;
+    let (chroma, channels) = if handle.has_alpha_channel() {
+        (RgbChroma::Rgba, 4)
+    } else {
+        (RgbChroma::Rgb, 3)
+    };
+    // libheif applies the irot/imir transformations unless told otherwise
+    let image = LibHeif::new()
+        .decode(&handle, ColorSpace::Rgb(chroma), None)
+        .map_err(decoding_error)?;
+
+    let planes = image.planes();
+    let plane = planes.interleaved.ok_or_else(|| {
+        ImageError::Unsupported(UnsupportedError::from_format_and_kind(
+            hint(),
+            UnsupportedErrorKind::GenericFeature("non-interleaved output".into()),
+        ))
+    })?;
+    // Rows are padded up to the stride
+    let row = plane.width as usize * channels;
+    let pixels: Vec<u8> = plane
+        .data
+        .chunks(plane.stride)
+        .take(plane.height as usize)
+        .flat_map(|line| &line[..row])
+        .copied()
+        .collect();
+
+    let invalid = || {
+        ImageError::Unsupported(UnsupportedError::from_format_and_kind(
+            hint(),
+            UnsupportedErrorKind::GenericFeature("truncated image planes".into()),
+        ))
+    };
+    Ok(if channels == 4 {
+        DynamicImage::ImageRgba8(
+            ImageBuffer::from_raw(plane.width, plane.height, pixels).ok_or_else(invalid)?,
+        )
+    } else {
+        DynamicImage::ImageRgb8(
+            ImageBuffer::from_raw(plane.width, plane.height, pixels).ok_or_else(invalid)?,
+        )
+    })
+}
+
+/// Encodes `img` as AVIF, at `quality` from 0 to 100 and `speed` from 0 to
+/// 10 when they are set, and as libheif picks otherwise.
+#[cfg(feature = "avif")]
+pub(crate) fn encode_avif(
+    img: &DynamicImage,
+    quality: Option<u8>,
+    speed: Option<u8>,
+) -> image::ImageResult<Vec<u8>> {
+    use image::error::{EncodingError, ImageFormatHint};
+    use image::{GenericImageView, ImageError, ImageFormat};
+    use libheif_rs::{
+        Channel, ColorSpace, CompressionFormat, EncoderParameterValue, EncoderQuality, HeifContext,
+        Image, LibHeif, RgbChroma,
+    };
+
+    let encoding_error = |e: Box<dyn std::error::Error + Send + Sync>| {
+        ImageError::Encoding(EncodingError::new(
+            ImageFormatHint::Exact(ImageFormat::Avif),
+            e,
+        ))
+    };
+    let heif_error = |e: libheif_rs::HeifError| encoding_error(e.into());
+
+    let (width, height) = img.dimensions();
+    let (chroma, channels, pixels) = if img.color().has_alpha() {
+        (RgbChroma::Rgba, 4, img.to_rgba8().into_raw())
+    } else {
+        (RgbChroma::Rgb, 3, img.to_rgb8().into_raw())
+    };
+    let mut image = Image::new(width, height, ColorSpace::Rgb(chroma)).map_err(heif_error)?;
+    image
+        .create_plane(Channel::Interleaved, width, height, 8)
+        .map_err(heif_error)?;
+    {
+        let plane = image
+            .planes_mut()
+            .interleaved
+            .ok_or_else(|| encoding_error("no interleaved plane to write into".into()))?;
+        // Rows are padded up to the stride
+        let row = width as usize * channels;
+        for (line, pixels) in plane.data.chunks_mut(plane.stride).zip(pixels.chunks(row)) {
+            line[..row].copy_from_slice(pixels);
+        }
+    }
+
+    let lib = LibHeif::new();
+    let mut encoder = lib
+        .encoder_for_format(CompressionFormat::Av1)
+        .map_err(heif_error)?;
+    if let Some(quality) = quality {
+        encoder
+            .set_quality(EncoderQuality::Lossy(quality.min(100)))
+            .map_err(heif_error)?;
+    }
+    if let Some(speed) = speed {
+        encoder
+            .set_parameter_value("speed", EncoderParameterValue::Int(speed.min(10).into()))
+            .map_err(heif_error)?;
+    }
+    let mut context = HeifContext::new().map_err(heif_error)?;
+    context
+        .encode_image(&image, &mut encoder, None)
+        .map_err(heif_error)?;
+    context.write_to_bytes().map_err(heif_error)
+}
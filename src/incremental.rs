@@ -0,0 +1,189 @@
+//! Skipping entries whose destination is already up to date.
+//!
+//! Every destination written with `skipUpToDate` gets a hidden sidecar
+//! recording the settings it was made with, so changing the ratio or any
+//! other setting that affects the output still reprocesses it.
+
+use std::fs;
+use std::path::Path;
+use std::time::SystemTime;
+
+use serde::{Deserialize, Serialize};
+
+use crate::atomic;
+use crate::error::{Context, ExtendError, Stage};
+use crate::format::{OutputFormat, PngCompression};
+use crate::{
+    AnimationColors, AspectRatio, CropAlign, Flip, ImageInfo, Mode, Rounding, Style, Variant,
+};
+
+/// Everything that affects the output of an entry.
+#[derive(Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct Settings {
+    source: String,
+    aspect_ratio: AspectRatio,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    allowed_ratios: Option<Vec<(u32, u32)>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    tolerance: Option<f32>,
+    output_format: Option<OutputFormat>,
+    quality: Option<u8>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    png_compression: Option<PngCompression>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    avif_speed: Option<u8>,
+    animation_colors: AnimationColors,
+    first_frame_only: bool,
+    max_output_pixels: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    rotate: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    flip: Option<Flip>,
+    #[serde(default, skip_serializing_if = "Mode::is_extend")]
+    mode: Mode,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    crop_align: Option<CropAlign>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    crop_threshold: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    max_zoom: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    rounding: Option<Rounding>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    target_size: Option<(u32, u32)>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    allow_upscale: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    max_width: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    max_height: Option<u32>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    variants: Vec<Variant>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    outputs: Vec<Settings>,
+    /// Turning `sidecar` on has to write the analysis of outputs that are
+    /// otherwise up to date.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    sidecar: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    preview_width: Option<u32>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    keep_metadata: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    auto_orient: Option<bool>,
+    #[serde(flatten)]
+    style: Style,
+}
+
+impl Settings {
+    pub(crate) fn new(info: &ImageInfo) -> Self {
+        let options = &info.options;
+        Settings {
+            source: info.source.clone(),
+            aspect_ratio: info.aspect_ratio,
+            allowed_ratios: options
+                .allowed_ratios
+                .clone()
+                .filter(|_| info.aspect_ratio == AspectRatio::Auto),
+            tolerance: options.tolerance,
+            output_format: options.output_format,
+            quality: options.quality,
+            png_compression: options.png_compression,
+            avif_speed: options.avif_speed,
+            animation_colors: options.animation_colors,
+            first_frame_only: options.first_frame_only,
+            max_output_pixels: options.max_output_pixels,
+            rotate: options.rotate,
+            flip: options.flip,
+            mode: options.mode,
+            crop_align: options.crop_align,
+            crop_threshold: options.crop_threshold,
+            max_zoom: options.max_zoom,
+            rounding: options.rounding,
+            target_size: options.target_size,
+            allow_upscale: options.allow_upscale,
+            max_width: options.max_width,
+            max_height: options.max_height,
+            variants: info.variants.clone(),
+            outputs: info
+                .outputs
+                .iter()
+                .map(|output| Settings::new(&info.output_entry(output)))
+                .collect(),
+            sidecar: options.sidecar,
+            preview_width: options.preview_width,
+            keep_metadata: options.keep_metadata,
+            auto_orient: options.auto_orient,
+            style: options.style.clone(),
+        }
+    }
+
+    /// The same settings for another spelling of the source path.
+    pub(crate) fn with_source(mut self, source: &str) -> Self {
+        self.source = source.to_string();
+        self.outputs = self
+            .outputs
+            .into_iter()
+            .map(|output| output.with_source(source))
+            .collect();
+        self
+    }
+}
+
+/// `.<name>.ibe.json` next to the destination.
+fn sidecar_path(dest: &str) -> String {
+    let dest = Path::new(dest);
+    let name = dest
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    dest.with_file_name(format!(".{}.ibe.json", name))
+        .to_string_lossy()
+        .into_owned()
+}
+
+fn modified(path: &str) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|meta| meta.modified()).ok()
+}
+
+/// Whether the destination of `info` exists, is newer than the source and was
+/// made with the same settings. Anything that cannot be checked counts as
+/// out of date.
+pub(crate) fn is_up_to_date(info: &ImageInfo) -> bool {
+    let outputs = info.outputs();
+    let sidecar = sidecar_path(&outputs[0]);
+    // Sources in an archive count as changed whenever the archive did
+    let source =
+        crate::archive::split(&info.source).map_or(info.source.as_str(), |(archive, _)| archive);
+    let (source, recorded) = match (modified(source), modified(&sidecar)) {
+        (Some(source), Some(recorded)) => (source, recorded),
+        _ => return false,
+    };
+    if recorded < source {
+        return false;
+    }
+    for dest in &outputs {
+        #[cfg(feature = "tiff")]
+        let dest = dest.replace(crate::pages::PAGE_PLACEHOLDER, "1");
+        #[cfg(not(feature = "tiff"))]
+        let dest = dest.clone();
+        if modified(&dest).is_none_or(|dest| dest < source) {
+            return false;
+        }
+    }
+    fs::read(&sidecar)
+        .ok()
+        .and_then(|data| serde_json::from_slice::<Settings>(&data).ok())
+        .is_some_and(|settings| settings == Settings::new(info))
+}
+
+/// Writes the sidecar for a destination that was just written, next to the
+/// first variant for entries with variants.
+pub(crate) fn record(info: &ImageInfo) -> Result<(), ExtendError> {
+    let sidecar = sidecar_path(&info.outputs()[0]);
+    let data = serde_json::to_vec(&Settings::new(info)).context(Stage::Write, &sidecar)?;
+    atomic::write(&sidecar, |temp| {
+        fs::write(temp, &data).context(Stage::Write, &sidecar)
+    })
+}
@@ -1,55 +1,1077 @@
-use serde::Deserialize;
+use std::borrow::Cow;
+use std::convert::TryFrom;
+use std::io::Cursor;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
+use serde::{Deserialize, Serialize};
+
+use image::error::{ImageError, ImageFormatHint};
 use image::io::Reader as ImageReader;
-use image::{imageops, DynamicImage, GenericImage, GenericImageView};
+use image::{imageops, ColorType, DynamicImage, GenericImageView, ImageBuffer, ImageFormat};
+
+#[cfg(feature = "gif")]
+mod animation;
+pub mod archive;
+mod atomic;
+mod bands;
+pub mod batch;
+pub mod cache;
+mod caption;
+pub mod color;
+pub mod config;
+pub mod contact_sheet;
+mod density;
+mod depth;
+pub mod error;
+pub mod fill;
+pub mod format;
+pub mod geometry;
+pub mod glob;
+mod heif;
+mod incremental;
+#[cfg(feature = "tracing")]
+pub mod logging;
+pub mod manifest;
+mod metadata;
+#[cfg(feature = "tokio")]
+mod nonblocking;
+mod padding;
+#[cfg(feature = "tiff")]
+mod pages;
+mod palette;
+pub mod pipeline;
+mod preview;
+pub mod report;
+mod resample;
+pub mod retry;
+mod saliency;
+mod seams;
+mod smear;
+mod stages;
+pub mod storage;
+pub mod style;
+mod timeout;
+pub mod validate;
+mod watermark;
+
+use error::{BoxError, Context, Stage};
+use report::{Analysis, ErrorInfo};
+
+pub use error::{
+    CanvasTooLarge, CorruptSource, ErrorKind, ExtendError, InternalPanic, SourceTooLarge, TimedOut,
+    WatermarkError,
+};
+pub use fill::{BackgroundFill, FillContext};
+pub use format::{FormatNotCompiled, OutputFormat, PngCompression};
+pub use geometry::Orientation;
+/// The version of `image` that `extend_image` takes images of.
+pub use image;
+#[cfg(feature = "tokio")]
+pub use nonblocking::compile_image_async;
+pub use report::{
+    ColorSource, EntryResult, ImageReport, OutputPlan, OutputReport, Placement, Plan, SkipReason,
+    Timings, Trim, VariantReport,
+};
+pub use stages::StageLimits;
+pub use style::{Background, BackgroundAlpha, ColorSampling, Style};
+
+#[cfg(feature = "ffi")]
+pub mod ffi;
+#[cfg(feature = "python")]
+mod python;
+#[cfg(feature = "server")]
+pub mod server;
+#[cfg(any(test, feature = "test-util"))]
+pub mod test_util;
+#[cfg(feature = "wasm")]
+mod wasm;
+#[cfg(feature = "watch")]
+pub mod watch;
 
 #[derive(Deserialize, Clone)]
-#[serde(rename_all = "camelCase")]
+#[serde(try_from = "serde_json::Value")]
 pub struct ImageInfo {
     source: String,
+    /// Left out when `batch::map_to_dir` picks it instead.
     destination: String,
-    aspect_ratio: (u32, u32),
+    aspect_ratio: AspectRatio,
+    /// Further outputs of the same source with other backgrounds, written
+    /// instead of the destination itself.
+    variants: Vec<Variant>,
+    /// Outputs of the same source with ratios and settings of their own,
+    /// written instead of the destination itself.
+    outputs: Vec<Output>,
+    options: Options,
+    /// Identical entries of the job list folded into this one.
+    duplicates: u32,
 }
 
-#[derive(Copy, Clone)]
-pub enum Orientation {
-    Landscape,
-    Portrait,
+/// An entry as the job list has it, before its `outputs` are resolved.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Entry {
+    source: String,
+    #[serde(default)]
+    destination: String,
+    aspect_ratio: Option<AspectRatio>,
+    #[serde(default)]
+    variants: Vec<Variant>,
+    /// The fields other than `ENTRY_FIELDS`, which `Options` refuses when
+    /// it has no such field.
+    #[serde(skip)]
+    options: Options,
+}
+
+/// Fields of an entry that are not among its options.
+const ENTRY_FIELDS: &[&str] = &["source", "destination", "aspectRatio", "variants"];
+
+impl Entry {
+    fn from_value(value: serde_json::Value) -> Result<Self, String> {
+        let mut options = match value {
+            serde_json::Value::Object(fields) => fields,
+            _ => return Err("expected the fields of an entry".into()),
+        };
+        let fields: serde_json::Map<String, serde_json::Value> = ENTRY_FIELDS
+            .iter()
+            .filter_map(|field| options.remove_entry(*field))
+            .collect();
+        let mut entry: Entry = serde_json::from_value(fields.into()).map_err(|e| e.to_string())?;
+        entry.options = serde_json::from_value(options.into()).map_err(|e| e.to_string())?;
+        Ok(entry)
+    }
+}
+
+/// Fields of an entry that its outputs cannot set.
+const ENTRY_ONLY: &[&str] = &["source", "variants", "outputs"];
+
+impl TryFrom<serde_json::Value> for ImageInfo {
+    type Error = String;
+
+    /// Each of `outputs` takes the fields of the entry it sets none of,
+    /// other than the destination.
+    fn try_from(mut value: serde_json::Value) -> Result<Self, String> {
+        let outputs = match value
+            .as_object_mut()
+            .and_then(|entry| entry.remove("outputs"))
+        {
+            None => Vec::new(),
+            Some(serde_json::Value::Array(outputs)) => outputs,
+            Some(_) => return Err("outputs must be a list".into()),
+        };
+        let outputs = outputs
+            .into_iter()
+            .enumerate()
+            .map(|(index, output)| {
+                let invalid = |e: String| format!("output {}: {}", index, e);
+                let fields = match output {
+                    serde_json::Value::Object(fields) => fields,
+                    _ => return Err(invalid("expected the fields of an output".into())),
+                };
+                if let Some(field) = ENTRY_ONLY.iter().find(|field| fields.contains_key(**field)) {
+                    return Err(invalid(format!("{} is set by the entry", field)));
+                }
+                let mut merged = value.clone();
+                if let serde_json::Value::Object(entry) = &mut merged {
+                    entry.remove("destination");
+                    entry.remove("variants");
+                    entry.extend(fields);
+                }
+                let output = Entry::from_value(merged).map_err(invalid)?;
+                Ok(Output {
+                    destination: output.destination,
+                    aspect_ratio: output
+                        .aspect_ratio
+                        .ok_or_else(|| invalid("missing field `aspectRatio`".into()))?,
+                    options: output.options,
+                })
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        let entry = Entry::from_value(value)?;
+        let aspect_ratio = match (entry.aspect_ratio, outputs.first()) {
+            (Some(aspect_ratio), _) => aspect_ratio,
+            (None, Some(output)) => output.aspect_ratio,
+            (None, None) => return Err("missing field `aspectRatio`".into()),
+        };
+        Ok(ImageInfo {
+            source: entry.source,
+            destination: entry.destination,
+            aspect_ratio,
+            variants: entry.variants,
+            outputs,
+            options: entry.options,
+            duplicates: 0,
+        })
+    }
+}
+
+/// The `aspectRatio` of an entry.
+///
+/// Written as `[width, height]` in the job list, as a string that
+/// `parse_ratio` reads, or as `"auto"`. Fixed ratios are kept in lowest
+/// terms, so `[1920, 1080]` is `16:9`.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(try_from = "RatioValue", into = "RatioValue")]
+pub enum AspectRatio {
+    Fixed((u32, u32)),
+    /// Whichever of `allowedRatios` the source is closest to.
+    Auto,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum RatioValue {
+    Sides((u32, u32)),
+    Text(String),
+}
+
+impl TryFrom<RatioValue> for AspectRatio {
+    type Error = String;
+
+    fn try_from(value: RatioValue) -> Result<Self, Self::Error> {
+        match value {
+            RatioValue::Sides(sides) => Ok(AspectRatio::Fixed(reduce_ratio(sides))),
+            RatioValue::Text(text) if text == "auto" => Ok(AspectRatio::Auto),
+            RatioValue::Text(text) => parse_ratio(&text).map(AspectRatio::Fixed),
+        }
+    }
+}
+
+/// Parses a ratio written as `16:9` or `4/3`, or with decimals as `1.85:1`,
+/// in lowest terms.
+pub fn parse_ratio(text: &str) -> Result<(u32, u32), String> {
+    let invalid = || format!("invalid aspect ratio {:?}, expected W:H", text);
+    let (width, height) = text.split_once([':', '/']).ok_or_else(invalid)?;
+    let width = decimal(width.trim()).ok_or_else(invalid)?;
+    let height = decimal(height.trim()).ok_or_else(invalid)?;
+    // Both sides scaled by the same power of ten to whole numbers
+    let places = width.1.max(height.1);
+    let whole = |(digits, decimals): (u64, u32)| {
+        10u64
+            .checked_pow(places - decimals)
+            .and_then(|scale| digits.checked_mul(scale))
+            .and_then(|side| u32::try_from(side).ok())
+    };
+    let sides = (
+        whole(width).ok_or_else(invalid)?,
+        whole(height).ok_or_else(invalid)?,
+    );
+    if sides.0 == 0 || sides.1 == 0 {
+        return Err(format!(
+            "invalid aspect ratio {:?}, both sides must be nonzero",
+            text
+        ));
+    }
+    Ok(reduce_ratio(sides))
+}
+
+/// A decimal number as its digits and how many of them follow the point.
+fn decimal(text: &str) -> Option<(u64, u32)> {
+    let (whole, fraction) = text.split_once('.').unwrap_or((text, ""));
+    let digits = format!("{}{}", whole, fraction);
+    if digits.is_empty() || !digits.bytes().all(|byte| byte.is_ascii_digit()) {
+        return None;
+    }
+    Some((digits.parse().ok()?, u32::try_from(fraction.len()).ok()?))
+}
+
+/// `(width, height)` in lowest terms, as `(16, 9)` for `(1920, 1080)`. A
+/// side of zero is left alone for validation to report.
+pub fn reduce_ratio((width, height): (u32, u32)) -> (u32, u32) {
+    if width == 0 || height == 0 {
+        return (width, height);
+    }
+    let (mut a, mut b) = (width, height);
+    while b != 0 {
+        (a, b) = (b, a % b);
+    }
+    (width / a, height / a)
+}
+
+impl From<AspectRatio> for RatioValue {
+    fn from(ratio: AspectRatio) -> Self {
+        match ratio {
+            AspectRatio::Fixed(sides) => RatioValue::Sides(sides),
+            AspectRatio::Auto => RatioValue::Text("auto".to_string()),
+        }
+    }
+}
+
+/// What `aspectRatio: "auto"` picks from when `allowedRatios` is not set.
+pub const DEFAULT_ALLOWED_RATIOS: &[(u32, u32)] = &[(1, 1), (4, 5), (3, 2), (16, 9), (9, 16)];
+
+impl AspectRatio {
+    /// The ratio to extend a source of `(width, height)` to. `Auto` takes
+    /// the one of `allowed` nearest to the ratio of the source on a log
+    /// scale, so the least background is added, and the earlier of two that
+    /// are as near. `Auto` fails when nothing is `allowed`.
+    pub fn resolve(
+        self,
+        (width, height): (u32, u32),
+        allowed: &[(u32, u32)],
+    ) -> Result<(u32, u32), validate::Invalid> {
+        let allowed = match self {
+            AspectRatio::Fixed(sides) => return Ok(sides),
+            AspectRatio::Auto => allowed,
+        };
+        // How many times wider or taller than the source the ratio is, as
+        // an exact fraction
+        let distance = |&(ratio_width, ratio_height): &(u32, u32)| {
+            let wide = ratio_width as u64 * height as u64;
+            let tall = ratio_height as u64 * width as u64;
+            (wide.max(tall) as u128, wide.min(tall) as u128)
+        };
+        let (&first, rest) = allowed.split_first().ok_or_else(|| {
+            validate::Invalid::new(Stage::Composite, "allowedRatios", "must not be empty")
+        })?;
+        let mut best = first;
+        for ratio in rest {
+            let (numerator, denominator) = distance(ratio);
+            let (best_numerator, best_denominator) = distance(&best);
+            if numerator * best_denominator < best_numerator * denominator {
+                best = *ratio;
+            }
+        }
+        Ok(best)
+    }
+}
+
+/// Replaced by the name of the background in the destination of a variant.
+pub const MODE_PLACEHOLDER: &str = "{mode}";
+
+/// One output of an entry with `variants`, drawn with its own background.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Variant {
+    #[serde(alias = "backgroundMode")]
+    pub background: Background,
+    /// Defaults to the destination of the entry, with `{mode}` replaced by
+    /// the name of the background.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub destination: Option<String>,
+}
+
+impl Variant {
+    /// Where the variant is written for an entry destination of `template`.
+    pub fn destination(&self, template: &str) -> String {
+        self.destination
+            .clone()
+            .unwrap_or_else(|| template.replace(MODE_PLACEHOLDER, self.background.name()))
+    }
+}
+
+/// One output of an entry with `outputs`, extended from the same decode of
+/// the source to a ratio and with settings of its own.
+///
+/// How the source is decoded, as `autoOrient` and `keepMetadata` have it,
+/// is up to the entry.
+#[derive(Clone)]
+pub struct Output {
+    pub destination: String,
+    pub aspect_ratio: AspectRatio,
+    pub options: Options,
+}
+
+/// Per-entry settings that apply to both the file and in-memory paths.
+///
+/// Fields it does not have are refused, so that misspelt settings do not
+/// pass unnoticed.
+#[derive(Deserialize, Clone, Default)]
+#[serde(rename_all = "camelCase", default, deny_unknown_fields)]
+pub struct Options {
+    /// Overrides the format implied by the destination extension, also
+    /// read as `format`.
+    #[serde(alias = "format")]
+    pub output_format: Option<OutputFormat>,
+    /// Encoder quality from 0 to 100 for JPEG, AVIF and animated WebP
+    /// output. JPEG defaults to 75, and AVIF to what libheif picks.
+    pub quality: Option<u8>,
+    /// Compression of PNG output. Defaults to `fast`.
+    pub png_compression: Option<PngCompression>,
+    /// How fast AVIF output is encoded, from 0, the slowest and smallest, to
+    /// 10. Defaults to what libheif picks.
+    pub avif_speed: Option<u8>,
+    /// How hard the lossless JPEG XL encoder looks for a smaller output,
+    /// from 0 to 127. Defaults to 4.
+    pub jxl_effort: Option<u8>,
+    pub animation_colors: AnimationColors,
+    /// Write only the first frame of an animated source, or the first page of
+    /// a multi-page one, instead of failing when the output cannot hold them all.
+    pub first_frame_only: bool,
+    /// Allow the destination to be the source, which is then replaced atomically.
+    pub in_place: bool,
+    /// Largest canvas allowed, in pixels. Defaults to `DEFAULT_MAX_OUTPUT_PIXELS`.
+    #[serde(rename = "maxOutputPixels")]
+    pub max_output_pixels: Option<u64>,
+    /// Largest source allowed, in pixels, checked against its header before
+    /// it is decoded so that huge sources fail without taking the memory.
+    /// No limit by default.
+    #[serde(rename = "maxSourcePixels")]
+    pub max_source_pixels: Option<u64>,
+    /// Seconds the entry may take before it is abandoned.
+    pub timeout: Option<u64>,
+    /// Extra attempts after a transient I/O error while reading or writing.
+    pub retries: Option<u32>,
+    /// Delay before the first retry, doubled for every one after it.
+    /// Defaults to `retry::DEFAULT_BACKOFF_MS`.
+    pub retry_backoff_ms: Option<u64>,
+    /// Skip the entry when its destination is newer than the source and was
+    /// made with the same settings.
+    pub skip_up_to_date: bool,
+    /// What happens to destinations that exist already. Defaults to
+    /// replacing them.
+    pub overwrite: Option<Overwrite>,
+    /// Turns the decoded source clockwise by 90, 180 or 270 degrees before
+    /// anything else, for scans that arrive sideways.
+    pub rotate: Option<u32>,
+    /// Mirrors the decoded source, after `rotate`.
+    pub flip: Option<Flip>,
+    /// Whether the source is extended, cropped or seam carved to the ratio.
+    #[serde(alias = "fitMode")]
+    pub mode: Mode,
+    /// Which part of the source `mode: crop` keeps. Defaults to the middle.
+    pub crop_align: Option<CropAlign>,
+    /// Largest share of the source, from 0 to 1, that `mode: extendOrCrop`
+    /// crops away rather than extending. Defaults to
+    /// `DEFAULT_CROP_THRESHOLD`.
+    pub crop_threshold: Option<f32>,
+    /// Largest share of the long side of the source, from 0 to below 1, that
+    /// `mode: zoomToFill` crops off towards the ratio before extending the
+    /// rest. Defaults to `DEFAULT_MAX_ZOOM`.
+    pub max_zoom: Option<f32>,
+    /// How `mode: extend` gets the sides of the source to a multiple of the
+    /// ratio. Defaults to growing the canvas, which keeps every pixel.
+    pub rounding: Option<Rounding>,
+    /// Exact size of the output, as `[width, height]`, in place of the
+    /// aspect ratio of the entry for `mode: extend`. The source is scaled to
+    /// fit inside it and the background fills the rest.
+    pub target_size: Option<(u32, u32)>,
+    /// Scale sources smaller than `targetSize` up to fit it, instead of
+    /// placing them at their own size.
+    pub allow_upscale: bool,
+    /// Largest width of the output. Anything wider is scaled down to it once
+    /// extended, keeping its ratio, with the `resizeFilter`.
+    pub max_width: Option<u32>,
+    /// Largest height of the output, as `maxWidth` is for the width.
+    pub max_height: Option<u32>,
+    /// Ratios `aspectRatio: "auto"` picks from, `DEFAULT_ALLOWED_RATIOS`
+    /// when not set.
+    pub allowed_ratios: Option<Vec<(u32, u32)>>,
+    /// How far off the aspect ratio a source may be, as a fraction of it,
+    /// and still be left as it is.
+    pub tolerance: Option<f32>,
+    /// Write `<destination>.json` next to each output, describing where the
+    /// image was placed and the colors around it.
+    pub sidecar: bool,
+    /// Also write a JPEG at most this many pixels wide at
+    /// `<destination>.preview.jpg`, scaled down from the same canvas as the
+    /// output. Not written for animations or multi-page TIFFs.
+    pub preview_width: Option<u32>,
+    /// Copy the EXIF data and the ICC profile of a JPEG or PNG source into
+    /// JPEG and PNG outputs. The orientation in the EXIF data is reset to
+    /// upright, as the output is written the way the source was decoded.
+    pub keep_metadata: bool,
+    /// Turn JPEG and PNG sources upright as their EXIF orientation says,
+    /// before anything else. Defaults to true.
+    pub auto_orient: Option<bool>,
+    #[serde(flatten)]
+    pub style: Style,
+}
+
+/// Canvases above 250 megapixels are most likely a mistyped ratio.
+pub const DEFAULT_MAX_OUTPUT_PIXELS: u64 = 250_000_000;
+
+/// A tenth of a photo is usually sky or floor that nobody misses.
+pub const DEFAULT_CROP_THRESHOLD: f32 = 0.1;
+
+/// Small enough that the crop goes unnoticed.
+pub const DEFAULT_MAX_ZOOM: f32 = 0.05;
+
+impl Options {
+    pub(crate) fn max_output_pixels(&self) -> u64 {
+        self.max_output_pixels.unwrap_or(DEFAULT_MAX_OUTPUT_PIXELS)
+    }
+
+    /// `mode`, with `extendOrCrop` settled into cropping or extending a
+    /// source of `dimensions` by the share of it the crop would remove.
+    pub(crate) fn mode_for(&self, (width, height): (u32, u32), aspect_ratio: (u32, u32)) -> Mode {
+        if self.mode != Mode::ExtendOrCrop {
+            return self.mode;
+        }
+        let multiplier = (width / aspect_ratio.0).min(height / aspect_ratio.1) as f64;
+        let kept = (aspect_ratio.0 as f64 * multiplier) * (aspect_ratio.1 as f64 * multiplier)
+            / (width as f64 * height as f64);
+        let threshold = self.crop_threshold.unwrap_or(DEFAULT_CROP_THRESHOLD) as f64;
+        if multiplier > 0.0 && 1.0 - kept <= threshold {
+            Mode::Crop
+        } else {
+            Mode::Extend
+        }
+    }
+
+    pub(crate) fn auto_orient(&self) -> bool {
+        self.auto_orient.unwrap_or(true)
+    }
+
+    pub(crate) fn overwrite(&self) -> Overwrite {
+        self.overwrite.unwrap_or_default()
+    }
+
+    /// Whether `tolerance` lets a source of `dimensions` count as having
+    /// `aspect_ratio`, which it does not exactly. An extended `targetSize`
+    /// is always met exactly.
+    pub(crate) fn tolerates(&self, (width, height): (u32, u32), aspect_ratio: (u32, u32)) -> bool {
+        let tolerance = match self.tolerance {
+            _ if self.target_size.is_some() && self.mode.is_extend() => return false,
+            Some(tolerance) if tolerance > 0.0 => tolerance as f64,
+            _ => return false,
+        };
+        let off = (width as f64 * aspect_ratio.1 as f64) / (height as f64 * aspect_ratio.0 as f64);
+        !geometry::fits_exactly((width, height), aspect_ratio) && (off - 1.0).abs() <= tolerance
+    }
+
+    /// Size an output of `size` is scaled down to for `maxWidth` and
+    /// `maxHeight`. `None` when it fits.
+    pub(crate) fn max_size(&self, (width, height): (u32, u32)) -> Option<(u32, u32)> {
+        let scale = |max: Option<u32>, side: u32| max.map_or(1.0, |max| max as f64 / side as f64);
+        let scale = scale(self.max_width, width).min(scale(self.max_height, height));
+        let side = |side: u32| ((side as f64 * scale).round() as u32).max(1);
+        (scale < 1.0).then(|| (side(width), side(height)))
+    }
+
+    /// `canvas` scaled down to fit `maxWidth` and `maxHeight`.
+    pub(crate) fn shrink<T: depth::Channel>(
+        &self,
+        canvas: ImageBuffer<image::Rgba<T>, Vec<T>>,
+    ) -> ImageBuffer<image::Rgba<T>, Vec<T>> {
+        match self.max_size(canvas.dimensions()) {
+            Some((width, height)) => resample::resize(
+                &canvas,
+                width,
+                height,
+                self.style.filter(imageops::FilterType::Lanczos3),
+            ),
+            None => canvas,
+        }
+    }
+
+    pub(crate) fn allowed_ratios(&self) -> &[(u32, u32)] {
+        self.allowed_ratios
+            .as_deref()
+            .unwrap_or(DEFAULT_ALLOWED_RATIOS)
+    }
+
+    /// Whether `rotate` or `flip` change the source, which then cannot be
+    /// copied through even when it fits the ratio.
+    pub(crate) fn reorients(&self) -> bool {
+        self.rotate.is_some_and(|angle| angle != 0) || self.flip.is_some()
+    }
+
+    /// Dimensions of a source of `(width, height)` once `rotate` is applied.
+    pub(crate) fn oriented(&self, (width, height): (u32, u32)) -> (u32, u32) {
+        match self.rotate {
+            Some(90) | Some(270) => (height, width),
+            _ => (width, height),
+        }
+    }
+
+    /// The decoded source turned and mirrored as `rotate` and `flip` ask.
+    pub(crate) fn orient(&self, img: DynamicImage) -> DynamicImage {
+        let img = match self.rotate {
+            Some(90) => img.rotate90(),
+            Some(180) => img.rotate180(),
+            Some(270) => img.rotate270(),
+            _ => img,
+        };
+        match self.flip {
+            Some(Flip::Horizontal) => img.fliph(),
+            Some(Flip::Vertical) => img.flipv(),
+            None => img,
+        }
+    }
+}
+
+/// Direction to mirror the source in.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum Flip {
+    /// Left to right.
+    Horizontal,
+    /// Top to bottom.
+    Vertical,
+}
+
+/// What is done to a source that does not have the aspect ratio.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum Mode {
+    /// Put it on a larger canvas with a background around it.
+    #[default]
+    Extend,
+    /// Cut it down to the largest part that has the ratio.
+    Crop,
+    /// Widen the image itself to the canvas by repeating its plainest
+    /// seams, with no background around it. Slow on large images, and not
+    /// for animations.
+    SeamCarve,
+    /// Crop sources that lose no more than `cropThreshold` of themselves to
+    /// the ratio, and extend the rest.
+    ExtendOrCrop,
+    /// Crop up to `maxZoom` of the long side off the middle of the source
+    /// towards the ratio, and extend it the rest of the way.
+    ZoomToFill,
+}
+
+impl Mode {
+    pub(crate) fn is_extend(&self) -> bool {
+        *self == Mode::Extend
+    }
+}
+
+/// What happens to a destination that exists already. Outputs are only
+/// ever put in place once complete, whichever is chosen.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum Overwrite {
+    /// Replace it.
+    #[default]
+    Replace,
+    /// Fail the entry.
+    Error,
+    /// Leave the entry alone. Callers skip it when `keeps_existing` says so,
+    /// and it fails if it is processed anyway.
+    Skip,
+    /// Write the entry to the first of `photo-1.png`, `photo-2.png` and so
+    /// on that none of its outputs exist under.
+    Rename,
+}
+
+impl Overwrite {
+    pub fn name(self) -> &'static str {
+        match self {
+            Overwrite::Replace => "replace",
+            Overwrite::Error => "error",
+            Overwrite::Skip => "skip",
+            Overwrite::Rename => "rename",
+        }
+    }
+}
+
+impl std::str::FromStr for Overwrite {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        serde_json::from_value(serde_json::Value::String(name.to_string())).map_err(|_| {
+            format!(
+                "unknown overwrite policy {}, expected replace, error, skip or rename",
+                name
+            )
+        })
+    }
+}
+
+/// Part of the source kept along the side that is cropped.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum CropAlign {
+    /// The top or the left.
+    Start,
+    #[default]
+    Center,
+    /// The bottom or the right.
+    End,
+}
+
+/// How the sides of an extended source are brought to a multiple of the ratio.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum Rounding {
+    /// Round the canvas up to the next size that holds the whole source.
+    #[default]
+    Up,
+    /// Crop up to one pixel less than the ratio off the source, half on
+    /// either side, so that it divides evenly.
+    Down,
+}
+
+/// How background colors are chosen for animated sources.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum AnimationColors {
+    /// Sample the first frame and reuse its colors, so the background never flickers.
+    #[default]
+    FirstFrame,
+    /// Sample every frame on its own.
+    PerFrame,
+}
+
+/// How deep the strip sampled along an edge is, for a side of `length`
+/// across it.
+fn calculate_edge_length(length: u32, sample: Option<style::Length>) -> u32 {
+    let edge = match sample {
+        Some(sample) => sample.resolve(length),
+        None => (length as f32 * 0.05).floor() as u32,
+    };
+    // Images under 20 pixels across still have an edge to sample, and no
+    // strip is deeper than the image
+    edge.clamp(1, length)
+}
+
+/// Fails sources of `dimensions` that have more pixels than
+/// `maxSourcePixels` of `options`.
+pub(crate) fn check_source_pixels(
+    (width, height): (u32, u32),
+    options: &Options,
+) -> Result<(), SourceTooLarge> {
+    match options.max_source_pixels {
+        Some(limit) if width as u64 * height as u64 > limit => Err(SourceTooLarge {
+            width,
+            height,
+            limit,
+        }),
+        _ => Ok(()),
+    }
+}
+
+/// Fails sources that decode to no pixels, which have no edges to extend.
+pub(crate) fn check_dimensions(
+    (width, height): (u32, u32),
+    path: Option<&str>,
+) -> Result<(), CorruptSource> {
+    if width == 0 || height == 0 {
+        return Err(CorruptSource::new(
+            path,
+            None,
+            format!("decodes to {}x{} pixels", width, height),
+        ));
+    }
+    Ok(())
+}
+
+/// What each channel value counts as in an average: itself, or its linear
+/// light from 0 to 1 with `linear`.
+fn channel_levels(linear: bool) -> [f64; 256] {
+    std::array::from_fn(|channel| match linear {
+        true => color::to_linear(channel as u8) as f64,
+        false => channel as f64,
+    })
+}
+
+/// The channel value for an average of `channel_levels`.
+fn from_level(level: f64, linear: bool) -> u8 {
+    match linear {
+        true => color::from_linear(level as f32),
+        false => level.round() as u8,
+    }
+}
+
+/// The average of every channel of the pixels of `img`, summed directly
+/// rather than by scaling the image down, the color in linear light with
+/// `linear`.
+fn average_color(img: &DynamicImage, linear: bool) -> image::Rgba<u8> {
+    let levels = channel_levels(linear);
+    let mut sums = [0f64; 4];
+    let mut pixels = 0u64;
+    for (_, _, pixel) in img.pixels() {
+        for (sum, &channel) in sums[..3].iter_mut().zip(&pixel.0[..3]) {
+            *sum += levels[channel as usize];
+        }
+        sums[3] += pixel.0[3] as f64;
+        pixels += 1;
+    }
+    let pixels = pixels.max(1) as f64;
+    image::Rgba([
+        from_level(sums[0] / pixels, linear),
+        from_level(sums[1] / pixels, linear),
+        from_level(sums[2] / pixels, linear),
+        (sums[3] / pixels).round() as u8,
+    ])
+}
+
+/// Average opacity, from 0 to 1, an edge needs for its color to be used;
+/// below it the color mostly comes from what lies under transparent pixels.
+const MIN_EDGE_COVERAGE: f32 = 0.01;
+
+/// The average of the pixels of `img` weighted by their alpha, which leaves
+/// out the colors under transparent pixels, with their average opacity from
+/// 0 to 1. No color when no pixel is visible at all.
+fn weighted_average(img: &DynamicImage, linear: bool) -> (Option<image::Rgba<u8>>, f32) {
+    let levels = channel_levels(linear);
+    let mut sums = [0f64; 3];
+    let mut weight = 0u64;
+    let mut pixels = 0u64;
+    for (_, _, pixel) in img.pixels() {
+        let alpha = pixel.0[3] as u64;
+        for (sum, &channel) in sums.iter_mut().zip(&pixel.0[..3]) {
+            *sum += levels[channel as usize] * alpha as f64;
+        }
+        weight += alpha;
+        pixels += 1;
+    }
+    if weight == 0 {
+        return (None, 0.0);
+    }
+    let average = |sum: f64| from_level(sum / weight as f64, linear);
+    let color = image::Rgba([
+        average(sums[0]),
+        average(sums[1]),
+        average(sums[2]),
+        (weight as f64 / pixels as f64).round() as u8,
+    ]);
+    (Some(color), weight as f32 / (pixels * 255) as f32)
+}
+
+/// The middle value of each channel among the visible pixels of `img`.
+fn median_color(img: &DynamicImage) -> [u8; 3] {
+    let mut histograms = [[0u32; 256]; 3];
+    let mut visible = 0u32;
+    for (_, _, pixel) in img.pixels().filter(|(_, _, pixel)| pixel.0[3] > 0) {
+        for (histogram, &channel) in histograms.iter_mut().zip(&pixel.0[..3]) {
+            histogram[channel as usize] += 1;
+        }
+        visible += 1;
+    }
+    histograms.map(|histogram| {
+        let mut seen = 0;
+        histogram
+            .iter()
+            .position(|&count| {
+                seen += count;
+                2 * seen > visible
+            })
+            .unwrap_or(0) as u8
+    })
+}
+
+/// The average of the visible pixels of `img` of its most frequent color,
+/// each channel quantized to 16 levels, in linear light with `linear`. The
+/// first such color wins a tie.
+fn dominant_color(img: &DynamicImage, linear: bool) -> [u8; 3] {
+    let levels = channel_levels(linear);
+    // Count and channel sums for every quantized color
+    let mut buckets = vec![(0u32, [0f64; 3]); 1 << 12];
+    for (_, _, pixel) in img.pixels().filter(|(_, _, pixel)| pixel.0[3] > 0) {
+        let [r, g, b, _] = pixel.0;
+        let bucket =
+            &mut buckets[(r as usize >> 4) << 8 | (g as usize >> 4) << 4 | b as usize >> 4];
+        bucket.0 += 1;
+        for (sum, channel) in bucket.1.iter_mut().zip([r, g, b]) {
+            *sum += levels[channel as usize];
+        }
+    }
+    let (count, sums) = buckets.iter().fold(&(0, [0.0; 3]), |most, bucket| {
+        if bucket.0 > most.0 {
+            bucket
+        } else {
+            most
+        }
+    });
+    sums.map(|sum| from_level(sum / (*count).max(1) as f64, linear))
+}
+
+/// The color of an edge strip and how opaque it is on average. Strips with
+/// any transparency are weighed by alpha for the mean. The median and
+/// dominant colors leave out invisible pixels. Averages are in linear light
+/// with `linear`, which leaves the median as it is.
+fn edge_color(
+    strip: DynamicImage,
+    sampling: ColorSampling,
+    linear: bool,
+) -> (image::Rgba<u8>, f32) {
+    let (mut alpha, mut coverage) = (255, 1.0);
+    if strip.color().has_alpha() {
+        match weighted_average(&strip, linear) {
+            (Some(color), covered) if covered < 1.0 => {
+                if sampling == ColorSampling::Mean {
+                    return (color, covered);
+                }
+                (alpha, coverage) = (color.0[3], covered);
+            }
+            (None, _) => return (image::Rgba([0; 4]), 0.0),
+            _ => {}
+        }
+    }
+    let [r, g, b] = match sampling {
+        ColorSampling::Mean => return (average_color(&strip, linear), 1.0),
+        ColorSampling::Median => median_color(&strip),
+        ColorSampling::Dominant => dominant_color(&strip, linear),
+    };
+    (image::Rgba([r, g, b, alpha]), coverage)
 }
 
-fn div(base: u32, modulo: u32) -> (u32, u32) {
-    (base / modulo, base % modulo)
+/// Colors sampled from the two edges of an image.
+#[derive(Clone, Copy)]
+struct Edges {
+    colors: (image::Rgba<u8>, image::Rgba<u8>),
+    /// Which edges were next to fully transparent and took the fallback.
+    fallback: [bool; 2],
 }
 
-fn calculate_edge_length(length: u32) -> u32 {
-    (length as f32 * 0.05).floor() as u32
+/// `fallbackColor`, or the average of the opaque pixels of `img` standing
+/// for them, which is opaque itself.
+fn fallback_color(img: &DynamicImage, style: &Style) -> image::Rgba<u8> {
+    match style.fallback_color {
+        Some(color) => color.0,
+        None => match weighted_average(img, style.linear_color.unwrap_or(false)).0 {
+            Some(image::Rgba([r, g, b, _])) => image::Rgba([r, g, b, 255]),
+            None => style::DEFAULT_FALLBACK.0,
+        },
+    }
 }
 
-fn average_color(img: DynamicImage) -> image::Rgba<u8> {
-    let resized = img.resize_exact(1, 1, imageops::FilterType::Nearest);
-    resized.get_pixel(0, 0)
+/// Lays the canvas over `base`, leaving no pixel transparent.
+fn flatten(canvas: &mut image::RgbaImage, base: image::Rgba<u8>) {
+    let row_len = canvas.width() as usize * 4;
+    bands::rows(canvas, row_len, |_, rows| {
+        for pixel in rows.chunks_exact_mut(4) {
+            let alpha = pixel[3] as u32;
+            for (channel, &under) in pixel[..3].iter_mut().zip(&base.0[..3]) {
+                *channel =
+                    ((*channel as u32 * alpha + under as u32 * (255 - alpha) + 127) / 255) as u8;
+            }
+            pixel[3] = 255;
+        }
+    });
 }
 
 fn aggregate_edge_colors(
     base_img: &DynamicImage,
     orientation: Orientation,
-) -> (image::Rgba<u8>, image::Rgba<u8>) {
+    style: &Style,
+) -> Edges {
+    let sampling = style.color_sampling.unwrap_or_default();
+    let linear = style.linear_color.unwrap_or(false);
     let (width, height) = base_img.dimensions();
     let first_edge;
     let second_edge;
     if let Orientation::Landscape = orientation {
         // Image is wider than the desired aspect ratio
-        let edge_length = calculate_edge_length(height);
-        first_edge = average_color(base_img.crop_imm(0, 0, width, edge_length));
-        second_edge = average_color(base_img.crop_imm(0, height - edge_length, width, edge_length));
+        let edge_length = calculate_edge_length(height, style.edge_sample);
+        first_edge = edge_color(
+            base_img.crop_imm(0, 0, width, edge_length),
+            sampling,
+            linear,
+        );
+        second_edge = edge_color(
+            base_img.crop_imm(0, height - edge_length, width, edge_length),
+            sampling,
+            linear,
+        );
     } else {
         // Image is taller than the desired aspect ratio
-        let edge_length = calculate_edge_length(width);
-        first_edge = average_color(base_img.crop_imm(0, 0, edge_length, height));
-        second_edge = average_color(base_img.crop_imm(width - edge_length, 0, edge_length, height));
+        let edge_length = calculate_edge_length(width, style.edge_sample);
+        first_edge = edge_color(
+            base_img.crop_imm(0, 0, edge_length, height),
+            sampling,
+            linear,
+        );
+        second_edge = edge_color(
+            base_img.crop_imm(width - edge_length, 0, edge_length, height),
+            sampling,
+            linear,
+        );
     }
 
-    (first_edge, second_edge)
+    let fallback = [
+        first_edge.1 < MIN_EDGE_COVERAGE,
+        second_edge.1 < MIN_EDGE_COVERAGE,
+    ];
+    let fallback_color = || fallback_color(base_img, style);
+    let colors = match fallback {
+        [false, false] => (first_edge.0, second_edge.0),
+        [true, true] => (fallback_color(), fallback_color()),
+        [true, false] => (fallback_color(), second_edge.0),
+        [false, true] => (first_edge.0, fallback_color()),
+    };
+    Edges { colors, fallback }
+}
+
+/// Colors of the top left, top right, bottom left and bottom right corners
+/// of an image, each sampled from a block as deep as the edge strips in
+/// both directions.
+fn corner_colors(img: &DynamicImage, style: &Style) -> [image::Rgba<u8>; 4] {
+    let sampling = style.color_sampling.unwrap_or_default();
+    let linear = style.linear_color.unwrap_or(false);
+    let (width, height) = img.dimensions();
+    let (block_width, block_height) = (
+        calculate_edge_length(width, style.edge_sample),
+        calculate_edge_length(height, style.edge_sample),
+    );
+    let (right, bottom) = (width - block_width, height - block_height);
+    [(0, 0), (right, 0), (0, bottom), (right, bottom)].map(|(x, y)| {
+        let (color, coverage) = edge_color(
+            img.crop_imm(x, y, block_width, block_height),
+            sampling,
+            linear,
+        );
+        if coverage < MIN_EDGE_COVERAGE {
+            fallback_color(img, style)
+        } else {
+            color
+        }
+    })
+}
+
+/// The background colors for the sampled edge colors, adjusted as `style`
+/// asks, with the largest lightness change made by the contrast guard if any.
+///
+/// `firstColor` and `secondColor` take the place of the sampled colors,
+/// while the contrast guard still keeps to the edges of the image.
+fn pick_colors(
+    edges: (image::Rgba<u8>, image::Rgba<u8>),
+    style: &Style,
+) -> ((image::Rgba<u8>, image::Rgba<u8>), Option<f32>) {
+    if let Some(color) = style.background_color {
+        return ((color.0, color.0), None);
+    }
+    let first = style.first_color.map_or(edges.0, |color| color.0);
+    let second = style.second_color.map_or(edges.1, |color| color.0);
+    let (colors, changes) = match style.background {
+        Background::Complement | Background::Uniform => {
+            let fill = match style.background {
+                Background::Complement => style.complement(first, second),
+                _ => style.uniform(first, second),
+            };
+            // The one fill touches both edges
+            let (fill, change) =
+                style.guard_contrast(style.adjust_background(fill), &[edges.0, edges.1]);
+            ((fill, fill), [change, None])
+        }
+        // Only the report shows the colors of backgrounds made from the image
+        Background::Blur
+        | Background::Smear
+        | Background::Mirror
+        | Background::Replicate
+        | Background::Corners
+        | Background::Palette => (
+            (
+                style.adjust_background(first),
+                style.adjust_background(second),
+            ),
+            [None, None],
+        ),
+        Background::Split | Background::Gradient => {
+            // Forced colors are used exactly as given
+            let fill = |value, forced: Option<color::Color>, edge| match forced {
+                Some(_) => (value, None),
+                None => style.guard_contrast(style.adjust_background(value), &[edge]),
+            };
+            let (first_fill, first_change) = fill(first, style.first_color, edges.0);
+            let (second_fill, second_change) = fill(second, style.second_color, edges.1);
+            ((first_fill, second_fill), [first_change, second_change])
+        }
+    };
+    let change = changes
+        .iter()
+        .flatten()
+        .copied()
+        .fold(None, |largest: Option<f32>, change| match largest {
+            Some(largest) if largest.abs() >= change.abs() => Some(largest),
+            _ => Some(change),
+        });
+    (colors, change)
 }
 
 fn normalise_image(
@@ -65,99 +1087,2507 @@ fn normalise_image(
     )
 }
 
-fn calculate_canvas_dimensions(
-    (aspect_width, aspect_height): (u32, u32),
-    (width_multiplier, height_multiplier): (u32, u32),
+fn create_split_background(
+    canvas: &mut image::RgbaImage,
+    first_color: image::Rgba<u8>,
+    second_color: image::Rgba<u8>,
     orientation: Orientation,
-) -> (u32, u32) {
-    return if let Orientation::Landscape = orientation {
-        (
-            aspect_width * width_multiplier,
-            aspect_height * width_multiplier,
-        )
-    } else {
-        (
-            aspect_width * height_multiplier,
-            aspect_height * height_multiplier,
-        )
+    (middle_x, middle_y): (u32, u32),
+) {
+    let row_len = canvas.width() as usize * 4;
+    bands::rows(canvas, row_len, |first, rows| {
+        for (y, row) in rows.chunks_exact_mut(row_len).enumerate() {
+            let y = (first + y) as u32;
+            for (x, pixel) in row.chunks_exact_mut(4).enumerate() {
+                let first_half = match orientation {
+                    Orientation::Landscape => y < middle_y,
+                    Orientation::Portrait => (x as u32) < middle_x,
+                };
+                let color = if first_half {
+                    first_color
+                } else {
+                    second_color
+                };
+                pixel.copy_from_slice(&color.0);
+            }
+        }
+    });
+}
+
+/// The canvas of a layout with room on all four sides of the image, each
+/// band beside the image in the color of the edge it touches, given as top,
+/// bottom, left and right, and each corner halfway between its two edges.
+fn create_frame_background(
+    layout: &Layout,
+    [top, bottom, left, right]: [image::Rgba<u8>; 4],
+) -> image::RgbaImage {
+    let (width, height) = layout.canvas;
+    let (x, y) = layout.offset();
+    let (placed_width, placed_height) = layout.image;
+    let corner = |horizontal, vertical| style::mix(horizontal, vertical, 0.5);
+    // Rows above, beside and below the image
+    let row = |vertical: Option<image::Rgba<u8>>| -> Vec<u8> {
+        (0..width)
+            .flat_map(|column| {
+                let horizontal = if column < x {
+                    Some(left)
+                } else if column >= x + placed_width {
+                    Some(right)
+                } else {
+                    None
+                };
+                let color = match (horizontal, vertical) {
+                    (Some(horizontal), Some(vertical)) => corner(horizontal, vertical),
+                    (Some(color), None) | (None, Some(color)) => color,
+                    // Covered by the image
+                    (None, None) => top,
+                };
+                color.0
+            })
+            .collect()
     };
+    let (above, beside, below) = (row(Some(top)), row(None), row(Some(bottom)));
+    let mut canvas = image::RgbaImage::new(width, height);
+    let row_len = width as usize * 4;
+    bands::rows(&mut canvas, row_len, |first, rows| {
+        for (index, target) in rows.chunks_exact_mut(row_len).enumerate() {
+            let index = (first + index) as u32;
+            let source = if index < y {
+                &above
+            } else if index >= y + placed_height {
+                &below
+            } else {
+                &beside
+            };
+            target.copy_from_slice(source);
+        }
+    });
+    canvas
 }
 
-fn create_split_background(
+/// Levels of the table linear light is turned back into sRGB with, enough
+/// that neighbouring levels of 8-bit colors cannot be told apart.
+const LINEAR_LEVELS: usize = 4096;
+
+/// The canvas blended between the colors of the four corners of the image,
+/// given as top left, top right, bottom left and bottom right, in linear
+/// light. Beyond the image each color carries on as it is.
+fn create_corner_background(layout: &Layout, corners: [image::Rgba<u8>; 4]) -> image::RgbaImage {
+    let (width, height) = layout.canvas;
+    let (x, y) = layout.offset();
+    let (placed_width, placed_height) = layout.image;
+    let linear = corners.map(|corner| {
+        let [r, g, b, a] = corner.0;
+        [
+            color::to_linear(r),
+            color::to_linear(g),
+            color::to_linear(b),
+            a as f32 / 255.0,
+        ]
+    });
+    let lerp = |from: [f32; 4], to: [f32; 4], t: f32| {
+        let mut mixed = [0.0; 4];
+        for (channel, value) in mixed.iter_mut().enumerate() {
+            *value = from[channel] + (to[channel] - from[channel]) * t;
+        }
+        mixed
+    };
+    // How far across the image `at` is, from 0 to 1
+    let across = |at: u32, start: u32, len: u32| {
+        if len <= 1 {
+            return 0.5;
+        }
+        ((at as f32 - start as f32) / (len - 1) as f32).clamp(0.0, 1.0)
+    };
+    let encode: Vec<u8> = (0..LINEAR_LEVELS)
+        .map(|level| color::from_linear(level as f32 / (LINEAR_LEVELS - 1) as f32))
+        .collect();
+    let level = |value: f32| (value.clamp(0.0, 1.0) * (LINEAR_LEVELS - 1) as f32).round() as usize;
+
+    let mut canvas = image::RgbaImage::new(width, height);
+    let row_len = width as usize * 4;
+    bands::rows(&mut canvas, row_len, |first, rows| {
+        for (index, row) in rows.chunks_exact_mut(row_len).enumerate() {
+            let t = across((first + index) as u32, y, placed_height);
+            let (left, right) = (lerp(linear[0], linear[2], t), lerp(linear[1], linear[3], t));
+            for (column, pixel) in row.chunks_exact_mut(4).enumerate() {
+                let [r, g, b, a] = lerp(left, right, across(column as u32, x, placed_width));
+                pixel.copy_from_slice(&[
+                    encode[level(r)],
+                    encode[level(g)],
+                    encode[level(b)],
+                    (a.clamp(0.0, 1.0) * 255.0).round() as u8,
+                ]);
+            }
+        }
+    });
+    canvas
+}
+
+/// The canvas in a single color, copied in a row at a time.
+fn create_uniform_background(
+    (width, height): (u32, u32),
+    color: image::Rgba<u8>,
+) -> image::RgbaImage {
+    let row: Vec<u8> = color
+        .0
+        .iter()
+        .copied()
+        .cycle()
+        .take(width as usize * 4)
+        .collect();
+    let mut canvas = image::RgbaImage::new(width, height);
+    bands::rows(&mut canvas, row.len(), |_, rows| {
+        for target in rows.chunks_exact_mut(row.len()) {
+            target.copy_from_slice(&row);
+        }
+    });
+    canvas
+}
+
+/// Steps of the gradient ramp, enough that neighbouring steps of 8-bit
+/// colors cannot be told apart.
+const GRADIENT_STEPS: usize = 1024;
+
+fn create_gradient_background(
     canvas: &mut image::RgbaImage,
     first_color: image::Rgba<u8>,
     second_color: image::Rgba<u8>,
     orientation: Orientation,
+    angle: Option<f32>,
 ) {
     let (width, height) = canvas.dimensions();
+    let angle = angle.unwrap_or(match orientation {
+        Orientation::Landscape => 0.0,
+        Orientation::Portrait => 90.0,
+    });
+    // Exact directions for the axes, which sin and cos only come close to
+    let (dx, dy) = match angle.rem_euclid(360.0) {
+        0.0 => (0.0, 1.0),
+        90.0 => (1.0, 0.0),
+        180.0 => (0.0, -1.0),
+        270.0 => (-1.0, 0.0),
+        a => {
+            let radians = (a as f64).to_radians();
+            (radians.sin(), radians.cos())
+        }
+    };
 
-    if let Orientation::Landscape = orientation {
-        for y in 0..height {
-            let color = if y > height / 2 {
-                first_color
-            } else {
-                second_color
-            };
-            for x in 0..width {
-                canvas.put_pixel(x, y, color);
+    // Blended in linear light, once per step instead of once per pixel
+    let ramp: Vec<image::Rgba<u8>> = (0..GRADIENT_STEPS)
+        .map(|step| {
+            let t = step as f32 / (GRADIENT_STEPS - 1) as f32;
+            let mut pixel = [0; 4];
+            for (channel, value) in pixel.iter_mut().enumerate().take(3) {
+                let (from, to) = (
+                    color::to_linear(first_color.0[channel]),
+                    color::to_linear(second_color.0[channel]),
+                );
+                *value = color::from_linear(from + (to - from) * t);
             }
-        }
-    } else {
-        for x in 0..width {
-            let color = if x < width / 2 {
-                first_color
-            } else {
-                second_color
-            };
-            for y in 0..height {
-                canvas.put_pixel(x, y, color);
+            let (from, to) = (first_color.0[3] as f32, second_color.0[3] as f32);
+            pixel[3] = (from + (to - from) * t).round() as u8;
+            image::Rgba(pixel)
+        })
+        .collect();
+
+    // Pixel centres projected onto the direction, scaled so the corners
+    // furthest along it land on 0 and 1
+    let extent = width as f64 * dx.abs() + height as f64 * dy.abs();
+    let (step_x, step_y) = (dx / extent, dy / extent);
+    let start =
+        0.5 - (width as f64 * dx + height as f64 * dy) / (2.0 * extent) + (step_x + step_y) / 2.0;
+    let last = (GRADIENT_STEPS - 1) as f64;
+    let row_len = width as usize * 4;
+    bands::rows(canvas, row_len, |first, rows| {
+        for (y, row) in rows.chunks_exact_mut(row_len).enumerate() {
+            let row_start = start + (first + y) as f64 * step_y;
+            for (x, pixel) in row.chunks_exact_mut(4).enumerate() {
+                let t = (row_start + x as f64 * step_x).clamp(0.0, 1.0);
+                pixel.copy_from_slice(&ramp[(t * last).round() as usize].0);
             }
         }
-    }
+    });
 }
 
-pub fn compile_image<'a>(info: &'a ImageInfo) -> Result<&'a str, Box<dyn std::error::Error>> {
-    let src = info.source.as_str();
-    let dest = info.destination.as_str();
-    let img = ImageReader::open(src)?.decode()?;
-
-    let (width, height) = img.dimensions();
+struct Extended {
+    image: image::RgbaImage,
+    colors: Vec<image::Rgba<u8>>,
+    color_sources: Vec<report::ColorSource>,
+    contrast_adjustment: Option<f32>,
+    placement: Placement,
+    source: (u32, u32),
+    crop: (u32, u32),
+    trim: Option<Trim>,
+    /// The canvas in 16 bits a channel, for 16-bit sources.
+    deep: Option<depth::Rgba16Image>,
+}
 
-    let (width_multiplier, width_overflow) = div(width, info.aspect_ratio.0);
-    let (height_multiplier, height_overflow) = div(height, info.aspect_ratio.1);
-    // Check if image is exactly within aspect ratio
-    if !(width_overflow == 0 && height_overflow == 0 && width_multiplier == height_multiplier) {
-        let orientation = if width_multiplier > height_multiplier {
-            Orientation::Landscape
-        } else {
-            Orientation::Portrait
+impl Extended {
+    /// With the 16-bit canvas of `img` as placed, for 16-bit sources whose
+    /// canvas was not drawn in 16 bits already.
+    fn widened(mut self, img: &DynamicImage) -> Self {
+        if self.deep.is_some() || !depth::is_deep(img.color()) {
+            return self;
+        }
+        let origin = match self.trim {
+            Some(trim) => (trim.left, trim.top),
+            None => (self.crop.0 / 2, self.crop.1 / 2),
         };
+        let unscaled = img.dimensions() == self.source
+            && (self.placement.width, self.placement.height)
+                == (self.source.0 - self.crop.0, self.source.1 - self.crop.1);
+        self.deep = Some(depth::widen(
+            img,
+            &self.image,
+            unscaled.then_some((self.placement, origin)),
+        ));
+        self
+    }
 
-        let img = normalise_image(&img, (width_overflow, height_overflow));
-        let (first_edge, second_edge) = aggregate_edge_colors(&img, orientation);
+    /// Scaled down to fit `maxWidth` and `maxHeight` of `options`, with
+    /// the placement scaled along.
+    fn shrunk(mut self, options: &Options) -> Self {
+        let (width, height) = self.image.dimensions();
+        if options.max_size((width, height)).is_none() {
+            return self;
+        }
+        self.image = options.shrink(self.image);
+        self.deep = self.deep.map(|deep| options.shrink(deep));
+        let scale = |value: u32, from: u32, to: u32| {
+            (value as f64 * to as f64 / from as f64).round() as u32
+        };
+        let (to_width, to_height) = self.image.dimensions();
+        let placement = &mut self.placement;
+        placement.x = scale(placement.x, width, to_width);
+        placement.y = scale(placement.y, height, to_height);
+        placement.width = scale(placement.width, width, to_width).max(1);
+        placement.height = scale(placement.height, height, to_height).max(1);
+        self
+    }
 
-        let (canvas_width, canvas_height) = calculate_canvas_dimensions(
-            info.aspect_ratio,
-            (width_multiplier, height_multiplier),
-            orientation,
-        );
+    fn report(&self) -> ImageReport {
+        ImageReport {
+            source_width: self.source.0,
+            source_height: self.source.1,
+            aspect_ratio: None,
+            crop: self.crop,
+            trim: self.trim,
+            output_width: self.image.width(),
+            output_height: self.image.height(),
+            colors: self.colors.iter().map(|color| color.0).collect(),
+            color_sources: self.color_sources.clone(),
+            placement: Some(self.placement),
+            contrast_adjustment: self.contrast_adjustment,
+            copied: false,
+            tolerated: false,
+            frames: None,
+            pages: None,
+            page_mode: None,
+            variants: Vec::new(),
+            outputs: Vec::new(),
+            timings: Timings::default(),
+            attempts: 1,
+            renamed: None,
+        }
+    }
 
-        let (width, height) = img.dimensions();
-        let new_img = {
-            let mut bg_img = image::RgbaImage::new(canvas_width, canvas_height);
-            create_split_background(&mut bg_img, first_edge, second_edge, orientation);
-            bg_img.copy_from(
-                &img,
-                std::cmp::max((canvas_width).saturating_sub(width) / 2, 0),
-                std::cmp::max((canvas_height).saturating_sub(height) / 2, 0),
-            )?;
-            bg_img
-        };
+    /// The canvas in the color type of `source` wherever that loses
+    /// nothing: without alpha when neither has transparency, and gray when
+    /// the source is and every pixel of the canvas as well. Anything else
+    /// stays RGBA. 16-bit sources give 16-bit canvases.
+    fn into_image(self, source: &DynamicImage) -> DynamicImage {
+        let color = source.color();
+        if let Some(canvas) = self.deep {
+            let (width, height) = canvas.dimensions();
+            return match kept_channels(&canvas, color, u16::MAX) {
+                4 => DynamicImage::ImageRgba16(canvas),
+                1 => DynamicImage::ImageLuma16(
+                    ImageBuffer::from_raw(width, height, keep_channels(canvas, 1))
+                        .expect("buffer matches the dimensions"),
+                ),
+                _ => DynamicImage::ImageRgb16(
+                    ImageBuffer::from_raw(width, height, keep_channels(canvas, 3))
+                        .expect("buffer matches the dimensions"),
+                ),
+            };
+        }
+        let canvas = self.image;
+        let (width, height) = canvas.dimensions();
+        match kept_channels(&canvas, color, u8::MAX) {
+            4 => DynamicImage::ImageRgba8(canvas),
+            1 => DynamicImage::ImageLuma8(
+                image::GrayImage::from_raw(width, height, keep_channels(canvas, 1))
+                    .expect("buffer matches the dimensions"),
+            ),
+            _ => DynamicImage::ImageRgb8(
+                image::RgbImage::from_raw(width, height, keep_channels(canvas, 3))
+                    .expect("buffer matches the dimensions"),
+            ),
+        }
+    }
+}
 
-        new_img.save(dest)?;
+/// How many channels of `canvas` an output of a source of `color` needs:
+/// 4 with any transparency in either, 1 for a gray source on a gray canvas
+/// and 3 otherwise.
+fn kept_channels<T: image::Primitive + 'static>(
+    canvas: &ImageBuffer<image::Rgba<T>, Vec<T>>,
+    color: ColorType,
+    opaque: T,
+) -> usize {
+    if color.has_alpha() || canvas.pixels().any(|pixel| pixel.0[3] < opaque) {
+        return 4;
+    }
+    let gray = color.channel_count() == 1
+        && canvas
+            .pixels()
+            .all(|pixel| pixel.0[0] == pixel.0[1] && pixel.0[1] == pixel.0[2]);
+    if gray {
+        1
     } else {
-        std::fs::copy(src, dest)?;
+        3
     }
+}
+
+/// The first `channels` channels of every pixel of `canvas`.
+fn keep_channels<T: image::Primitive + 'static>(
+    canvas: ImageBuffer<image::Rgba<T>, Vec<T>>,
+    channels: usize,
+) -> Vec<T> {
+    canvas
+        .pixels()
+        .flat_map(|pixel| pixel.0[..channels].iter().copied())
+        .collect()
+}
+
+/// Where an image of a given size ends up on the extended canvas.
+#[derive(Copy, Clone)]
+struct Layout {
+    orientation: Orientation,
+    overflow: (u32, u32),
+    canvas: (u32, u32),
+    /// Size of the image on the canvas, cropped to a multiple of the ratio
+    /// with `rounding: down`.
+    image: (u32, u32),
+    gravity: style::Gravity,
+}
+
+impl Layout {
+    /// The image as it is, filling the whole canvas.
+    fn unchanged(dimensions: (u32, u32)) -> Self {
+        Layout {
+            orientation: Orientation::Landscape,
+            overflow: (0, 0),
+            canvas: dimensions,
+            image: dimensions,
+            gravity: style::Gravity::Center,
+        }
+    }
+
+    /// Whether the canvas is wider and taller than the image, leaving room
+    /// on all four sides.
+    fn pads_both_axes(&self) -> bool {
+        self.canvas.0 > self.image.0 && self.canvas.1 > self.image.1
+    }
+
+    /// Top left corner of the image on the canvas, as `place` puts it.
+    fn offset(&self) -> (u32, u32) {
+        self.gravity.offset(self.canvas, self.image)
+    }
+
+    /// Part of the canvas beside the image on the side of `area`, as `(x, y,
+    /// width, height)`, where captions and watermarks may go.
+    fn area(&self, area: style::CaptionArea) -> (u32, u32, u32, u32) {
+        let (x, y) = self.offset();
+        let (canvas_width, canvas_height) = self.canvas;
+        let (image_width, image_height) = self.image;
+        match (self.orientation, area) {
+            // Extended above and below
+            (Orientation::Landscape, style::CaptionArea::Before) => (0, 0, canvas_width, y),
+            (Orientation::Landscape, style::CaptionArea::After) => (
+                0,
+                y + image_height,
+                canvas_width,
+                canvas_height - y - image_height,
+            ),
+            // Extended to the sides
+            (Orientation::Portrait, style::CaptionArea::Before) => (0, 0, x, canvas_height),
+            (Orientation::Portrait, style::CaptionArea::After) => (
+                x + image_width,
+                0,
+                canvas_width - x - image_width,
+                canvas_height,
+            ),
+        }
+    }
+
+    /// Where the `split` background changes color: the middle of the
+    /// canvas, or of the image when `gravity` moves it off the middle.
+    fn middle(&self) -> (u32, u32) {
+        if self.gravity == style::Gravity::Center {
+            return (self.canvas.0 / 2, self.canvas.1 / 2);
+        }
+        let (x, y) = self.offset();
+        (x + self.image.0 / 2, y + self.image.1 / 2)
+    }
+}
+
+/// Returns `None` when the dimensions are already exactly within the aspect ratio.
+fn plan_layout(
+    (width, height): (u32, u32),
+    aspect_ratio: (u32, u32),
+    max_pixels: u64,
+    rounding: Rounding,
+) -> Result<Option<Layout>, CanvasTooLarge> {
+    if geometry::fits_exactly((width, height), aspect_ratio) {
+        return Ok(None);
+    }
+    let orientation = geometry::orientation((width, height), aspect_ratio, rounding);
+    let (width_overflow, height_overflow) =
+        geometry::overflow((width, height), aspect_ratio, rounding);
+    let (canvas_width, canvas_height) =
+        geometry::canvas_dimensions((width, height), aspect_ratio, rounding);
+    if canvas_width * canvas_height > max_pixels
+        || canvas_width > u32::MAX as u64
+        || canvas_height > u32::MAX as u64
+    {
+        return Err(CanvasTooLarge {
+            width: canvas_width,
+            height: canvas_height,
+            limit: max_pixels,
+        });
+    }
+
+    Ok(Some(Layout {
+        orientation,
+        overflow: (width_overflow, height_overflow),
+        canvas: (canvas_width as u32, canvas_height as u32),
+        image: (width - width_overflow, height - height_overflow),
+        gravity: style::Gravity::Center,
+    }))
+}
+
+/// The canvas of exactly `target`, with an image of `dimensions` scaled to
+/// fit inside it, but only scaled up when `upscale` is set.
+///
+/// Returns `None` when the dimensions are the target already.
+fn plan_target(
+    (width, height): (u32, u32),
+    target: (u32, u32),
+    upscale: bool,
+    max_pixels: u64,
+) -> Result<Option<Layout>, CanvasTooLarge> {
+    if (width, height) == target {
+        return Ok(None);
+    }
+    let (target_width, target_height) = target;
+    if target_width as u64 * target_height as u64 > max_pixels {
+        return Err(CanvasTooLarge {
+            width: target_width as u64,
+            height: target_height as u64,
+            limit: max_pixels,
+        });
+    }
+    let scale = (target_width as f64 / width as f64).min(target_height as f64 / height as f64);
+    let scale = if upscale { scale } else { scale.min(1.0) };
+    let placed = (
+        ((width as f64 * scale).round() as u32).clamp(1, target_width),
+        ((height as f64 * scale).round() as u32).clamp(1, target_height),
+    );
+    // Wider than the target leaves room above and below
+    let orientation = if width as u64 * target_height as u64 > target_width as u64 * height as u64 {
+        Orientation::Landscape
+    } else {
+        Orientation::Portrait
+    };
+    Ok(Some(Layout {
+        orientation,
+        overflow: (0, 0),
+        canvas: target,
+        image: placed,
+        gravity: style::Gravity::Center,
+    }))
+}
+
+/// `plan_layout`, or `plan_target` for `targetSize`, with the tolerance and
+/// inset of `options` applied, the latter needing a canvas even for images
+/// that have the ratio already. `mode: zoomToFill` crops the source first.
+fn layout_for(
+    dimensions: (u32, u32),
+    aspect_ratio: (u32, u32),
+    options: &Options,
+) -> Result<Option<Layout>, CanvasTooLarge> {
+    let zoom = match (options.mode, options.target_size) {
+        (Mode::ZoomToFill, None) => zoom_crop(
+            dimensions,
+            aspect_ratio,
+            options.max_zoom.unwrap_or(DEFAULT_MAX_ZOOM),
+        ),
+        _ => (0, 0),
+    };
+    if zoom != (0, 0) {
+        let zoomed = (dimensions.0 - zoom.0, dimensions.1 - zoom.1);
+        let mut layout = fitted_layout(zoomed, aspect_ratio, options)?.unwrap_or_else(|| Layout {
+            gravity: options.style.gravity.unwrap_or_default(),
+            ..Layout::unchanged(zoomed)
+        });
+        layout.overflow = (layout.overflow.0 + zoom.0, layout.overflow.1 + zoom.1);
+        return Ok(Some(layout));
+    }
+    fitted_layout(dimensions, aspect_ratio, options)
+}
+
+/// `layout_for` an image of `dimensions` as it is, without zooming.
+fn fitted_layout(
+    dimensions: (u32, u32),
+    aspect_ratio: (u32, u32),
+    options: &Options,
+) -> Result<Option<Layout>, CanvasTooLarge> {
+    // The canvas is laid out for the image with its padding around it
+    let padding = options.style.padding(dimensions);
+    let padded = (
+        dimensions.0.saturating_add(padding.saturating_mul(2)),
+        dimensions.1.saturating_add(padding.saturating_mul(2)),
+    );
+    let layout = match options.target_size {
+        Some(target) => plan_target(
+            padded,
+            target,
+            options.allow_upscale,
+            options.max_output_pixels(),
+        )?,
+        None if options.tolerates(padded, aspect_ratio) => None,
+        None => plan_layout(
+            padded,
+            aspect_ratio,
+            options.max_output_pixels(),
+            options.rounding.unwrap_or_default(),
+        )?,
+    };
+    let layout = match layout {
+        _ if padding == 0 => layout,
+        Some(layout) => Some(unpad(layout, dimensions, padding)),
+        None => Some(unpad(Layout::unchanged(padded), dimensions, padding)),
+    };
+    let placed = layout.map_or(dimensions, |layout| layout.image);
+    let inset = options.style.inset(placed);
+    if inset == placed && !options.reorients() {
+        return Ok(layout.map(|layout| Layout {
+            gravity: options.style.gravity.unwrap_or_default(),
+            ..layout
+        }));
+    }
+    let mut layout = layout.unwrap_or_else(|| Layout::unchanged(dimensions));
+    layout.image = inset;
+    layout.gravity = options.style.gravity.unwrap_or_default();
+    Ok(Some(layout))
+}
+
+/// How much `mode: zoomToFill` crops off the width and height of a source
+/// of `dimensions`: as much of its long side as brings it to the ratio, up
+/// to `max_zoom` of that side.
+fn zoom_crop(
+    (width, height): (u32, u32),
+    (ratio_width, ratio_height): (u32, u32),
+    max_zoom: f32,
+) -> (u32, u32) {
+    let (wide, high) = (
+        width as u64 * ratio_height as u64,
+        height as u64 * ratio_width as u64,
+    );
+    // Side the ratio asks for along the long axis, and the most taken off it
+    let fitting = |side: u32, other: u32, ratio_side: u32, ratio_other: u32| {
+        let fitting = (other as f64 * ratio_side as f64 / ratio_other as f64).round() as u32;
+        let most = (side as f64 * max_zoom as f64).floor() as u32;
+        side.saturating_sub(fitting.max(1)).min(most)
+    };
+    if wide > high {
+        (fitting(width, height, ratio_width, ratio_height), 0)
+    } else if wide < high {
+        (0, fitting(height, width, ratio_height, ratio_width))
+    } else {
+        (0, 0)
+    }
+}
+
+/// `layout` of a padded image of `dimensions` with the padding taken back
+/// off the placed image, at the scale the image is placed at. A crop
+/// towards the ratio is left at least a pixel of the image.
+fn unpad(mut layout: Layout, dimensions: (u32, u32), padding: u32) -> Layout {
+    let padding = padding as f64;
+    let unpad = |placed: u32, side: u32, overflow: u32| {
+        let scale = placed as f64 / (side as f64 + 2.0 * padding - overflow as f64);
+        let placed = placed.saturating_sub((2.0 * padding * scale).round() as u32);
+        (placed.max(1), overflow.min(side - 1))
+    };
+    let (width, width_overflow) = unpad(layout.image.0, dimensions.0, layout.overflow.0);
+    let (height, height_overflow) = unpad(layout.image.1, dimensions.1, layout.overflow.1);
+    layout.image = (width, height);
+    layout.overflow = (width_overflow, height_overflow);
+    layout
+}
+
+/// The part of a source that `mode: crop` keeps.
+#[derive(Copy, Clone)]
+struct Crop {
+    source: (u32, u32),
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+}
+
+impl Crop {
+    /// All of an image of `dimensions`.
+    fn whole(dimensions: (u32, u32)) -> Self {
+        Crop {
+            source: dimensions,
+            x: 0,
+            y: 0,
+            width: dimensions.0,
+            height: dimensions.1,
+        }
+    }
+
+    fn size(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    /// Pixels removed from the width and the height.
+    fn removed(&self) -> (u32, u32) {
+        (self.source.0 - self.width, self.source.1 - self.height)
+    }
+
+    fn trim(&self) -> Trim {
+        Trim {
+            top: self.y,
+            right: self.source.0 - self.x - self.width,
+            bottom: self.source.1 - self.y - self.height,
+            left: self.x,
+        }
+    }
+
+    /// Cuts the kept part out of `img`, with no background drawn.
+    fn apply(&self, img: &DynamicImage, style: &Style) -> Result<Extended, BoxError> {
+        let mut canvas = img
+            .crop_imm(self.x, self.y, self.width, self.height)
+            .to_rgba8();
+        watermark::apply(&mut canvas, None, style)?;
+        Ok(Extended {
+            image: canvas,
+            colors: Vec::new(),
+            color_sources: Vec::new(),
+            contrast_adjustment: None,
+            placement: Placement {
+                x: 0,
+                y: 0,
+                width: self.width,
+                height: self.height,
+            },
+            source: self.source,
+            crop: self.removed(),
+            trim: Some(self.trim()),
+            deep: None,
+        })
+    }
+}
+
+/// The largest part of an image of `dimensions` that is a multiple of the
+/// aspect ratio, placed along the cropped side by `align`.
+///
+/// Returns `None` when the dimensions are already exactly within the aspect ratio.
+fn plan_crop(
+    (width, height): (u32, u32),
+    aspect_ratio: (u32, u32),
+    align: CropAlign,
+) -> Result<Option<Crop>, BoxError> {
+    // The smaller of the multipliers of `plan_layout` fits on both sides
+    let multiplier = (width / aspect_ratio.0).min(height / aspect_ratio.1);
+    if multiplier == 0 {
+        return Err(format!(
+            "{}x{} is too small to crop to {}:{}",
+            width, height, aspect_ratio.0, aspect_ratio.1
+        )
+        .into());
+    }
+    let (crop_width, crop_height) = (aspect_ratio.0 * multiplier, aspect_ratio.1 * multiplier);
+    if (crop_width, crop_height) == (width, height) {
+        return Ok(None);
+    }
+    let start = |removed: u32| match align {
+        CropAlign::Start => 0,
+        CropAlign::Center => removed / 2,
+        CropAlign::End => removed,
+    };
+    Ok(Some(Crop {
+        source: (width, height),
+        x: start(width - crop_width),
+        y: start(height - crop_height),
+        width: crop_width,
+        height: crop_height,
+    }))
+}
+
+/// `plan_crop` with the tolerance and alignment of `options`, which needs a
+/// crop even for images that have the ratio already when they are turned
+/// or mirrored.
+fn crop_for(
+    dimensions: (u32, u32),
+    aspect_ratio: (u32, u32),
+    options: &Options,
+) -> Result<Option<Crop>, BoxError> {
+    let crop = if options.tolerates(dimensions, aspect_ratio) {
+        None
+    } else {
+        plan_crop(
+            dimensions,
+            aspect_ratio,
+            options.crop_align.unwrap_or_default(),
+        )?
+    };
+    if crop.is_none() && options.reorients() {
+        return Ok(Some(Crop::whole(dimensions)));
+    }
+    Ok(crop)
+}
+
+/// Scales a normalised image down to its inset size, if it has one.
+fn inset(img: DynamicImage, layout: &Layout, style: &Style) -> DynamicImage {
+    if img.dimensions() == layout.image {
+        return img;
+    }
+    let filter = style.filter(imageops::FilterType::Lanczos3);
+    img.resize_exact(layout.image.0, layout.image.1, filter)
+}
+
+/// The normalised image scaled to cover the whole canvas and blurred.
+///
+/// The blur runs on a copy scaled down by `blurDownscale`, as blurring at
+/// full size takes far longer for the same look.
+fn create_blur_background(img: &DynamicImage, layout: &Layout, style: &Style) -> image::RgbaImage {
+    blur_background(img, layout, style, |pixel| style.adjust_background(pixel))
+}
+
+/// `create_blur_background` in channels of `T`, each pixel changed by
+/// `adjust` before it is scaled back up.
+fn blur_background<T: depth::Channel>(
+    img: &DynamicImage,
+    layout: &Layout,
+    style: &Style,
+    adjust: impl Fn(image::Rgba<T>) -> image::Rgba<T> + Sync,
+) -> ImageBuffer<image::Rgba<T>, Vec<T>> {
+    let (canvas_width, canvas_height) = layout.canvas;
+    let downscale = style.blur_downscale();
+    let small = (
+        ((canvas_width as f32 / downscale).round() as u32).max(1),
+        ((canvas_height as f32 / downscale).round() as u32).max(1),
+    );
+
+    // Only the middle part of the image that covers the canvas is scaled,
+    // so long and thin images do not blow up on the way
+    let (width, height) = img.dimensions();
+    let scale = (small.0 as f32 / width as f32).max(small.1 as f32 / height as f32);
+    let visible = (
+        ((small.0 as f32 / scale).round() as u32).clamp(1, width),
+        ((small.1 as f32 / scale).round() as u32).clamp(1, height),
+    );
+    let covering = img.crop_imm(
+        (width - visible.0) / 2,
+        (height - visible.1) / 2,
+        visible.0,
+        visible.1,
+    );
+    let filter = style.filter(imageops::FilterType::Triangle);
+    let mut background = imageops::resize(&T::rgba(&covering), small.0, small.1, filter);
+
+    let sigma = style.blur_sigma(layout.canvas) / downscale;
+    // Anything narrower is lost when scaling back up anyway
+    if sigma >= 0.5 {
+        background = resample::blur(&background, sigma);
+    }
+    let row_len = small.0 as usize * 4;
+    bands::rows(&mut background, row_len, |_, rows| {
+        for pixel in rows.chunks_exact_mut(4) {
+            let adjusted = adjust(image::Rgba([pixel[0], pixel[1], pixel[2], pixel[3]]));
+            pixel.copy_from_slice(&adjusted.0);
+        }
+    });
+    if small == layout.canvas {
+        background
+    } else {
+        resample::resize(&background, canvas_width, canvas_height, filter)
+    }
+}
+
+/// Everything of the canvas but the image itself, which is placed on top of it.
+///
+/// `img` is the normalised image, which the `blur`, `smear`, `mirror` and
+/// `replicate` backgrounds use, and `split` for the edges across it when
+/// the canvas has room on all four sides.
+fn fill_background(
+    img: &DynamicImage,
+    layout: &Layout,
+    (first_color, second_color): (image::Rgba<u8>, image::Rgba<u8>),
+    style: &Style,
+) -> Result<image::RgbaImage, BoxError> {
+    let (canvas_width, canvas_height) = layout.canvas;
+    let opaque = style.background_alpha.unwrap_or_default() == BackgroundAlpha::Opaque;
+    let (first_color, second_color) = if opaque {
+        let solid = |image::Rgba([r, g, b, _]): image::Rgba<u8>| image::Rgba([r, g, b, 255]);
+        (solid(first_color), solid(second_color))
+    } else {
+        (first_color, second_color)
+    };
+    let ctx = fill::FillContext {
+        image: img,
+        layout,
+        colors: (first_color, second_color),
+        style,
+    };
+    let mut canvas = image::RgbaImage::new(canvas_width, canvas_height);
+    match &style.fill {
+        // Both arrive as two copies of the same color
+        _ if style.background_color.is_some() => {
+            canvas = create_uniform_background(layout.canvas, first_color)
+        }
+        Some(name) => {
+            let custom = fill::registered(name)
+                .ok_or_else(|| format!("no fill is registered as {}", name))?;
+            custom.fill(&mut canvas, &ctx);
+            if canvas.dimensions() != layout.canvas {
+                return Err(format!(
+                    "fill {} made a canvas of {}x{} instead of {}x{}",
+                    name,
+                    canvas.width(),
+                    canvas.height(),
+                    canvas_width,
+                    canvas_height
+                )
+                .into());
+            }
+        }
+        None => style.background.fill(&mut canvas, &ctx),
+    }
+    // Backgrounds made from the image carry its transparency along
+    if opaque && canvas.pixels().any(|pixel| pixel.0[3] < 255) {
+        flatten(&mut canvas, fallback_color(img, style));
+    }
+    if let Some(vignette) = &style.vignette {
+        style::draw_vignette(&mut canvas, layout.offset(), layout.image, vignette);
+    }
+    let radius = style.corner_radius(layout.image);
+    if let Some(shadow) = &style.shadow {
+        style::draw_shadow(&mut canvas, layout.offset(), layout.image, shadow, radius);
+    }
+    if let Some(grain) = &style.grain {
+        style::draw_grain(&mut canvas, layout.offset(), layout.image, grain);
+    }
+    if let Some(border) = &style.border {
+        style::draw_border(&mut canvas, layout.offset(), layout.image, border, radius);
+    }
+    if let Some(caption) = &style.caption {
+        caption::draw(&mut canvas, layout, caption)?;
+    }
+    Ok(canvas)
+}
+
+/// Puts an already normalised image on the canvas where the gravity of
+/// `layout` says, in the middle unless set.
+fn place(
+    canvas: &mut image::RgbaImage,
+    img: &DynamicImage,
+    layout: &Layout,
+    style: &Style,
+) -> Result<(), ImageError> {
+    let (width, height) = img.dimensions();
+    let (x, y) = layout.gravity.offset(canvas.dimensions(), (width, height));
+    let put = |canvas: &mut image::RgbaImage| match style.corner_radius((width, height)) {
+        0 => style::composite(canvas, img, (x, y)),
+        radius => style::place_rounded(canvas, img, (x, y), radius),
+    };
+    match style.blend_width((width, height)) {
+        0 => put(canvas),
+        blend => style::place_feathered(canvas, (x, y), (width, height), blend, put),
+    }
+}
+
+/// Places `img` on a new canvas, sampling the background colors from `img`
+/// unless they are given.
+fn render(
+    img: &DynamicImage,
+    layout: &Layout,
+    colors: Option<(image::Rgba<u8>, image::Rgba<u8>)>,
+    style: &Style,
+) -> Result<Extended, BoxError> {
+    let source = img.dimensions();
+    let img = normalise_image(img, layout.overflow);
+    let (colors, contrast_adjustment, color_sources) = match colors {
+        Some(colors) => (colors, None, style.color_sources([false; 2])),
+        None => {
+            let edges = aggregate_edge_colors(&img, layout.orientation, style);
+            let (colors, change) = pick_colors(edges.colors, style);
+            (colors, change, style.color_sources(edges.fallback))
+        }
+    };
+
+    let mut canvas = fill_background(&img, layout, colors, style)?;
+    let deep = match depth::is_deep(img.color()) {
+        true => depth::render(&img, layout, &canvas, style),
+        false => None,
+    };
+    place(&mut canvas, &inset(img, layout, style), layout, style)?;
+    watermark::apply(&mut canvas, Some(layout), style)?;
+    Ok(Extended {
+        image: canvas,
+        colors: vec![colors.0, colors.1],
+        color_sources,
+        contrast_adjustment,
+        placement: Placement {
+            x: layout.offset().0,
+            y: layout.offset().1,
+            width: layout.image.0,
+            height: layout.image.1,
+        },
+        source,
+        crop: layout.overflow,
+        trim: None,
+        deep,
+    })
+}
+
+fn extend(
+    img: &DynamicImage,
+    aspect_ratio: (u32, u32),
+    options: &Options,
+) -> Result<Option<Extended>, BoxError> {
+    let img = if options.reorients() {
+        Cow::Owned(options.orient(img.clone()))
+    } else {
+        Cow::Borrowed(img)
+    };
+    let img = &*img;
+    // A watermark still has to be stamped on an image that fits already,
+    // and one too large scaled down
+    let redrawn = options.style.watermark.is_some() || options.max_size(img.dimensions()).is_some();
+    let mode = options.mode_for(img.dimensions(), aspect_ratio);
+    if mode == Mode::Crop {
+        let crop = match crop_for(img.dimensions(), aspect_ratio, options)? {
+            Some(crop) => crop,
+            None if redrawn => Crop::whole(img.dimensions()),
+            None => return Ok(None),
+        };
+        return Ok(Some(
+            crop.apply(img, &options.style)?
+                .shrunk(options)
+                .widened(img),
+        ));
+    }
+    let layout = match layout_for(img.dimensions(), aspect_ratio, options)? {
+        Some(layout) => layout,
+        None if redrawn => Layout::unchanged(img.dimensions()),
+        None => return Ok(None),
+    };
+    let layout = saliency::settle(img, layout, &options.style);
+    let extended = if mode == Mode::SeamCarve {
+        carve(img, &layout, &options.style)?
+    } else {
+        render(img, &layout, None, &options.style)?
+    };
+    Ok(Some(extended.shrunk(options).widened(img)))
+}
+
+/// `mode: seamCarve`, the image stretched over the whole canvas with no
+/// background to pick colors for.
+fn carve(img: &DynamicImage, layout: &Layout, style: &Style) -> Result<Extended, BoxError> {
+    let source = img.dimensions();
+    let img = inset(normalise_image(img, layout.overflow), layout, style);
+    let mut canvas = seams::extend(&img, layout.canvas);
+    watermark::apply(&mut canvas, None, style)?;
+    Ok(Extended {
+        image: canvas,
+        colors: Vec::new(),
+        color_sources: Vec::new(),
+        contrast_adjustment: None,
+        placement: Placement {
+            x: 0,
+            y: 0,
+            width: layout.canvas.0,
+            height: layout.canvas.1,
+        },
+        source,
+        crop: layout.overflow,
+        trim: None,
+        deep: None,
+    })
+}
+
+/// Panics on purpose for sources whose path contains the value of
+/// `IMAGE_BG_EXTENDER_INJECT_PANIC`, to test how panics are contained.
+#[cfg(any(test, feature = "test-util"))]
+fn inject_panic(src: &str) {
+    if std::env::var("IMAGE_BG_EXTENDER_INJECT_PANIC").is_ok_and(|name| src.contains(&name)) {
+        panic!("injected panic for {}", src);
+    }
+}
+
+/// Whether `dest` is the source itself, which is refused unless `inPlace` is set.
+///
+/// Paths that cannot be resolved are left for the read or write to fail on.
+fn check_in_place(src: &str, dest: &str, options: &Options) -> Result<bool, ExtendError> {
+    let same = atomic::same_file(src, dest).unwrap_or(false);
+    if same && !options.in_place {
+        return Err(ExtendError::new(
+            Stage::Write,
+            Some(dest),
+            "destination is the source file, set inPlace to overwrite it",
+        ));
+    }
+    Ok(same)
+}
+
+/// Fails on the first setting of the entry that cannot work.
+fn check_fields(
+    src: &str,
+    dest: &str,
+    aspect_ratio: AspectRatio,
+    options: &Options,
+) -> Result<(), ExtendError> {
+    match validate::fields(dest, aspect_ratio, options)
+        .into_iter()
+        .next()
+    {
+        Some(problem) => {
+            let path = if problem.stage == Stage::Write {
+                dest
+            } else {
+                src
+            };
+            Err(ExtendError::new(problem.stage, Some(path), problem))
+        }
+        None => Ok(()),
+    }
+}
+
+/// Whether the source at `src` is an animation, which is extended frame by
+/// frame.
+#[cfg_attr(
+    not(all(feature = "gif", feature = "animated-webp")),
+    allow(unused_variables)
+)]
+fn is_animated(src: &str, source_format: Option<ImageFormat>) -> bool {
+    match source_format {
+        Some(ImageFormat::Gif) => true,
+        #[cfg(all(feature = "gif", feature = "animated-webp"))]
+        Some(ImageFormat::WebP) => animation::is_animated_webp(src),
+        _ => false,
+    }
+}
+
+pub(crate) fn extend_file(
+    src: &str,
+    dest: &str,
+    aspect_ratio: (u32, u32),
+    options: &Options,
+) -> Result<ImageReport, ExtendError> {
+    let started = Instant::now();
+    check_fields(src, dest, AspectRatio::Fixed(aspect_ratio), options)?;
+    let in_place = check_in_place(src, dest, options)?;
+    // Streamed sources encode on their own, everything else below
+    #[cfg(any(feature = "tiff", feature = "gif"))]
+    let format = options
+        .output_format
+        .or_else(|| OutputFormat::from_path(storage::name(dest)));
+    let source_format = probe_format(src)?;
+    if let Some(format) = source_format {
+        format::ensure_compiled(format).context(Stage::Decode, src)?;
+    }
+    let in_memory = in_memory(src);
+    check_writable(dest, source_format, options, || {
+        !in_memory && is_animated(src, source_format)
+    })?;
+    timeout::checkpoint(Stage::Decode, src)?;
+    // Animations and multi-page sources are streamed, all in the process
+    // stage, unless they are read whole and decoded as still images
+    #[cfg(feature = "tiff")]
+    {
+        if source_format == Some(ImageFormat::Tiff) && !in_memory {
+            let _permit = stages::acquire(Stage::Decode);
+            if let Some(report) = pages::extend_tiff(src, dest, aspect_ratio, format, options)? {
+                return Ok(report);
+            }
+        }
+    }
+    let (img, reencode, metadata, permit) = match source_format {
+        #[cfg(feature = "gif")]
+        Some(ImageFormat::Gif) if !in_memory => {
+            let permit = stages::acquire(Stage::Decode);
+            match animation::extend_gif(src, dest, aspect_ratio, format, options)? {
+                animation::GifOutcome::Animated(report) => return Ok(*report),
+                animation::GifOutcome::Still(img) => {
+                    (img, false, metadata::Metadata::default(), permit)
+                }
+            }
+        }
+        #[cfg(all(feature = "gif", feature = "animated-webp"))]
+        Some(ImageFormat::WebP) if !in_memory && animation::is_animated_webp(src) => {
+            let permit = stages::acquire(Stage::Decode);
+            match animation::extend_webp(src, dest, aspect_ratio, format, options)? {
+                animation::GifOutcome::Animated(report) => return Ok(*report),
+                animation::GifOutcome::Still(img) => {
+                    (img, false, metadata::Metadata::default(), permit)
+                }
+            }
+        }
+        _ => read_still(src, source_format, options)?,
+    };
+    let timings = Timings {
+        decode_ms: started.elapsed().as_secs_f64() * 1000.0,
+        ..Timings::default()
+    };
+    let still = Still {
+        img: &img,
+        reencode,
+        metadata: &metadata,
+    };
+    write_still(
+        src,
+        dest,
+        &still,
+        aspect_ratio,
+        options,
+        in_place,
+        permit,
+        timings,
+    )
+}
+
+/// Fails a destination that cannot be written, which is told before decoding.
+/// Only animations are written in some formats, as `animated` says the
+/// source is, unless the source can be copied as it is.
+fn check_writable(
+    dest: &str,
+    source_format: Option<ImageFormat>,
+    options: &Options,
+    animated: impl FnOnce() -> bool,
+) -> Result<(), ExtendError> {
+    let encoder = match options.output_format {
+        Some(format) => Some(format.encoder()),
+        None => Path::new(storage::name(dest))
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .and_then(format::encoder_for_extension),
+    };
+    let encoder = encoder
+        .filter(|encoder| encoder.image_format.is_none() || encoder.image_format != source_format);
+    match encoder {
+        Some(encoder) => encoder
+            .ensure_writable(animated())
+            .context(Stage::Write, dest),
+        None => Ok(()),
+    }
+}
+
+/// `extend_file` of the local still source of `info`, read already into
+/// `data`, for `compile_image_async`. The source is decoded, extended and
+/// encoded, and the output comes back to be written rather than written:
+/// `None` when it is the source as it is.
+#[cfg(feature = "tokio")]
+pub(crate) fn extend_read(
+    info: &ImageInfo,
+    data: &[u8],
+) -> Result<(ImageReport, Option<Vec<u8>>), ExtendError> {
+    let started = Instant::now();
+    let (src, dest, options) = (
+        info.source.as_str(),
+        info.destination.as_str(),
+        &info.options,
+    );
+    let aspect_ratio = info.resolve_ratio()?;
+    check_fields(src, dest, AspectRatio::Fixed(aspect_ratio), options)?;
+    check_in_place(src, dest, options)?;
+    let source_format = ImageFormat::from_path(src).ok();
+    if let Some(format) = source_format {
+        format::ensure_compiled(format).context(Stage::Decode, src)?;
+    }
+    check_writable(dest, source_format, options, || false)?;
+    timeout::checkpoint(Stage::Decode, src)?;
+    let (mut img, heif) = decode_data(data, source_format, options)
+        .map_err(|e| CorruptSource::classify(Some(src), data.len() as u64, e))
+        .context(Stage::Decode, src)?;
+    check_dimensions(img.dimensions(), Some(src)).context(Stage::Decode, src)?;
+    let mut metadata = metadata::read(data, options.keep_metadata);
+    let turned = options.auto_orient() && metadata.turn_upright(&mut img);
+    let metadata = metadata.oriented(options);
+    let mut timings = Timings {
+        decode_ms: started.elapsed().as_secs_f64() * 1000.0,
+        ..Timings::default()
+    };
+
+    timeout::checkpoint(Stage::Composite, src)?;
+    let extended = Timings::measure(&mut timings.composite_ms, || {
+        extend(&img, aspect_ratio, options)
+    })
+    .context(Stage::Composite, src)?;
+    timeout::checkpoint(Stage::Write, dest)?;
+    let format = options.output_format;
+    let (mut report, output) = match extended {
+        Some(extended) => (extended.report(), Some(extended.into_image(&img))),
+        None if format.is_some() || heif || turned => {
+            (ImageReport::unchanged(img.dimensions()), Some(img))
+        }
+        None => (ImageReport::unchanged(img.dimensions()), None),
+    };
+    let encoded = match output {
+        Some(output) => Some(Timings::measure(&mut timings.write_ms, || {
+            encode(
+                output,
+                format,
+                format::Encoding::of(options),
+                dest,
+                &metadata,
+            )
+        })?),
+        None => None,
+    };
+    report.timings = timings;
+    conclude(info, aspect_ratio, &mut report)?;
+    Ok((report, encoded))
+}
+
+/// A source decoded as a still image, with what `read_still` tells of it.
+struct Still<'a> {
+    img: &'a DynamicImage,
+    /// It cannot be copied through for being HEIF or having been turned.
+    reencode: bool,
+    metadata: &'a metadata::Metadata,
+}
+
+/// Extends `still` to `aspect_ratio` and writes it to `dest`, or copies the
+/// source through when it fits already and nothing asks for re-encoding it.
+/// `permit` of the decode stage is let go of before writing.
+#[allow(clippy::too_many_arguments)]
+fn write_still(
+    src: &str,
+    dest: &str,
+    still: &Still<'_>,
+    aspect_ratio: (u32, u32),
+    options: &Options,
+    in_place: bool,
+    permit: Option<stages::Permit>,
+    mut timings: Timings,
+) -> Result<ImageReport, ExtendError> {
+    let (img, reencode) = (still.img, still.reencode);
+    let format = options.output_format;
+    let metadata = still.metadata.clone().oriented(options);
+    timeout::checkpoint(Stage::Composite, src)?;
+    #[cfg(any(test, feature = "test-util"))]
+    inject_panic(src);
+    let extended = Timings::measure(&mut timings.composite_ms, || {
+        extend(img, aspect_ratio, options)
+    })
+    .context(Stage::Composite, src)?;
+    timeout::checkpoint(Stage::Write, dest)?;
+    // Without an encoded image the source is copied through
+    let (mut report, encoded, preview) = match extended {
+        Some(extended) => {
+            let report = extended.report();
+            let preview = preview::scaled(&extended.image, options);
+            let encoded = Timings::measure(&mut timings.write_ms, || {
+                encode(
+                    extended.into_image(img),
+                    format,
+                    format::Encoding::of(options),
+                    dest,
+                    &metadata,
+                )
+            })?;
+            (report, Some(encoded), preview)
+        }
+        // Already in place, nothing to copy
+        None if in_place && format.is_none() && !reencode => {
+            let preview = preview::of_source(img, options);
+            drop(permit);
+            let _permit = stages::acquire(Stage::Write);
+            Timings::measure(&mut timings.write_ms, || {
+                preview::write(dest, preview.as_ref(), options)
+            })?;
+            let mut report = ImageReport::unchanged(img.dimensions());
+            report.timings = timings;
+            return Ok(report);
+        }
+        None if format.is_some() || reencode => {
+            let preview = preview::of_source(img, options);
+            let encoded = Timings::measure(&mut timings.write_ms, || {
+                encode(
+                    img.clone(),
+                    format,
+                    format::Encoding::of(options),
+                    dest,
+                    &metadata,
+                )
+            })?;
+            (
+                ImageReport::unchanged(img.dimensions()),
+                Some(encoded),
+                preview,
+            )
+        }
+        None => (
+            ImageReport::unchanged(img.dimensions()),
+            None,
+            preview::of_source(img, options),
+        ),
+    };
+    drop(permit);
+    let _permit = stages::acquire(Stage::Write);
+    Timings::measure(&mut timings.write_ms, || {
+        atomic::write(dest, |temp| match &encoded {
+            Some(encoded) => std::fs::write(temp, encoded).context(Stage::Write, dest),
+            None if in_memory(src) => {
+                std::fs::write(temp, read_source(src)?).context(Stage::Write, dest)
+            }
+            None => std::fs::copy(src, temp)
+                .map(|_| ())
+                .context(Stage::Write, dest),
+        })?;
+        preview::write(dest, preview.as_ref(), options)
+    })?;
+    report.timings = timings;
+    Ok(report)
+}
+
+/// The EXIF orientation of the source at `src` that `autoOrient` turns it
+/// upright from, read from `data` when the source was read whole already.
+/// 1 when there is nothing to turn.
+fn source_orientation(src: &str, data: Option<&[u8]>, options: &Options) -> u8 {
+    match data {
+        _ if !options.auto_orient() => 1,
+        Some(data) => metadata::read(data, false).orientation,
+        None => metadata::file_orientation(src),
+    }
+}
+
+/// Dimensions of the source at `src` once turned upright and `rotate` is
+/// applied, read from its header. HEIF sources are decoded for them.
+fn source_dimensions(src: &str, options: &Options) -> Result<(u32, u32), ExtendError> {
+    let mut orientation = 1;
+    let dimensions = if in_memory(src) {
+        let data = read_source(src)?;
+        orientation = source_orientation(src, Some(&data), options);
+        decode_data(&data, probe_format(src)?, options).map(|(img, _)| img.dimensions())
+    } else if heif::is_heif_file(src).context(Stage::Read, src)? {
+        heif::decode(&std::fs::read(src).context(Stage::Read, src)?).map(|img| img.dimensions())
+    } else {
+        orientation = source_orientation(src, None, options);
+        ImageReader::open(src)
+            .context(Stage::Read, src)?
+            .into_dimensions()
+            .map_err(BoxError::from)
+            .and_then(|dimensions| {
+                check_source_pixels(dimensions, options)?;
+                Ok(dimensions)
+            })
+    }
+    .map_err(|e| CorruptSource::classify_file(src, e))
+    .context(Stage::Decode, src)?;
+    check_dimensions(dimensions, Some(src)).context(Stage::Decode, src)?;
+    Ok(options.oriented(metadata::upright_dimensions(dimensions, orientation)))
+}
+
+/// Whether the source at `src` is read whole and decoded from memory, as
+/// sources in an archive and remote ones are, rather than opened as a file.
+fn in_memory(src: &str) -> bool {
+    archive::split(src).is_some() || storage::is_remote(src)
+}
+
+/// Reads the whole source at `src`, from its archive for paths such as
+/// `bundle.zip!photo.jpg`.
+pub(crate) fn read_source(src: &str) -> Result<Vec<u8>, ExtendError> {
+    if archive::split(src).is_some() {
+        archive::read(src)
+    } else {
+        storage::source(src).read(src)
+    }
+    .context(Stage::Read, src)
+}
+
+/// Format of the source at `src` as the extension of its name tells, which
+/// for sources in an archive is that of their entry.
+fn probe_format(src: &str) -> Result<Option<ImageFormat>, ExtendError> {
+    Ok(match archive::split(src) {
+        Some((_, entry)) => ImageFormat::from_path(entry).ok(),
+        None if storage::is_remote(src) => ImageFormat::from_path(storage::name(src)).ok(),
+        None => {
+            // Opening is a read of the source as well, and may hang as one
+            let _permit = stages::acquire(Stage::Read);
+            ImageReader::open(src).context(Stage::Read, src)?.format()
+        }
+    })
+}
+
+/// Decodes an encoded source, along with whether it is HEIF.
+fn decode_data(
+    data: &[u8],
+    source_format: Option<ImageFormat>,
+    options: &Options,
+) -> Result<(DynamicImage, bool), BoxError> {
+    // HEIF sources cannot be copied through, as nothing can write them back
+    let heif = source_format.is_none() && heif::is_heif(data);
+    let img = if heif {
+        if options.max_source_pixels.is_some() {
+            check_source_pixels(heif::dimensions(data)?, options)?;
+        }
+        heif::decode(data)?
+    } else {
+        let reader = || {
+            let mut reader = ImageReader::new(Cursor::new(data));
+            if let Some(format) = source_format {
+                reader.set_format(format);
+            }
+            reader
+        };
+        if options.max_source_pixels.is_some() {
+            check_source_pixels(reader().into_dimensions()?, options)?;
+        }
+        reader().decode()?
+    };
+    Ok((img, heif))
+}
+
+/// Reads and decodes a source that is not streamed, turned upright as
+/// `autoOrient` asks, along with whether it cannot be copied through for
+/// being HEIF or having been turned, the metadata carried over from it and
+/// the permit of the decode stage, held on through compositing.
+#[allow(clippy::type_complexity)]
+fn read_still(
+    src: &str,
+    source_format: Option<ImageFormat>,
+    options: &Options,
+) -> Result<
+    (
+        DynamicImage,
+        bool,
+        metadata::Metadata,
+        Option<stages::Permit>,
+    ),
+    ExtendError,
+> {
+    let data = {
+        let _permit = stages::acquire(Stage::Read);
+        read_source(src)?
+    };
+    let permit = stages::acquire(Stage::Decode);
+    let (mut img, heif) = decode_data(&data, source_format, options)
+        .map_err(|e| CorruptSource::classify(Some(src), data.len() as u64, e))
+        .context(Stage::Decode, src)?;
+    check_dimensions(img.dimensions(), Some(src)).context(Stage::Decode, src)?;
+    let mut metadata = metadata::read(&data, options.keep_metadata);
+    let turned = options.auto_orient() && metadata.turn_upright(&mut img);
+    Ok((img, heif || turned, metadata, permit))
+}
+
+/// Writes every variant of `info` from a single decode of the source and a
+/// single sampling of its edges.
+///
+/// GIF and TIFF sources, which may be streamed, and sources written out
+/// unchanged go through `extend_file` once per variant instead. A variant
+/// that fails is reported without stopping the others, and the entry only
+/// fails when all of them do.
+fn extend_variants(info: &ImageInfo, aspect_ratio: (u32, u32)) -> Result<ImageReport, ExtendError> {
+    let started = Instant::now();
+    let src = info.source.as_str();
+    // Every problem of the list is in writing the variants
+    if let Some(problem) = validate::variants(info).into_iter().next() {
+        return Err(ExtendError::new(
+            problem.stage,
+            Some(&info.destination),
+            problem,
+        ));
+    }
+    let variants: Vec<(Background, String, Options)> = info
+        .variants
+        .iter()
+        .map(|variant| {
+            (
+                variant.background,
+                variant.destination(&info.destination),
+                info.variant_options(variant),
+            )
+        })
+        .collect();
+    for (_, dest, options) in &variants {
+        check_fields(src, dest, AspectRatio::Fixed(aspect_ratio), options)?;
+        check_in_place(src, dest, options)?;
+    }
+
+    let source_format = probe_format(src)?;
+    if let Some(format) = source_format {
+        format::ensure_compiled(format).context(Stage::Decode, src)?;
+    }
+    let one_by_one = || {
+        let results = variants
+            .iter()
+            .map(|(_, dest, options)| extend_file(src, dest, aspect_ratio, options))
+            .collect();
+        combine_variants(info, &variants, results)
+    };
+    if !in_memory(src)
+        && matches!(
+            source_format,
+            Some(ImageFormat::Gif) | Some(ImageFormat::Tiff)
+        )
+    {
+        return one_by_one();
+    }
+
+    timeout::checkpoint(Stage::Decode, src)?;
+    let (img, _, metadata, permit) = read_still(src, source_format, &info.options)?;
+    let img = info.options.orient(img);
+    let metadata = metadata.oriented(&info.options);
+    let decode_ms = started.elapsed().as_secs_f64() * 1000.0;
+    let layout = match layout_for(img.dimensions(), aspect_ratio, &info.options)
+        .context(Stage::Composite, src)?
+    {
+        Some(layout) => layout,
+        None if info.options.style.watermark.is_some()
+            || info.options.max_size(img.dimensions()).is_some() =>
+        {
+            Layout::unchanged(img.dimensions())
+        }
+        None => {
+            drop(permit);
+            return one_by_one();
+        }
+    };
+    let layout = saliency::settle(&img, layout, &info.options.style);
+    let normalised = normalise_image(&img, layout.overflow);
+    let edges = aggregate_edge_colors(&normalised, layout.orientation, &info.options.style);
+
+    let results = variants
+        .iter()
+        .map(|(_, dest, options)| {
+            let mut timings = Timings {
+                decode_ms,
+                ..Timings::default()
+            };
+            timeout::checkpoint(Stage::Composite, src)?;
+            let extended = Timings::measure(&mut timings.composite_ms, || {
+                let (colors, contrast_adjustment) = pick_colors(edges.colors, &options.style);
+                let mut extended = render(&img, &layout, Some(colors), &options.style)?;
+                extended.contrast_adjustment = contrast_adjustment;
+                extended.color_sources = options.style.color_sources(edges.fallback);
+                Ok::<_, BoxError>(extended.shrunk(options).widened(&img))
+            })
+            .context(Stage::Composite, src)?;
+            let mut report = extended.report();
+            let format = options.output_format;
+            let encoded = Timings::measure(&mut timings.write_ms, || {
+                encode(
+                    extended.into_image(&img),
+                    format,
+                    format::Encoding::of(options),
+                    dest,
+                    &metadata,
+                )
+            })?;
+            timeout::checkpoint(Stage::Write, dest)?;
+            let _permit = stages::acquire(Stage::Write);
+            Timings::measure(&mut timings.write_ms, || {
+                atomic::write(dest, |temp| {
+                    std::fs::write(temp, &encoded).context(Stage::Write, dest)
+                })
+            })?;
+            report.timings = timings;
+            Ok(report)
+        })
+        .collect();
+    combine_variants(info, &variants, results)
+}
+
+/// The report of an entry with variants: the first one written, listing
+/// them all. When every variant failed, the first failure.
+fn combine_variants(
+    info: &ImageInfo,
+    variants: &[(Background, String, Options)],
+    results: Vec<Result<ImageReport, ExtendError>>,
+) -> Result<ImageReport, ExtendError> {
+    let results: Vec<_> = results
+        .into_iter()
+        .zip(variants)
+        .map(|(result, (background, dest, options))| {
+            let report = result?;
+            if options.sidecar {
+                write_analysis(info, dest, *background, &report)?;
+            }
+            Ok(report)
+        })
+        .collect();
+    let listed = variants
+        .iter()
+        .zip(&results)
+        .map(|((background, dest, _), result)| VariantReport {
+            background: *background,
+            destination: dest.clone(),
+            error: result.as_ref().err().map(ErrorInfo::new),
+        })
+        .collect();
+    let mut report = first_written(results)?;
+    report.variants = listed;
+    Ok(report)
+}
+
+/// The first of `results` that was written, with the time they all took.
+/// When none was, the first failure.
+fn first_written(
+    results: Vec<Result<ImageReport, ExtendError>>,
+) -> Result<ImageReport, ExtendError> {
+    let mut written = None;
+    let mut first_error = None;
+    let mut timings = Timings::default();
+    for result in results {
+        match result {
+            Ok(report) => {
+                timings.decode_ms = timings.decode_ms.max(report.timings.decode_ms);
+                timings.composite_ms += report.timings.composite_ms;
+                timings.write_ms += report.timings.write_ms;
+                written.get_or_insert(report);
+            }
+            Err(e) => {
+                first_error.get_or_insert(e);
+            }
+        }
+    }
+    match (written, first_error) {
+        (Some(mut report), _) => {
+            report.timings = timings;
+            Ok(report)
+        }
+        (None, Some(e)) => Err(e),
+        (None, None) => unreachable!("entries with several outputs have at least one"),
+    }
+}
+
+/// Writes every output of `info` from a single decode of the source, each
+/// extended to its own ratio with its own settings.
+///
+/// Animations and multi-page sources, which are streamed, go through
+/// `extend_file` once per output instead. An output that fails is reported
+/// without stopping the others, and the entry only fails when all of them
+/// do.
+fn extend_outputs(info: &ImageInfo) -> Result<ImageReport, ExtendError> {
+    let started = Instant::now();
+    let src = info.source.as_str();
+    if let Some(problem) = validate::outputs(info).into_iter().next() {
+        return Err(ExtendError::new(problem.stage, Some(src), problem));
+    }
+    let outputs: Vec<ImageInfo> = info
+        .outputs
+        .iter()
+        .map(|output| info.output_entry(output))
+        .collect();
+    let mut in_place = Vec::with_capacity(outputs.len());
+    for output in &outputs {
+        in_place.push(check_in_place(src, &output.destination, &output.options)?);
+    }
+
+    let source_format = probe_format(src)?;
+    if let Some(format) = source_format {
+        format::ensure_compiled(format).context(Stage::Decode, src)?;
+    }
+    if !in_memory(src)
+        && (is_animated(src, source_format) || source_format == Some(ImageFormat::Tiff))
+    {
+        let results = outputs
+            .iter()
+            .map(|output| {
+                let aspect_ratio = output.resolve_ratio()?;
+                let mut report =
+                    extend_file(src, &output.destination, aspect_ratio, &output.options)?;
+                conclude(output, aspect_ratio, &mut report)?;
+                Ok(report)
+            })
+            .collect();
+        return combine_outputs(&outputs, results);
+    }
+
+    timeout::checkpoint(Stage::Decode, src)?;
+    let (img, reencode, metadata, permit) = read_still(src, source_format, &info.options)?;
+    let decode_ms = started.elapsed().as_secs_f64() * 1000.0;
+    let still = Still {
+        img: &img,
+        reencode,
+        metadata: &metadata,
+    };
+    // Each output composites under a permit of the decode stage, as it
+    // would have decoded the source itself
+    let mut permit = Some(permit);
+    let results = outputs
+        .iter()
+        .zip(in_place)
+        .map(|(output, in_place)| {
+            let options = &output.options;
+            let aspect_ratio = output
+                .aspect_ratio
+                .resolve(options.oriented(img.dimensions()), options.allowed_ratios())
+                .context(Stage::Composite, src)?;
+            let timings = Timings {
+                decode_ms,
+                ..Timings::default()
+            };
+            let permit = permit
+                .take()
+                .unwrap_or_else(|| stages::acquire(Stage::Decode));
+            let mut report = write_still(
+                src,
+                &output.destination,
+                &still,
+                aspect_ratio,
+                options,
+                in_place,
+                permit,
+                timings,
+            )?;
+            conclude(output, aspect_ratio, &mut report)?;
+            Ok(report)
+        })
+        .collect();
+    combine_outputs(&outputs, results)
+}
+
+/// The report of an entry with outputs: the first one written, listing them
+/// all. When every output failed, the first failure.
+fn combine_outputs(
+    outputs: &[ImageInfo],
+    results: Vec<Result<ImageReport, ExtendError>>,
+) -> Result<ImageReport, ExtendError> {
+    let listed = outputs
+        .iter()
+        .zip(&results)
+        .map(|(output, result)| {
+            let report = result.as_ref().ok();
+            OutputReport {
+                destination: output.destination.clone(),
+                aspect_ratio: report.and_then(|report| report.aspect_ratio).or(
+                    match output.aspect_ratio {
+                        AspectRatio::Fixed(sides) => Some(sides),
+                        AspectRatio::Auto => None,
+                    },
+                ),
+                output_width: report.map(|report| report.output_width),
+                output_height: report.map(|report| report.output_height),
+                error: result.as_ref().err().map(ErrorInfo::new),
+            }
+        })
+        .collect();
+    let mut report = first_written(results)?;
+    report.outputs = listed;
+    Ok(report)
+}
+
+/// Encodes `img` for `dest`, as `format` or the format its extension implies
+/// with the settings of `encoding`, with as much of `metadata` as the format
+/// can hold.
+fn encode(
+    img: DynamicImage,
+    format: Option<OutputFormat>,
+    encoding: format::Encoding,
+    dest: &str,
+    metadata: &metadata::Metadata,
+) -> Result<Vec<u8>, ExtendError> {
+    let name = storage::name(dest);
+    let format = match format {
+        Some(format) => format.image_format(),
+        None if OutputFormat::from_path(name) == Some(OutputFormat::Jxl) => None,
+        None => Some(ImageFormat::from_path(name).context(Stage::Write, dest)?),
+    };
+    let format = match format {
+        Some(format) => format,
+        // The JPEG XL encoder takes no metadata
+        None => return format::encode_jxl(&img, encoding).context(Stage::Write, dest),
+    };
+    let encoded = format::encode(&img, format, encoding).context(Stage::Write, dest)?;
+    Ok(metadata::embed(encoded, format, metadata))
+}
+
+/// Extends an encoded image held in memory and returns the encoded result.
+///
+/// The output is encoded as `format`, or in the format of the input when `None`.
+pub fn extend_bytes(
+    data: &[u8],
+    aspect_ratio: (u32, u32),
+    format: Option<ImageFormat>,
+) -> Result<Vec<u8>, ExtendError> {
+    extend_bytes_with(data, aspect_ratio, format, &Options::default())
+}
+
+/// Fails settings of the functions without a job list that cannot work, as
+/// `validate::settings` finds them.
+fn check_settings(aspect_ratio: (u32, u32), options: &Options) -> Result<(), ExtendError> {
+    match validate::settings(AspectRatio::Fixed(aspect_ratio), options)
+        .into_iter()
+        .next()
+    {
+        Some(problem) => Err(ExtendError::new(problem.stage, None, problem)),
+        None => Ok(()),
+    }
+}
+
+/// `extend_bytes` drawing and encoding the image as `options` say, in the
+/// `outputFormat` of `options` when `format` is `None`. JPEG XL, which
+/// `ImageFormat` has no variant for, is only asked for that way.
+pub fn extend_bytes_with(
+    data: &[u8],
+    aspect_ratio: (u32, u32),
+    format: Option<ImageFormat>,
+    options: &Options,
+) -> Result<Vec<u8>, ExtendError> {
+    check_settings(aspect_ratio, options)?;
+    let reader = ImageReader::new(Cursor::new(data))
+        .with_guessed_format()
+        .stage(Stage::Read)?;
+    let input_format = reader.format();
+    if let Some(format) = input_format {
+        format::ensure_compiled(format).stage(Stage::Decode)?;
+    }
+    let jxl = format.is_none() && options.output_format == Some(OutputFormat::Jxl);
+    let format = format.or_else(|| options.output_format.and_then(OutputFormat::image_format));
+    if jxl {
+        let encoder = OutputFormat::Jxl.encoder();
+        encoder.ensure_writable(false).stage(Stage::Write)?;
+    } else if let Some(format) = format {
+        format::ensure_writable(format, false).stage(Stage::Write)?;
+    }
+    let (mut img, _) = decode_data(data, input_format, options)
+        .map_err(|e| CorruptSource::classify(None, data.len() as u64, e))
+        .stage(Stage::Decode)?;
+    check_dimensions(img.dimensions(), None).stage(Stage::Decode)?;
+    let mut metadata = metadata::read(data, options.keep_metadata);
+    let turned = options.auto_orient() && metadata.turn_upright(&mut img);
+    let format = match format.or(input_format) {
+        _ if jxl => None,
+        Some(format) => Some(format),
+        None => {
+            return Err(ExtendError::new(
+                Stage::Write,
+                None,
+                ImageError::Unsupported(ImageFormatHint::Unknown.into()),
+            ))
+        }
+    };
+
+    let new_img = match extend(&img, aspect_ratio, options).stage(Stage::Composite)? {
+        Some(extended) => extended.into_image(&img),
+        None if format.is_some() && format == input_format && !options.reorients() && !turned => {
+            return Ok(data.to_vec())
+        }
+        None => options.orient(img),
+    };
+    let format = match format {
+        Some(format) => format,
+        None => {
+            return format::encode_jxl(&new_img, format::Encoding::of(options)).stage(Stage::Write)
+        }
+    };
+
+    let encoded =
+        format::encode(&new_img, format, format::Encoding::of(options)).stage(Stage::Write)?;
+    Ok(metadata::embed(
+        encoded,
+        format,
+        &metadata.oriented(options),
+    ))
+}
+
+/// Extends an image already decoded, without reading or writing any file.
+///
+/// An image that fits the aspect ratio already comes back as it is, turned
+/// as `rotate` and `flip` ask. Extended images are 16-bit for 16-bit
+/// sources and 8-bit otherwise, RGB or gray like the source when the canvas
+/// needs no more, and RGBA otherwise.
+pub fn extend_image(
+    img: &DynamicImage,
+    aspect_ratio: (u32, u32),
+    options: &Options,
+) -> Result<DynamicImage, ExtendError> {
+    check_settings(aspect_ratio, options)?;
+    check_dimensions(img.dimensions(), None).stage(Stage::Decode)?;
+    Ok(
+        match extend(img, aspect_ratio, options).stage(Stage::Composite)? {
+            Some(extended) => extended.into_image(img),
+            None => options.orient(img.clone()),
+        },
+    )
+}
+
+/// Pixels returned by `extend_raw`: 8-bit RGBA, rows tightly packed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RawOutput {
+    pub width: u32,
+    pub height: u32,
+    pub data: Vec<u8>,
+}
+
+/// Extends raw 8-bit RGBA pixels, `width * height * 4` bytes with no padding
+/// between rows, without anything being decoded or encoded.
+///
+/// Buffers of row strides other than `width * 4` are refused, as their
+/// length cannot tell them apart; such rows have to be packed first.
+pub fn extend_raw(
+    buf: &[u8],
+    width: u32,
+    height: u32,
+    aspect_ratio: (u32, u32),
+    options: &Options,
+) -> Result<RawOutput, ExtendError> {
+    check_settings(aspect_ratio, options)?;
+    if width == 0 || height == 0 {
+        return Err(ExtendError::new(
+            Stage::Decode,
+            None,
+            format!("{}x{} pixels is no image", width, height),
+        ));
+    }
+    if width as u64 * height as u64 * 4 != buf.len() as u64 {
+        return Err(ExtendError::new(
+            Stage::Decode,
+            None,
+            format!(
+                "buffer of {} bytes is not {}x{} RGBA pixels, which take {} bytes \
+                 with rows tightly packed",
+                buf.len(),
+                width,
+                height,
+                width as u64 * height as u64 * 4
+            ),
+        ));
+    }
+    let img = image::RgbaImage::from_raw(width, height, buf.to_vec())
+        .map(DynamicImage::ImageRgba8)
+        .ok_or("buffer does not fit its dimensions")
+        .stage(Stage::Decode)?;
+    let image = extend_image(&img, aspect_ratio, options)?.into_rgba8();
+    Ok(RawOutput {
+        width: image.width(),
+        height: image.height(),
+        data: image.into_raw(),
+    })
+}
+
+impl ImageInfo {
+    /// An entry with the default options, as the job list would give it.
+    pub fn new(
+        source: impl Into<String>,
+        destination: impl Into<String>,
+        aspect_ratio: AspectRatio,
+    ) -> Self {
+        ImageInfo {
+            source: source.into(),
+            destination: destination.into(),
+            aspect_ratio,
+            variants: Vec::new(),
+            outputs: Vec::new(),
+            options: Options::default(),
+            duplicates: 0,
+        }
+    }
+
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    pub fn destination(&self) -> &str {
+        &self.destination
+    }
+
+    pub fn aspect_ratio(&self) -> AspectRatio {
+        self.aspect_ratio
+    }
+
+    /// The ratio the source is extended to, which for `"auto"` takes
+    /// reading the dimensions of the source.
+    pub fn resolve_ratio(&self) -> Result<(u32, u32), ExtendError> {
+        if let AspectRatio::Fixed(sides) = self.aspect_ratio {
+            return Ok(sides);
+        }
+        check_fields(
+            &self.source,
+            &self.destination,
+            self.aspect_ratio,
+            &self.options,
+        )?;
+        let dimensions = source_dimensions(&self.source, &self.options)?;
+        self.aspect_ratio
+            .resolve(dimensions, self.options.allowed_ratios())
+            .context(Stage::Composite, &self.source)
+    }
+
+    pub fn options(&self) -> &Options {
+        &self.options
+    }
+
+    pub fn variants(&self) -> &[Variant] {
+        &self.variants
+    }
+
+    /// Every file the entry writes: one per variant or output, or just the
+    /// destination.
+    pub fn outputs(&self) -> Vec<String> {
+        if !self.outputs.is_empty() {
+            return self
+                .outputs
+                .iter()
+                .map(|output| output.destination.clone())
+                .collect();
+        }
+        if self.variants.is_empty() {
+            return vec![self.destination.clone()];
+        }
+        self.variants
+            .iter()
+            .map(|variant| variant.destination(&self.destination))
+            .collect()
+    }
+
+    /// `output` as an entry of its own, with the source of this one.
+    fn output_entry(&self, output: &Output) -> ImageInfo {
+        ImageInfo {
+            source: self.source.clone(),
+            destination: output.destination.clone(),
+            aspect_ratio: output.aspect_ratio,
+            variants: Vec::new(),
+            outputs: Vec::new(),
+            options: output.options.clone(),
+            duplicates: 0,
+        }
+    }
+
+    /// The options of `variant`, which only differ in the background.
+    fn variant_options(&self, variant: &Variant) -> Options {
+        let mut options = self.options.clone();
+        options.style.background = variant.background;
+        options
+    }
+
+    /// Every setting of the entry and its variants that cannot work, such
+    /// as an aspect ratio with a side of zero, without touching the file
+    /// system. Processing the entry fails on the first of them.
+    pub fn validate(&self) -> Vec<validate::Invalid> {
+        if !self.outputs.is_empty() {
+            return validate::outputs(self);
+        }
+        let mut problems = validate::fields(&self.destination, self.aspect_ratio, &self.options);
+        problems.extend(validate::variants(self));
+        problems
+    }
+
+    /// Number of identical entries `batch::dedupe` folded into this one.
+    pub fn duplicates(&self) -> u32 {
+        self.duplicates
+    }
+
+    pub fn options_mut(&mut self) -> &mut Options {
+        &mut self.options
+    }
+
+    /// The options of the entry and of each of its outputs.
+    pub fn all_options_mut(&mut self) -> impl Iterator<Item = &mut Options> {
+        std::iter::once(&mut self.options)
+            .chain(self.outputs.iter_mut().map(|output| &mut output.options))
+    }
+
+    /// Puts `base` in front of the relative paths of the entry: the source,
+    /// the destinations, the watermark and the caption font. `-` is left
+    /// alone, as it stands for stdin or stdout, and so are URLs.
+    pub fn resolve_paths(&mut self, base: &Path) {
+        let resolve = |path: &mut String| {
+            if path != "-" && !storage::is_remote(path) && Path::new(path.as_str()).is_relative() {
+                *path = base.join(&*path).to_string_lossy().into_owned();
+            }
+        };
+        resolve(&mut self.source);
+        resolve(&mut self.destination);
+        for variant in &mut self.variants {
+            if let Some(destination) = &mut variant.destination {
+                resolve(destination);
+            }
+        }
+        for output in &mut self.outputs {
+            resolve(&mut output.destination);
+        }
+        for options in self.all_options_mut() {
+            if let Some(watermark) = &mut options.style.watermark {
+                resolve(&mut watermark.path);
+            }
+            if let Some(caption) = &mut options.style.caption {
+                resolve(&mut caption.font);
+            }
+        }
+    }
+
+    /// Whether the entry can be skipped under `skipUpToDate`.
+    pub fn is_up_to_date(&self) -> bool {
+        self.options.skip_up_to_date && incremental::is_up_to_date(self)
+    }
+
+    /// Whether the entry is to be skipped under `overwrite: skip`, as an
+    /// output of it exists.
+    pub fn keeps_existing(&self) -> bool {
+        self.options.overwrite() == Overwrite::Skip && self.existing_output().is_some()
+    }
+
+    /// The first output of the entry that is on disk already, other than
+    /// the source an `inPlace` entry replaces. Outputs in an archive, which
+    /// is written anew, and remote ones are never found.
+    pub(crate) fn existing_output(&self) -> Option<String> {
+        self.outputs().into_iter().find(|dest| {
+            if archive::split(dest).is_some() || storage::is_remote(dest) {
+                return false;
+            }
+            #[cfg(feature = "tiff")]
+            let dest = &dest.replace(pages::PAGE_PLACEHOLDER, "1");
+            Path::new(dest).exists()
+                && !(self.options.in_place
+                    && atomic::same_file(&self.source, dest).unwrap_or(false))
+        })
+    }
+
+    /// The entry written to the first `-1`, `-2` and so on after the stem of
+    /// its destination, or of those of its `outputs`, that none of its
+    /// outputs exist under.
+    fn renamed(&self) -> ImageInfo {
+        let numbered = |dest: &str, n: u32| {
+            let path = Path::new(dest);
+            let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+            let extension = path
+                .extension()
+                .map(|ext| format!(".{}", ext.to_string_lossy()))
+                .unwrap_or_default();
+            let name = format!("{}-{}{}", stem, n, extension);
+            path.with_file_name(name).to_string_lossy().into_owned()
+        };
+        let mut renamed = self.clone();
+        for n in 1.. {
+            renamed.destination = numbered(&self.destination, n);
+            for (output, original) in renamed.outputs.iter_mut().zip(&self.outputs) {
+                output.destination = numbered(&original.destination, n);
+            }
+            if renamed.existing_output().is_none() {
+                break;
+            }
+        }
+        renamed
+    }
+}
+
+pub fn compile_image(info: &ImageInfo) -> Result<ImageReport, ExtendError> {
+    #[cfg(feature = "tracing")]
+    let _span = logging::span(info);
+    let result = match info.options.timeout {
+        Some(seconds) => {
+            let info = info.clone();
+            timeout::run(
+                Duration::from_secs(seconds),
+                &info.source.clone(),
+                move || contain_panics(&info),
+            )
+        }
+        None => contain_panics(info),
+    };
+    #[cfg(feature = "tracing")]
+    logging::finished(&result);
+    result
+}
+
+/// Runs `compile_image` for every entry on up to `jobs` workers and hands
+/// each result to `report`, on the calling thread.
+///
+/// Results come in job list order, each as soon as it and the ones before
+/// it are done, so output stays the same however many jobs there are.
+pub fn compile_images_parallel(
+    entries: Vec<ImageInfo>,
+    jobs: usize,
+    report: impl FnMut(ImageInfo, Result<ImageReport, ExtendError>),
+) {
+    run_batch(entries, jobs, None, report)
+}
+
+/// `compile_images_parallel`, telling `progress` on the workers as each
+/// entry is started and finished, for progress bars of long batches.
+pub fn compile_images_with_progress(
+    entries: Vec<ImageInfo>,
+    jobs: usize,
+    progress: impl Fn(pipeline::Progress<'_>) + Send + Sync + 'static,
+    report: impl FnMut(ImageInfo, Result<ImageReport, ExtendError>),
+) {
+    run_batch(entries, jobs, Some(Arc::new(progress)), report)
+}
+
+fn run_batch(
+    entries: Vec<ImageInfo>,
+    jobs: usize,
+    progress: Option<pipeline::ProgressFn>,
+    mut report: impl FnMut(ImageInfo, Result<ImageReport, ExtendError>),
+) {
+    let pipeline = pipeline::Pipeline {
+        limits: StageLimits::uniform(jobs),
+        progress,
+        max_in_flight: None,
+        interrupted: None,
+    };
+    pipeline.run(entries, compile_image, |info, result| {
+        report(info, result);
+        true
+    });
+}
+
+/// `process_entry`, with a panic turned into an `InternalPanic` error of the
+/// entry. Nothing it leaves behind is used again: temporary outputs are
+/// removed as they are dropped, and locks are taken regardless of poisoning.
+fn contain_panics(info: &ImageInfo) -> Result<ImageReport, ExtendError> {
+    contained(info, || process_entry(info))
+}
+
+/// Runs `work` on the entry `info`, failing it with an `InternalPanic` at the
+/// stage it reached when `work` panics.
+pub(crate) fn contained<T>(
+    info: &ImageInfo,
+    work: impl FnOnce() -> Result<T, ExtendError>,
+) -> Result<T, ExtendError> {
+    timeout::reset_stage();
+    panic::catch_unwind(AssertUnwindSafe(work)).unwrap_or_else(|payload| {
+        Err(ExtendError::new(
+            timeout::stage(),
+            Some(&info.source),
+            InternalPanic::new(&info.source, &*payload),
+        ))
+    })
+}
+
+fn process_entry(info: &ImageInfo) -> Result<ImageReport, ExtendError> {
+    let renamed = match (info.options.overwrite(), info.existing_output()) {
+        (Overwrite::Replace, _) | (_, None) => None,
+        (Overwrite::Rename, Some(_)) => Some(info.renamed()),
+        (overwrite, Some(dest)) => {
+            return Err(ExtendError::new(
+                Stage::Write,
+                Some(&dest),
+                format!("destination exists, and overwrite is {}", overwrite.name()),
+            ))
+        }
+    };
+    let info = renamed.as_ref().unwrap_or(info);
+    let mut report = retry::run(&info.options, || {
+        if !info.outputs.is_empty() {
+            return extend_outputs(info);
+        }
+        let aspect_ratio = info.resolve_ratio()?;
+        let mut report = if info.variants.is_empty() {
+            extend_file(&info.source, &info.destination, aspect_ratio, &info.options)?
+        } else {
+            extend_variants(info, aspect_ratio)?
+        };
+        conclude(info, aspect_ratio, &mut report)?;
+        Ok(report)
+    })?;
+    if info.options.skip_up_to_date {
+        incremental::record(info)?;
+    }
+    // Outputs list their own destinations
+    report.renamed = renamed
+        .filter(|renamed| renamed.outputs.is_empty())
+        .map(|renamed| renamed.destination);
+    Ok(report)
+}
+
+/// Fills in what `report` of `info` tells of `aspect_ratio`, and writes the
+/// `sidecar` unless the entry has variants, which write their own.
+fn conclude(
+    info: &ImageInfo,
+    aspect_ratio: (u32, u32),
+    report: &mut ImageReport,
+) -> Result<(), ExtendError> {
+    if info.aspect_ratio == AspectRatio::Auto {
+        report.aspect_ratio = Some(aspect_ratio);
+    }
+    report.tolerated = info
+        .options
+        .tolerates((report.source_width, report.source_height), aspect_ratio);
+    if info.variants.is_empty() && info.options.sidecar {
+        write_analysis(
+            info,
+            &info.destination,
+            info.options.style.background,
+            report,
+        )?;
+    }
+    Ok(())
+}
+
+/// Writes the `sidecar` of the output at `dest`. For `{page}` destinations it
+/// sits next to the first page, which the report describes.
+fn write_analysis(
+    info: &ImageInfo,
+    dest: &str,
+    background: Background,
+    report: &ImageReport,
+) -> Result<(), ExtendError> {
+    #[cfg(feature = "tiff")]
+    let dest = &dest.replace(pages::PAGE_PLACEHOLDER, "1");
+    let path = format!("{}.json", dest);
+    let analysis = Analysis::new(&info.source, dest, background, report);
+    let data = serde_json::to_vec(&analysis).context(Stage::Write, &path)?;
+    atomic::write(&path, |temp| {
+        std::fs::write(temp, &data).context(Stage::Write, &path)
+    })
+}
+
+/// Works out what `compile_image` would do for `info` without writing anything.
+///
+/// Only the image header is read unless `sample_colors` is set, in which
+/// case the source is decoded to pick the background colors.
+pub fn plan_image(info: &ImageInfo, sample_colors: bool) -> Result<Plan, ExtendError> {
+    let src = info.source.as_str();
+    if !info.outputs.is_empty() {
+        if let Some(problem) = validate::outputs(info).into_iter().next() {
+            return Err(ExtendError::new(problem.stage, Some(src), problem));
+        }
+        let outputs = info
+            .outputs
+            .iter()
+            .map(|output| {
+                Ok(OutputPlan {
+                    destination: output.destination.clone(),
+                    plan: plan_image(&info.output_entry(output), sample_colors)?,
+                })
+            })
+            .collect::<Result<Vec<_>, ExtendError>>()?;
+        let mut plan = outputs[0].plan.clone();
+        plan.outputs = outputs;
+        return Ok(plan);
+    }
+    check_fields(src, &info.destination, info.aspect_ratio, &info.options)?;
+    check_in_place(src, &info.destination, &info.options)?;
+    let source_format = probe_format(src)?;
+    // Sources in an archive or remote are read whole, and decoded for their
+    // header too
+    let data = if in_memory(src) {
+        Some(read_source(src)?)
+    } else {
+        None
+    };
+    let heif = source_format.is_none()
+        && match &data {
+            Some(data) => heif::is_heif(data),
+            None => heif::is_heif_file(src).context(Stage::Read, src)?,
+        };
+    let decode = || -> Result<DynamicImage, ExtendError> {
+        if let Some(data) = &data {
+            decode_data(data, source_format, &info.options).map(|(img, _)| img)
+        } else if heif {
+            heif::decode(&std::fs::read(src).context(Stage::Read, src)?)
+        } else {
+            ImageReader::open(src)
+                .context(Stage::Read, src)?
+                .decode()
+                .map_err(BoxError::from)
+        }
+        .map_err(|e| CorruptSource::classify_file(src, e))
+        .context(Stage::Decode, src)
+    };
+
+    let orientation = source_orientation(src, data.as_deref(), &info.options);
+    let mut decoded = None;
+    let dimensions = if heif || sample_colors || data.is_some() {
+        let img = info
+            .options
+            .orient(metadata::upright(decode()?, orientation));
+        let dimensions = img.dimensions();
+        decoded = Some(img);
+        dimensions
+    } else {
+        info.options.oriented(metadata::upright_dimensions(
+            ImageReader::open(src)
+                .context(Stage::Read, src)?
+                .into_dimensions()
+                .map_err(|e| CorruptSource::classify_file(src, e))
+                .context(Stage::Decode, src)?,
+            orientation,
+        ))
+    };
+    check_dimensions(dimensions, Some(src)).context(Stage::Decode, src)?;
+    check_source_pixels(dimensions, &info.options).context(Stage::Decode, src)?;
+    let aspect_ratio = info
+        .aspect_ratio
+        .resolve(dimensions, info.options.allowed_ratios())
+        .context(Stage::Composite, src)?;
+
+    let crop = match info.options.mode_for(dimensions, aspect_ratio) {
+        Mode::Crop => {
+            Some(crop_for(dimensions, aspect_ratio, &info.options).context(Stage::Composite, src)?)
+        }
+        Mode::Extend | Mode::SeamCarve | Mode::ExtendOrCrop | Mode::ZoomToFill => None,
+    };
+    let layout = match crop {
+        Some(_) => None,
+        None => {
+            layout_for(dimensions, aspect_ratio, &info.options).context(Stage::Composite, src)?
+        }
+    };
+    let unchanged = match crop {
+        Some(crop) => crop.is_none(),
+        None => layout.is_none(),
+    };
+    let crop = crop.flatten();
+    let colors = match (&layout, &decoded) {
+        (Some(layout), Some(img)) => {
+            let edges = aggregate_edge_colors(
+                &normalise_image(img, layout.overflow),
+                layout.orientation,
+                &info.options.style,
+            );
+            let ((first, second), _) = pick_colors(edges.colors, &info.options.style);
+            vec![first.0, second.0]
+        }
+        _ => Vec::new(),
+    };
+    // Mirrors the unchanged path of `extend_file`: animated GIFs are copied
+    // only into GIFs, and anything with an explicit format or turned upright
+    // is re-encoded
+    let copy = unchanged
+        && info.options.max_size(dimensions).is_none()
+        && !heif
+        && orientation == 1
+        && match (source_format, info.options.output_format) {
+            (Some(ImageFormat::Gif), format) => {
+                format.or_else(|| OutputFormat::from_path(storage::name(&info.destination)))
+                    == Some(OutputFormat::Gif)
+            }
+            (_, format) => format.is_none(),
+        };
+
+    let canvas = match (crop, layout) {
+        (Some(crop), _) => crop.size(),
+        (None, Some(layout)) => layout.canvas,
+        (None, None) => dimensions,
+    };
+    let canvas = info.options.max_size(canvas).unwrap_or(canvas);
 
-    Ok(dest)
+    Ok(Plan {
+        source_width: dimensions.0,
+        source_height: dimensions.1,
+        aspect_ratio: (info.aspect_ratio == AspectRatio::Auto).then_some(aspect_ratio),
+        tolerated: info.options.tolerates(dimensions, aspect_ratio),
+        orientation: layout.map(|layout| layout.orientation),
+        crop: match (crop, layout) {
+            (Some(crop), _) => crop.removed(),
+            (None, layout) => layout.map_or((0, 0), |layout| layout.overflow),
+        },
+        trim: crop.map(|crop| crop.trim()),
+        canvas_width: canvas.0,
+        canvas_height: canvas.1,
+        colors,
+        copy,
+        outputs: Vec::new(),
+    })
 }
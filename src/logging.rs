@@ -0,0 +1,292 @@
+//! `tracing` spans for entries, and a subscriber writing them to stderr for
+//! `--log-level`.
+//!
+//! Each entry `compile_image` processes runs in an `image` span with its
+//! `source` and `destination`, and ends with an event giving the orientation
+//! and size of the canvas and how long decoding, compositing and writing
+//! took, or the error it failed with. Library users see them through
+//! whichever subscriber they install. `init` installs one printing a line
+//! per event, as text or as a JSON object, with the fields of the spans it
+//! happened in.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::io::Write;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde_json::{Map, Value};
+use tracing::field::{Field, Visit};
+use tracing::level_filters::LevelFilter;
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Level, Metadata, Subscriber};
+
+use crate::{ExtendError, ImageInfo, ImageReport};
+
+/// The span an entry is processed in, at the error level so that its
+/// fields go with whatever is logged about the entry.
+pub(crate) fn span(info: &ImageInfo) -> tracing::span::EnteredSpan {
+    tracing::error_span!(
+        "image",
+        source = %info.source,
+        destination = %info.destination,
+    )
+    .entered()
+}
+
+/// Sides of the canvas the image was given room on, from its report.
+fn orientation(report: &ImageReport) -> &'static str {
+    match report.placement {
+        Some(placement) if placement.height < report.output_height => "landscape",
+        Some(placement) if placement.width < report.output_width => "portrait",
+        _ => "none",
+    }
+}
+
+/// Logs how the entry of the current span ended.
+pub(crate) fn finished(result: &Result<ImageReport, ExtendError>) {
+    match result {
+        Ok(report) => tracing::info!(
+            orientation = orientation(report),
+            canvas = %format_args!("{}x{}", report.output_width, report.output_height),
+            copied = report.copied,
+            decode_ms = report.timings.decode_ms,
+            composite_ms = report.timings.composite_ms,
+            write_ms = report.timings.write_ms,
+            "extended"
+        ),
+        Err(e) => tracing::error!(
+            stage = ?e.stage(),
+            kind = e.kind().name(),
+            error = %e,
+            "failed"
+        ),
+    }
+}
+
+/// How `init` writes each event.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LogFormat {
+    /// `LEVEL span{field=value}: message field=value`.
+    Text,
+    /// A JSON object a line, for log aggregation.
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(format!("unknown log format {}, expected text or json", s)),
+        }
+    }
+}
+
+/// Writes the events of `level` and above to stderr as `format` says, for
+/// the rest of the process.
+pub fn init(level: Level, format: LogFormat) -> Result<(), String> {
+    let logger = Logger {
+        level,
+        format,
+        next_id: AtomicU64::new(1),
+        spans: Mutex::new(HashMap::new()),
+    };
+    tracing::subscriber::set_global_default(logger).map_err(|e| e.to_string())
+}
+
+struct SpanData {
+    name: &'static str,
+    fields: Map<String, Value>,
+    references: usize,
+}
+
+struct Logger {
+    level: Level,
+    format: LogFormat,
+    next_id: AtomicU64,
+    spans: Mutex<HashMap<u64, SpanData>>,
+}
+
+thread_local! {
+    /// Spans entered on this thread, innermost last.
+    static ENTERED: RefCell<Vec<u64>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Collects the fields of a span or event.
+struct Fields<'a>(&'a mut Map<String, Value>);
+
+impl Visit for Fields<'_> {
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.0.insert(field.name().into(), value.into());
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.0.insert(field.name().into(), value.into());
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.0.insert(field.name().into(), value.into());
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.0.insert(field.name().into(), value.into());
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name().into(), value.into());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.0
+            .insert(field.name().into(), format!("{:?}", value).into());
+    }
+}
+
+impl Logger {
+    fn spans(&self) -> std::sync::MutexGuard<'_, HashMap<u64, SpanData>> {
+        self.spans.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn text(
+        &self,
+        level: &Level,
+        fields: &Map<String, Value>,
+        spans: &[(&str, Map<String, Value>)],
+    ) -> String {
+        // Strings without the quotes of JSON
+        let pairs = |fields: &Map<String, Value>| {
+            fields
+                .iter()
+                .filter(|(name, _)| *name != "message")
+                .map(|(name, value)| match value.as_str() {
+                    Some(value) => format!("{}={}", name, value),
+                    None => format!("{}={}", name, value),
+                })
+                .collect::<Vec<_>>()
+                .join(" ")
+        };
+        let mut line = format!("{:>5} ", level);
+        for (name, fields) in spans {
+            line.push_str(&format!("{}{{{}}}: ", name, pairs(fields)));
+        }
+        if let Some(Value::String(message)) = fields.get("message") {
+            line.push_str(message);
+        }
+        let rest = pairs(fields);
+        if !rest.is_empty() {
+            line.push(' ');
+            line.push_str(&rest);
+        }
+        line
+    }
+}
+
+impl Subscriber for Logger {
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        *metadata.level() <= self.level
+    }
+
+    fn max_level_hint(&self) -> Option<LevelFilter> {
+        Some(LevelFilter::from_level(self.level))
+    }
+
+    fn new_span(&self, span: &Attributes<'_>) -> Id {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let mut fields = Map::new();
+        span.record(&mut Fields(&mut fields));
+        self.spans().insert(
+            id,
+            SpanData {
+                name: span.metadata().name(),
+                fields,
+                references: 1,
+            },
+        );
+        Id::from_u64(id)
+    }
+
+    fn record(&self, span: &Id, values: &Record<'_>) {
+        if let Some(data) = self.spans().get_mut(&span.into_u64()) {
+            values.record(&mut Fields(&mut data.fields));
+        }
+    }
+
+    fn record_follows_from(&self, _span: &Id, _follows: &Id) {}
+
+    fn event(&self, event: &Event<'_>) {
+        let mut fields = Map::new();
+        event.record(&mut Fields(&mut fields));
+        let entered = ENTERED.with(|entered| entered.borrow().clone());
+        let spans: Vec<(&str, Map<String, Value>)> = {
+            let spans = self.spans();
+            entered
+                .iter()
+                .filter_map(|id| spans.get(id))
+                .map(|span| (span.name, span.fields.clone()))
+                .collect()
+        };
+        let metadata = event.metadata();
+        let line = match self.format {
+            LogFormat::Text => self.text(metadata.level(), &fields, &spans),
+            LogFormat::Json => {
+                let mut object = Map::new();
+                let timestamp = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map_or(0, |since| since.as_millis() as u64);
+                object.insert("timestamp".into(), timestamp.into());
+                object.insert("level".into(), metadata.level().as_str().into());
+                object.insert("target".into(), metadata.target().into());
+                // The fields of the innermost span win over the outer ones
+                for (name, span_fields) in &spans {
+                    object.insert("span".into(), (*name).into());
+                    object.extend(span_fields.clone());
+                }
+                object.extend(fields);
+                Value::Object(object).to_string()
+            }
+        };
+        // Nowhere to report a log that could not be written
+        let _ = writeln!(std::io::stderr().lock(), "{}", line);
+    }
+
+    fn enter(&self, span: &Id) {
+        ENTERED.with(|entered| entered.borrow_mut().push(span.into_u64()));
+    }
+
+    fn exit(&self, span: &Id) {
+        ENTERED.with(|entered| {
+            let mut entered = entered.borrow_mut();
+            if let Some(at) = entered.iter().rposition(|&id| id == span.into_u64()) {
+                entered.remove(at);
+            }
+        });
+    }
+
+    fn clone_span(&self, span: &Id) -> Id {
+        if let Some(data) = self.spans().get_mut(&span.into_u64()) {
+            data.references += 1;
+        }
+        span.clone()
+    }
+
+    fn try_close(&self, span: Id) -> bool {
+        let mut spans = self.spans();
+        match spans.get_mut(&span.into_u64()) {
+            Some(data) if data.references > 1 => {
+                data.references -= 1;
+                false
+            }
+            Some(_) => {
+                spans.remove(&span.into_u64());
+                true
+            }
+            None => false,
+        }
+    }
+}
@@ -1,19 +1,1723 @@
-use std::io;
+use std::io::{self, BufRead};
+use std::process::ExitCode;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::Arc;
+use std::time::Duration;
 
-fn main() -> io::Result<()> {
-    let handle = io::stdin();
-    let info_list: Vec<image_bg_extender::ImageInfo> = serde_json::from_reader(handle)?;
+#[derive(PartialEq)]
+enum OutputMode {
+    Text,
+    Json,
+}
+
+#[derive(PartialEq, Clone, Copy)]
+enum DryRun {
+    /// Read only the image headers.
+    Header,
+    /// Decode the sources as well to sample the background colors.
+    Full,
+}
+
+#[derive(PartialEq, PartialOrd, Clone, Copy)]
+enum Verbosity {
+    /// Only errors.
+    Quiet,
+    /// One line per image.
+    Normal,
+    /// Dimensions, crop, colors and stage timings for every image, on stderr.
+    Verbose,
+}
+
+struct Args {
+    serve: Option<String>,
+    /// Directory whose new images are processed as they are dropped in.
+    watch: Option<String>,
+    /// File holding the job list, stdin when `None` or `-`.
+    input: Option<String>,
+    /// Directory that relative paths in the job list are taken from,
+    /// instead of the working directory.
+    relative_to: Option<String>,
+    /// Directory every entry is written to, under the name of its source.
+    out_dir: Option<String>,
+    /// Fields every entry starts from, from `--config`.
+    defaults: image_bg_extender::config::Defaults,
+    /// Keep the relative path of each source under `out_dir`.
+    preserve_tree: bool,
+    /// First page of a contact sheet of every source next to its output.
+    contact_sheet: Option<String>,
+    sheet: image_bg_extender::contact_sheet::SheetOptions,
+    /// Manifest of every file written, with its SHA-256 and dimensions.
+    manifest: Option<String>,
+    output: OutputMode,
+    verbosity: Verbosity,
+    dry_run: Option<DryRun>,
+    /// Only validate the job list, without decoding or writing anything.
+    check: bool,
+    /// Stop at the first failing entry instead of carrying on.
+    fail_fast: bool,
+    /// Applies to entries that do not set `maxOutputPixels` themselves.
+    max_output_pixels: Option<u64>,
+    /// Applies to entries that do not set `maxSourcePixels` themselves.
+    max_source_pixels: Option<u64>,
+    /// Seconds per entry, for entries that do not set `timeout` themselves.
+    timeout: Option<u64>,
+    retries: Option<u32>,
+    retry_backoff_ms: Option<u64>,
+    /// For entries that do not set `resizeFilter` themselves.
+    resize_filter: Option<image_bg_extender::style::Filter>,
+    /// For entries that do not set `overwrite` themselves.
+    overwrite: Option<image_bg_extender::Overwrite>,
+    /// Lowest level of the `tracing` events logged to stderr, with
+    /// `--log-level`.
+    log_level: Option<String>,
+    /// `text` or `json`, with `--log-format`.
+    log_format: Option<String>,
+    /// Skip entries whose destination is up to date.
+    incremental: bool,
+    /// Process the entries `--incremental`, `skipUpToDate` or `--cache`
+    /// would skip, still recording them for the next run.
+    force: bool,
+    /// Write an analysis next to every output.
+    sidecar: bool,
+    /// Content-hash cache of finished entries.
+    cache: Option<String>,
+    /// Keep the last of several entries writing the same destination.
+    last_wins: bool,
+    /// Entries allowed in each stage at the same time.
+    limits: image_bg_extender::StageLimits,
+    /// Entries taken from the job list and not yet reported, at most, which
+    /// bounds how far ahead of the slowest entry a stream is read.
+    max_in_flight: Option<usize>,
+    /// Pipe mode: a single image from `--in` to `--out` instead of a job list.
+    pipe_in: Option<String>,
+    pipe_out: Option<String>,
+    ratio: Option<(u32, u32)>,
+    out_format: Option<image_bg_extender::OutputFormat>,
+    /// Background of the image in pipe mode.
+    mode: Option<image_bg_extender::style::Background>,
+    /// Read the job list from stdin, as when no job list is given.
+    stdin: bool,
+    /// The job list is one entry per line, each processed as it arrives.
+    ndjson: bool,
+    /// Draw a progress bar on stderr.
+    progress: bool,
+}
+
+/// Source or destination meaning stdin or stdout.
+const STDIO: &str = "-";
+
+fn invalid_input(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+/// Parses the value of a flag taking a positive count.
+fn count(flag: &str, value: Option<String>) -> io::Result<usize> {
+    value
+        .and_then(|value| value.parse().ok())
+        .filter(|&count| count > 0)
+        .ok_or_else(|| invalid_input(format!("{} expects a positive number", flag)))
+}
+
+fn value(flag: &str, value: Option<String>) -> io::Result<String> {
+    value.ok_or_else(|| invalid_input(format!("{} requires a value", flag)))
+}
+
+/// Takes the job list from `path`, given either positionally or with `--input`.
+fn set_input(args: &mut Args, path: String) -> io::Result<()> {
+    if let Some(input) = &args.input {
+        return Err(invalid_input(format!(
+            "job list given twice, as {} and {}",
+            input, path
+        )));
+    }
+    args.input = Some(path);
+    Ok(())
+}
+
+fn parse_args() -> io::Result<Args> {
+    let mut args = Args {
+        serve: None,
+        watch: None,
+        input: None,
+        relative_to: None,
+        out_dir: None,
+        defaults: image_bg_extender::config::Defaults::default(),
+        preserve_tree: false,
+        contact_sheet: None,
+        sheet: image_bg_extender::contact_sheet::SheetOptions::default(),
+        manifest: None,
+        output: OutputMode::Text,
+        verbosity: Verbosity::Normal,
+        dry_run: None,
+        check: false,
+        fail_fast: false,
+        max_output_pixels: None,
+        max_source_pixels: None,
+        timeout: None,
+        retries: None,
+        retry_backoff_ms: None,
+        incremental: false,
+        force: false,
+        sidecar: false,
+        cache: None,
+        last_wins: false,
+        limits: image_bg_extender::StageLimits::uniform(1),
+        max_in_flight: None,
+        pipe_in: None,
+        pipe_out: None,
+        ratio: None,
+        mode: None,
+        stdin: false,
+        ndjson: false,
+        progress: false,
+        out_format: None,
+        resize_filter: None,
+        overwrite: None,
+        log_level: None,
+        log_format: None,
+    };
+    let mut iter = std::env::args().skip(1);
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--serve" => {
+                let addr = iter
+                    .next()
+                    .ok_or_else(|| invalid_input("--serve requires an address".into()))?;
+                args.serve = Some(addr);
+            }
+            "--watch" => args.watch = Some(value(&arg, iter.next())?),
+            "--output" => {
+                args.output = match iter.next().as_deref() {
+                    Some("text") => OutputMode::Text,
+                    Some("json") => OutputMode::Json,
+                    _ => return Err(invalid_input("--output expects text or json".into())),
+                };
+            }
+            "--json" => args.output = OutputMode::Json,
+            "-q" | "--quiet" => args.verbosity = Verbosity::Quiet,
+            "-v" | "--verbose" => args.verbosity = Verbosity::Verbose,
+            "--dry-run" => args.dry_run = Some(DryRun::Header),
+            "--dry-run=full" => args.dry_run = Some(DryRun::Full),
+            "--check" => args.check = true,
+            "--fail-fast" => args.fail_fast = true,
+            "--incremental" => args.incremental = true,
+            "--force" => args.force = true,
+            "--sidecar" => args.sidecar = true,
+            "--last-wins" => args.last_wins = true,
+            "--jobs" => {
+                args.limits = image_bg_extender::StageLimits::uniform(count(&arg, iter.next())?)
+            }
+            "--read-jobs" => args.limits.read = count(&arg, iter.next())?,
+            "--process-jobs" => args.limits.process = count(&arg, iter.next())?,
+            "--write-jobs" => args.limits.write = count(&arg, iter.next())?,
+            "--max-in-flight" => args.max_in_flight = Some(count(&arg, iter.next())?),
+            "--input" => set_input(&mut args, value(&arg, iter.next())?)?,
+            "--relative-to" => args.relative_to = Some(value(&arg, iter.next())?),
+            "--out-dir" => args.out_dir = Some(value(&arg, iter.next())?),
+            "--config" => {
+                let path = value(&arg, iter.next())?;
+                args.defaults =
+                    image_bg_extender::config::Defaults::read(&path).map_err(invalid_input)?;
+            }
+            "--preserve-tree" => args.preserve_tree = true,
+            "--contact-sheet" => args.contact_sheet = Some(value(&arg, iter.next())?),
+            "--contact-cell" => args.sheet.cell = count(&arg, iter.next())? as u32,
+            "--contact-max" => args.sheet.max_dimension = count(&arg, iter.next())? as u32,
+            "--contact-font" => args.sheet.font = Some(value(&arg, iter.next())?),
+            "--manifest" => args.manifest = Some(value(&arg, iter.next())?),
+            "--in" | "--src" => args.pipe_in = Some(value(&arg, iter.next())?),
+            "--out" | "--dest" => args.pipe_out = Some(value(&arg, iter.next())?),
+            // Both ends on the standard streams unless given otherwise
+            "--pipe" => {
+                args.pipe_in.get_or_insert_with(|| STDIO.to_string());
+                args.pipe_out.get_or_insert_with(|| STDIO.to_string());
+            }
+            "--mode" => {
+                let name = value(&arg, iter.next())?;
+                args.mode = Some(name.parse().map_err(invalid_input)?);
+            }
+            "--log-level" => args.log_level = Some(value(&arg, iter.next())?),
+            "--log-format" => args.log_format = Some(value(&arg, iter.next())?),
+            "--stdin" => args.stdin = true,
+            "--ndjson" => args.ndjson = true,
+            "--progress" if cfg!(feature = "progress") => args.progress = true,
+            "--progress" => {
+                return Err(invalid_input(
+                    "--progress requires building with the `progress` feature".into(),
+                ))
+            }
+            "--ratio" | "--aspect" => {
+                let ratio = value(&arg, iter.next())?;
+                args.ratio = Some(image_bg_extender::parse_ratio(&ratio).map_err(invalid_input)?);
+            }
+            "--out-format" => {
+                let name = value(&arg, iter.next())?;
+                args.out_format = Some(name.parse().map_err(invalid_input)?);
+            }
+            "--cache" => {
+                let path = iter
+                    .next()
+                    .ok_or_else(|| invalid_input("--cache requires a path".into()))?;
+                args.cache = Some(path);
+            }
+            "--max-output-pixels" => {
+                let limit = iter
+                    .next()
+                    .and_then(|limit| limit.parse().ok())
+                    .ok_or_else(|| invalid_input("--max-output-pixels expects a number".into()))?;
+                args.max_output_pixels = Some(limit);
+            }
+            "--max-source-pixels" => {
+                let limit = iter
+                    .next()
+                    .and_then(|limit| limit.parse().ok())
+                    .ok_or_else(|| invalid_input("--max-source-pixels expects a number".into()))?;
+                args.max_source_pixels = Some(limit);
+            }
+            "--timeout" => {
+                let seconds = iter
+                    .next()
+                    .and_then(|seconds| seconds.parse().ok())
+                    .ok_or_else(|| invalid_input("--timeout expects a number of seconds".into()))?;
+                args.timeout = Some(seconds);
+            }
+            "--retries" => {
+                let retries = iter
+                    .next()
+                    .and_then(|retries| retries.parse().ok())
+                    .ok_or_else(|| invalid_input("--retries expects a number".into()))?;
+                args.retries = Some(retries);
+            }
+            "--retry-backoff-ms" => {
+                let backoff = iter
+                    .next()
+                    .and_then(|backoff| backoff.parse().ok())
+                    .ok_or_else(|| {
+                        invalid_input("--retry-backoff-ms expects a number of milliseconds".into())
+                    })?;
+                args.retry_backoff_ms = Some(backoff);
+            }
+            "--resize-filter" => {
+                let name = value(&arg, iter.next())?;
+                args.resize_filter = Some(name.parse().map_err(invalid_input)?);
+            }
+            "--overwrite" => {
+                let name = value(&arg, iter.next())?;
+                args.overwrite = Some(name.parse().map_err(invalid_input)?);
+            }
+            _ if arg == STDIO || !arg.starts_with('-') => set_input(&mut args, arg)?,
+            _ => return Err(invalid_input(format!("unknown argument {}", arg))),
+        }
+    }
+    Ok(args)
+}
+
+/// Logs the events of each entry to stderr as `--log-level` and
+/// `--log-format` ask, at `info` when only the format is given.
+#[cfg(feature = "tracing")]
+fn start_logging(args: &Args) -> io::Result<()> {
+    if args.log_level.is_none() && args.log_format.is_none() {
+        return Ok(());
+    }
+    let level = match args.log_level.as_deref() {
+        Some(level) => level
+            .parse()
+            .map_err(|_| invalid_input(format!("unknown log level {}", level)))?,
+        None => tracing::Level::INFO,
+    };
+    let format = match args.log_format.as_deref() {
+        Some(format) => format.parse().map_err(invalid_input)?,
+        None => image_bg_extender::logging::LogFormat::Text,
+    };
+    image_bg_extender::logging::init(level, format).map_err(invalid_input)
+}
+
+#[cfg(not(feature = "tracing"))]
+fn start_logging(args: &Args) -> io::Result<()> {
+    if args.log_level.is_none() && args.log_format.is_none() {
+        return Ok(());
+    }
+    Err(invalid_input(
+        "--log-level requires building with the `tracing` feature".into(),
+    ))
+}
+
+#[cfg(feature = "server")]
+fn serve(args: &Args, addr: &str) -> io::Result<()> {
+    let addr = addr
+        .parse()
+        .map_err(|e| invalid_input(format!("invalid address {}: {}", addr, e)))?;
+    eprintln!("Listening on {}", addr);
+    image_bg_extender::server::run(image_bg_extender::server::ServerConfig {
+        max_output_pixels: args.max_output_pixels,
+        max_source_pixels: args.max_source_pixels,
+        ..image_bg_extender::server::ServerConfig::new(addr)
+    })
+}
+
+#[cfg(not(feature = "server"))]
+fn serve(_args: &Args, _addr: &str) -> io::Result<()> {
+    Err(invalid_input(
+        "--serve requires building with the `server` feature".into(),
+    ))
+}
+
+/// The progress bar of `--progress`, drawn on stderr.
+#[cfg(feature = "progress")]
+struct Bar(indicatif::ProgressBar);
+
+/// Without the `progress` feature there is never a bar.
+#[cfg(not(feature = "progress"))]
+enum Bar {}
+
+/// A bar for `--progress` of as many entries as `size_hint` says there
+/// are, or a counter when it cannot tell.
+#[cfg(feature = "progress")]
+fn progress_bar(args: &Args, size_hint: (usize, Option<usize>)) -> Option<Bar> {
+    use indicatif::{ProgressBar, ProgressStyle};
+
+    if !args.progress {
+        return None;
+    }
+    let bar = match size_hint {
+        (length, Some(upper)) if length == upper => ProgressBar::new(length as u64).with_style(
+            ProgressStyle::with_template(
+                "{elapsed_precise} [{wide_bar}] {pos}/{len}, ETA {eta} {msg}",
+            )
+            .expect("valid template"),
+        ),
+        _ => ProgressBar::no_length().with_style(
+            ProgressStyle::with_template("{spinner} {elapsed_precise} {pos} done {msg}")
+                .expect("valid template"),
+        ),
+    };
+    Some(Bar(bar))
+}
+
+#[cfg(not(feature = "progress"))]
+fn progress_bar(_args: &Args, _size_hint: (usize, Option<usize>)) -> Option<Bar> {
+    None
+}
+
+#[cfg(feature = "progress")]
+impl Bar {
+    /// Shows the source last started and counts the entries finished.
+    fn callback(&self) -> image_bg_extender::pipeline::ProgressFn {
+        use image_bg_extender::pipeline::Progress;
+
+        let bar = self.0.clone();
+        std::sync::Arc::new(move |progress| match progress {
+            Progress::Started { info, .. } => bar.set_message(info.source().to_string()),
+            Progress::Finished { .. } => bar.inc(1),
+        })
+    }
+
+    /// Runs `f` with the bar hidden, so lines printed meanwhile stay whole.
+    fn suspend<R>(&self, f: impl FnOnce() -> R) -> R {
+        self.0.suspend(f)
+    }
+
+    fn finish(self) {
+        self.0.finish_and_clear();
+    }
+}
+
+#[cfg(not(feature = "progress"))]
+impl Bar {
+    fn callback(&self) -> image_bg_extender::pipeline::ProgressFn {
+        match *self {}
+    }
+
+    fn suspend<R>(&self, _f: impl FnOnce() -> R) -> R {
+        match *self {}
+    }
+
+    fn finish(self) {
+        match self {}
+    }
+}
+
+/// Process exit codes, also relied upon by wrapping scripts.
+mod exit {
+    pub const SUCCESS: u8 = 0;
+    /// Every entry failed.
+    pub const FAILURE: u8 = 1;
+    /// Some entries failed while others succeeded.
+    pub const PARTIAL_FAILURE: u8 = 2;
+    /// The arguments or the job list could not be read or parsed, entries of the job
+    /// list conflict, or `--check` found problems.
+    pub const INVALID_INPUT: u8 = 3;
+    /// The job list contained no entries.
+    pub const NO_ENTRIES: u8 = 4;
+    /// `--fail-fast` stopped the run at a failing entry.
+    pub const ABORTED: u8 = 5;
+    /// Processing panicked for some entries, whatever became of the others.
+    pub const INTERNAL_ERROR: u8 = 6;
+    /// SIGINT stopped a stream, after the entries in progress were finished,
+    /// or at once for a second one.
+    pub const INTERRUPTED: u8 = 130;
+}
+
+/// SIGINT while a stream is processed: the first one stops entries from
+/// being taken and lets the ones in progress finish and be reported, and a
+/// second one exits at once.
+mod interrupt {
+    use std::sync::atomic::AtomicBool;
+    use std::sync::{Arc, OnceLock};
+
+    static FLAG: OnceLock<Arc<AtomicBool>> = OnceLock::new();
+
+    /// Catches SIGINT from now on, and returns the flag it sets.
+    pub fn catch() -> Arc<AtomicBool> {
+        let flag = Arc::clone(FLAG.get_or_init(Default::default));
+        install();
+        flag
+    }
+
+    /// The flag of `catch`, once it was called.
+    pub fn flag() -> Option<Arc<AtomicBool>> {
+        FLAG.get().cloned()
+    }
+
+    #[cfg(unix)]
+    fn install() {
+        extern "C" fn interrupted(_: libc::c_int) {
+            if let Some(flag) = FLAG.get() {
+                if flag.swap(true, std::sync::atomic::Ordering::SeqCst) {
+                    // SAFETY: _exit is async-signal-safe
+                    unsafe { libc::_exit(super::exit::INTERRUPTED as libc::c_int) };
+                }
+            }
+        }
+        let handler = interrupted as extern "C" fn(libc::c_int);
+        // SAFETY: the handler does nothing but swap an atomic and exit
+        unsafe { libc::signal(libc::SIGINT, handler as libc::sighandler_t) };
+    }
+
+    /// Elsewhere SIGINT ends the process as it always did.
+    #[cfg(not(unix))]
+    fn install() {}
+}
+
+#[derive(Default)]
+struct Summary {
+    ok: usize,
+    skipped: usize,
+    /// Sources of the entries that failed, in job list order.
+    failed: Vec<String>,
+    /// Sources of the entries that panicked, which are not in `failed`.
+    panicked: Vec<String>,
+    /// Entries left unprocessed after `--fail-fast` stopped the run.
+    not_attempted: usize,
+    /// Whether `--fail-fast` stopped the run with entries left, which for a
+    /// stream may be more than `not_attempted` counts.
+    stopped: bool,
+    /// Whether SIGINT stopped a stream.
+    interrupted: bool,
+}
+
+impl Summary {
+    fn exit_code(&self) -> u8 {
+        if self.interrupted {
+            exit::INTERRUPTED
+        } else if self.ok + self.skipped + self.failed.len() + self.panicked.len() == 0 {
+            exit::NO_ENTRIES
+        } else if self.failed.is_empty() && self.panicked.is_empty() {
+            exit::SUCCESS
+        } else if self.stopped {
+            exit::ABORTED
+        } else if !self.panicked.is_empty() {
+            exit::INTERNAL_ERROR
+        } else if self.ok + self.skipped == 0 {
+            exit::FAILURE
+        } else {
+            exit::PARTIAL_FAILURE
+        }
+    }
+}
+
+fn describe_plan(
+    info: &image_bg_extender::ImageInfo,
+    destination: &str,
+    plan: &image_bg_extender::Plan,
+) -> String {
+    let mut line = format!(
+        "{} -> {}: {}x{}",
+        info.source(),
+        destination,
+        plan.source_width,
+        plan.source_height
+    );
+    if let Some((width, height)) = plan.aspect_ratio {
+        line.push_str(&format!(", ratio {}:{}", width, height));
+    }
+    match (&plan.trim, plan.orientation) {
+        (Some(trim), _) => line.push_str(&format!(
+            ", {}, canvas {}x{}",
+            describe_trim(trim),
+            plan.canvas_width,
+            plan.canvas_height
+        )),
+        (None, None) => {
+            if plan.tolerated {
+                line.push_str(", within tolerance");
+            }
+            match plan.copy {
+                true => line.push_str(", copy as is"),
+                false if plan.tolerated => line.push_str(", re-encode"),
+                false => line.push_str(", already in ratio, re-encode"),
+            }
+        }
+        (None, Some(orientation)) => {
+            let orientation = match orientation {
+                image_bg_extender::Orientation::Landscape => "landscape",
+                image_bg_extender::Orientation::Portrait => "portrait",
+            };
+            line.push_str(&format!(
+                ", {}, crop {}x{}, canvas {}x{}",
+                orientation, plan.crop.0, plan.crop.1, plan.canvas_width, plan.canvas_height
+            ));
+        }
+    }
+    for color in &plan.colors {
+        line.push_str(&format!(
+            " #{:02x}{:02x}{:02x}{:02x}",
+            color[0], color[1], color[2], color[3]
+        ));
+    }
+    line
+}
+
+fn describe_trim(trim: &image_bg_extender::Trim) -> String {
+    format!(
+        "trim top {} right {} bottom {} left {}",
+        trim.top, trim.right, trim.bottom, trim.left
+    )
+}
+
+fn describe_report(report: &image_bg_extender::ImageReport) -> String {
+    let mut line = format!(
+        "{}x{} -> {}x{}",
+        report.source_width, report.source_height, report.output_width, report.output_height
+    );
+    if let Some((width, height)) = report.aspect_ratio {
+        line.push_str(&format!(", ratio {}:{}", width, height));
+    }
+    if report.tolerated {
+        line.push_str(", within tolerance");
+    }
+    if report.copied {
+        line.push_str(", copied");
+    } else {
+        line.push_str(&format!(", crop {}x{}", report.crop.0, report.crop.1));
+    }
+    if let Some(trim) = &report.trim {
+        line.push_str(&format!(", {}", describe_trim(trim)));
+    }
+    if let Some(frames) = report.frames {
+        line.push_str(&format!(", {} frames", frames));
+    }
+    if let Some(pages) = report.pages {
+        line.push_str(&format!(", {} pages", pages));
+    }
+    for color in &report.colors {
+        line.push_str(&format!(
+            " #{:02x}{:02x}{:02x}{:02x}",
+            color[0], color[1], color[2], color[3]
+        ));
+    }
+    let timings = &report.timings;
+    line.push_str(&format!(
+        ", decode {:.1}ms, composite {:.1}ms, write {:.1}ms",
+        timings.decode_ms, timings.composite_ms, timings.write_ms
+    ));
+    line
+}
+
+/// Notes identical entries folded into `info`.
+fn duplicates(info: &image_bg_extender::ImageInfo) -> String {
+    match info.duplicates() {
+        0 => String::new(),
+        1 => " (1 duplicate entry)".to_string(),
+        n => format!(" ({} duplicate entries)", n),
+    }
+}
+
+/// Why `info` does not need processing, if it does not and `--force` is
+/// not given. An existing destination that `overwrite: skip` keeps is kept
+/// with `--force` too.
+fn skip_reason(
+    args: &Args,
+    cache: Option<&image_bg_extender::cache::Cache>,
+    info: &image_bg_extender::ImageInfo,
+    key: Option<&str>,
+) -> Option<image_bg_extender::SkipReason> {
+    if info.keeps_existing() {
+        Some(image_bg_extender::SkipReason::Exists)
+    } else if args.force {
+        None
+    } else if info.is_up_to_date() {
+        Some(image_bg_extender::SkipReason::UpToDate)
+    } else if let (Some(cache), Some(key)) = (cache, key) {
+        cache
+            .is_fresh(info, key)
+            .then_some(image_bg_extender::SkipReason::Cached)
+    } else {
+        None
+    }
+}
+
+/// Reports an entry that is left alone.
+fn skip(
+    args: &Args,
+    summary: &mut Summary,
+    info: &image_bg_extender::ImageInfo,
+    reason: image_bg_extender::SkipReason,
+) -> io::Result<()> {
+    summary.skipped += 1;
+    if args.output == OutputMode::Json {
+        let entry = image_bg_extender::EntryResult::skipped(info, reason);
+        println!("{}", serde_json::to_string(&entry)?);
+    }
+    if args.verbosity > Verbosity::Quiet {
+        if args.output == OutputMode::Json {
+            eprintln!("Skipped {} ({})", info.destination(), reason)
+        } else {
+            println!("Skipped {} ({})", info.destination(), reason)
+        }
+    }
+    Ok(())
+}
+
+fn plan(
+    args: &Args,
+    dry_run: DryRun,
+    info_list: impl IntoIterator<Item = image_bg_extender::ImageInfo>,
+    mut summary: Summary,
+) -> io::Result<Summary> {
+    let cache = args
+        .cache
+        .as_deref()
+        .map(image_bg_extender::cache::Cache::open);
     for info in info_list {
-        match image_bg_extender::compile_image(&info) {
-            Ok(dest) => println!("Image saved to {}", dest),
+        let key = cache.as_ref().and_then(|cache| cache.key(&info).ok());
+        if let Some(reason) = skip_reason(args, cache.as_ref(), &info, key.as_deref()) {
+            skip(args, &mut summary, &info, reason)?;
+            continue;
+        }
+        let result = image_bg_extender::plan_image(&info, dry_run == DryRun::Full);
+        if args.output == OutputMode::Json {
+            let entry = image_bg_extender::EntryResult::planned(&info, &result);
+            println!("{}", serde_json::to_string(&entry)?);
+        }
+        match result {
+            Ok(plan) => {
+                summary.ok += 1;
+                let lines: Vec<String> = if plan.outputs.is_empty() {
+                    vec![describe_plan(&info, info.destination(), &plan)]
+                } else {
+                    plan.outputs
+                        .iter()
+                        .map(|output| describe_plan(&info, &output.destination, &output.plan))
+                        .collect()
+                };
+                for line in lines {
+                    if args.output == OutputMode::Json {
+                        eprintln!("{}{}", line, duplicates(&info))
+                    } else {
+                        println!("{}{}", line, duplicates(&info))
+                    }
+                }
+            }
             Err(e) => {
-                if let Some(err) = e.downcast_ref::<io::Error>() {
-                    eprintln!("IO error: {:?}", err)
-                } else if let Some(err) = e.downcast_ref::<image::ImageError>() {
-                    eprintln!("Image error: {:?}", err)
+                summary.failed.push(info.source().to_string());
+                eprintln!("{}", e);
+            }
+        }
+    }
+    Ok(summary)
+}
+
+/// Processes `entries` after reporting the `superseded` ones as skipped.
+/// `entries` may be a stream, each entry being taken once a worker is free.
+fn process<I>(
+    args: &Args,
+    superseded: &[image_bg_extender::ImageInfo],
+    entries: I,
+    mut sheet: Option<image_bg_extender::contact_sheet::ContactSheet>,
+) -> io::Result<Summary>
+where
+    I: IntoIterator<Item = image_bg_extender::ImageInfo>,
+    I::IntoIter: Send,
+{
+    let mut summary = Summary::default();
+    for info in superseded {
+        skip(
+            args,
+            &mut summary,
+            info,
+            image_bg_extender::SkipReason::Superseded,
+        )?;
+    }
+    if let Some(dry_run) = args.dry_run {
+        return plan(args, dry_run, entries, summary);
+    }
+    let cache = args
+        .cache
+        .as_deref()
+        .map(image_bg_extender::cache::Cache::open);
+    let mut entries = entries.into_iter();
+    let bar = progress_bar(args, entries.size_hint());
+    let pipeline = image_bg_extender::pipeline::Pipeline {
+        limits: args.limits,
+        progress: bar.as_ref().map(Bar::callback),
+        max_in_flight: args.max_in_flight,
+        interrupted: interrupt::flag(),
+    };
+    let mut manifest = args
+        .manifest
+        .as_deref()
+        .map(image_bg_extender::manifest::Manifest::new);
+    let mut error = None;
+    let contact_sheet = sheet.is_some();
+    let hashed = manifest.is_some();
+    let mut proceed = true;
+    pipeline.run_stream(
+        entries.by_ref(),
+        |info| {
+            let key = cache.as_ref().and_then(|cache| cache.key(info).ok());
+            match skip_reason(args, cache.as_ref(), info, key.as_deref()) {
+                Some(reason) => Outcome::Skipped(reason),
+                None => {
+                    let result = image_bg_extender::compile_image(info);
+                    // Thumbnails are made here, while the outputs are fresh
+                    let pair = match &result {
+                        Ok(report) if contact_sheet => {
+                            Some(image_bg_extender::contact_sheet::Pair::new(
+                                info,
+                                report,
+                                args.sheet.cell,
+                            ))
+                        }
+                        _ => None,
+                    };
+                    let records = match &result {
+                        Ok(report) if hashed => {
+                            Some(image_bg_extender::manifest::Record::all(info, report))
+                        }
+                        _ => None,
+                    };
+                    Outcome::Done(Box::new(result), key, pair, records)
+                }
+            }
+        },
+        |info, outcome| {
+            let report_entry = || {
+                report(
+                    args,
+                    &mut summary,
+                    cache.as_ref(),
+                    sheet.as_mut(),
+                    manifest.as_mut(),
+                    &info,
+                    outcome,
+                )
+            };
+            let reported = match &bar {
+                Some(bar) => bar.suspend(report_entry),
+                None => report_entry(),
+            };
+            match reported {
+                Ok(carry_on) => {
+                    proceed &= carry_on;
+                    carry_on
+                }
+                Err(e) => {
+                    error = Some(e);
+                    false
+                }
+            }
+        },
+    );
+    if let Some(bar) = bar {
+        bar.finish();
+    }
+    // Whatever the pipeline did not take was never started. A stream cannot
+    // tell whether anything is left in it, so it counts as stopped early
+    summary.not_attempted = entries.size_hint().0;
+    summary.stopped = !proceed && (summary.not_attempted > 0 || entries.size_hint().1 != Some(0));
+    if let Some(cache) = cache {
+        if let Err(e) = cache.save() {
+            eprintln!("{}", e);
+        }
+    }
+    match image_bg_extender::archive::finish() {
+        Ok(archives) if args.verbosity > Verbosity::Quiet => {
+            for archive in archives {
+                eprintln!("Archive saved to {}", archive);
+            }
+        }
+        Ok(_) => {}
+        Err(e) => {
+            error.get_or_insert(io::Error::other(e.to_string()));
+        }
+    }
+    if let Some(sheet) = sheet {
+        match sheet.finish() {
+            Ok(pages) if args.verbosity > Verbosity::Quiet => {
+                for page in pages {
+                    eprintln!("Contact sheet saved to {}", page);
                 }
             }
+            Ok(_) => {}
+            Err(e) => eprintln!("Contact sheet: {}", e),
+        }
+    }
+    if let Some(manifest) = manifest {
+        match manifest.finish() {
+            Ok(path) if args.verbosity > Verbosity::Quiet => {
+                eprintln!("Manifest saved to {}", path)
+            }
+            Ok(_) => {}
+            Err(e) => {
+                error.get_or_insert(io::Error::other(e.to_string()));
+            }
+        }
+    }
+    match error {
+        Some(e) => Err(e),
+        None => Ok(summary),
+    }
+}
+
+/// What a worker did with an entry.
+enum Outcome {
+    Skipped(image_bg_extender::SkipReason),
+    /// Processed, along with the cache key of the entry, its thumbnails
+    /// for the contact sheet and the records of its files for the manifest.
+    Done(
+        Box<Result<image_bg_extender::ImageReport, image_bg_extender::ExtendError>>,
+        Option<String>,
+        Option<Result<image_bg_extender::contact_sheet::Pair, image_bg_extender::ExtendError>>,
+        Option<Result<Vec<image_bg_extender::manifest::Record>, image_bg_extender::ExtendError>>,
+    ),
+}
+
+/// Reports an entry in job list order and returns whether to carry on.
+fn report(
+    args: &Args,
+    summary: &mut Summary,
+    cache: Option<&image_bg_extender::cache::Cache>,
+    sheet: Option<&mut image_bg_extender::contact_sheet::ContactSheet>,
+    manifest: Option<&mut image_bg_extender::manifest::Manifest>,
+    info: &image_bg_extender::ImageInfo,
+    outcome: Outcome,
+) -> io::Result<bool> {
+    let (result, key, pair, records) = match outcome {
+        Outcome::Skipped(reason) => {
+            skip(args, summary, info, reason)?;
+            return Ok(true);
+        }
+        Outcome::Done(result, key, pair, records) => (*result, key, pair, records),
+    };
+    // A missing thumbnail leaves the entry itself alone
+    if let (Some(sheet), Some(pair)) = (sheet, pair) {
+        if let Err(e) = pair.and_then(|pair| sheet.push(pair)) {
+            eprintln!("Contact sheet: {}", e);
+        }
+    }
+    if let (Some(manifest), Some(records)) = (manifest, records) {
+        match records {
+            Ok(records) => manifest.push(records),
+            Err(e) => eprintln!("Manifest: {}", e),
+        }
+    }
+    if args.output == OutputMode::Json {
+        let entry = image_bg_extender::EntryResult::new(info, &result);
+        println!("{}", serde_json::to_string(&entry)?);
+    }
+    match result {
+        Ok(report) => {
+            summary.ok += 1;
+            if let (Some(cache), Some(key)) = (cache, key) {
+                if let Err(e) = cache.insert(info, key) {
+                    eprintln!("{}", e);
+                }
+            }
+            if args.verbosity > Verbosity::Quiet {
+                let mut lines = Vec::new();
+                if report.variants.is_empty() && report.outputs.is_empty() {
+                    lines.push(format!(
+                        "Image saved to {}{}",
+                        report.renamed.as_deref().unwrap_or(info.destination()),
+                        duplicates(info)
+                    ));
+                }
+                for variant in &report.variants {
+                    lines.push(match &variant.error {
+                        None => format!(
+                            "Image saved to {} ({}){}",
+                            variant.destination,
+                            variant.background.name(),
+                            duplicates(info)
+                        ),
+                        Some(error) => format!(
+                            "Variant {} of {} failed: {}",
+                            variant.background.name(),
+                            info.source(),
+                            error.message
+                        ),
+                    });
+                }
+                for output in &report.outputs {
+                    lines.push(match &output.error {
+                        None => format!(
+                            "Image saved to {}{}{}",
+                            output.destination,
+                            output.aspect_ratio.map_or_else(
+                                String::new,
+                                |(width, height)| format!(" ({}:{})", width, height)
+                            ),
+                            duplicates(info)
+                        ),
+                        Some(error) => format!(
+                            "Output {} of {} failed: {}",
+                            output.destination,
+                            info.source(),
+                            error.message
+                        ),
+                    });
+                }
+                for line in lines {
+                    if args.output == OutputMode::Json {
+                        eprintln!("{}", line)
+                    } else {
+                        println!("{}", line)
+                    }
+                }
+            }
+            if args.verbosity == Verbosity::Verbose {
+                eprintln!("  {}", describe_report(&report));
+            }
+            Ok(true)
+        }
+        Err(e) => {
+            if e.kind() == image_bg_extender::ErrorKind::Panic {
+                summary.panicked.push(info.source().to_string());
+            } else {
+                summary.failed.push(info.source().to_string());
+            }
+            eprintln!("{}", e);
+            Ok(!args.fail_fast)
+        }
+    }
+}
+
+/// Rejects `-` where it cannot work: anywhere in a batch of several entries,
+/// and as a source, as stdin already holds the job list.
+fn check_stdio(info_list: &[image_bg_extender::ImageInfo]) -> Result<(), String> {
+    for (index, info) in info_list.iter().enumerate() {
+        if info.source() == STDIO {
+            return Err(format!(
+                "entry {}: the source cannot be \"-\" while stdin holds the job list, \
+                 use --in - instead",
+                index
+            ));
+        }
+        if info.destination() == STDIO && info_list.len() > 1 {
+            return Err(format!(
+                "entry {}: the destination can only be \"-\" in a job list of one entry",
+                index
+            ));
         }
     }
     Ok(())
 }
+
+/// Validates every entry of the job list for `--check` and lists all of the
+/// problems found, without decoding or writing anything.
+fn check(args: &Args, info_list: Vec<image_bg_extender::ImageInfo>) -> ExitCode {
+    use image_bg_extender::validate;
+
+    let total = info_list.len();
+    let mut problems = 0;
+    if let Err(message) = check_stdio(&info_list) {
+        eprintln!("{}", message);
+        problems += 1;
+    }
+    for (index, info) in info_list.iter().enumerate() {
+        let mut found = info.validate();
+        if info.source() != STDIO {
+            found.extend(
+                validate::paths(info).into_iter().filter(|problem| {
+                    problem.field != "destination" || info.destination() != STDIO
+                }),
+            );
+        }
+        for problem in &found {
+            eprintln!("entry {} ({}): {}", index, info.source(), problem);
+        }
+        problems += found.len();
+    }
+    if !args.last_wins {
+        if let Err(conflicts) = image_bg_extender::batch::dedupe(info_list, false) {
+            for conflict in &conflicts {
+                let entries: Vec<String> = conflict
+                    .entries
+                    .iter()
+                    .map(|entry| entry.to_string())
+                    .collect();
+                eprintln!(
+                    "entries {}: conflicting destination: {}",
+                    entries.join(", "),
+                    conflict
+                );
+            }
+            problems += conflicts.len();
+        }
+    }
+
+    if problems > 0 {
+        eprintln!("{} problems found in {} entries", problems, total);
+        ExitCode::from(exit::INVALID_INPUT)
+    } else {
+        if args.verbosity > Verbosity::Quiet {
+            eprintln!("{} entries ok", total);
+        }
+        ExitCode::from(exit::SUCCESS)
+    }
+}
+
+/// Extends a single image from `input` to `output`, one of which at least
+/// is `-` for stdin or stdout. Diagnostics only ever go to stderr.
+fn pipe(
+    input: &str,
+    output: &str,
+    ratio: (u32, u32),
+    format: Option<image_bg_extender::OutputFormat>,
+    options: &image_bg_extender::Options,
+) -> Result<(), image_bg_extender::ExtendError> {
+    use image_bg_extender::error::Stage;
+    use std::io::{Read, Write};
+
+    let data = if input == STDIO {
+        let mut data = Vec::new();
+        io::stdin().lock().read_to_end(&mut data).map(|_| data)
+    } else {
+        std::fs::read(input)
+    }
+    .map_err(|e| image_bg_extender::ExtendError::new(Stage::Read, Some(input), e))?;
+
+    let options = image_bg_extender::Options {
+        output_format: format.or(options.output_format),
+        ..options.clone()
+    };
+    let extended = image_bg_extender::extend_bytes_with(&data, ratio, None, &options)?;
+    if output == STDIO {
+        let mut stdout = io::stdout().lock();
+        stdout.write_all(&extended).and_then(|()| stdout.flush())
+    } else {
+        std::fs::write(output, &extended)
+    }
+    .map_err(|e| image_bg_extender::ExtendError::new(Stage::Write, Some(output), e))
+}
+
+/// `pipe` with `options`, which for an entry of a job list are the ones its
+/// file would have been written with.
+fn run_pipe(
+    args: &Args,
+    input: &str,
+    output: &str,
+    ratio: (u32, u32),
+    options: &image_bg_extender::Options,
+) -> ExitCode {
+    // Stdout gets the format of the input unless told otherwise
+    let format = options.output_format.or_else(|| {
+        image_bg_extender::OutputFormat::from_path(image_bg_extender::storage::name(output))
+    });
+    let format = match format {
+        Some(format) => Some(format),
+        None if output == STDIO => None,
+        None => {
+            eprintln!(
+                "Cannot tell the output format of {}, pass --out-format",
+                output
+            );
+            return ExitCode::from(exit::INVALID_INPUT);
+        }
+    };
+    match pipe(input, output, ratio, format, options) {
+        Ok(()) => {
+            if args.verbosity == Verbosity::Verbose {
+                eprintln!("Image written to {}", output);
+            }
+            ExitCode::from(exit::SUCCESS)
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            ExitCode::from(exit::FAILURE)
+        }
+    }
+}
+
+/// Expands the patterns and archive wildcards among the sources of
+/// `info_list` and applies the flags that reach into every entry.
+fn prepare(
+    args: &Args,
+    info_list: Vec<image_bg_extender::ImageInfo>,
+) -> Result<Vec<image_bg_extender::ImageInfo>, String> {
+    let mut expanded = Vec::with_capacity(info_list.len());
+    // Entries made from a pattern, whose directories are made before writing
+    let mut patterned = Vec::new();
+    // Entries without a destination, which go to the `outDir` of the config
+    let mut unset = Vec::new();
+    for info in info_list {
+        let first = expanded.len();
+        let has_destination = !info.destination().is_empty();
+        let base = args.relative_to.as_deref().map(std::path::Path::new);
+        let archived = image_bg_extender::archive::expand(&info, base)
+            .map(|expanded| expanded.map_err(|e| format!("Cannot list archive: {}", e)))
+            .or_else(|| {
+                image_bg_extender::glob::expand(&info, base)
+                    .map(|expanded| expanded.map_err(|e| format!("Cannot expand source: {}", e)))
+            });
+        match archived {
+            Some(Ok(archived)) => {
+                if args.verbosity > Verbosity::Quiet {
+                    for source in archived.skipped {
+                        eprintln!("Skipping {}: not an image", source);
+                    }
+                }
+                patterned.extend(expanded.len()..expanded.len() + archived.entries.len());
+                expanded.extend(archived.entries);
+            }
+            Some(Err(message)) => return Err(message),
+            None => expanded.push(info),
+        }
+        if !has_destination {
+            unset.extend(first..expanded.len());
+        }
+    }
+    let mut info_list = expanded;
+    if let Some(dir) = &args.out_dir {
+        // Taken from the working directory, not from `--relative-to`
+        let dir =
+            std::path::absolute(dir).map_err(|e| format!("Invalid --out-dir {}: {}", dir, e))?;
+        map_to_dir(args, &mut info_list, &dir, "--out-dir")?;
+    } else if let Some(dir) = &args.defaults.out_dir {
+        let mut entries: Vec<_> = unset.iter().map(|&at| info_list[at].clone()).collect();
+        map_to_dir(args, &mut entries, std::path::Path::new(dir), "outDir")?;
+        for (&at, info) in unset.iter().zip(entries) {
+            info_list[at] = info;
+        }
+    }
+    for info in &mut info_list {
+        if let Some(dir) = &args.relative_to {
+            info.resolve_paths(std::path::Path::new(dir));
+        }
+        for options in info.all_options_mut() {
+            apply_flags(args, options);
+        }
+    }
+    if args.out_dir.is_none() && !args.check && args.dry_run.is_none() {
+        for &position in &patterned {
+            for dest in info_list[position].outputs() {
+                create_parent(&dest)?;
+            }
+        }
+    }
+    Ok(info_list)
+}
+
+/// Fills in what the flags reaching into every entry set and `options`
+/// leaves unset.
+fn apply_flags(args: &Args, options: &mut image_bg_extender::Options) {
+    if let Some(limit) = args.max_output_pixels {
+        options.max_output_pixels.get_or_insert(limit);
+    }
+    if let Some(limit) = args.max_source_pixels {
+        options.max_source_pixels.get_or_insert(limit);
+    }
+    if let Some(seconds) = args.timeout {
+        options.timeout.get_or_insert(seconds);
+    }
+    if let Some(retries) = args.retries {
+        options.retries.get_or_insert(retries);
+    }
+    if let Some(backoff) = args.retry_backoff_ms {
+        options.retry_backoff_ms.get_or_insert(backoff);
+    }
+    if let Some(filter) = args.resize_filter {
+        options.style.resize_filter.get_or_insert(filter);
+    }
+    if let Some(overwrite) = args.overwrite {
+        options.overwrite.get_or_insert(overwrite);
+    }
+    options.skip_up_to_date |= args.incremental;
+    options.sidecar |= args.sidecar;
+}
+
+/// Writes `info_list` under `dir`, the `--out-dir` or `outDir` that `flag`
+/// names, and makes the directories unless nothing is written.
+fn map_to_dir(
+    args: &Args,
+    info_list: &mut [image_bg_extender::ImageInfo],
+    dir: &std::path::Path,
+    flag: &str,
+) -> Result<(), String> {
+    if let Err(conflicts) = image_bg_extender::batch::map_to_dir(info_list, dir, args.preserve_tree)
+    {
+        let mut lines: Vec<String> = conflicts
+            .iter()
+            .map(|conflict| format!("Colliding destination under {}: {}", flag, conflict))
+            .collect();
+        if !args.preserve_tree {
+            lines.push("Pass --preserve-tree to keep the directories of the sources apart".into());
+        }
+        return Err(lines.join("\n"));
+    }
+    // Only runs that write anything get their directories made
+    if !args.check && args.dry_run.is_none() {
+        for dest in info_list.iter().flat_map(|info| info.outputs()) {
+            create_parent(&dest)?;
+        }
+    }
+    Ok(())
+}
+
+/// Makes the directory `dest` is written into, or the directory of its
+/// archive for destinations in one.
+fn create_parent(dest: &str) -> Result<(), String> {
+    if image_bg_extender::storage::is_remote(dest) {
+        return Ok(());
+    }
+    let path = image_bg_extender::archive::split(dest).map_or(dest, |(archive, _)| archive);
+    match std::path::Path::new(path).parent() {
+        Some(parent) => std::fs::create_dir_all(parent)
+            .map_err(|e| format!("Cannot create the directory of {}: {}", dest, e)),
+        None => Ok(()),
+    }
+}
+
+/// Reads the job list from the file at `input`, or from stdin when it is
+/// `None` or `-`, with `defaults` filled into every entry.
+fn read_job_list(
+    input: Option<&str>,
+    defaults: &image_bg_extender::config::Defaults,
+) -> Result<Vec<image_bg_extender::ImageInfo>, String> {
+    let (reader, name): (Box<dyn io::Read>, String) = match input {
+        None | Some(STDIO) => (Box::new(io::stdin()), "job list".into()),
+        Some(path) => {
+            let file = std::fs::File::open(path)
+                .map_err(|e| format!("Cannot open job list {}: {}", path, e))?;
+            (
+                Box::new(io::BufReader::new(file)),
+                format!("job list {}", path),
+            )
+        }
+    };
+    if defaults.is_empty() {
+        return serde_json::from_reader(reader).map_err(|e| format!("Invalid {}: {}", name, e));
+    }
+    let entries: Vec<serde_json::Value> =
+        serde_json::from_reader(reader).map_err(|e| format!("Invalid {}: {}", name, e))?;
+    entries
+        .into_iter()
+        .enumerate()
+        .map(|(index, mut entry)| {
+            defaults.apply(&mut entry);
+            serde_json::from_value(entry)
+                .map_err(|e| format!("Invalid {}: entry {}: {}", name, index + 1, e))
+        })
+        .collect()
+}
+
+/// Parses a line of a streamed job list, with `defaults` filled in.
+fn parse_line(
+    line: &str,
+    defaults: &image_bg_extender::config::Defaults,
+) -> Result<image_bg_extender::ImageInfo, String> {
+    if defaults.is_empty() {
+        return serde_json::from_str(line).map_err(|e| e.to_string());
+    }
+    let mut entry = serde_json::from_str(line).map_err(|e| e.to_string())?;
+    defaults.apply(&mut entry);
+    serde_json::from_value(entry).map_err(|e| e.to_string())
+}
+
+/// Processes a job list of one entry per line from the file at `input`, or
+/// from stdin, starting each entry as soon as its line is read. Entries are
+/// not checked against each other, and a line that cannot be read or parsed
+/// is reported and counted as failed while the others carry on.
+fn stream(args: &Args) -> ExitCode {
+    let reader: Box<dyn BufRead + Send> = match args.input.as_deref() {
+        None | Some(STDIO) => Box::new(io::BufReader::new(io::stdin())),
+        Some(path) => match std::fs::File::open(path) {
+            Ok(file) => Box::new(io::BufReader::new(file)),
+            Err(e) => {
+                eprintln!("Cannot open job list {}: {}", path, e);
+                return ExitCode::from(exit::INVALID_INPUT);
+            }
+        },
+    };
+    let sheet = match contact_sheet(args) {
+        Ok(sheet) => sheet,
+        Err(code) => return code,
+    };
+    let interrupted = interrupt::catch();
+    let invalid = std::sync::Mutex::new(Vec::new());
+    let entries = lines(reader, Arc::clone(&interrupted))
+        .enumerate()
+        .map(|(index, line)| (index + 1, line))
+        .filter(|(_, line)| line.as_ref().map_or(true, |line| !line.trim().is_empty()))
+        .flat_map(|(number, line)| {
+            let entries = line
+                .map_err(|e| e.to_string())
+                .and_then(|line| parse_line(&line, &args.defaults))
+                .and_then(|info| {
+                    if info.source() == STDIO || info.destination() == STDIO {
+                        Err("\"-\" cannot be a source or destination in a stream".to_string())
+                    } else {
+                        prepare(args, vec![info])
+                    }
+                });
+            entries.unwrap_or_else(|message| {
+                let line = format!("line {}", number);
+                eprintln!("{}: {}", line, message);
+                invalid.lock().unwrap_or_else(|e| e.into_inner()).push(line);
+                Vec::new()
+            })
+        });
+    let result = process(args, &[], entries, sheet).map(|mut summary| {
+        let invalid = invalid.into_inner().unwrap_or_else(|e| e.into_inner());
+        summary.failed.extend(invalid);
+        summary.interrupted = interrupted.load(Ordering::SeqCst);
+        summary
+    });
+    finish(args, result)
+}
+
+/// The lines of `reader`, read on a thread of their own so that an
+/// interrupt is noticed while the next line is awaited. A line is only read
+/// once the one before it was taken, so a producer that writes faster than
+/// the entries are processed is held back by the pipe filling up.
+fn lines(
+    reader: Box<dyn BufRead + Send>,
+    interrupted: Arc<AtomicBool>,
+) -> impl Iterator<Item = io::Result<String>> {
+    let (sender, receiver) = mpsc::sync_channel(0);
+    // Left blocked on the pipe after an interrupt, until the process exits
+    std::thread::spawn(move || {
+        for line in reader.lines() {
+            if sender.send(line).is_err() {
+                break;
+            }
+        }
+    });
+    std::iter::from_fn(move || loop {
+        if interrupted.load(Ordering::SeqCst) {
+            return None;
+        }
+        match receiver.recv_timeout(Duration::from_millis(100)) {
+            Ok(line) => return Some(line),
+            Err(RecvTimeoutError::Timeout) => continue,
+            Err(RecvTimeoutError::Disconnected) => return None,
+        }
+    })
+}
+
+/// Processes every image dropped into `dir` until the process is stopped,
+/// writing each under `--out-dir` with the ratio and background of
+/// `--ratio` and `--mode`.
+#[cfg(feature = "watch")]
+fn watch(args: &Args, dir: &str) -> ExitCode {
+    let conflicting = if args.input.is_some() || args.stdin || args.ndjson {
+        Some("a job list")
+    } else if args.pipe_in.is_some() || args.pipe_out.is_some() {
+        Some("--src or --dest")
+    } else if args.check || args.dry_run.is_some() {
+        Some("--check or --dry-run")
+    } else {
+        None
+    };
+    if let Some(conflicting) = conflicting {
+        eprintln!("--watch cannot be combined with {}", conflicting);
+        return ExitCode::from(exit::INVALID_INPUT);
+    }
+    let ratio = match args.ratio {
+        Some(ratio) => ratio,
+        None => {
+            eprintln!("--watch requires --ratio (or --aspect) W:H");
+            return ExitCode::from(exit::INVALID_INPUT);
+        }
+    };
+    let out_dir = match &args.out_dir {
+        Some(out_dir) => std::path::Path::new(out_dir),
+        None => {
+            eprintln!("--watch requires --out-dir");
+            return ExitCode::from(exit::INVALID_INPUT);
+        }
+    };
+    // Outputs written into the watched directory would be taken as new images
+    if let (Ok(dir), Ok(out_dir)) = (std::fs::canonicalize(dir), std::fs::canonicalize(out_dir)) {
+        if dir == out_dir {
+            eprintln!("--out-dir must be another directory than the watched one");
+            return ExitCode::from(exit::INVALID_INPUT);
+        }
+    }
+    let images = match image_bg_extender::watch::watch(std::path::Path::new(dir)) {
+        Ok(images) => images,
+        Err(e) => {
+            eprintln!("Cannot watch {}: {}", dir, e);
+            return ExitCode::from(exit::INVALID_INPUT);
+        }
+    };
+    let sheet = match contact_sheet(args) {
+        Ok(sheet) => sheet,
+        Err(code) => return code,
+    };
+    if args.verbosity > Verbosity::Quiet {
+        eprintln!("Watching {} for new images", dir);
+    }
+    let invalid = std::sync::Mutex::new(Vec::new());
+    let entries = images.flat_map(|image| {
+        let entries = image.map_err(|e| e.to_string()).and_then(|path| {
+            let mut info = image_bg_extender::ImageInfo::new(
+                path.to_string_lossy(),
+                "",
+                image_bg_extender::AspectRatio::Fixed(ratio),
+            );
+            let options = info.options_mut();
+            if let Some(mode) = args.mode {
+                options.style.background = mode;
+            }
+            options.output_format = args.out_format;
+            prepare(args, vec![info])
+        });
+        entries.unwrap_or_else(|message| {
+            eprintln!("{}: {}", dir, message);
+            invalid
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .push(dir.to_string());
+            Vec::new()
+        })
+    });
+    let result = process(args, &[], entries, sheet).map(|mut summary| {
+        let invalid = invalid.into_inner().unwrap_or_else(|e| e.into_inner());
+        summary.failed.extend(invalid);
+        summary
+    });
+    finish(args, result)
+}
+
+#[cfg(not(feature = "watch"))]
+fn watch(_args: &Args, _dir: &str) -> ExitCode {
+    eprintln!("--watch requires building with the `watch` feature");
+    ExitCode::from(exit::INVALID_INPUT)
+}
+
+fn main() -> ExitCode {
+    let args = match parse_args() {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{}", e);
+            return ExitCode::from(exit::INVALID_INPUT);
+        }
+    };
+    if let Err(e) = start_logging(&args) {
+        eprintln!("{}", e);
+        return ExitCode::from(exit::INVALID_INPUT);
+    }
+    if let Some(addr) = &args.serve {
+        return match serve(&args, addr) {
+            Ok(()) => ExitCode::SUCCESS,
+            Err(e) => {
+                eprintln!("{}", e);
+                ExitCode::FAILURE
+            }
+        };
+    }
+
+    if let Some(dir) = &args.watch {
+        return watch(&args, dir);
+    }
+
+    if args.stdin && (args.pipe_in.is_some() || args.pipe_out.is_some()) {
+        eprintln!("--stdin reads a job list and cannot be combined with --src or --dest");
+        return ExitCode::from(exit::INVALID_INPUT);
+    }
+    if args.stdin {
+        if let Some(input) = args.input.as_deref().filter(|&input| input != STDIO) {
+            eprintln!("--stdin reads the job list from stdin, not from {}", input);
+            return ExitCode::from(exit::INVALID_INPUT);
+        }
+    }
+    if args.pipe_in.is_some() || args.pipe_out.is_some() {
+        let input = args.pipe_in.as_deref().unwrap_or(STDIO);
+        let output = args.pipe_out.as_deref().unwrap_or(STDIO);
+        let ratio = match args.ratio {
+            Some(ratio) => ratio,
+            None => {
+                eprintln!("Pipe mode requires --ratio (or --aspect) W:H");
+                return ExitCode::from(exit::INVALID_INPUT);
+            }
+        };
+        // Only the standard streams are piped, files are an entry like any
+        if input != STDIO && output != STDIO {
+            let mut info = image_bg_extender::ImageInfo::new(
+                input,
+                output,
+                image_bg_extender::AspectRatio::Fixed(ratio),
+            );
+            let options = info.options_mut();
+            if let Some(mode) = args.mode {
+                options.style.background = mode;
+            }
+            options.output_format = args.out_format;
+            return run_entries(&args, vec![info]);
+        }
+        let mut options = image_bg_extender::Options {
+            output_format: args.out_format,
+            ..Default::default()
+        };
+        if let Some(mode) = args.mode {
+            options.style.background = mode;
+        }
+        apply_flags(&args, &mut options);
+        return run_pipe(&args, input, output, ratio, &options);
+    }
+
+    if args.ndjson {
+        if args.check {
+            eprintln!("--check needs the whole job list and cannot be combined with --ndjson");
+            return ExitCode::from(exit::INVALID_INPUT);
+        }
+        return stream(&args);
+    }
+    let info_list = match read_job_list(args.input.as_deref(), &args.defaults) {
+        Ok(info_list) => info_list,
+        Err(message) => {
+            eprintln!("{}", message);
+            return ExitCode::from(exit::INVALID_INPUT);
+        }
+    };
+    run_entries(&args, info_list)
+}
+
+/// Checks or processes `info_list`, read from the job list or made from
+/// `--src` and `--dest`.
+fn run_entries(args: &Args, info_list: Vec<image_bg_extender::ImageInfo>) -> ExitCode {
+    let info_list = match prepare(args, info_list) {
+        Ok(info_list) => info_list,
+        Err(message) => {
+            eprintln!("{}", message);
+            return ExitCode::from(exit::INVALID_INPUT);
+        }
+    };
+    if args.check {
+        return check(args, info_list);
+    }
+    if let Err(message) = check_stdio(&info_list) {
+        eprintln!("{}", message);
+        return ExitCode::from(exit::INVALID_INPUT);
+    }
+    if let [info] = &info_list[..] {
+        if info.destination() == STDIO {
+            let ratio = match info.resolve_ratio() {
+                Ok(ratio) => ratio,
+                Err(e) => {
+                    eprintln!("{}", e);
+                    return ExitCode::from(exit::FAILURE);
+                }
+            };
+            // The options the entry would have been written to a file with
+            let options = info.options().clone();
+            return run_pipe(args, info.source(), STDIO, ratio, &options);
+        }
+    }
+
+    let batch = match image_bg_extender::batch::dedupe(info_list, args.last_wins) {
+        Ok(batch) => batch,
+        Err(conflicts) => {
+            for conflict in &conflicts {
+                eprintln!("Conflicting destination: {}", conflict);
+            }
+            eprintln!("Pass --last-wins to keep only the last entry for each destination");
+            return ExitCode::from(exit::INVALID_INPUT);
+        }
+    };
+    let sheet = match contact_sheet(args) {
+        Ok(sheet) => sheet,
+        Err(code) => return code,
+    };
+    finish(args, process(args, &batch.superseded, batch.entries, sheet))
+}
+
+/// The contact sheet asked for, unless nothing is written.
+fn contact_sheet(
+    args: &Args,
+) -> Result<Option<image_bg_extender::contact_sheet::ContactSheet>, ExitCode> {
+    match (&args.contact_sheet, args.dry_run) {
+        (Some(path), None) => {
+            match image_bg_extender::contact_sheet::ContactSheet::new(path, args.sheet.clone()) {
+                Ok(sheet) => Ok(Some(sheet)),
+                Err(e) => {
+                    eprintln!("Contact sheet: {}", e);
+                    Err(ExitCode::from(exit::INVALID_INPUT))
+                }
+            }
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Prints the summary of a run and picks its exit code.
+fn finish(args: &Args, result: io::Result<Summary>) -> ExitCode {
+    match result {
+        Ok(summary) => {
+            let panicked = !summary.panicked.is_empty();
+            if args.verbosity > Verbosity::Quiet || !summary.failed.is_empty() || panicked {
+                let mut line = format!(
+                    "{} ok, {} skipped, {} failed",
+                    summary.ok,
+                    summary.skipped,
+                    summary.failed.len()
+                );
+                if panicked {
+                    line.push_str(&format!(", {} panicked", summary.panicked.len()));
+                }
+                eprintln!("{}", line);
+            }
+            if summary.not_attempted > 0 {
+                eprintln!(
+                    "Stopped at the first failure, {} entries not attempted",
+                    summary.not_attempted
+                );
+            } else if summary.stopped {
+                eprintln!("Stopped at the first failure");
+            }
+            if summary.interrupted {
+                eprintln!("Interrupted, after finishing the entries in progress");
+            }
+            if !summary.failed.is_empty() {
+                eprintln!("Failed sources:");
+                for source in &summary.failed {
+                    eprintln!("  {}", source);
+                }
+            }
+            if panicked {
+                eprintln!("Panicked sources:");
+                for source in &summary.panicked {
+                    eprintln!("  {}", source);
+                }
+            }
+            ExitCode::from(summary.exit_code())
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,166 @@
+//! Manifests of the outputs a batch wrote, with the SHA-256 and dimensions
+//! of each, for checking transfers and finding identical outputs
+//! downstream.
+//!
+//! Each output is read back and hashed as its entry finishes, and the
+//! manifest is written once the batch is done: as CSV when its path ends in
+//! `.csv`, and as a JSON array otherwise. A variant, output or page is a
+//! row of its own. Entries skipped as up to date wrote nothing and are left
+//! out.
+
+use std::fmt::Write as _;
+use std::io::Cursor;
+use std::path::Path;
+
+use image::io::Reader as ImageReader;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+use crate::error::{Context, ExtendError, Stage};
+use crate::{atomic, read_source, ImageInfo, ImageReport};
+
+/// One file written for an entry.
+#[derive(Serialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Record {
+    pub source: String,
+    pub destination: String,
+    /// Lowercase hex.
+    pub sha256: String,
+    /// Read from the file, and left out for formats whose header cannot be.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub width: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub height: Option<u32>,
+}
+
+/// The destinations `report` says were written for `info`, one for each
+/// variant, output and page.
+fn written(info: &ImageInfo, report: &ImageReport) -> Vec<String> {
+    let variants = report
+        .variants
+        .iter()
+        .filter(|variant| variant.error.is_none())
+        .map(|variant| variant.destination.clone());
+    let outputs = report
+        .outputs
+        .iter()
+        .filter(|output| output.error.is_none())
+        .map(|output| output.destination.clone());
+    let mut written: Vec<String> = variants.chain(outputs).collect();
+    if report.variants.is_empty() && report.outputs.is_empty() {
+        written.push(
+            report
+                .renamed
+                .clone()
+                .unwrap_or_else(|| info.destination().to_string()),
+        );
+    }
+    #[cfg(feature = "tiff")]
+    {
+        use crate::pages::PAGE_PLACEHOLDER;
+        let pages = report.pages.unwrap_or(1);
+        written = written
+            .into_iter()
+            .flat_map(|dest| match dest.contains(PAGE_PLACEHOLDER) {
+                true => (1..=pages)
+                    .map(|page| dest.replace(PAGE_PLACEHOLDER, &page.to_string()))
+                    .collect(),
+                false => vec![dest],
+            })
+            .collect();
+    }
+    written
+}
+
+impl Record {
+    /// Records of the files `report` says were written for `info`, read
+    /// back from where they were put.
+    pub fn all(info: &ImageInfo, report: &ImageReport) -> Result<Vec<Record>, ExtendError> {
+        written(info, report)
+            .into_iter()
+            .map(|dest| {
+                let data = read_source(&dest)?;
+                let dimensions = ImageReader::new(Cursor::new(&data[..]))
+                    .with_guessed_format()
+                    .ok()
+                    .and_then(|reader| reader.into_dimensions().ok());
+                Ok(Record {
+                    source: info.source().to_string(),
+                    sha256: hex(&Sha256::digest(&data)),
+                    destination: dest,
+                    width: dimensions.map(|(width, _)| width),
+                    height: dimensions.map(|(_, height)| height),
+                })
+            })
+            .collect()
+    }
+}
+
+fn hex(digest: &[u8]) -> String {
+    digest.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// A CSV field, quoted when it has to be.
+fn field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// A manifest being filled in, entry by entry.
+pub struct Manifest {
+    path: String,
+    records: Vec<Record>,
+}
+
+impl Manifest {
+    /// A manifest to be written to `path`.
+    pub fn new(path: &str) -> Self {
+        Manifest {
+            path: path.to_string(),
+            records: Vec::new(),
+        }
+    }
+
+    /// Adds the records of an entry.
+    pub fn push(&mut self, records: Vec<Record>) {
+        self.records.extend(records);
+    }
+
+    fn is_csv(&self) -> bool {
+        Path::new(&self.path)
+            .extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("csv"))
+    }
+
+    /// Writes the manifest, in job list order, and returns its path.
+    pub fn finish(self) -> Result<String, ExtendError> {
+        let data = if self.is_csv() {
+            let mut csv = String::from("source,destination,sha256,width,height\n");
+            for record in &self.records {
+                let dimension =
+                    |value: Option<u32>| value.map_or_else(String::new, |v| v.to_string());
+                // Writing to a String cannot fail
+                let _ = writeln!(
+                    csv,
+                    "{},{},{},{},{}",
+                    field(&record.source),
+                    field(&record.destination),
+                    record.sha256,
+                    dimension(record.width),
+                    dimension(record.height)
+                );
+            }
+            csv.into_bytes()
+        } else {
+            serde_json::to_vec(&self.records).context(Stage::Write, &self.path)?
+        };
+        atomic::write(&self.path, |temp| {
+            std::fs::write(temp, &data).context(Stage::Write, &self.path)
+        })?;
+        Ok(self.path)
+    }
+}
@@ -0,0 +1,359 @@
+//! Metadata carried over from the source to the destination: the density
+//! always, and with `keepMetadata` the EXIF data and the ICC profile, as the
+//! APP1 and APP2 segments of JPEG and the `eXIf` and `iCCP` chunks of PNG.
+//!
+//! As with the density, the encoders of `image` write neither, so both are
+//! read from the encoded source and patched into the encoded output. Other
+//! formats go without them.
+
+use std::convert::TryInto;
+
+use image::{DynamicImage, ImageFormat};
+
+use crate::density::{self, be16, be32, Density};
+
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+const EXIF_HEADER: &[u8] = b"Exif\0\0";
+const ICC_HEADER: &[u8] = b"ICC_PROFILE\0";
+/// Room for the profile in one APP2 segment, after its length, header and
+/// the sequence number and count.
+const ICC_CHUNK: usize = 65535 - 2 - ICC_HEADER.len() - 2;
+/// The EXIF tag of the orientation of the image.
+const ORIENTATION: u16 = 0x0112;
+
+/// Sources are looked for their orientation in this much of their start
+/// when only their header is read.
+const HEADER_LEN: u64 = 256 * 1024;
+
+/// What is written into the output besides the pixels.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Metadata {
+    pub(crate) density: Option<Density>,
+    /// The TIFF-structured EXIF data, without the `Exif` header of JPEG.
+    exif: Option<Vec<u8>>,
+    icc: Option<Vec<u8>>,
+    /// The EXIF orientation the source was stored in, 1 when upright.
+    pub(crate) orientation: u8,
+}
+
+impl Metadata {
+    /// For the image turned as `rotate` turns it.
+    pub(crate) fn oriented(self, options: &crate::Options) -> Self {
+        Metadata {
+            density: self.density.map(|density| density.oriented(options)),
+            ..self
+        }
+    }
+
+    /// Turns `img` upright as its EXIF orientation says, along with the
+    /// density, and returns whether it had to be turned.
+    pub(crate) fn turn_upright(&mut self, img: &mut DynamicImage) -> bool {
+        if self.orientation == 1 {
+            return false;
+        }
+        let stored = std::mem::replace(img, DynamicImage::new_rgba8(0, 0));
+        *img = upright(stored, self.orientation);
+        if swaps_sides(self.orientation) {
+            self.density = self.density.map(|density| Density {
+                x: density.y,
+                y: density.x,
+                ..density
+            });
+        }
+        self.orientation = 1;
+        true
+    }
+}
+
+/// The metadata of an encoded source, the EXIF data and ICC profile only
+/// when `keep` is set. The orientation is read either way.
+pub(crate) fn read(data: &[u8], keep: bool) -> Metadata {
+    let mut metadata = Metadata {
+        density: density::read(data),
+        orientation: 1,
+        ..Metadata::default()
+    };
+    if data.starts_with(PNG_SIGNATURE) {
+        read_png(data, keep, &mut metadata);
+    } else if data.starts_with(&[0xFF, 0xD8]) {
+        read_jpeg(data, &mut metadata);
+    }
+    metadata.orientation = metadata
+        .exif
+        .as_deref()
+        .and_then(exif_orientation)
+        .unwrap_or(1);
+    if !keep {
+        metadata.exif = None;
+        metadata.icc = None;
+    }
+    metadata
+}
+
+/// The EXIF orientation of the source at `path`, from the start of the
+/// file only. 1 for sources without one.
+pub(crate) fn file_orientation(path: &str) -> u8 {
+    use std::io::Read;
+
+    let mut data = Vec::new();
+    let header =
+        std::fs::File::open(path).and_then(|file| file.take(HEADER_LEN).read_to_end(&mut data));
+    match header {
+        Ok(_) => read(&data, false).orientation,
+        Err(_) => 1,
+    }
+}
+
+/// Whether the EXIF `orientation` has the sides of the stored image
+/// swapped, the upright one being as wide as the stored one is high.
+fn swaps_sides(orientation: u8) -> bool {
+    (5..=8).contains(&orientation)
+}
+
+/// Dimensions of an image of `(width, height)` stored in the EXIF
+/// `orientation`, as it is meant to be shown.
+pub(crate) fn upright_dimensions((width, height): (u32, u32), orientation: u8) -> (u32, u32) {
+    if swaps_sides(orientation) {
+        (height, width)
+    } else {
+        (width, height)
+    }
+}
+
+/// `img`, stored in the EXIF `orientation`, as it is meant to be shown.
+pub(crate) fn upright(img: DynamicImage, orientation: u8) -> DynamicImage {
+    match orientation {
+        2 => img.fliph(),
+        3 => img.rotate180(),
+        4 => img.flipv(),
+        5 => img.rotate90().fliph(),
+        6 => img.rotate90(),
+        7 => img.rotate270().fliph(),
+        8 => img.rotate270(),
+        _ => img,
+    }
+}
+
+fn read_jpeg(data: &[u8], metadata: &mut Metadata) -> Option<()> {
+    let mut at = 2;
+    let mut icc: Vec<(u8, &[u8])> = Vec::new();
+    loop {
+        if data.get(at)? != &0xFF {
+            break;
+        }
+        let marker = *data.get(at + 1)?;
+        let len = be16(data, at + 2)? as usize;
+        let segment = data.get(at + 4..at + 2 + len)?;
+        match marker {
+            0xE1 if segment.starts_with(EXIF_HEADER) && metadata.exif.is_none() => {
+                metadata.exif = Some(segment[EXIF_HEADER.len()..].to_vec());
+            }
+            0xE2 if segment.starts_with(ICC_HEADER) => {
+                let chunk = &segment[ICC_HEADER.len()..];
+                if chunk.len() >= 2 {
+                    icc.push((chunk[0], &chunk[2..]));
+                }
+            }
+            // Start of scan, past all of the headers
+            0xDA => break,
+            _ => {}
+        }
+        at += 2 + len;
+    }
+    if !icc.is_empty() {
+        // Numbered from 1, and not necessarily written in order
+        icc.sort_by_key(|&(sequence, _)| sequence);
+        metadata.icc = Some(
+            icc.into_iter()
+                .flat_map(|(_, chunk)| chunk.to_vec())
+                .collect(),
+        );
+    }
+    Some(())
+}
+
+fn read_png(data: &[u8], keep: bool, metadata: &mut Metadata) -> Option<()> {
+    let mut at = PNG_SIGNATURE.len();
+    loop {
+        let len = be32(data, at)? as usize;
+        let chunk = data.get(at + 8..at + 8 + len)?;
+        match data.get(at + 4..at + 8)? {
+            b"eXIf" => metadata.exif = Some(chunk.to_vec()),
+            b"iCCP" if keep => metadata.icc = inflate_profile(chunk),
+            // Both come before the image data
+            b"IDAT" | b"IEND" => return Some(()),
+            _ => {}
+        }
+        at += 12 + len;
+    }
+}
+
+/// The profile of an `iCCP` chunk: its name, a NUL, the compression method
+/// and the zlib stream.
+#[cfg(feature = "png")]
+fn inflate_profile(chunk: &[u8]) -> Option<Vec<u8>> {
+    use std::io::Read;
+
+    let name = chunk.iter().position(|&byte| byte == 0)?;
+    let stream = chunk.get(name + 2..)?;
+    let mut profile = Vec::new();
+    flate2::read::ZlibDecoder::new(stream)
+        .read_to_end(&mut profile)
+        .ok()?;
+    Some(profile)
+}
+
+/// Builds without PNG cannot have read one.
+#[cfg(not(feature = "png"))]
+fn inflate_profile(_chunk: &[u8]) -> Option<Vec<u8>> {
+    None
+}
+
+/// Where the value of the orientation in `exif` is, and whether the data
+/// is big-endian.
+fn orientation_value(exif: &[u8]) -> Option<(usize, bool)> {
+    let big_endian = match exif.get(..4)? {
+        b"MM\0*" => true,
+        b"II*\0" => false,
+        _ => return None,
+    };
+    let u16_at = |at: usize| -> Option<u16> {
+        let bytes = exif.get(at..at + 2)?.try_into().ok()?;
+        Some(if big_endian {
+            u16::from_be_bytes(bytes)
+        } else {
+            u16::from_le_bytes(bytes)
+        })
+    };
+    let bytes: [u8; 4] = exif.get(4..8)?.try_into().ok()?;
+    let ifd = if big_endian {
+        u32::from_be_bytes(bytes)
+    } else {
+        u32::from_le_bytes(bytes)
+    } as usize;
+    let count = u16_at(ifd)? as usize;
+    (0..count)
+        .map(|index| ifd + 2 + index * 12)
+        // A SHORT, held in the first two bytes of the value
+        .find(|&entry| u16_at(entry) == Some(ORIENTATION) && u16_at(entry + 2) == Some(3))
+        .map(|entry| (entry + 8, big_endian))
+}
+
+fn exif_orientation(exif: &[u8]) -> Option<u8> {
+    let (at, big_endian) = orientation_value(exif)?;
+    let bytes = exif.get(at..at + 2)?.try_into().ok()?;
+    let value = if big_endian {
+        u16::from_be_bytes(bytes)
+    } else {
+        u16::from_le_bytes(bytes)
+    };
+    Some(value as u8).filter(|_| (1..=8).contains(&value))
+}
+
+/// Marks the EXIF data as of an upright image, as the output is written as
+/// its pixels are. Data laid out otherwise is left as it is.
+fn mark_upright(exif: &mut [u8]) {
+    if let Some((at, big_endian)) = orientation_value(exif) {
+        let one = if big_endian {
+            1u16.to_be_bytes()
+        } else {
+            1u16.to_le_bytes()
+        };
+        if let Some(value) = exif.get_mut(at..at + 2) {
+            value.copy_from_slice(&one);
+        }
+    }
+}
+
+fn png_chunk(kind: &[u8; 4], data: &[u8]) -> Vec<u8> {
+    let mut chunk = Vec::with_capacity(12 + data.len());
+    chunk.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    chunk.extend_from_slice(kind);
+    chunk.extend_from_slice(data);
+    let crc = density::crc32(&chunk[4..]);
+    chunk.extend_from_slice(&crc.to_be_bytes());
+    chunk
+}
+
+#[cfg(feature = "png")]
+fn deflate_profile(profile: &[u8]) -> Option<Vec<u8>> {
+    use std::io::Write;
+
+    let mut chunk = b"ICC profile\0\0".to_vec();
+    let mut encoder = flate2::write::ZlibEncoder::new(&mut chunk, flate2::Compression::default());
+    encoder.write_all(profile).ok()?;
+    encoder.finish().ok()?;
+    Some(chunk)
+}
+
+#[cfg(not(feature = "png"))]
+fn deflate_profile(_profile: &[u8]) -> Option<Vec<u8>> {
+    None
+}
+
+fn jpeg_segment(marker: u8, parts: &[&[u8]]) -> Vec<u8> {
+    let len: usize = 2 + parts.iter().map(|part| part.len()).sum::<usize>();
+    let mut segment = vec![0xFF, marker];
+    segment.extend_from_slice(&(len as u16).to_be_bytes());
+    for part in parts {
+        segment.extend_from_slice(part);
+    }
+    segment
+}
+
+/// Writes `metadata` into `data`, encoded as `format` by `format::encode`.
+/// Other formats, or data laid out otherwise, are left as they are, and so
+/// is EXIF data too large for a JPEG segment.
+pub(crate) fn embed(data: Vec<u8>, format: ImageFormat, metadata: &Metadata) -> Vec<u8> {
+    let mut data = match metadata.density {
+        Some(density) => density::embed(data, format, density),
+        None => data,
+    };
+    let exif = metadata.exif.clone().map(|mut exif| {
+        mark_upright(&mut exif);
+        exif
+    });
+    match format {
+        ImageFormat::Png => {
+            // Right after IHDR, each chunk ahead of the ones inserted before
+            let at = PNG_SIGNATURE.len() + 12 + 13;
+            if data.len() < at
+                || data.get(PNG_SIGNATURE.len() + 4..PNG_SIGNATURE.len() + 8) != Some(&b"IHDR"[..])
+            {
+                return data;
+            }
+            if let Some(exif) = &exif {
+                data.splice(at..at, png_chunk(b"eXIf", exif));
+            }
+            if let Some(chunk) = metadata.icc.as_deref().and_then(deflate_profile) {
+                data.splice(at..at, png_chunk(b"iCCP", &chunk));
+            }
+        }
+        ImageFormat::Jpeg => {
+            // The encoder starts with a JFIF header, which has to stay first
+            if data.get(2..4) != Some(&[0xFF, 0xE0][..]) {
+                return data;
+            }
+            let at = match be16(&data, 4) {
+                Some(len) => 4 + len as usize,
+                None => return data,
+            };
+            let mut segments = Vec::new();
+            if let Some(exif) = exif.filter(|exif| exif.len() <= 65535 - 2 - EXIF_HEADER.len()) {
+                segments.extend(jpeg_segment(0xE1, &[EXIF_HEADER, &exif]));
+            }
+            if let Some(icc) = &metadata.icc {
+                let chunks: Vec<&[u8]> = icc.chunks(ICC_CHUNK).collect();
+                if chunks.len() <= 255 {
+                    for (index, chunk) in chunks.iter().enumerate() {
+                        let numbers = [index as u8 + 1, chunks.len() as u8];
+                        segments.extend(jpeg_segment(0xE2, &[ICC_HEADER, &numbers, chunk]));
+                    }
+                }
+            }
+            data.splice(at..at, segments);
+        }
+        _ => {}
+    }
+    data
+}
@@ -0,0 +1,98 @@
+//! `compile_image_async`, for services running on tokio.
+//!
+//! A still image going from a file to a file is read and written with
+//! `tokio::fs`, and only decoded, extended and encoded on the blocking pool
+//! of the runtime. The temporary output is renamed into place as the other
+//! entries' is. Everything else, from animations and multi-page sources to
+//! archives, remote storage, variants and the settings that look at the
+//! destination or retry the entry, runs whole on the blocking pool, reads
+//! and writes included, as `compile_image`.
+//!
+//! Dropping the future cancels the entry: the worker gives up at the next
+//! stage it reaches, and the temporary output is removed.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use image::ImageFormat;
+
+use crate::error::{Context, ExtendError, Stage};
+use crate::{archive, atomic, storage, timeout, ImageInfo, ImageReport, Overwrite};
+
+/// Sets the flag of the worker when the future is dropped, done or not.
+struct CancelOnDrop(Arc<AtomicBool>);
+
+impl Drop for CancelOnDrop {
+    fn drop(&mut self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+}
+
+/// `compile_image` on the current tokio runtime.
+///
+/// Dropping the future before it is done cancels the entry, which stops at
+/// its next stage and leaves the destination as it was. Panics outside of a
+/// runtime, as `tokio::task::spawn_blocking` does.
+pub async fn compile_image_async(info: ImageInfo) -> Result<ImageReport, ExtendError> {
+    let cancelled = Arc::new(AtomicBool::new(false));
+    let _cancel = CancelOnDrop(Arc::clone(&cancelled));
+    if !read_whole(&info).await {
+        let source = info.source.clone();
+        return tokio::task::spawn_blocking(move || {
+            timeout::cancellable(cancelled, || crate::compile_image(&info))
+        })
+        .await
+        // Panics are contained by `compile_image`, which leaves the runtime
+        // shutting down under the worker
+        .unwrap_or_else(|e| Err(ExtendError::new(Stage::Read, Some(&source), e)));
+    }
+
+    let (src, dest) = (info.source.clone(), info.destination.clone());
+    let data = tokio::fs::read(&src).await.context(Stage::Read, &src)?;
+    let (report, encoded, data, temp) = tokio::task::spawn_blocking(move || {
+        timeout::cancellable(cancelled, || {
+            crate::contained(&info, || {
+                let (report, encoded) = crate::extend_read(&info, &data)?;
+                // Where symlinks point is looked up here, off the runtime
+                let temp = atomic::TempFile::detached(&info.destination);
+                Ok((report, encoded, data, temp))
+            })
+        })
+    })
+    .await
+    .unwrap_or_else(|e| Err(ExtendError::new(Stage::Read, Some(&src), e)))?;
+    // Copied through when nothing was encoded
+    let output = encoded.as_deref().unwrap_or(&data);
+    tokio::fs::write(temp.path(), output)
+        .await
+        .context(Stage::Write, &dest)?;
+    temp.commit_async().await?;
+    Ok(report)
+}
+
+/// Whether `info` is a single still image from a file on this machine to
+/// another, which `compile_image_async` reads and writes itself.
+async fn read_whole(info: &ImageInfo) -> bool {
+    let (src, dest, options) = (&info.source, &info.destination, &info.options);
+    let local = |path: &str| archive::split(path).is_none() && !storage::is_remote(path);
+    // Streamed sources are extended as they are read
+    let streamed = matches!(
+        ImageFormat::from_path(src),
+        Ok(ImageFormat::Gif | ImageFormat::Tiff | ImageFormat::WebP)
+    );
+    let plain = info.outputs.is_empty()
+        && info.variants.is_empty()
+        && local(src)
+        && local(dest)
+        && !streamed
+        && !options.in_place
+        && !options.sidecar
+        && !options.skip_up_to_date
+        && options.preview_width.is_none()
+        && options.timeout.is_none()
+        && options.retries.is_none();
+    if !plain {
+        return false;
+    }
+    options.overwrite() == Overwrite::Replace || !tokio::fs::try_exists(dest).await.unwrap_or(true)
+}
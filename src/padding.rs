@@ -0,0 +1,84 @@
+//! Backgrounds padding the image out with its own pixels: `mirror`, which
+//! reflects the image at each of its edges into the room beside it, and
+//! `replicate`, which repeats the outermost pixels of each row and column.
+//!
+//! Past a reflection the image carries on as it is, and then reflected
+//! again, so bands wider than the image are filled without a seam.
+
+use image::{imageops, DynamicImage, GenericImageView, ImageBuffer, Rgba, RgbaImage};
+
+use crate::depth::Channel;
+use crate::{bands, Layout, Style};
+
+/// Where `at` falls in a line of `len` pixels starting at `start` that is
+/// reflected at both of its ends, the edge pixel being repeated once.
+pub(crate) fn reflect(at: u32, start: u32, len: u32) -> u32 {
+    let period = 2 * i64::from(len);
+    let offset = (i64::from(at) - i64::from(start)).rem_euclid(period);
+    if offset < i64::from(len) {
+        offset as u32
+    } else {
+        (period - 1 - offset) as u32
+    }
+}
+
+/// Where `at` falls in a line of `len` pixels starting at `start` whose end
+/// pixels go on forever.
+pub(crate) fn clamp(at: u32, start: u32, len: u32) -> u32 {
+    at.saturating_sub(start).min(len - 1)
+}
+
+/// The canvas of `layout` filled with reflections of the normalised image
+/// at its placed size, the middle being left to the image placed on top.
+pub(crate) fn mirror(img: &DynamicImage, layout: &Layout, style: &Style) -> RgbaImage {
+    pad(img, layout, style, reflect, |pixel| {
+        style.adjust_background(pixel)
+    })
+}
+
+/// The canvas of `layout` with the edge pixels of the normalised image at
+/// its placed size repeated outwards, so each row and column keeps its own
+/// color and gradients along the edges carry on.
+pub(crate) fn replicate(img: &DynamicImage, layout: &Layout, style: &Style) -> RgbaImage {
+    pad(img, layout, style, clamp, |pixel| {
+        style.adjust_background(pixel)
+    })
+}
+
+/// The canvas of `layout`, each pixel taken from the placed image where
+/// `map` takes its column and row and changed by `adjust`.
+pub(crate) fn pad<T: Channel>(
+    img: &DynamicImage,
+    layout: &Layout,
+    style: &Style,
+    map: fn(u32, u32, u32) -> u32,
+    adjust: impl Fn(Rgba<T>) -> Rgba<T> + Sync,
+) -> ImageBuffer<Rgba<T>, Vec<T>> {
+    let (canvas_width, canvas_height) = layout.canvas;
+    let (placed_width, placed_height) = layout.image;
+    let (x, y) = layout.offset();
+    // Scaled as the placed image is, so the padding meets it pixel for pixel
+    let placed = T::rgba(img);
+    let placed = if img.dimensions() == layout.image {
+        placed
+    } else {
+        let filter = style.filter(imageops::FilterType::Lanczos3);
+        imageops::resize(&placed, placed_width, placed_height, filter)
+    };
+    let columns: Vec<u32> = (0..canvas_width)
+        .map(|column| map(column, x, placed_width))
+        .collect();
+
+    let mut canvas = ImageBuffer::new(canvas_width, canvas_height);
+    let row_len = canvas_width as usize * 4;
+    bands::rows(&mut canvas, row_len, |first, rows| {
+        for (index, row) in rows.chunks_exact_mut(row_len).enumerate() {
+            let source = map((first + index) as u32, y, placed_height);
+            for (pixel, &column) in row.chunks_exact_mut(4).zip(&columns) {
+                let adjusted = adjust(*placed.get_pixel(column, source));
+                pixel.copy_from_slice(&adjusted.0);
+            }
+        }
+    });
+    canvas
+}
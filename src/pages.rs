@@ -0,0 +1,211 @@
+//! Multi-page TIFF sources.
+//!
+//! Every page is extended on its own and either collected into a multi-page
+//! TIFF or written to its own file through a `{page}` destination placeholder.
+
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::time::Instant;
+
+use image::error::{DecodingError, EncodingError, UnsupportedError, UnsupportedErrorKind};
+use image::{DynamicImage, GenericImageView, ImageBuffer, ImageError, ImageFormat};
+use tiff::decoder::{Decoder, DecodingResult};
+use tiff::encoder::{colortype, TiffEncoder};
+use tiff::ColorType;
+
+use crate::atomic::{self, TempFile};
+use crate::error::{Context, CorruptSource, ExtendError, Stage};
+use crate::report::{PageMode, Timings};
+use crate::{check_dimensions, check_source_pixels, extend, ImageReport, Options, OutputFormat};
+use crate::{format, timeout};
+
+/// Replaced by the 1-based page number in split mode.
+pub const PAGE_PLACEHOLDER: &str = "{page}";
+
+fn decoding_error(e: tiff::TiffError) -> ImageError {
+    ImageError::Decoding(DecodingError::new(ImageFormat::Tiff.into(), e))
+}
+
+fn encoding_error(e: tiff::TiffError) -> ImageError {
+    ImageError::Encoding(EncodingError::new(ImageFormat::Tiff.into(), e))
+}
+
+fn unsupported(feature: String) -> ImageError {
+    ImageError::Unsupported(UnsupportedError::from_format_and_kind(
+        ImageFormat::Tiff.into(),
+        UnsupportedErrorKind::GenericFeature(feature),
+    ))
+}
+
+fn open(src: &str) -> Result<Decoder<BufReader<File>>, ImageError> {
+    Decoder::new(BufReader::new(File::open(src)?)).map_err(decoding_error)
+}
+
+/// Walks the directory chain without decoding any pixel data.
+fn count_pages(src: &str) -> Result<u32, ImageError> {
+    let mut decoder = open(src)?;
+    let mut pages = 1;
+    while decoder.more_images() {
+        decoder.next_image().map_err(decoding_error)?;
+        pages += 1;
+    }
+    Ok(pages)
+}
+
+fn read_page(decoder: &mut Decoder<BufReader<File>>) -> Result<DynamicImage, ImageError> {
+    let (width, height) = decoder.dimensions().map_err(decoding_error)?;
+    let color_type = decoder.colortype().map_err(decoding_error)?;
+    let invalid = || unsupported(format!("page buffer does not match {:?}", color_type));
+    let image = match (color_type, decoder.read_image().map_err(decoding_error)?) {
+        (ColorType::Gray(8), DecodingResult::U8(data)) => DynamicImage::ImageLuma8(
+            ImageBuffer::from_raw(width, height, data).ok_or_else(invalid)?,
+        ),
+        (ColorType::GrayA(8), DecodingResult::U8(data)) => DynamicImage::ImageLumaA8(
+            ImageBuffer::from_raw(width, height, data).ok_or_else(invalid)?,
+        ),
+        (ColorType::RGB(8), DecodingResult::U8(data)) => {
+            DynamicImage::ImageRgb8(ImageBuffer::from_raw(width, height, data).ok_or_else(invalid)?)
+        }
+        (ColorType::RGBA(8), DecodingResult::U8(data)) => DynamicImage::ImageRgba8(
+            ImageBuffer::from_raw(width, height, data).ok_or_else(invalid)?,
+        ),
+        (ColorType::Gray(16), DecodingResult::U16(data)) => DynamicImage::ImageLuma16(
+            ImageBuffer::from_raw(width, height, data).ok_or_else(invalid)?,
+        ),
+        (ColorType::GrayA(16), DecodingResult::U16(data)) => DynamicImage::ImageLumaA16(
+            ImageBuffer::from_raw(width, height, data).ok_or_else(invalid)?,
+        ),
+        (ColorType::RGB(16), DecodingResult::U16(data)) => DynamicImage::ImageRgb16(
+            ImageBuffer::from_raw(width, height, data).ok_or_else(invalid)?,
+        ),
+        (ColorType::RGBA(16), DecodingResult::U16(data)) => DynamicImage::ImageRgba16(
+            ImageBuffer::from_raw(width, height, data).ok_or_else(invalid)?,
+        ),
+        (color_type, _) => return Err(unsupported(format!("{:?} pages", color_type))),
+    };
+    Ok(image)
+}
+
+/// Extends every page of a multi-page TIFF.
+///
+/// Returns `None` for single-page sources, which take the regular path.
+pub(crate) fn extend_tiff(
+    src: &str,
+    dest: &str,
+    aspect_ratio: (u32, u32),
+    format: Option<OutputFormat>,
+    options: &Options,
+) -> Result<Option<ImageReport>, ExtendError> {
+    let started = Instant::now();
+    if options.first_frame_only {
+        return Ok(None);
+    }
+    let pages = count_pages(src)
+        .map_err(|e| CorruptSource::classify_file(src, e))
+        .context(Stage::Decode, src)?;
+    if pages == 1 {
+        return Ok(None);
+    }
+
+    let mode = if dest.contains(PAGE_PLACEHOLDER) {
+        PageMode::Split
+    } else if format == Some(OutputFormat::Tiff) {
+        PageMode::MultiPage
+    } else {
+        return Err(unsupported(format!(
+            "multi-page source needs a TIFF destination or a {} placeholder \
+             (set firstFrameOnly to keep only the first page)",
+            PAGE_PLACEHOLDER
+        )))
+        .context(Stage::Write, dest);
+    };
+
+    let output = match mode {
+        PageMode::MultiPage => Some(TempFile::new(dest)),
+        PageMode::Split => None,
+    };
+    let mut encoder = match &output {
+        Some(output) => {
+            let file = File::create(output.path()).context(Stage::Write, dest)?;
+            Some(
+                TiffEncoder::new(BufWriter::new(file))
+                    .map_err(encoding_error)
+                    .context(Stage::Write, dest)?,
+            )
+        }
+        None => None,
+    };
+
+    let mut decoder = open(src)
+        .map_err(|e| CorruptSource::classify_file(src, e))
+        .context(Stage::Decode, src)?;
+    let mut timings = Timings::default();
+    let mut report = None;
+    for page in 1..=pages {
+        timeout::checkpoint(Stage::Decode, src)?;
+        if page > 1 {
+            decoder
+                .next_image()
+                .map_err(|e| CorruptSource::classify_file(src, decoding_error(e)))
+                .context(Stage::Decode, src)?;
+        }
+        let dimensions = decoder
+            .dimensions()
+            .map_err(|e| CorruptSource::classify_file(src, decoding_error(e)))
+            .context(Stage::Decode, src)?;
+        check_source_pixels(dimensions, options).context(Stage::Decode, src)?;
+        let img = read_page(&mut decoder)
+            .map_err(|e| CorruptSource::classify_file(src, e))
+            .context(Stage::Decode, src)?;
+        check_dimensions(img.dimensions(), Some(src)).context(Stage::Decode, src)?;
+        let extended = Timings::measure(&mut timings.composite_ms, || {
+            extend(&img, aspect_ratio, options)
+        })
+        .context(Stage::Composite, src)?;
+        let (page_report, canvas) = match extended {
+            Some(extended) => (extended.report(), extended.image),
+            None => (ImageReport::unchanged(img.dimensions()), img.into_rgba8()),
+        };
+
+        Timings::measure(&mut timings.write_ms, || match &mut encoder {
+            Some(encoder) => encoder
+                .write_image::<colortype::RGBA8>(canvas.width(), canvas.height(), &canvas)
+                .map_err(encoding_error)
+                .context(Stage::Write, dest),
+            None => {
+                let path = dest.replace(PAGE_PLACEHOLDER, &page.to_string());
+                atomic::write(&path, |temp| match format.map(OutputFormat::image_format) {
+                    Some(Some(format)) => canvas
+                        .save_with_format(temp, format)
+                        .context(Stage::Write, &path),
+                    // JPEG XL, which `image` cannot save
+                    Some(None) => {
+                        let encoding = format::Encoding::of(options);
+                        let img = DynamicImage::ImageRgba8(canvas.clone());
+                        let encoded =
+                            format::encode_jxl(&img, encoding).context(Stage::Write, &path)?;
+                        std::fs::write(temp, encoded).context(Stage::Write, &path)
+                    }
+                    None => canvas.save(temp).context(Stage::Write, &path),
+                })
+            }
+        })?;
+        // The first page is representative for the dimensions and colors
+        report.get_or_insert(page_report);
+    }
+
+    // The encoder has to flush its last directory first
+    drop(encoder);
+    if let Some(output) = output {
+        output.commit()?;
+    }
+
+    let mut report = report.expect("multi-page sources have pages");
+    report.copied = false;
+    report.pages = Some(pages);
+    report.page_mode = Some(mode);
+    timings.decode_ms =
+        started.elapsed().as_secs_f64() * 1000.0 - timings.composite_ms - timings.write_ms;
+    report.timings = timings;
+    Ok(Some(report))
+}
@@ -0,0 +1,178 @@
+//! `background: palette`, the few colors most of the image is made of
+//! painted into the room around it, for images whose edges average out to a
+//! color found nowhere in them.
+//!
+//! The colors are found by k-means in Oklab over a copy of the image scaled
+//! down to at most `SAMPLE_SIDE` pixels a side. The first cluster starts at
+//! the pixel furthest from the average and each further one at the pixel
+//! furthest from those chosen, so the same image always gives the same
+//! palette. They are laid out from the lightest to the darkest across the
+//! room on both sides of the image, as if the image were not there, in
+//! bands of equal size or blended into one another with `paletteGradient`.
+
+use image::{imageops, DynamicImage, GenericImageView, Rgba, RgbaImage};
+
+use crate::color::{self, Oklab};
+use crate::style::Style;
+use crate::{bands, Layout, Orientation};
+
+/// Longest side of the copy the colors are found in.
+const SAMPLE_SIDE: u32 = 64;
+/// Rounds of k-means, by which the clusters of so few pixels have settled.
+const ROUNDS: usize = 16;
+
+fn distance(a: &Oklab, b: &Oklab) -> f32 {
+    (a.l - b.l).powi(2) + (a.a - b.a).powi(2) + (a.b - b.b).powi(2)
+}
+
+fn nearest(centers: &[Oklab], pixel: &Oklab) -> usize {
+    (0..centers.len())
+        .min_by(|&i, &j| distance(&centers[i], pixel).total_cmp(&distance(&centers[j], pixel)))
+        .expect("at least one center")
+}
+
+fn mean(pixels: &[&(Oklab, f32)]) -> (Oklab, f32) {
+    let count = pixels.len().max(1) as f32;
+    let sum = pixels
+        .iter()
+        .fold((0.0, 0.0, 0.0, 0.0), |sum, (lab, alpha)| {
+            (sum.0 + lab.l, sum.1 + lab.a, sum.2 + lab.b, sum.3 + alpha)
+        });
+    (
+        Oklab {
+            l: sum.0 / count,
+            a: sum.1 / count,
+            b: sum.2 / count,
+        },
+        sum.3 / count,
+    )
+}
+
+/// The `count` dominant colors of the visible pixels of `img`, from the
+/// lightest to the darkest. Fewer when the image has fewer colors, and
+/// none when it has no visible pixels.
+pub(crate) fn colors(img: &DynamicImage, count: usize, style: &Style) -> Vec<Rgba<u8>> {
+    let (width, height) = img.dimensions();
+    let scale = (SAMPLE_SIDE as f32 / width.max(height) as f32).min(1.0);
+    let small = imageops::resize(
+        img,
+        ((width as f32 * scale).round() as u32).max(1),
+        ((height as f32 * scale).round() as u32).max(1),
+        style.filter(imageops::FilterType::Triangle),
+    );
+    let pixels: Vec<(Oklab, f32)> = small
+        .pixels()
+        .filter(|pixel| pixel.0[3] > 0)
+        .map(|&pixel| (Oklab::from_rgb(pixel), pixel.0[3] as f32))
+        .collect();
+    if pixels.is_empty() {
+        return Vec::new();
+    }
+
+    let average = mean(&pixels.iter().collect::<Vec<_>>()).0;
+    let mut centers: Vec<Oklab> = Vec::with_capacity(count);
+    while centers.len() < count {
+        let furthest = pixels.iter().map(|(lab, _)| lab).max_by(|a, b| {
+            let from = |lab: &Oklab| match centers.is_empty() {
+                true => distance(&average, lab),
+                false => distance(&centers[nearest(&centers, lab)], lab),
+            };
+            from(a).total_cmp(&from(b))
+        });
+        match furthest {
+            Some(lab)
+                if centers.is_empty() || distance(&centers[nearest(&centers, lab)], lab) > 0.0 =>
+            {
+                centers.push(*lab)
+            }
+            // Every pixel is one of the centers already
+            _ => break,
+        }
+    }
+
+    let mut clusters = Vec::new();
+    for _ in 0..ROUNDS {
+        clusters = vec![Vec::new(); centers.len()];
+        for pixel in &pixels {
+            clusters[nearest(&centers, &pixel.0)].push(pixel);
+        }
+        clusters.retain(|cluster: &Vec<&(Oklab, f32)>| !cluster.is_empty());
+        centers = clusters.iter().map(|cluster| mean(cluster).0).collect();
+    }
+    let mut found: Vec<(Oklab, f32)> = clusters.iter().map(|cluster| mean(cluster)).collect();
+    found.sort_by(|a, b| b.0.l.total_cmp(&a.0.l));
+    found
+        .into_iter()
+        .map(|(lab, alpha)| lab.to_rgb(alpha.round() as u8))
+        .collect()
+}
+
+/// The canvas of `layout` painted with `colors` along the sides the image
+/// was extended on, blended in linear light when `gradient` is set.
+pub(crate) fn background(layout: &Layout, colors: &[Rgba<u8>], gradient: bool) -> RgbaImage {
+    let (width, height) = layout.canvas;
+    let (x, y) = layout.offset();
+    // Room above and below, or to the sides
+    let (start, placed, length) = match layout.orientation {
+        Orientation::Landscape => (y, layout.image.1, height),
+        Orientation::Portrait => (x, layout.image.0, width),
+    };
+    let room = length.saturating_sub(placed).max(1) as f32;
+    // How far along the room a row or column is, from 0 to 1, skipping the
+    // image
+    let along = |at: u32| {
+        let at = if at < start {
+            at
+        } else if at < start + placed {
+            start
+        } else {
+            at - placed
+        };
+        ((at as f32 + 0.5) / room).clamp(0.0, 1.0)
+    };
+    let linear: Vec<[f32; 4]> = colors
+        .iter()
+        .map(|color| {
+            let [r, g, b, a] = color.0;
+            [
+                color::to_linear(r),
+                color::to_linear(g),
+                color::to_linear(b),
+                a as f32,
+            ]
+        })
+        .collect();
+    let last = colors.len() - 1;
+    let paint = |t: f32| -> Rgba<u8> {
+        if !gradient || last == 0 {
+            return colors[((t * colors.len() as f32) as usize).min(last)];
+        }
+        let at = t * last as f32;
+        let index = (at as usize).min(last - 1);
+        let between = at - index as f32;
+        let (from, to) = (linear[index], linear[index + 1]);
+        let mix = |channel: usize| from[channel] + (to[channel] - from[channel]) * between;
+        Rgba([
+            color::from_linear(mix(0)),
+            color::from_linear(mix(1)),
+            color::from_linear(mix(2)),
+            mix(3).round() as u8,
+        ])
+    };
+    let steps: Vec<Rgba<u8>> = (0..length).map(|at| paint(along(at))).collect();
+
+    let mut canvas = RgbaImage::new(width, height);
+    let row_len = width as usize * 4;
+    bands::rows(&mut canvas, row_len, |first, rows| {
+        for (index, row) in rows.chunks_exact_mut(row_len).enumerate() {
+            for (column, pixel) in row.chunks_exact_mut(4).enumerate() {
+                let color = match layout.orientation {
+                    Orientation::Landscape => steps[first + index],
+                    Orientation::Portrait => steps[column],
+                };
+                pixel.copy_from_slice(&color.0);
+            }
+        }
+    });
+    canvas
+}
@@ -0,0 +1,466 @@
+//! Processing a batch of entries on several workers.
+//!
+//! Each worker carries an entry through reading, processing and writing,
+//! taking the permit of each stage from `stages` as it gets there, rather
+//! than handing it on to a pool for each stage over bounded channels. Both
+//! bound the same things. No stage ever has more entries in it than its
+//! limit, and an entry waiting on a stage waits with its worker, just as it
+//! would wait in a full channel, holding up the one before it. So no more
+//! than `workers` entries are between being read and being written at any
+//! time, which is as many decoded images as channels of one slot between
+//! the stages would hold. Finished results wait to be reported in job list
+//! order, at most `max_in_flight` entries being taken and not reported.
+
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::stages::{self, Gates, StageLimits};
+use crate::ImageInfo;
+
+/// What a worker is doing with an entry, for following a long batch. The
+/// index counts the entries in the order they were handed to the pipeline.
+pub enum Progress<'a> {
+    /// A worker took the entry.
+    Started { index: usize, info: &'a ImageInfo },
+    /// The worker is done with the entry, which may wait for the ones before
+    /// it before it is reported.
+    Finished {
+        index: usize,
+        info: &'a ImageInfo,
+        elapsed: Duration,
+    },
+}
+
+/// Called on the worker threads as entries start and finish.
+pub type ProgressFn = Arc<dyn Fn(Progress<'_>) + Send + Sync>;
+
+pub struct Pipeline {
+    pub limits: StageLimits,
+    pub progress: Option<ProgressFn>,
+    /// Entries taken from the job list and not yet reported, at most. An
+    /// entry finished out of order holds its place until the ones before it
+    /// are reported, so a stream is read no further ahead than this however
+    /// slow one of them is. `None` bounds them by the workers alone.
+    pub max_in_flight: Option<usize>,
+    /// Once set, as from a signal handler, no further entries are started
+    /// and the ones already started are finished and reported.
+    pub interrupted: Option<Arc<AtomicBool>>,
+}
+
+/// Entries taken and not yet reported, for `max_in_flight`.
+struct InFlight {
+    count: Mutex<usize>,
+    changed: Condvar,
+}
+
+impl InFlight {
+    /// Waits for room for another entry, and takes it unless `stopped` says
+    /// no entry should be started any more.
+    fn take(&self, max: Option<usize>, stopped: impl Fn() -> bool) -> bool {
+        let mut count = self.count.lock().unwrap_or_else(|e| e.into_inner());
+        while max.is_some_and(|max| *count >= max) && !stopped() {
+            count = self.changed.wait(count).unwrap_or_else(|e| e.into_inner());
+        }
+        if stopped() {
+            return false;
+        }
+        *count += 1;
+        true
+    }
+
+    /// Makes room for an entry, reported or never taken after all.
+    fn release(&self) {
+        *self.count.lock().unwrap_or_else(|e| e.into_inner()) -= 1;
+        self.changed.notify_all();
+    }
+}
+
+impl Pipeline {
+    /// One entry after another on the calling thread.
+    pub fn sequential() -> Self {
+        Pipeline {
+            limits: StageLimits::uniform(1),
+            progress: None,
+            max_in_flight: None,
+            interrupted: None,
+        }
+    }
+
+    fn is_interrupted(&self) -> bool {
+        self.interrupted
+            .as_ref()
+            .is_some_and(|interrupted| interrupted.load(Ordering::SeqCst))
+    }
+
+    /// `process` for the `index`th entry, with its progress told.
+    fn process_one<T>(
+        &self,
+        index: usize,
+        info: &ImageInfo,
+        process: &impl Fn(&ImageInfo) -> T,
+    ) -> T {
+        let progress = match &self.progress {
+            Some(progress) => progress,
+            None => return process(info),
+        };
+        let started = Instant::now();
+        progress(Progress::Started { index, info });
+        let result = process(info);
+        progress(Progress::Finished {
+            index,
+            info,
+            elapsed: started.elapsed(),
+        });
+        result
+    }
+
+    /// As many workers as the busiest stage allows.
+    fn workers(&self) -> usize {
+        self.limits
+            .read
+            .max(self.limits.process)
+            .max(self.limits.write)
+            .max(1)
+    }
+
+    /// Runs `process` for every entry and hands the results to `report` in
+    /// job list order, on the calling thread.
+    ///
+    /// Once `report` returns `false`, or `interrupted` is set, no further
+    /// entries are started, though the ones other workers already started
+    /// are still reported. Returns the
+    /// number of entries that were never started.
+    pub fn run<T: Send>(
+        &self,
+        entries: Vec<ImageInfo>,
+        process: impl Fn(&ImageInfo) -> T + Sync,
+        report: impl FnMut(ImageInfo, T) -> bool,
+    ) -> usize {
+        let total = entries.len();
+        total - self.run_stream(entries, process, report)
+    }
+
+    /// `run` for entries that arrive one by one, as lines read from a pipe.
+    /// An entry is only taken from `entries` once a worker is free for it,
+    /// so nothing is read ahead. Returns the number of entries reported.
+    pub fn run_stream<T: Send, I>(
+        &self,
+        entries: I,
+        process: impl Fn(&ImageInfo) -> T + Sync,
+        mut report: impl FnMut(ImageInfo, T) -> bool,
+    ) -> usize
+    where
+        I: IntoIterator<Item = ImageInfo>,
+        I::IntoIter: Send,
+    {
+        let workers = self.workers();
+        if workers == 1 {
+            let mut reported = 0;
+            let mut entries = entries.into_iter();
+            while !self.is_interrupted() {
+                let info = match entries.next() {
+                    Some(info) => info,
+                    None => break,
+                };
+                let result = self.process_one(reported, &info, &process);
+                reported += 1;
+                if !report(info, result) {
+                    break;
+                }
+            }
+            return reported;
+        }
+
+        let gates = Gates::new(self.limits);
+        let queue = Mutex::new(entries.into_iter().enumerate());
+        let stopped = AtomicBool::new(false);
+        let in_flight = InFlight {
+            count: Mutex::new(0),
+            changed: Condvar::new(),
+        };
+        // Workers wait while the results are reported, which keeps at most
+        // `workers` decoded images alive at a time
+        let (sender, receiver) = mpsc::sync_channel(workers);
+        let mut reported = 0;
+        thread::scope(|scope| {
+            for _ in 0..workers {
+                let sender = sender.clone();
+                let gates = Arc::clone(&gates);
+                let (queue, stopped, in_flight, process) = (&queue, &stopped, &in_flight, &process);
+                let is_stopped = move || stopped.load(Ordering::Relaxed) || self.is_interrupted();
+                scope.spawn(move || {
+                    stages::enter(Some(gates));
+                    while in_flight.take(self.max_in_flight, is_stopped) {
+                        let next = queue.lock().unwrap_or_else(|e| e.into_inner()).next();
+                        let (index, info) = match next {
+                            Some(next) => next,
+                            None => {
+                                in_flight.release();
+                                break;
+                            }
+                        };
+                        let result = self.process_one(index, &info, process);
+                        if sender.send((index, info, result)).is_err() {
+                            break;
+                        }
+                    }
+                });
+            }
+            drop(sender);
+
+            // Entries are started in order, so the started ones have no gaps
+            let mut pending = BTreeMap::new();
+            for (index, info, result) in receiver {
+                pending.insert(index, (info, result));
+                while let Some((info, result)) = pending.remove(&reported) {
+                    reported += 1;
+                    if !report(info, result) {
+                        stopped.store(true, Ordering::Relaxed);
+                    }
+                    in_flight.release();
+                }
+            }
+        });
+        reported
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use std::sync::atomic::AtomicUsize;
+
+    use image::{DynamicImage, ImageFormat, Rgba};
+
+    use super::*;
+    use crate::test_util;
+
+    /// Runs `test` on a thread of its own, failing if it deadlocks.
+    fn within(seconds: u64, test: impl FnOnce() + Send + 'static) {
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            test();
+            let _ = sender.send(());
+        });
+        match receiver.recv_timeout(Duration::from_secs(seconds)) {
+            Ok(()) => {}
+            Err(mpsc::RecvTimeoutError::Timeout) => panic!("deadlocked"),
+            Err(mpsc::RecvTimeoutError::Disconnected) => panic!("the test panicked"),
+        }
+    }
+
+    fn entry(source: &str, destination: &str) -> ImageInfo {
+        serde_json::from_value(serde_json::json!({
+            "source": source,
+            "destination": destination,
+            "aspectRatio": "1:1",
+        }))
+        .unwrap()
+    }
+
+    fn entries(count: usize) -> Vec<ImageInfo> {
+        (0..count)
+            .map(|n| entry(&format!("{}.png", n), &format!("{}-out.png", n)))
+            .collect()
+    }
+
+    fn pipeline(limits: StageLimits) -> Pipeline {
+        Pipeline {
+            limits,
+            ..Pipeline::sequential()
+        }
+    }
+
+    /// The index of an entry made by `entries`.
+    fn index(info: &ImageInfo) -> usize {
+        info.source().trim_end_matches(".png").parse().unwrap()
+    }
+
+    #[test]
+    fn reports_in_job_list_order_under_stress() {
+        within(60, || {
+            for workers in [2, 3, 8, 16] {
+                let busy = AtomicUsize::new(0);
+                let most = AtomicUsize::new(0);
+                let mut reported = Vec::new();
+                let never_started = pipeline(StageLimits::uniform(workers)).run(
+                    entries(400),
+                    |info| {
+                        let now = busy.fetch_add(1, Ordering::SeqCst) + 1;
+                        most.fetch_max(now, Ordering::SeqCst);
+                        // Later entries often finish first
+                        thread::sleep(Duration::from_micros((index(info) % 7) as u64 * 300));
+                        busy.fetch_sub(1, Ordering::SeqCst);
+                        index(info)
+                    },
+                    |info, result| {
+                        assert_eq!(index(&info), result);
+                        reported.push(result);
+                        true
+                    },
+                );
+                assert_eq!(never_started, 0);
+                assert_eq!(reported, (0..400).collect::<Vec<_>>());
+                assert!(most.load(Ordering::SeqCst) <= workers);
+            }
+        });
+    }
+
+    /// `count` small sources of alternating orientations in `dir`.
+    fn sources(dir: &Path, count: usize) -> Vec<ImageInfo> {
+        (0..count)
+            .map(|n| {
+                let (width, height) = if n.is_multiple_of(2) {
+                    (12, 6)
+                } else {
+                    (6, 12)
+                };
+                let img = test_util::checkerboard(
+                    width,
+                    height,
+                    2,
+                    Rgba([200, 30, 30, 255]),
+                    Rgba([30, 30, 200, 255]),
+                );
+                let source = dir.join(format!("{}.png", n));
+                let data = test_util::encode(&DynamicImage::ImageRgba8(img), ImageFormat::Png);
+                std::fs::write(&source, data).unwrap();
+                let dest = dir.join(format!("{}-out.png", n));
+                entry(source.to_str().unwrap(), dest.to_str().unwrap())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn uneven_stage_limits_do_not_deadlock() {
+        within(120, || {
+            let dir = tempfile::tempdir().unwrap();
+            let entries = sources(dir.path(), 200);
+            for limits in [
+                StageLimits {
+                    read: 2,
+                    process: 8,
+                    write: 1,
+                },
+                StageLimits {
+                    read: 1,
+                    process: 1,
+                    write: 6,
+                },
+                StageLimits {
+                    read: 5,
+                    process: 2,
+                    write: 3,
+                },
+            ] {
+                let mut reported = Vec::new();
+                pipeline(limits).run(entries.clone(), crate::compile_image, |info, result| {
+                    let report = result.unwrap();
+                    assert_eq!(report.output_width, report.output_height);
+                    reported.push(info.source().to_string());
+                    true
+                });
+                let expected: Vec<String> = entries
+                    .iter()
+                    .map(|info| info.source().to_string())
+                    .collect();
+                assert_eq!(reported, expected);
+            }
+            for n in 0..200 {
+                let written = std::fs::read(dir.path().join(format!("{}-out.png", n))).unwrap();
+                test_util::assert_dimensions(&test_util::decode(&written), (12, 12));
+            }
+        });
+    }
+
+    #[test]
+    fn max_in_flight_bounds_what_is_read_ahead() {
+        within(60, || {
+            let pulled = AtomicUsize::new(0);
+            let reported = AtomicUsize::new(0);
+            let stream = entries(100).into_iter().inspect(|_| {
+                pulled.fetch_add(1, Ordering::SeqCst);
+            });
+            let pipeline = Pipeline {
+                max_in_flight: Some(3),
+                ..pipeline(StageLimits::uniform(4))
+            };
+            let count = pipeline.run_stream(
+                stream,
+                |info| {
+                    // The first entry of every ten is slow, and the others
+                    // finished behind it wait to be reported
+                    if index(info).is_multiple_of(10) {
+                        thread::sleep(Duration::from_millis(20));
+                    }
+                },
+                |_, ()| {
+                    let ahead = pulled.load(Ordering::SeqCst) - reported.load(Ordering::SeqCst);
+                    assert!(ahead <= 3, "{} entries taken and not reported", ahead);
+                    reported.fetch_add(1, Ordering::SeqCst);
+                    true
+                },
+            );
+            assert_eq!(count, 100);
+        });
+    }
+
+    #[test]
+    fn an_interrupt_finishes_what_was_started() {
+        within(60, || {
+            let interrupted = Arc::new(AtomicBool::new(false));
+            let started = AtomicUsize::new(0);
+            let pipeline = Pipeline {
+                interrupted: Some(Arc::clone(&interrupted)),
+                ..pipeline(StageLimits::uniform(4))
+            };
+            let mut reported = 0;
+            let count = pipeline.run_stream(
+                entries(1000),
+                |_| {
+                    started.fetch_add(1, Ordering::SeqCst);
+                    thread::sleep(Duration::from_millis(1));
+                },
+                |_, ()| {
+                    reported += 1;
+                    if reported == 10 {
+                        interrupted.store(true, Ordering::SeqCst);
+                    }
+                    true
+                },
+            );
+            assert_eq!(count, reported);
+            assert_eq!(count, started.load(Ordering::SeqCst));
+            assert!(count < 1000);
+        });
+    }
+
+    #[test]
+    fn stops_when_report_says_so() {
+        within(60, || {
+            for workers in [1, 4] {
+                // Entries after the sixth wait for it to be reported, so the
+                // workers cannot run through the whole list first
+                let stopped = AtomicBool::new(false);
+                let never_started = pipeline(StageLimits::uniform(workers)).run(
+                    entries(100),
+                    |info| {
+                        while index(info) > 5 && !stopped.load(Ordering::SeqCst) {
+                            thread::sleep(Duration::from_millis(1));
+                        }
+                        index(info)
+                    },
+                    |_, result| {
+                        stopped.fetch_or(result == 5, Ordering::SeqCst);
+                        result != 5
+                    },
+                );
+                // What the other workers already started is still reported
+                assert!((50..=94).contains(&never_started), "{}", never_started);
+            }
+        });
+    }
+}
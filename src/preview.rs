@@ -0,0 +1,70 @@
+//! `previewWidth`, a small JPEG written next to a still output.
+//!
+//! The preview is scaled down from the canvas the output is encoded from,
+//! or from the decoded source when the output is the source as it is, so
+//! it costs a resize and an encode rather than another decode and
+//! composite. JPEG has no transparency, so whatever is left transparent is
+//! laid over white.
+
+use image::buffer::ConvertBuffer;
+use image::imageops::FilterType;
+use image::{DynamicImage, ImageFormat, RgbImage, Rgba, RgbaImage};
+
+use crate::error::{Context, Stage};
+use crate::{atomic, format, resample, ExtendError, Options};
+
+/// Where the preview of the output at `dest` goes.
+pub(crate) fn path(dest: &str) -> String {
+    format!("{}.preview.jpg", dest)
+}
+
+/// `canvas` scaled down to the preview `previewWidth` asks for, and `None`
+/// without one.
+pub(crate) fn scaled(canvas: &RgbaImage, options: &Options) -> Option<RgbaImage> {
+    let width = options.preview_width?;
+    let (canvas_width, canvas_height) = canvas.dimensions();
+    let width = width.min(canvas_width);
+    let height =
+        ((canvas_height as f64 * width as f64 / canvas_width as f64).round() as u32).max(1);
+    let mut preview = match width == canvas_width {
+        true => canvas.clone(),
+        false => resample::resize(
+            canvas,
+            width,
+            height,
+            options.style.filter(FilterType::Triangle),
+        ),
+    };
+    crate::flatten(&mut preview, Rgba([255, 255, 255, 255]));
+    Some(preview)
+}
+
+/// The preview of a source written out unchanged.
+pub(crate) fn of_source(img: &DynamicImage, options: &Options) -> Option<RgbaImage> {
+    options
+        .preview_width
+        .and_then(|_| scaled(&img.to_rgba8(), options))
+}
+
+/// Writes `preview` next to the output at `dest`, if there is one.
+pub(crate) fn write(
+    dest: &str,
+    preview: Option<&RgbaImage>,
+    options: &Options,
+) -> Result<(), ExtendError> {
+    let preview = match preview {
+        Some(preview) => preview,
+        None => return Ok(()),
+    };
+    let path = path(dest);
+    let rgb: RgbImage = preview.convert();
+    let encoding = format::Encoding {
+        quality: options.quality,
+        ..Default::default()
+    };
+    let encoded = format::encode(&DynamicImage::ImageRgb8(rgb), ImageFormat::Jpeg, encoding)
+        .context(Stage::Write, &path)?;
+    atomic::write(&path, |temp| {
+        std::fs::write(temp, &encoded).context(Stage::Write, &path)
+    })
+}
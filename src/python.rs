@@ -0,0 +1,125 @@
+//! Python bindings, enabled with the `python` feature and built with maturin.
+//!
+//! The GIL is released while images are decoded, extended and encoded, so
+//! threaded callers run in parallel.
+
+use pyo3::create_exception;
+use pyo3::exceptions::{PyException, PyTypeError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::{PyBytes, PyDict};
+
+use crate::{ErrorKind, Options, OutputFormat};
+
+create_exception!(image_bg_extender, ExtendError, PyException);
+create_exception!(image_bg_extender, SourceIoError, ExtendError);
+create_exception!(image_bg_extender, ImageError, ExtendError);
+create_exception!(image_bg_extender, CorruptImageError, ImageError);
+create_exception!(image_bg_extender, LimitsError, ExtendError);
+create_exception!(image_bg_extender, ExtendTimeoutError, ExtendError);
+
+fn to_py_err(e: crate::ExtendError) -> PyErr {
+    let message = e.to_string();
+    match e.kind() {
+        ErrorKind::Io => SourceIoError::new_err(message),
+        ErrorKind::Image => ImageError::new_err(message),
+        ErrorKind::Corrupt => CorruptImageError::new_err(message),
+        ErrorKind::Invalid => PyValueError::new_err(message),
+        ErrorKind::Limits => LimitsError::new_err(message),
+        ErrorKind::Timeout => ExtendTimeoutError::new_err(message),
+        ErrorKind::Panic | ErrorKind::Other => ExtendError::new_err(message),
+    }
+}
+
+fn parse_ratio(ratio: &Bound<'_, PyAny>) -> PyResult<(u32, u32)> {
+    if let Ok(text) = ratio.extract::<String>() {
+        return crate::parse_ratio(&text).map_err(PyValueError::new_err);
+    }
+    let (width, height) = ratio
+        .extract::<(u32, u32)>()
+        .map_err(|_| PyTypeError::new_err("ratio must be a (width, height) tuple or \"W:H\""))?;
+    if width == 0 || height == 0 {
+        return Err(PyValueError::new_err("ratio must be nonzero"));
+    }
+    Ok(crate::reduce_ratio((width, height)))
+}
+
+fn parse_options(opts: Option<&Bound<'_, PyDict>>) -> PyResult<Options> {
+    let mut options = Options::default();
+    if let Some(opts) = opts {
+        for (key, value) in opts.iter() {
+            match key.extract::<String>()?.as_str() {
+                "format" => {
+                    let name = value.extract::<String>()?;
+                    options.output_format = Some(
+                        name.parse::<OutputFormat>()
+                            .map_err(PyValueError::new_err)?,
+                    );
+                }
+                "quality" => options.quality = Some(value.extract()?),
+                "first_frame_only" => options.first_frame_only = value.extract()?,
+                key => {
+                    return Err(PyTypeError::new_err(format!(
+                        "unexpected keyword argument {:?}",
+                        key
+                    )))
+                }
+            }
+        }
+    }
+    Ok(options)
+}
+
+/// extend_file(src, dest, ratio, *, format=None, quality=None, first_frame_only=False)
+///
+/// Extends the image at `src` to `ratio` and writes it to `dest`.
+#[pyfunction]
+#[pyo3(signature = (src, dest, ratio, **opts))]
+fn extend_file(
+    py: Python<'_>,
+    src: String,
+    dest: String,
+    ratio: &Bound<'_, PyAny>,
+    opts: Option<&Bound<'_, PyDict>>,
+) -> PyResult<()> {
+    let aspect_ratio = parse_ratio(ratio)?;
+    let options = parse_options(opts)?;
+    py.allow_threads(|| crate::extend_file(&src, &dest, aspect_ratio, &options))
+        .map_err(to_py_err)?;
+    Ok(())
+}
+
+/// extend_bytes(data, ratio, *, format=None, quality=None, first_frame_only=False) -> bytes
+///
+/// Extends an encoded image and returns the encoded result, in the format of
+/// the input unless `format` is given.
+#[pyfunction]
+#[pyo3(signature = (data, ratio, **opts))]
+fn extend_bytes<'py>(
+    py: Python<'py>,
+    data: &[u8],
+    ratio: &Bound<'py, PyAny>,
+    opts: Option<&Bound<'py, PyDict>>,
+) -> PyResult<Bound<'py, PyBytes>> {
+    let aspect_ratio = parse_ratio(ratio)?;
+    let options = parse_options(opts)?;
+    let encoded = py
+        .allow_threads(|| crate::extend_bytes_with(data, aspect_ratio, None, &options))
+        .map_err(to_py_err)?;
+    Ok(PyBytes::new(py, &encoded))
+}
+
+#[pymodule]
+fn image_bg_extender(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_function(wrap_pyfunction!(extend_file, m)?)?;
+    m.add_function(wrap_pyfunction!(extend_bytes, m)?)?;
+    m.add("ExtendError", m.py().get_type::<ExtendError>())?;
+    m.add("SourceIoError", m.py().get_type::<SourceIoError>())?;
+    m.add("ImageError", m.py().get_type::<ImageError>())?;
+    m.add("CorruptImageError", m.py().get_type::<CorruptImageError>())?;
+    m.add("LimitsError", m.py().get_type::<LimitsError>())?;
+    m.add(
+        "ExtendTimeoutError",
+        m.py().get_type::<ExtendTimeoutError>(),
+    )?;
+    Ok(())
+}
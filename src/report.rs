@@ -0,0 +1,474 @@
+//! Structured results of processing an entry.
+
+use std::fmt;
+use std::time::Instant;
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::{ExtendError, Stage};
+use crate::{Background, ImageInfo, Orientation};
+
+/// What `compile_image` produced for a single entry.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ImageReport {
+    pub source_width: u32,
+    pub source_height: u32,
+    /// The ratio `aspectRatio: "auto"` picked.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub aspect_ratio: Option<(u32, u32)>,
+    /// Pixels cropped from the width and the height so the source divides evenly.
+    pub crop: (u32, u32),
+    /// Pixels removed from each side by `mode: crop`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trim: Option<Trim>,
+    pub output_width: u32,
+    pub output_height: u32,
+    /// Background colors as RGBA, empty when the source already had the aspect ratio.
+    pub colors: Vec<[u8; 4]>,
+    /// Whether each of `colors` was sampled, forced by `firstColor` and
+    /// `secondColor` or taken from `fallbackColor`, empty when all were
+    /// sampled.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub color_sources: Vec<ColorSource>,
+    /// Where the image ended up on the output.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub placement: Option<Placement>,
+    /// Largest change in Oklab lightness made to a background color by
+    /// `minContrast`, negative when it was darkened.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub contrast_adjustment: Option<f32>,
+    /// The source matched the aspect ratio and was written out unchanged.
+    pub copied: bool,
+    /// The source was off the aspect ratio by no more than `tolerance`,
+    /// and was left as it is.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub tolerated: bool,
+    /// Number of frames written for animated outputs.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub frames: Option<u32>,
+    /// Number of pages of a multi-page source.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pages: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub page_mode: Option<PageMode>,
+    /// Every output of an entry with variants, the others describing the
+    /// first one written.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub variants: Vec<VariantReport>,
+    /// Every output of an entry with outputs, the others describing the
+    /// first one written.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub outputs: Vec<OutputReport>,
+    #[serde(default)]
+    pub timings: Timings,
+    /// How often the entry was tried, more than once when transient I/O
+    /// errors were retried.
+    #[serde(default = "one")]
+    pub attempts: u32,
+    /// The destination written to instead, under `overwrite: rename`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub renamed: Option<String>,
+}
+
+fn one() -> u32 {
+    1
+}
+
+/// Where a background color came from.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum ColorSource {
+    /// Sampled from the edge of the image.
+    Sampled,
+    /// Given by `firstColor` or `secondColor`.
+    Forced,
+    /// The edge was next to fully transparent, so `fallbackColor` was used.
+    Fallback,
+}
+
+/// Rectangle of the output covered by the placed image, in pixels.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct Placement {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Pixels removed from each side of the source.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct Trim {
+    pub top: u32,
+    pub right: u32,
+    pub bottom: u32,
+    pub left: u32,
+}
+
+/// Bumped whenever a field of `Analysis` changes or goes away. Added fields
+/// keep the version.
+pub const ANALYSIS_VERSION: u32 = 1;
+
+/// What `sidecar` writes next to each output, for tools that place overlays
+/// without analysing the image again.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Analysis {
+    /// `ANALYSIS_VERSION` when written.
+    pub version: u32,
+    pub source: String,
+    pub destination: String,
+    pub source_width: u32,
+    pub source_height: u32,
+    /// Pixels cropped from the width and the height so the source divides evenly.
+    pub crop: (u32, u32),
+    pub output_width: u32,
+    pub output_height: u32,
+    pub placement: Placement,
+    pub background: Background,
+    /// Background colors as RGBA, empty when the source was written unchanged.
+    pub colors: Vec<[u8; 4]>,
+}
+
+impl Analysis {
+    pub(crate) fn new(
+        source: &str,
+        destination: &str,
+        background: Background,
+        report: &ImageReport,
+    ) -> Self {
+        Analysis {
+            version: ANALYSIS_VERSION,
+            source: source.to_string(),
+            destination: destination.to_string(),
+            source_width: report.source_width,
+            source_height: report.source_height,
+            crop: report.crop,
+            output_width: report.output_width,
+            output_height: report.output_height,
+            placement: report.placement.unwrap_or(Placement {
+                x: 0,
+                y: 0,
+                width: report.output_width,
+                height: report.output_height,
+            }),
+            background,
+            colors: report.colors.clone(),
+        }
+    }
+}
+
+/// One output of an entry with variants.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct VariantReport {
+    pub background: Background,
+    pub destination: String,
+    /// Set when this variant failed, while others may have been written.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<ErrorInfo>,
+}
+
+/// One output of an entry with outputs.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct OutputReport {
+    pub destination: String,
+    /// Left out when an `"auto"` ratio was never picked.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub aspect_ratio: Option<(u32, u32)>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output_width: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output_height: Option<u32>,
+    /// Set when this output failed, while others may have been written.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<ErrorInfo>,
+}
+
+/// Wall-clock time spent in each stage, in milliseconds.
+///
+/// Reading the source counts towards decoding.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Timings {
+    pub decode_ms: f64,
+    pub composite_ms: f64,
+    pub write_ms: f64,
+}
+
+impl Timings {
+    /// Runs `f`, adding the time it took to `slot`.
+    pub(crate) fn measure<T>(slot: &mut f64, f: impl FnOnce() -> T) -> T {
+        let started = Instant::now();
+        let result = f();
+        *slot += started.elapsed().as_secs_f64() * 1000.0;
+        result
+    }
+}
+
+/// How the pages of a multi-page source were written.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum PageMode {
+    /// All pages in a single multi-page TIFF.
+    MultiPage,
+    /// One file per page through the `{page}` placeholder.
+    Split,
+}
+
+impl ImageReport {
+    pub(crate) fn unchanged((output_width, output_height): (u32, u32)) -> Self {
+        ImageReport {
+            source_width: output_width,
+            source_height: output_height,
+            aspect_ratio: None,
+            crop: (0, 0),
+            trim: None,
+            output_width,
+            output_height,
+            colors: Vec::new(),
+            color_sources: Vec::new(),
+            placement: Some(Placement {
+                x: 0,
+                y: 0,
+                width: output_width,
+                height: output_height,
+            }),
+            contrast_adjustment: None,
+            copied: true,
+            tolerated: false,
+            frames: None,
+            pages: None,
+            page_mode: None,
+            variants: Vec::new(),
+            outputs: Vec::new(),
+            timings: Timings::default(),
+            attempts: 1,
+            renamed: None,
+        }
+    }
+}
+
+/// What `compile_image` would do for an entry, worked out without writing anything.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Plan {
+    pub source_width: u32,
+    pub source_height: u32,
+    /// The ratio `aspectRatio: "auto"` would pick.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub aspect_ratio: Option<(u32, u32)>,
+    /// `None` when the source already has the aspect ratio.
+    pub orientation: Option<Orientation>,
+    /// Pixels cropped from the width and the height so the source divides evenly.
+    pub crop: (u32, u32),
+    /// Pixels that `mode: crop` would remove from each side.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trim: Option<Trim>,
+    pub canvas_width: u32,
+    pub canvas_height: u32,
+    /// Background colors as RGBA, only sampled when the source is decoded.
+    #[serde(default)]
+    pub colors: Vec<[u8; 4]>,
+    /// The source would be copied to the destination as it is.
+    pub copy: bool,
+    /// The source is off the aspect ratio by no more than `tolerance`, and
+    /// would be left as it is.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub tolerated: bool,
+    /// The plans of every output of an entry with outputs, the others
+    /// describing the first.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub outputs: Vec<OutputPlan>,
+}
+
+/// The plan of one output of an entry with outputs.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct OutputPlan {
+    pub destination: String,
+    #[serde(flatten)]
+    pub plan: Plan,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum Status {
+    Ok,
+    Skipped,
+    Error,
+}
+
+/// Why an entry was not processed.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum SkipReason {
+    /// The destination is newer than the source and was made with the same settings.
+    UpToDate,
+    /// The cache has the same source and settings, and the destination is unchanged.
+    Cached,
+    /// A later entry writes the same destination, under `--last-wins`.
+    Superseded,
+    /// The destination exists, under `overwrite: skip`.
+    Exists,
+}
+
+impl fmt::Display for SkipReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            SkipReason::UpToDate => "up to date",
+            SkipReason::Cached => "cached",
+            SkipReason::Superseded => "superseded by a later entry",
+            SkipReason::Exists => "destination exists",
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ErrorInfo {
+    /// The `ErrorKind` by its name: one of `io`, `image`, `corrupt`,
+    /// `limits`, `timeout`, `panic`, `invalid` or `other`.
+    pub kind: String,
+    pub stage: Stage,
+    pub message: String,
+}
+
+impl ErrorInfo {
+    pub fn new(e: &ExtendError) -> Self {
+        ErrorInfo {
+            kind: e.kind().name().to_string(),
+            stage: e.stage(),
+            message: e.to_string(),
+        }
+    }
+}
+
+/// One line of the `--output json` stream. Fields are only ever added.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct EntryResult {
+    pub source: String,
+    pub destination: String,
+    pub status: Status,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<ErrorInfo>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub skip_reason: Option<SkipReason>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub aspect_ratio: Option<(u32, u32)>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output_width: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output_height: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trim: Option<Trim>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub tolerated: bool,
+    #[serde(default)]
+    pub colors: Vec<[u8; 4]>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub color_sources: Vec<ColorSource>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub contrast_adjustment: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pages: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub page_mode: Option<PageMode>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub variants: Vec<VariantReport>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub outputs: Vec<OutputReport>,
+    /// Set instead of the output fields on dry runs.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub plan: Option<Plan>,
+    /// Set when the entry took more than one attempt.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub attempts: Option<u32>,
+    /// Identical entries of the job list that were folded into this one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duplicates: Option<u32>,
+}
+
+impl EntryResult {
+    fn empty(info: &ImageInfo) -> Self {
+        EntryResult {
+            source: info.source().to_string(),
+            destination: info.destination().to_string(),
+            status: Status::Ok,
+            error: None,
+            skip_reason: None,
+            aspect_ratio: None,
+            output_width: None,
+            output_height: None,
+            trim: None,
+            tolerated: false,
+            colors: Vec::new(),
+            color_sources: Vec::new(),
+            contrast_adjustment: None,
+            pages: None,
+            page_mode: None,
+            variants: Vec::new(),
+            outputs: Vec::new(),
+            plan: None,
+            attempts: None,
+            duplicates: Some(info.duplicates()).filter(|&duplicates| duplicates > 0),
+        }
+    }
+
+    pub fn new(info: &ImageInfo, result: &Result<ImageReport, ExtendError>) -> Self {
+        let mut entry = EntryResult::empty(info);
+        let attempts = match result {
+            Ok(report) => report.attempts,
+            Err(e) => e.attempts(),
+        };
+        if attempts > 1 {
+            entry.attempts = Some(attempts);
+        }
+        match result {
+            Ok(report) => {
+                if let Some(renamed) = &report.renamed {
+                    entry.destination = renamed.clone();
+                }
+                entry.aspect_ratio = report.aspect_ratio;
+                entry.output_width = Some(report.output_width);
+                entry.output_height = Some(report.output_height);
+                entry.trim = report.trim;
+                entry.tolerated = report.tolerated;
+                entry.colors = report.colors.clone();
+                entry.color_sources = report.color_sources.clone();
+                entry.contrast_adjustment = report.contrast_adjustment;
+                entry.pages = report.pages;
+                entry.page_mode = report.page_mode;
+                entry.variants = report.variants.clone();
+                entry.outputs = report.outputs.clone();
+            }
+            Err(e) => {
+                entry.status = Status::Error;
+                entry.error = Some(ErrorInfo::new(e));
+            }
+        }
+        entry
+    }
+    pub fn skipped(info: &ImageInfo, reason: SkipReason) -> Self {
+        let mut entry = EntryResult::empty(info);
+        entry.status = Status::Skipped;
+        entry.skip_reason = Some(reason);
+        entry
+    }
+
+    pub fn planned(info: &ImageInfo, result: &Result<Plan, ExtendError>) -> Self {
+        let mut entry = EntryResult::empty(info);
+        match result {
+            Ok(plan) => entry.plan = Some(plan.clone()),
+            Err(e) => {
+                entry.status = Status::Error;
+                entry.error = Some(ErrorInfo::new(e));
+            }
+        }
+        entry
+    }
+}
@@ -0,0 +1,66 @@
+//! Retries of entries that failed on a transient I/O error, such as a stale
+//! handle on a network mount.
+//!
+//! Only reading and writing are retried; decoding the same bytes again would
+//! fail the same way. Output is written atomically, so a failed attempt leaves
+//! nothing behind for the next one to trip over.
+
+use std::io;
+use std::thread;
+use std::time::Duration;
+
+use crate::error::{ExtendError, Stage};
+use crate::{timeout, ImageReport, Options};
+
+/// Delay before the first retry, doubled for every one after it.
+pub const DEFAULT_BACKOFF_MS: u64 = 200;
+
+fn io_error(e: &ExtendError) -> Option<&io::Error> {
+    let inner = e.inner();
+    match inner.downcast_ref::<image::ImageError>() {
+        Some(image::ImageError::IoError(e)) => Some(e),
+        _ => inner.downcast_ref::<io::Error>(),
+    }
+}
+
+fn is_transient(e: &ExtendError) -> bool {
+    if e.stage() != Stage::Read && e.stage() != Stage::Write {
+        return false;
+    }
+    match io_error(e) {
+        Some(e) => matches!(
+            e.kind(),
+            io::ErrorKind::Interrupted
+                | io::ErrorKind::WouldBlock
+                | io::ErrorKind::TimedOut
+                | io::ErrorKind::ResourceBusy
+                | io::ErrorKind::StaleNetworkFileHandle
+        ),
+        None => false,
+    }
+}
+
+/// Runs `process` until it succeeds, fails for good or runs out of attempts.
+pub(crate) fn run(
+    options: &Options,
+    mut process: impl FnMut() -> Result<ImageReport, ExtendError>,
+) -> Result<ImageReport, ExtendError> {
+    let attempts = options.retries.unwrap_or(0) + 1;
+    let mut backoff = Duration::from_millis(options.retry_backoff_ms.unwrap_or(DEFAULT_BACKOFF_MS));
+    let mut attempt = 1;
+    loop {
+        match process() {
+            Ok(mut report) => {
+                report.attempts = attempt;
+                return Ok(report);
+            }
+            Err(e) if attempt < attempts && is_transient(&e) => {
+                thread::sleep(backoff);
+                timeout::checkpoint(e.stage(), e.path().unwrap_or_default())?;
+                backoff *= 2;
+                attempt += 1;
+            }
+            Err(e) => return Err(e.with_attempts(attempt)),
+        }
+    }
+}
@@ -0,0 +1,100 @@
+//! `gravity: saliency`, the image moved along the sides it was extended on
+//! so that its busiest part sits on a third of the canvas rather than in
+//! the middle of it.
+//!
+//! How busy a part of the image is comes from the edges in a gray copy of
+//! it scaled down to at most `SAMPLE_SIDE` pixels a side, which mark out a
+//! subject against a plainer ground well enough without anything trained.
+//! Their centre along the free axis is put on whichever third line of the
+//! canvas it is nearer to with the image in the middle.
+
+use image::{imageops, DynamicImage, GenericImageView};
+
+use crate::style::{Gravity, Style};
+use crate::{Layout, Orientation};
+
+/// Longest side of the copy the edges are found in.
+const SAMPLE_SIDE: u32 = 64;
+
+/// Where along `length` samples the weights of `weight` centre, from 0 to
+/// 1, or `None` when there is nothing to weigh.
+fn centre(length: usize, weight: impl Fn(usize) -> f32) -> Option<f32> {
+    let (total, moment) = (0..length).fold((0.0, 0.0), |(total, moment), at| {
+        let weight = weight(at);
+        (total + weight, moment + weight * (at as f32 + 0.5))
+    });
+    (total > 0.0).then(|| moment / total / length as f32)
+}
+
+/// How much of an edge each sample of `img` is on, scaled down, as rows.
+fn edges(img: &DynamicImage, style: &Style) -> Vec<Vec<f32>> {
+    let (width, height) = img.dimensions();
+    let scale = (SAMPLE_SIDE as f32 / width.max(height) as f32).min(1.0);
+    let small = imageops::resize(
+        &img.to_luma_alpha8(),
+        ((width as f32 * scale).round() as u32).max(1),
+        ((height as f32 * scale).round() as u32).max(1),
+        style.filter(imageops::FilterType::Triangle),
+    );
+    let (width, height) = small.dimensions();
+    // Transparent pixels count as nothing, so the edges of a cutout are
+    // where it meets the transparency
+    let at = |x: u32, y: u32| {
+        let [luma, alpha] = small.get_pixel(x.min(width - 1), y.min(height - 1)).0;
+        luma as f32 * alpha as f32 / 255.0
+    };
+    (0..height)
+        .map(|y| {
+            (0..width)
+                .map(|x| {
+                    let across = at(x + 1, y) - at(x.saturating_sub(1), y);
+                    let down = at(x, y + 1) - at(x, y.saturating_sub(1));
+                    across.abs() + down.abs()
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// `layout` with `gravity: saliency` settled into an offset for `img`, and
+/// as it is for any other gravity.
+pub(crate) fn settle(img: &DynamicImage, mut layout: Layout, style: &Style) -> Layout {
+    if layout.gravity != Gravity::Saliency {
+        return layout;
+    }
+    let (canvas_width, canvas_height) = layout.canvas;
+    let (image_width, image_height) = layout.image;
+    let (x, y) = Gravity::Center.offset(layout.canvas, layout.image);
+    let edges = edges(img, style);
+    let (placed, length, centred) = match layout.orientation {
+        Orientation::Landscape => (image_height, canvas_height, y),
+        Orientation::Portrait => (image_width, canvas_width, x),
+    };
+    let subject = match layout.orientation {
+        Orientation::Landscape => centre(edges.len(), |row| edges[row].iter().sum()),
+        Orientation::Portrait => centre(edges[0].len(), |column| {
+            edges.iter().map(|row| row[column]).sum()
+        }),
+    };
+    let subject = match subject {
+        Some(subject) => subject * placed as f32,
+        // A plain image has nothing to balance
+        None => {
+            layout.gravity = Gravity::Center;
+            return layout;
+        }
+    };
+    let thirds = [length as f32 / 3.0, length as f32 * 2.0 / 3.0];
+    let centred_at = centred as f32 + subject;
+    let line = match (centred_at - thirds[0]).abs() <= (centred_at - thirds[1]).abs() {
+        true => thirds[0],
+        false => thirds[1],
+    };
+    let room = length.saturating_sub(placed);
+    let along = ((line - subject).round().max(0.0) as u32).min(room);
+    layout.gravity = Gravity::Offset(match layout.orientation {
+        Orientation::Landscape => (x, along),
+        Orientation::Portrait => (along, y),
+    });
+    layout
+}
@@ -0,0 +1,191 @@
+//! `mode: seamCarve`, which widens the image itself to the canvas by adding
+//! seams through its plainest parts instead of drawing a background.
+//!
+//! A seam is a path of one pixel per row, each within a pixel of the one
+//! above, with the least detail along it. The seams a round adds are found
+//! together on the image as it was before the round, by taking them out one
+//! after another, so that they spread over the plain parts rather than all
+//! doubling the same line. Each is then put back next to the pixels it ran
+//! through, as their average with the pixel to their right. No round adds
+//! more than half the width, which keeps any part from being stretched to
+//! more than twice its size at once.
+
+use image::{DynamicImage, RgbaImage};
+
+/// The RGBA channels of an image as floats, row after row.
+struct Pixels {
+    width: usize,
+    height: usize,
+    data: Vec<[f32; 4]>,
+}
+
+impl Pixels {
+    fn from_image(img: &RgbaImage) -> Self {
+        Pixels {
+            width: img.width() as usize,
+            height: img.height() as usize,
+            data: img
+                .pixels()
+                .map(|pixel| pixel.0.map(|channel| channel as f32))
+                .collect(),
+        }
+    }
+
+    fn into_image(self) -> RgbaImage {
+        let data = self
+            .data
+            .iter()
+            .flat_map(|pixel| pixel.map(|channel| channel.round().clamp(0.0, 255.0) as u8))
+            .collect();
+        RgbaImage::from_raw(self.width as u32, self.height as u32, data)
+            .expect("buffer matches the dimensions")
+    }
+
+    /// Rows become columns, so that widening adds rows.
+    fn transpose(&self) -> Self {
+        let mut data = Vec::with_capacity(self.data.len());
+        for x in 0..self.width {
+            for y in 0..self.height {
+                data.push(self.data[y * self.width + x]);
+            }
+        }
+        Pixels {
+            width: self.height,
+            height: self.width,
+            data,
+        }
+    }
+
+    fn row(&self, y: usize) -> &[[f32; 4]] {
+        &self.data[y * self.width..(y + 1) * self.width]
+    }
+}
+
+/// Brightness, with fully transparent pixels dark so that empty areas count
+/// as plain.
+fn luma(pixel: &[f32; 4]) -> f32 {
+    (0.299 * pixel[0] + 0.587 * pixel[1] + 0.114 * pixel[2]) * pixel[3] / 255.0
+}
+
+/// How much detail each pixel of `rows` has, as the change in brightness to
+/// its neighbours across and down.
+fn energy(rows: &[Vec<f32>]) -> Vec<Vec<f32>> {
+    let height = rows.len();
+    rows.iter()
+        .enumerate()
+        .map(|(y, row)| {
+            let (up, down) = (&rows[y.saturating_sub(1)], &rows[(y + 1).min(height - 1)]);
+            let last = row.len() - 1;
+            (0..row.len())
+                .map(|x| {
+                    let across = row[(x + 1).min(last)] - row[x.saturating_sub(1)];
+                    let along = down[x] - up[x];
+                    across.abs() + along.abs()
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// The column of the seam with the least energy in each row.
+fn cheapest_seam(energy: &[Vec<f32>]) -> Vec<usize> {
+    let width = energy[0].len();
+    let mut total = vec![energy[0].clone()];
+    for row in &energy[1..] {
+        let above = total.last().expect("starts with the first row");
+        let next = (0..width)
+            .map(|x| {
+                let lowest = above[x.saturating_sub(1)..=(x + 1).min(width - 1)]
+                    .iter()
+                    .copied()
+                    .fold(f32::INFINITY, f32::min);
+                row[x] + lowest
+            })
+            .collect();
+        total.push(next);
+    }
+
+    let last = total.last().expect("at least one row");
+    let mut x = (0..width)
+        .min_by(|&a, &b| last[a].total_cmp(&last[b]))
+        .expect("at least one column");
+    let mut seam = vec![0; energy.len()];
+    for y in (0..energy.len()).rev() {
+        seam[y] = x;
+        if y > 0 {
+            let above = &total[y - 1];
+            x = (x.saturating_sub(1)..=(x + 1).min(width - 1))
+                .min_by(|&a, &b| above[a].total_cmp(&above[b]))
+                .expect("at least one column");
+        }
+    }
+    seam
+}
+
+/// The original columns of the `count` cheapest seams through `pixels`, by
+/// row.
+fn find_seams(pixels: &Pixels, count: usize) -> Vec<Vec<usize>> {
+    let mut brightness: Vec<Vec<f32>> = (0..pixels.height)
+        .map(|y| pixels.row(y).iter().map(luma).collect())
+        .collect();
+    let mut columns: Vec<Vec<usize>> = (0..pixels.height)
+        .map(|_| (0..pixels.width).collect())
+        .collect();
+    let mut seams = vec![Vec::with_capacity(count); pixels.height];
+    for _ in 0..count {
+        let seam = cheapest_seam(&energy(&brightness));
+        for (y, &x) in seam.iter().enumerate() {
+            seams[y].push(columns[y].remove(x));
+            brightness[y].remove(x);
+        }
+    }
+    seams
+}
+
+/// `pixels` widened by one round of at most half its width.
+fn widen_once(pixels: &Pixels, by: usize) -> Pixels {
+    let seams = find_seams(pixels, by);
+    let width = pixels.width + by;
+    let mut data = Vec::with_capacity(width * pixels.height);
+    for (y, mut seam) in seams.into_iter().enumerate() {
+        seam.sort_unstable();
+        let row = pixels.row(y);
+        let mut seam = seam.into_iter().peekable();
+        for (x, pixel) in row.iter().enumerate() {
+            data.push(*pixel);
+            if seam.next_if_eq(&x).is_some() {
+                let next = row.get(x + 1).unwrap_or(pixel);
+                let mut between = [0.0; 4];
+                for channel in 0..4 {
+                    between[channel] = (pixel[channel] + next[channel]) / 2.0;
+                }
+                data.push(between);
+            }
+        }
+    }
+    Pixels {
+        width,
+        height: pixels.height,
+        data,
+    }
+}
+
+fn widen(mut pixels: Pixels, width: usize) -> Pixels {
+    while pixels.width < width {
+        let by = (width - pixels.width).min((pixels.width / 2).max(1));
+        pixels = widen_once(&pixels, by);
+    }
+    pixels
+}
+
+/// `img` with seams added until it is `canvas` in size, wider first and then
+/// taller. Neither side of `canvas` may be smaller than the image.
+pub(crate) fn extend(img: &DynamicImage, canvas: (u32, u32)) -> RgbaImage {
+    let pixels = widen(Pixels::from_image(&img.to_rgba8()), canvas.0 as usize);
+    let pixels = if pixels.height < canvas.1 as usize {
+        widen(pixels.transpose(), canvas.1 as usize).transpose()
+    } else {
+        pixels
+    };
+    pixels.into_image()
+}
@@ -0,0 +1,351 @@
+//! HTTP front-end for the in-memory pipeline, enabled with the `server` feature.
+//!
+//! `POST /extend` takes a multipart form with an `image` part and the aspect
+//! ratio either as a `ratio` field (`16:9`) or inside a JSON `options` part,
+//! which takes the fields of an entry of the job list other than its paths
+//! (`{"aspectRatio": "16:9", "format": "png", "background": "blur"}`), and
+//! answers with the encoded result. Any other body is taken as the image
+//! itself, the ratio and format then coming from the query: `POST
+//! /extend?ratio=16:9&format=png`. Query parameters also apply to a form,
+//! which its fields override.
+//!
+//! Bodies are read within `read_timeout` and `max_upload_bytes` before the
+//! request waits for one of the `max_concurrent` slots, so slow uploads do
+//! not hold one. The pixel limits of the config apply to every request,
+//! which can only lower them and answers `413` when it is over them.
+
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::body::Bytes;
+use axum::extract::{DefaultBodyLimit, FromRequest, Multipart, Query, Request, State};
+use axum::http::{header, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::post;
+use axum::{Json, Router};
+use image::{ImageError, ImageFormat};
+use serde::Deserialize;
+use tokio::sync::Semaphore;
+
+use crate::error::Stage;
+use crate::{
+    validate, AspectRatio, ErrorKind, ExtendError, FormatNotCompiled, Options, OutputFormat,
+    DEFAULT_MAX_OUTPUT_PIXELS,
+};
+
+/// Largest accepted request body.
+pub const MAX_UPLOAD_BYTES: usize = 64 * 1024 * 1024;
+
+/// Longest a request body may take to arrive.
+pub const READ_TIMEOUT: Duration = Duration::from_secs(30);
+
+pub struct ServerConfig {
+    pub addr: SocketAddr,
+    /// Requests processed at the same time; the rest wait for a free slot.
+    pub max_concurrent: usize,
+    pub max_upload_bytes: usize,
+    /// Requests whose body has not arrived by then answer `408`.
+    pub read_timeout: Duration,
+    /// `maxOutputPixels` of every request, as `--max-output-pixels` sets it.
+    pub max_output_pixels: Option<u64>,
+    /// `maxSourcePixels` of every request, as `--max-source-pixels` sets it.
+    pub max_source_pixels: Option<u64>,
+}
+
+impl ServerConfig {
+    pub fn new(addr: SocketAddr) -> Self {
+        ServerConfig {
+            addr,
+            max_concurrent: std::thread::available_parallelism().map_or(4, |n| n.get()),
+            max_upload_bytes: MAX_UPLOAD_BYTES,
+            read_timeout: READ_TIMEOUT,
+            max_output_pixels: None,
+            max_source_pixels: None,
+        }
+    }
+}
+
+/// The ratio of a request and the options of the library it sets.
+#[derive(Default)]
+struct RequestOptions {
+    aspect_ratio: Option<(u32, u32)>,
+    options: Options,
+}
+
+struct ApiError {
+    status: StatusCode,
+    kind: &'static str,
+    message: String,
+}
+
+impl ApiError {
+    fn invalid(message: impl Into<String>) -> Self {
+        ApiError {
+            status: StatusCode::BAD_REQUEST,
+            kind: "invalidParameter",
+            message: message.into(),
+        }
+    }
+}
+
+impl From<ExtendError> for ApiError {
+    fn from(e: ExtendError) -> Self {
+        let (status, kind) = match e.kind() {
+            ErrorKind::Corrupt => (StatusCode::UNPROCESSABLE_ENTITY, "decode"),
+            ErrorKind::Invalid => (StatusCode::BAD_REQUEST, "invalidParameter"),
+            ErrorKind::Limits => (StatusCode::PAYLOAD_TOO_LARGE, "limits"),
+            ErrorKind::Timeout => (StatusCode::SERVICE_UNAVAILABLE, "timeout"),
+            // Only `image` tells a bad upload from a failed encode
+            ErrorKind::Image => match e.inner().downcast_ref::<ImageError>() {
+                Some(ImageError::Decoding(_)) => (StatusCode::UNPROCESSABLE_ENTITY, "decode"),
+                Some(ImageError::Unsupported(_)) => {
+                    (StatusCode::UNSUPPORTED_MEDIA_TYPE, "unsupported")
+                }
+                Some(ImageError::Limits(_)) => (StatusCode::PAYLOAD_TOO_LARGE, "limits"),
+                Some(ImageError::Parameter(_)) => (StatusCode::BAD_REQUEST, "invalidParameter"),
+                _ => (StatusCode::INTERNAL_SERVER_ERROR, "encode"),
+            },
+            ErrorKind::Other if e.inner().is::<FormatNotCompiled>() => {
+                (StatusCode::UNSUPPORTED_MEDIA_TYPE, "unsupported")
+            }
+            ErrorKind::Io | ErrorKind::Panic | ErrorKind::Other => {
+                (StatusCode::INTERNAL_SERVER_ERROR, "internal")
+            }
+        };
+        ApiError {
+            status,
+            kind,
+            message: e.to_string(),
+        }
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let body = serde_json::json!({
+            "error": { "kind": self.kind, "message": self.message }
+        });
+        (self.status, Json(body)).into_response()
+    }
+}
+
+fn parse_ratio(value: &str) -> Result<(u32, u32), ApiError> {
+    crate::parse_ratio(value).map_err(ApiError::invalid)
+}
+
+fn parse_format(value: &str) -> Result<OutputFormat, ApiError> {
+    value.parse().map_err(ApiError::invalid)
+}
+
+/// Reads the JSON `options` part of a form over `options`. Its fields are
+/// those of an entry, of which a missing `format` leaves the one of the
+/// query.
+fn parse_options(bytes: &[u8], options: &mut RequestOptions) -> Result<(), ApiError> {
+    let invalid = |e: serde_json::Error| ApiError::invalid(format!("invalid options: {}", e));
+    let mut fields: serde_json::Map<String, serde_json::Value> =
+        serde_json::from_slice(bytes).map_err(invalid)?;
+    if let Some(ratio) = fields.remove("aspectRatio") {
+        match serde_json::from_value(ratio).map_err(invalid)? {
+            AspectRatio::Fixed(ratio) => options.aspect_ratio = Some(ratio),
+            AspectRatio::Auto => {
+                return Err(ApiError::invalid(
+                    "invalid options: aspectRatio auto is only read from job lists",
+                ))
+            }
+        }
+    }
+    let format = options.options.output_format;
+    options.options = serde_json::from_value(fields.into()).map_err(invalid)?;
+    options.options.output_format = options.options.output_format.or(format);
+    Ok(())
+}
+
+fn content_type(format: ImageFormat) -> &'static str {
+    match format {
+        ImageFormat::Png => "image/png",
+        ImageFormat::Jpeg => "image/jpeg",
+        ImageFormat::Gif => "image/gif",
+        ImageFormat::WebP => "image/webp",
+        ImageFormat::Tiff => "image/tiff",
+        ImageFormat::Bmp => "image/bmp",
+        ImageFormat::Ico => "image/x-icon",
+        _ => "application/octet-stream",
+    }
+}
+
+#[derive(Deserialize)]
+struct RequestQuery {
+    ratio: Option<String>,
+    format: Option<String>,
+}
+
+/// Whether the body of `request` is a multipart form rather than the image.
+fn is_form(request: &Request) -> bool {
+    request
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("multipart/form-data"))
+}
+
+/// The image and the options of a form, on top of `options` from the query.
+async fn read_form(
+    mut multipart: Multipart,
+    mut options: RequestOptions,
+) -> Result<(Option<Bytes>, RequestOptions), ApiError> {
+    let mut data = None;
+    while let Some(field) = multipart
+        .next_field()
+        .await
+        .map_err(|e| ApiError::invalid(e.body_text()))?
+    {
+        let name = field.name().unwrap_or_default().to_string();
+        let bytes = field
+            .bytes()
+            .await
+            .map_err(|e| ApiError::invalid(e.body_text()))?;
+        let text = || String::from_utf8_lossy(&bytes).into_owned();
+        match name.as_str() {
+            "image" => data = Some(bytes),
+            "ratio" => options.aspect_ratio = Some(parse_ratio(&text())?),
+            "format" => options.options.output_format = Some(parse_format(&text())?),
+            "options" => parse_options(&bytes, &mut options)?,
+            _ => return Err(ApiError::invalid(format!("unexpected field {:?}", name))),
+        }
+    }
+    Ok((data, options))
+}
+
+/// What every request shares: the slots of `max_concurrent` and the
+/// limits of the config.
+struct Shared {
+    permits: Arc<Semaphore>,
+    read_timeout: Duration,
+    max_output_pixels: u64,
+    max_source_pixels: Option<u64>,
+}
+
+impl Shared {
+    /// Lowers the pixel limits of `options` to those of the config.
+    fn limit(&self, options: &mut Options) {
+        options.max_output_pixels = Some(options.max_output_pixels().min(self.max_output_pixels));
+        options.max_source_pixels = match (options.max_source_pixels, self.max_source_pixels) {
+            (Some(asked), Some(limit)) => Some(asked.min(limit)),
+            (asked, limit) => asked.or(limit),
+        };
+    }
+}
+
+/// The image and the options of `request`, on top of `options` from the
+/// query.
+async fn read_body(
+    request: Request,
+    options: RequestOptions,
+) -> Result<(Option<Bytes>, RequestOptions), ApiError> {
+    if is_form(&request) {
+        let multipart = Multipart::from_request(request, &())
+            .await
+            .map_err(|e| ApiError::invalid(e.body_text()))?;
+        return read_form(multipart, options).await;
+    }
+    let bytes = Bytes::from_request(request, &())
+        .await
+        .map_err(|e| ApiError {
+            status: e.status(),
+            kind: "invalidParameter",
+            message: e.body_text(),
+        })?;
+    Ok((Some(bytes).filter(|bytes| !bytes.is_empty()), options))
+}
+
+async fn extend(
+    State(shared): State<Arc<Shared>>,
+    query: Result<Query<RequestQuery>, axum::extract::rejection::QueryRejection>,
+    request: Request,
+) -> Result<Response, ApiError> {
+    let Query(query) = query.map_err(|e| ApiError::invalid(e.body_text()))?;
+    let options = RequestOptions {
+        aspect_ratio: query.ratio.as_deref().map(parse_ratio).transpose()?,
+        options: Options {
+            output_format: query.format.as_deref().map(parse_format).transpose()?,
+            ..Options::default()
+        },
+    };
+    let (data, options) = tokio::time::timeout(shared.read_timeout, read_body(request, options))
+        .await
+        .map_err(|_| ApiError {
+            status: StatusCode::REQUEST_TIMEOUT,
+            kind: "timeout",
+            message: format!(
+                "the request body did not arrive within {} seconds",
+                shared.read_timeout.as_secs_f32()
+            ),
+        })??;
+
+    let data = data.ok_or_else(|| ApiError::invalid("missing image"))?;
+    let aspect_ratio = options
+        .aspect_ratio
+        .ok_or_else(|| ApiError::invalid("missing ratio"))?;
+    let mut options = options.options;
+    shared.limit(&mut options);
+    if let Some(problem) = validate::settings(AspectRatio::Fixed(aspect_ratio), &options)
+        .into_iter()
+        .next()
+    {
+        return Err(ApiError::invalid(problem.to_string()));
+    }
+    let aspect_ratio = crate::reduce_ratio(aspect_ratio);
+    let format = match options.output_format {
+        Some(format) => format.image_format(),
+        None => Some(
+            image::guess_format(&data)
+                .map_err(|e| ApiError::from(ExtendError::new(Stage::Decode, None, e)))?,
+        ),
+    };
+
+    // Only extending takes a slot, the upload is in memory by now
+    let permit = Arc::clone(&shared.permits)
+        .acquire_owned()
+        .await
+        .expect("semaphore is never closed");
+    let encoded = tokio::task::spawn_blocking(move || {
+        let _permit = permit;
+        crate::extend_bytes_with(&data, aspect_ratio, format, &options).map_err(ApiError::from)
+    })
+    .await
+    .map_err(|e| ApiError {
+        status: StatusCode::INTERNAL_SERVER_ERROR,
+        kind: "internal",
+        message: e.to_string(),
+    })??;
+
+    // JPEG XL has no `ImageFormat`
+    let content_type = format.map_or("image/jxl", content_type);
+    Ok(([(header::CONTENT_TYPE, content_type)], encoded).into_response())
+}
+
+/// The routes of `run`, for serving them some other way.
+pub fn router(config: &ServerConfig) -> Router {
+    Router::new()
+        .route("/extend", post(extend))
+        .layer(DefaultBodyLimit::max(config.max_upload_bytes))
+        .with_state(Arc::new(Shared {
+            permits: Arc::new(Semaphore::new(config.max_concurrent)),
+            read_timeout: config.read_timeout,
+            max_output_pixels: config
+                .max_output_pixels
+                .unwrap_or(DEFAULT_MAX_OUTPUT_PIXELS),
+            max_source_pixels: config.max_source_pixels,
+        }))
+}
+
+/// Serves `POST /extend` until the process is stopped.
+pub fn run(config: ServerConfig) -> io::Result<()> {
+    let app = router(&config);
+    tokio::runtime::Runtime::new()?.block_on(async {
+        let listener = tokio::net::TcpListener::bind(config.addr).await?;
+        axum::serve(listener, app).await
+    })
+}
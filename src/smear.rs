@@ -0,0 +1,189 @@
+//! The `smear` background, which streaks the outermost rows and columns of
+//! the image outwards.
+//!
+//! Each line out from the image is the previous one shifted by a random
+//! pixel, softened and sprinkled with noise, so textures carry on past the
+//! edge without the banding of repeating one line.
+
+use image::{imageops, DynamicImage, GenericImageView, Rgba, RgbaImage};
+
+use crate::{Layout, Style};
+
+/// Largest noise added to each channel per line, in 8-bit levels.
+const NOISE: f32 = 1.5;
+
+/// splitmix64, which is all the randomness a smear needs and keeps its
+/// output the same for a seed on every platform.
+pub(crate) struct Rng(pub(crate) u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform in `-1.0..1.0`.
+    pub(crate) fn signed(&mut self) -> f32 {
+        (self.next() >> 40) as f32 / (1u64 << 23) as f32 - 1.0
+    }
+
+    /// -1, 0 or 1.
+    fn shift(&mut self) -> isize {
+        (self.next() % 3) as isize - 1
+    }
+}
+
+/// One step outwards from `line`, written into `next`.
+fn step(line: &[[f32; 4]], next: &mut [[f32; 4]], rng: &mut Rng) {
+    let last = line.len() as isize - 1;
+    let at = |i: isize| line[i.clamp(0, last) as usize];
+    let shift = rng.shift();
+    for (i, pixel) in next.iter_mut().enumerate() {
+        let i = i as isize + shift;
+        let (left, centre, right) = (at(i - 1), at(i), at(i + 1));
+        for channel in 0..4 {
+            let value = 0.25 * left[channel] + 0.5 * centre[channel] + 0.25 * right[channel];
+            let noise = if channel < 3 {
+                rng.signed() * NOISE
+            } else {
+                0.0
+            };
+            pixel[channel] = (value + noise).clamp(0.0, 255.0);
+        }
+    }
+}
+
+/// Runs `count` steps out from `line`, handing each to `write` with its
+/// distance from the image, starting at 1.
+fn smear(
+    mut line: Vec<[f32; 4]>,
+    count: u32,
+    rng: &mut Rng,
+    mut write: impl FnMut(u32, &[[f32; 4]]),
+) {
+    let mut next = line.clone();
+    for distance in 1..=count {
+        step(&line, &mut next, rng);
+        std::mem::swap(&mut line, &mut next);
+        write(distance, &line);
+    }
+}
+
+fn to_bytes(pixel: &[f32; 4]) -> [u8; 4] {
+    [
+        pixel[0].round() as u8,
+        pixel[1].round() as u8,
+        pixel[2].round() as u8,
+        pixel[3].round() as u8,
+    ]
+}
+
+/// A row or column of the image at the placed size, as floats, extended to
+/// `length` by repeating its ends from `start` on.
+fn edge(
+    strip: &DynamicImage,
+    filter: imageops::FilterType,
+    placed: u32,
+    horizontal: bool,
+    start: u32,
+    length: u32,
+) -> Vec<[f32; 4]> {
+    let (width, height) = if horizontal { (placed, 1) } else { (1, placed) };
+    let strip = if strip.dimensions() == (width, height) {
+        strip.to_rgba8()
+    } else {
+        imageops::resize(strip, width, height, filter)
+    };
+    let pixels: Vec<[f32; 4]> = strip
+        .pixels()
+        .map(|pixel| pixel.0.map(|channel| channel as f32))
+        .collect();
+    let last = pixels.len() - 1;
+    (0..length)
+        .map(|i| pixels[(i.saturating_sub(start) as usize).min(last)])
+        .collect()
+}
+
+/// The canvas of `layout` with the edges of the normalised image smeared
+/// outwards on every side that has room. Rows above and below span the
+/// whole canvas, and columns to the sides fill the rest.
+pub(crate) fn background(img: &DynamicImage, layout: &Layout, style: &Style) -> RgbaImage {
+    let (canvas_width, canvas_height) = layout.canvas;
+    let (width, height) = img.dimensions();
+    let (x, y) = layout.offset();
+    let (placed_width, placed_height) = layout.image;
+    let mut canvas = RgbaImage::new(canvas_width, canvas_height);
+    let mut rng = Rng(style.smear_seed.unwrap_or(0));
+    let filter = style.filter(imageops::FilterType::Triangle);
+    let stride = canvas_width as usize * 4;
+
+    let top = edge(
+        &img.crop_imm(0, 0, width, 1),
+        filter,
+        placed_width,
+        true,
+        x,
+        canvas_width,
+    );
+    let bottom = edge(
+        &img.crop_imm(0, height - 1, width, 1),
+        filter,
+        placed_width,
+        true,
+        x,
+        canvas_width,
+    );
+    let below = canvas_height - y - placed_height;
+    {
+        let raw: &mut [u8] = &mut canvas;
+        let mut write_row = |row: u32, line: &[[f32; 4]]| {
+            let start = row as usize * stride;
+            for (target, pixel) in raw[start..start + stride].chunks_exact_mut(4).zip(line) {
+                target.copy_from_slice(&style.adjust_background(Rgba(to_bytes(pixel))).0);
+            }
+        };
+        smear(top, y, &mut rng, |distance, line| {
+            write_row(y - distance, line)
+        });
+        smear(bottom, below, &mut rng, |distance, line| {
+            write_row(y + placed_height - 1 + distance, line)
+        });
+    }
+
+    // Only the rows beside the image are left for the columns
+    let left = edge(
+        &img.crop_imm(0, 0, 1, height),
+        filter,
+        placed_height,
+        false,
+        0,
+        placed_height,
+    );
+    let right = edge(
+        &img.crop_imm(width - 1, 0, 1, height),
+        filter,
+        placed_height,
+        false,
+        0,
+        placed_height,
+    );
+    let after = canvas_width - x - placed_width;
+    let raw: &mut [u8] = &mut canvas;
+    let mut write_column = |column: u32, line: &[[f32; 4]]| {
+        for (row, pixel) in line.iter().enumerate() {
+            let start = (y as usize + row) * stride + column as usize * 4;
+            raw[start..start + 4]
+                .copy_from_slice(&style.adjust_background(Rgba(to_bytes(pixel))).0);
+        }
+    };
+    smear(left, x, &mut rng, |distance, line| {
+        write_column(x - distance, line)
+    });
+    smear(right, after, &mut rng, |distance, line| {
+        write_column(x + placed_width - 1 + distance, line)
+    });
+    canvas
+}
@@ -0,0 +1,233 @@
+//! Concurrency limits for the stages of processing an entry.
+//!
+//! Workers of a `Pipeline` take a permit for the stage they are in, so a slow
+//! network share can be read by a few workers at a time while many others
+//! composite and encode. A worker holds at most one permit at a time, so the
+//! limits cannot deadlock.
+//!
+//! A worker that may be abandoned, as one running past its timeout is,
+//! takes its permits for a `Holder`, through which they are given back for
+//! it when it is: a source hanging on its read then holds up nobody else.
+
+use std::cell::RefCell;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+
+use crate::error::Stage;
+
+/// Entries allowed in each stage at the same time.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StageLimits {
+    /// Reading sources.
+    pub read: usize,
+    /// Decoding, extending and encoding.
+    pub process: usize,
+    /// Writing destinations.
+    pub write: usize,
+}
+
+impl StageLimits {
+    /// The same limit for every stage.
+    pub fn uniform(limit: usize) -> Self {
+        StageLimits {
+            read: limit,
+            process: limit,
+            write: limit,
+        }
+    }
+}
+
+struct Semaphore {
+    available: Mutex<usize>,
+    released: Condvar,
+}
+
+impl Semaphore {
+    fn new(permits: usize) -> Arc<Self> {
+        Arc::new(Semaphore {
+            available: Mutex::new(permits.max(1)),
+            released: Condvar::new(),
+        })
+    }
+}
+
+/// A permit taken from `semaphore`, returned once whichever of the worker
+/// and its `Holder` gets to it first.
+struct Taken {
+    semaphore: Arc<Semaphore>,
+    returned: AtomicBool,
+}
+
+impl Taken {
+    fn give_back(&self) {
+        if !self.returned.swap(true, Ordering::SeqCst) {
+            *self
+                .semaphore
+                .available
+                .lock()
+                .unwrap_or_else(|e| e.into_inner()) += 1;
+            self.semaphore.released.notify_one();
+        }
+    }
+}
+
+/// Returned to its semaphore when dropped.
+pub(crate) struct Permit(Arc<Taken>);
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        self.0.give_back();
+    }
+}
+
+/// The permits of a worker that may be abandoned.
+#[derive(Default)]
+pub(crate) struct Holder {
+    taken: Mutex<Vec<Arc<Taken>>>,
+    abandoned: AtomicBool,
+}
+
+impl Holder {
+    /// Gives back every permit the worker holds, and leaves it without any
+    /// from now on.
+    pub(crate) fn abandon(&self) {
+        self.abandoned.store(true, Ordering::SeqCst);
+        for taken in self
+            .taken
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .drain(..)
+        {
+            taken.give_back();
+        }
+    }
+
+    fn hold(&self, taken: &Arc<Taken>) {
+        let mut held = self.taken.lock().unwrap_or_else(|e| e.into_inner());
+        // Abandoned while the permit was waited for
+        if self.abandoned.load(Ordering::SeqCst) {
+            taken.give_back();
+            return;
+        }
+        held.retain(|taken| !taken.returned.load(Ordering::SeqCst));
+        held.push(Arc::clone(taken));
+    }
+}
+
+pub(crate) struct Gates {
+    read: Arc<Semaphore>,
+    process: Arc<Semaphore>,
+    write: Arc<Semaphore>,
+}
+
+impl Gates {
+    pub(crate) fn new(limits: StageLimits) -> Arc<Self> {
+        Arc::new(Gates {
+            read: Semaphore::new(limits.read),
+            process: Semaphore::new(limits.process),
+            write: Semaphore::new(limits.write),
+        })
+    }
+
+    fn semaphore(&self, stage: Stage) -> &Arc<Semaphore> {
+        match stage {
+            Stage::Read => &self.read,
+            Stage::Decode | Stage::Composite => &self.process,
+            Stage::Write => &self.write,
+        }
+    }
+}
+
+thread_local! {
+    static GATES: RefCell<Option<Arc<Gates>>> = const { RefCell::new(None) };
+    static HOLDER: RefCell<Option<Arc<Holder>>> = const { RefCell::new(None) };
+}
+
+/// Limits of the pipeline the current thread works for, to hand on to
+/// threads it spawns.
+pub(crate) fn current() -> Option<Arc<Gates>> {
+    GATES.with(|gates| gates.borrow().clone())
+}
+
+pub(crate) fn enter(gates: Option<Arc<Gates>>) {
+    GATES.with(|current| *current.borrow_mut() = gates);
+}
+
+/// `enter` for a worker whose permits `holder` gives back if it is
+/// abandoned.
+pub(crate) fn enter_held(gates: Option<Arc<Gates>>, holder: Arc<Holder>) {
+    enter(gates);
+    HOLDER.with(|current| *current.borrow_mut() = Some(holder));
+}
+
+fn holder() -> Option<Arc<Holder>> {
+    HOLDER.with(|holder| holder.borrow().clone())
+}
+
+/// A permit just taken from `semaphore`, handed to the holder of the thread.
+fn permit(semaphore: &Arc<Semaphore>, holder: Option<&Holder>) -> Permit {
+    let taken = Arc::new(Taken {
+        semaphore: Arc::clone(semaphore),
+        returned: AtomicBool::new(false),
+    });
+    if let Some(holder) = holder {
+        holder.hold(&taken);
+    }
+    Permit(taken)
+}
+
+/// Waits for a permit to work on `stage`. Outside of a pipeline there are no
+/// limits and `None` is returned right away, as it is for an abandoned
+/// worker.
+pub(crate) fn acquire(stage: Stage) -> Option<Permit> {
+    let gates = current()?;
+    let holder = holder();
+    if holder
+        .as_ref()
+        .is_some_and(|holder| holder.abandoned.load(Ordering::SeqCst))
+    {
+        return None;
+    }
+    let semaphore = gates.semaphore(stage);
+    let mut available = semaphore
+        .available
+        .lock()
+        .unwrap_or_else(|e| e.into_inner());
+    while *available == 0 {
+        available = semaphore
+            .released
+            .wait(available)
+            .unwrap_or_else(|e| e.into_inner());
+    }
+    *available -= 1;
+    drop(available);
+    Some(permit(semaphore, holder.as_deref()))
+}
+
+/// Takes every permit of `stage` that is free right now, without waiting,
+/// for work on the current entry to spread over. Outside of a pipeline there
+/// are none.
+#[cfg(feature = "parallel")]
+pub(crate) fn acquire_spare(stage: Stage) -> Vec<Permit> {
+    let gates = match current() {
+        Some(gates) => gates,
+        None => return Vec::new(),
+    };
+    let holder = holder();
+    if holder
+        .as_ref()
+        .is_some_and(|holder| holder.abandoned.load(Ordering::SeqCst))
+    {
+        return Vec::new();
+    }
+    let semaphore = gates.semaphore(stage);
+    let mut available = semaphore
+        .available
+        .lock()
+        .unwrap_or_else(|e| e.into_inner());
+    let spare = std::mem::take(&mut *available);
+    drop(available);
+    (0..spare)
+        .map(|_| permit(semaphore, holder.as_deref()))
+        .collect()
+}
@@ -0,0 +1,1291 @@
+//! How the image is drawn onto the extended canvas, beyond the background colors.
+
+use std::convert::TryFrom;
+use std::str::FromStr;
+
+use image::{imageops, DynamicImage, GenericImage, GenericImageView, ImageError, Pixel};
+use serde::{Deserialize, Serialize};
+
+use crate::color::{self, Color};
+use crate::depth::Channel;
+use crate::report::ColorSource;
+use crate::smear::Rng;
+
+/// Drawing settings of an entry, all off by default.
+///
+/// Settings left off are not serialized, so that adding new ones does not
+/// invalidate earlier `skipUpToDate` sidecars and caches.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(rename_all = "camelCase", default)]
+pub struct Style {
+    #[serde(alias = "backgroundMode", skip_serializing_if = "Background::is_split")]
+    pub background: Background,
+    /// Replaces the color sampled from the top or left edge, used as is by
+    /// the `split` and `gradient` backgrounds. The other color stays sampled
+    /// unless it is set too.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub first_color: Option<Color>,
+    /// Replaces the color sampled from the bottom or right edge, as
+    /// `first_color`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub second_color: Option<Color>,
+    /// Fills the whole canvas with this one color instead of `background`,
+    /// used exactly as given in place of anything sampled from the image.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub background_color: Option<Color>,
+    /// Takes the place of the color sampled from an edge that is next to
+    /// fully transparent. Defaults to the average of the opaque pixels of
+    /// the image, or `DEFAULT_FALLBACK` when there are none.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fallback_color: Option<Color>,
+    /// How the pixels along an edge are summed up into its color. Defaults
+    /// to `mean`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub color_sampling: Option<ColorSampling>,
+    /// Average the pixels of an edge in linear light rather than as their
+    /// sRGB values, which keeps fills from coming out darker than the edge
+    /// looks. Gradients and blends are in linear light either way.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub linear_color: Option<bool>,
+    /// Depth of the strip sampled along each edge, a percentage being of the
+    /// side of the image across the edge. Defaults to 5%, and is at least a
+    /// pixel and at most the whole image.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub edge_sample: Option<Length>,
+    /// Whether the background may be transparent where the image is.
+    /// Defaults to `keep`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub background_alpha: Option<BackgroundAlpha>,
+    /// Fill of the `complement` background when the edges are nearly gray
+    /// and dark. Defaults to `DEFAULT_COMPLEMENT_LIGHT`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub complement_light: Option<Color>,
+    /// Fill of the `complement` background when the edges are nearly gray
+    /// and light. Defaults to `DEFAULT_COMPLEMENT_DARK`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub complement_dark: Option<Color>,
+    /// Share of the first edge color in the `uniform` background, from 0 to
+    /// 1, the rest being the second. Defaults to an even blend.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub uniform_weight: Option<f32>,
+    /// Direction of the `gradient` background in degrees, from the first
+    /// edge color to the second: 0 runs down, 90 to the right, 180 up and 270
+    /// to the left. Defaults to along the extension, 0 for images extended
+    /// above and below and 90 for images extended to the sides.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gradient_angle: Option<f32>,
+    /// Standard deviation of the `blur` background in canvas pixels.
+    /// Defaults to 3% of the shorter side of the canvas.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub blur_sigma: Option<f32>,
+    /// The `blur` background is blurred at this fraction of the canvas size
+    /// and scaled up afterwards, which is faster and smoother. Defaults to
+    /// `DEFAULT_BLUR_DOWNSCALE`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub blur_downscale: Option<f32>,
+    /// Seeds the random streaks of the `smear` background, so different
+    /// seeds give different streaks. Defaults to 0.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub smear_seed: Option<u64>,
+    /// Number of colors the `palette` background is made of, from
+    /// `PALETTE_SIZES`. Defaults to `DEFAULT_PALETTE_SIZE`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub palette_size: Option<u8>,
+    /// Blends the colors of the `palette` background into one another
+    /// instead of painting them as bands. Defaults to false.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub palette_gradient: Option<bool>,
+    /// Name of a fill registered with `fill::register`, painted in place of
+    /// `background`, which still picks the colors it is handed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fill: Option<String>,
+    /// Drawn around the placed image to set it apart from the background.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub border: Option<Border>,
+    /// Cast by the placed image, under the border.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub shadow: Option<Shadow>,
+    /// Rounds the corners of the placed image, clamped to a capsule shape,
+    /// so that `"50%"` cuts a square image to a circle.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub corner_radius: Option<Length>,
+    /// Fades the placed image into the background over this far along each
+    /// side with background beside it, instead of a hard edge. At most half
+    /// the shorter side of the placed image.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub blend_width: Option<Length>,
+    /// Stamped over the finished canvas.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub watermark: Option<Watermark>,
+    /// Text drawn into the background next to the placed image. Needs the
+    /// `text` feature.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub caption: Option<Caption>,
+    /// Darkens the background towards the corners of the canvas.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub vignette: Option<Vignette>,
+    /// Noise over the background, so that large flat areas blend in with
+    /// the grain of a photo and do not band once recompressed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub grain: Option<Grain>,
+    /// Makes the sampled background colors darker, down to -1 for black, or
+    /// lighter, up to 1 for white.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub background_lightness: Option<f32>,
+    /// Multiplies the chroma of the sampled background colors: 0 turns them
+    /// gray, above 1 makes them more vivid.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub background_saturation: Option<f32>,
+    /// The two above as percentages, for either one left out:
+    /// `{ "brightness": -20, "saturation": -30 }` is a lightness of -0.2
+    /// and a saturation of 0.7.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub background_adjust: Option<BackgroundAdjust>,
+    /// Smallest difference in Oklab lightness, from 0 to 1, between a
+    /// background color and the edge of the image next to it. Colors closer
+    /// than that are made lighter or darker until they are not.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_contrast: Option<f32>,
+    /// Scales the placed image down within the canvas, from 0 to 1, so the
+    /// background shows on every side.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub inset_scale: Option<f32>,
+    /// Room kept on every side of the image, a percentage being of the
+    /// shorter side of the source. The canvas grows to make it, so images
+    /// that already have the ratio are given a background as well.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub padding: Option<Length>,
+    /// Where the placed image sits on the canvas. Defaults to `center`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gravity: Option<Gravity>,
+    /// Used for all scaling of the entry: the placed image, the `blur`,
+    /// `smear`, `mirror` and `replicate` backgrounds and the watermark.
+    /// Each defaults to its own filter, Lanczos3 for the placed image.
+    #[serde(alias = "resampleFilter", skip_serializing_if = "Option::is_none")]
+    pub resize_filter: Option<Filter>,
+}
+
+/// What fills the canvas around the placed image.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum Background {
+    /// Two halves in the colors sampled from the opposite edges of the image.
+    /// A canvas with room on all four sides has a band in the color of each
+    /// edge instead, the corners blending the two edges they join.
+    #[default]
+    #[serde(alias = "splitAverage")]
+    Split,
+    /// The image itself, scaled to cover the canvas and blurred.
+    Blur,
+    /// The outermost rows or columns of the image streaked outwards with a
+    /// little noise, continuing textures like grass or walls.
+    Smear,
+    /// The image reflected at each of its edges, carrying textures on more
+    /// faithfully than a flat color.
+    Mirror,
+    /// The outermost pixel of each row and column of the image repeated
+    /// outwards, keeping gradients along the edges as skies have them.
+    /// Unlike `smear` it adds no noise and never softens.
+    #[serde(alias = "clamp")]
+    Replicate,
+    /// A linear gradient between the colors sampled from the opposite edges,
+    /// reaching each at the far ends of the canvas.
+    Gradient,
+    /// One color opposite in hue to the average of the edges, to contrast
+    /// with the image instead of blending into it.
+    Complement,
+    /// One color blended from both edges, for images whose edges differ too
+    /// much for a split to look right.
+    Uniform,
+    /// Each corner of the image sampled on its own and the colors blended
+    /// between them, so every side of the background follows the edge it
+    /// touches along its length.
+    Corners,
+    /// The few colors most of the image is made of, lightest first, in bands
+    /// across the room around it or blended with `paletteGradient`.
+    Palette,
+}
+
+impl Background {
+    fn is_split(&self) -> bool {
+        *self == Background::Split
+    }
+
+    /// The name of the background in the job list.
+    pub fn name(self) -> &'static str {
+        match self {
+            Background::Split => "split",
+            Background::Blur => "blur",
+            Background::Smear => "smear",
+            Background::Mirror => "mirror",
+            Background::Replicate => "replicate",
+            Background::Gradient => "gradient",
+            Background::Complement => "complement",
+            Background::Uniform => "uniform",
+            Background::Corners => "corners",
+            Background::Palette => "palette",
+        }
+    }
+}
+
+impl FromStr for Background {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        serde_json::from_value(serde_json::Value::String(name.to_string())).map_err(|_| {
+            format!(
+                "unknown background {}, expected split, blur, smear, mirror, replicate, gradient, complement, uniform, corners or palette",
+                name
+            )
+        })
+    }
+}
+
+/// Transparency of the background of a source that has some.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum BackgroundAlpha {
+    /// As transparent as the edges the colors are sampled from, and as the
+    /// image itself for the backgrounds made from it.
+    #[default]
+    Keep,
+    /// Fully opaque. Sampled colors keep their own color, and backgrounds
+    /// made from the image are laid over `fallbackColor`.
+    Opaque,
+}
+
+/// Where the placed image sits on a canvas with room around it, the rest
+/// of the room going to the other side.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum Gravity {
+    #[default]
+    Center,
+    Top,
+    Bottom,
+    Left,
+    Right,
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+    /// The top left corner of the image at `[x, y]` on the canvas, written
+    /// as `{"offset": [x, y]}` in the job list. Moved in as far as it takes
+    /// for the whole image to stay on the canvas.
+    Offset((u32, u32)),
+    /// Moved along the sides the image was extended on so its busiest part
+    /// sits on a third of the canvas. Planned in the middle, as where it
+    /// goes depends on the pixels.
+    Saliency,
+}
+
+impl Gravity {
+    /// Top left corner of an image of `image` on a canvas of `canvas`.
+    pub(crate) fn offset(self, canvas: (u32, u32), image: (u32, u32)) -> (u32, u32) {
+        let room = (
+            canvas.0.saturating_sub(image.0),
+            canvas.1.saturating_sub(image.1),
+        );
+        let (x, y) = match self {
+            Gravity::Center | Gravity::Saliency => (room.0 / 2, room.1 / 2),
+            Gravity::Top => (room.0 / 2, 0),
+            Gravity::Bottom => (room.0 / 2, room.1),
+            Gravity::Left => (0, room.1 / 2),
+            Gravity::Right => (room.0, room.1 / 2),
+            Gravity::TopLeft => (0, 0),
+            Gravity::TopRight => (room.0, 0),
+            Gravity::BottomLeft => (0, room.1),
+            Gravity::BottomRight => room,
+            Gravity::Offset(offset) => offset,
+        };
+        (x.min(room.0), y.min(room.1))
+    }
+}
+
+/// How the color of an edge is taken from its pixels.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum ColorSampling {
+    /// The average of the pixels of the edge.
+    #[default]
+    Mean,
+    /// The middle value of each channel, which a small bright object near
+    /// the edge cannot pull away.
+    Median,
+    /// The most frequent color, its channels quantized to 16 levels to count
+    /// them, as the average of the pixels of that color.
+    Dominant,
+}
+
+/// Resampling filter, from fastest to sharpest. Nearest keeps the hard
+/// edges of pixel art.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum Filter {
+    Nearest,
+    Triangle,
+    CatmullRom,
+    Gaussian,
+    Lanczos3,
+}
+
+impl FromStr for Filter {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        serde_json::from_value(serde_json::Value::String(name.to_string())).map_err(|_| {
+            format!(
+                "unknown filter {}, expected nearest, triangle, catmullRom, gaussian or lanczos3",
+                name
+            )
+        })
+    }
+}
+
+impl Filter {
+    pub(crate) fn filter_type(self) -> imageops::FilterType {
+        match self {
+            Filter::Nearest => imageops::FilterType::Nearest,
+            Filter::Triangle => imageops::FilterType::Triangle,
+            Filter::CatmullRom => imageops::FilterType::CatmullRom,
+            Filter::Gaussian => imageops::FilterType::Gaussian,
+            Filter::Lanczos3 => imageops::FilterType::Lanczos3,
+        }
+    }
+}
+
+pub const DEFAULT_BLUR_DOWNSCALE: f32 = 4.0;
+
+pub const DEFAULT_PALETTE_SIZE: u8 = 3;
+pub const PALETTE_SIZES: std::ops::RangeInclusive<u8> = 2..=6;
+
+pub const DEFAULT_FALLBACK: Color = Color(image::Rgba([0xd0, 0xd0, 0xd0, 0xff]));
+
+pub const DEFAULT_COMPLEMENT_LIGHT: Color = Color(image::Rgba([0xf0, 0xf0, 0xf0, 0xff]));
+pub const DEFAULT_COMPLEMENT_DARK: Color = Color(image::Rgba([0x20, 0x20, 0x20, 0xff]));
+
+/// Oklab chroma below which a color counts as gray and has no useful
+/// complement.
+const NEUTRAL_CHROMA: f32 = 0.03;
+
+impl Style {
+    /// Where the two background colors came from, given which of the edges
+    /// fell back to `fallbackColor`. Empty when both were sampled.
+    pub(crate) fn color_sources(&self, fallback: [bool; 2]) -> Vec<ColorSource> {
+        let forced = match self.background_color {
+            Some(color) => [Some(color); 2],
+            None => [self.first_color, self.second_color],
+        };
+        let sources: Vec<ColorSource> = forced
+            .iter()
+            .zip(fallback)
+            .map(|(forced, fallback)| match (forced, fallback) {
+                (Some(_), _) => ColorSource::Forced,
+                (None, true) => ColorSource::Fallback,
+                (None, false) => ColorSource::Sampled,
+            })
+            .collect();
+        if sources.iter().all(|&source| source == ColorSource::Sampled) {
+            return Vec::new();
+        }
+        sources
+    }
+
+    /// The `resizeFilter` of the entry, or `default` for the caller.
+    pub(crate) fn filter(&self, default: imageops::FilterType) -> imageops::FilterType {
+        self.resize_filter.map_or(default, Filter::filter_type)
+    }
+
+    pub(crate) fn blur_sigma(&self, (width, height): (u32, u32)) -> f32 {
+        self.blur_sigma.unwrap_or(width.min(height) as f32 * 0.03)
+    }
+
+    /// Size of the placed image once the inset is applied to `size`.
+    pub(crate) fn inset(&self, size: (u32, u32)) -> (u32, u32) {
+        let scale = self.inset_scale.unwrap_or(1.0).clamp(0.0, 1.0);
+        if scale >= 1.0 {
+            return size;
+        }
+        (
+            ((size.0 as f32 * scale).round() as u32).max(1),
+            ((size.1 as f32 * scale).round() as u32).max(1),
+        )
+    }
+
+    /// Pixels of `padding` on each side of a source of `(width, height)`.
+    pub(crate) fn padding(&self, (width, height): (u32, u32)) -> u32 {
+        self.padding
+            .map_or(0, |padding| padding.resolve(width.min(height)))
+    }
+
+    pub(crate) fn blur_downscale(&self) -> f32 {
+        self.blur_downscale
+            .unwrap_or(DEFAULT_BLUR_DOWNSCALE)
+            .max(1.0)
+    }
+
+    pub(crate) fn palette_size(&self) -> usize {
+        self.palette_size.unwrap_or(DEFAULT_PALETTE_SIZE) as usize
+    }
+
+    pub(crate) fn background_lightness(&self) -> f32 {
+        self.background_lightness
+            .or_else(|| {
+                self.background_adjust
+                    .map(|adjust| adjust.brightness / 100.0)
+            })
+            .unwrap_or(0.0)
+    }
+
+    pub(crate) fn background_saturation(&self) -> f32 {
+        self.background_saturation
+            .or_else(|| {
+                self.background_adjust
+                    .map(|adjust| 1.0 + adjust.saturation / 100.0)
+            })
+            .unwrap_or(1.0)
+    }
+
+    /// Applies the background adjustments to a sampled color.
+    pub(crate) fn adjust_background(&self, sampled: image::Rgba<u8>) -> image::Rgba<u8> {
+        let lightness = self.background_lightness().clamp(-1.0, 1.0);
+        let saturation = self.background_saturation().max(0.0);
+        if lightness == 0.0 && saturation == 1.0 {
+            return sampled;
+        }
+        let mut lab = color::Oklab::from_rgb(sampled);
+        lab.l = if lightness > 0.0 {
+            lab.l + (1.0 - lab.l) * lightness
+        } else {
+            lab.l * (1.0 + lightness)
+        };
+        // Scaling a and b together changes the chroma but not the hue
+        lab.a *= saturation;
+        lab.b *= saturation;
+        lab.to_rgb(sampled.0[3])
+    }
+
+    /// The `complement` fill for the sampled edge colors, before the
+    /// background adjustments.
+    pub(crate) fn complement(
+        &self,
+        first: image::Rgba<u8>,
+        second: image::Rgba<u8>,
+    ) -> image::Rgba<u8> {
+        let (first, second) = (
+            color::Oklab::from_rgb(first),
+            color::Oklab::from_rgb(second),
+        );
+        let average = color::Oklab {
+            l: (first.l + second.l) / 2.0,
+            a: (first.a + second.a) / 2.0,
+            b: (first.b + second.b) / 2.0,
+        };
+        if average.a.hypot(average.b) < NEUTRAL_CHROMA {
+            let neutral = if average.l < 0.5 {
+                self.complement_light.unwrap_or(DEFAULT_COMPLEMENT_LIGHT)
+            } else {
+                self.complement_dark.unwrap_or(DEFAULT_COMPLEMENT_DARK)
+            };
+            return neutral.0;
+        }
+        // Half a turn of hue is negating a and b, which has no wrap-around
+        // at 0 and 360 degrees to get wrong
+        color::Oklab {
+            l: average.l,
+            a: -average.a,
+            b: -average.b,
+        }
+        .to_rgb(255)
+    }
+
+    /// The `uniform` fill for the sampled edge colors, blended in linear
+    /// light before the background adjustments.
+    pub(crate) fn uniform(
+        &self,
+        first: image::Rgba<u8>,
+        second: image::Rgba<u8>,
+    ) -> image::Rgba<u8> {
+        let weight = self.uniform_weight.unwrap_or(0.5).clamp(0.0, 1.0);
+        let blend = |a: f32, b: f32| a * weight + b * (1.0 - weight);
+        let channel = |i: usize| {
+            color::from_linear(blend(
+                color::to_linear(first.0[i]),
+                color::to_linear(second.0[i]),
+            ))
+        };
+        let alpha = blend(first.0[3] as f32, second.0[3] as f32).round() as u8;
+        image::Rgba([channel(0), channel(1), channel(2), alpha])
+    }
+
+    /// `color` moved in lightness until it differs by at least `minContrast`
+    /// from each of `edges`, with the change in Oklab lightness when there is
+    /// one.
+    ///
+    /// The smaller of the two moves wins. When neither fits between black
+    /// and white, the color goes as far as it can in the direction with more
+    /// room.
+    pub(crate) fn guard_contrast(
+        &self,
+        color: image::Rgba<u8>,
+        edges: &[image::Rgba<u8>],
+    ) -> (image::Rgba<u8>, Option<f32>) {
+        let min_contrast = match self.min_contrast {
+            Some(min_contrast) if min_contrast > 0.0 => min_contrast.min(1.0),
+            _ => return (color, None),
+        };
+        let mut lab = color::Oklab::from_rgb(color);
+        let edges: Vec<f32> = edges
+            .iter()
+            .map(|&edge| color::Oklab::from_rgb(edge).l)
+            .collect();
+        if edges
+            .iter()
+            .all(|edge| (lab.l - edge).abs() >= min_contrast)
+        {
+            return (color, None);
+        }
+        let darkest = edges.iter().copied().fold(f32::INFINITY, f32::min);
+        let lightest = edges.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+        let (lighter, darker) = (lightest + min_contrast, darkest - min_contrast);
+        let target = match (lighter <= 1.0, darker >= 0.0) {
+            (true, true) if lighter - lab.l <= lab.l - darker => lighter,
+            (true, true) => darker,
+            (true, false) => lighter,
+            (false, true) => darker,
+            (false, false) if 1.0 - lightest >= darkest => 1.0,
+            (false, false) => 0.0,
+        };
+        let change = target - lab.l;
+        lab.l = target;
+        (lab.to_rgb(color.0[3]), Some(change))
+    }
+
+    /// Corner radius in pixels for a placed image of the given size.
+    pub(crate) fn corner_radius(&self, (width, height): (u32, u32)) -> u32 {
+        let short_side = width.min(height);
+        self.corner_radius
+            .map_or(0, |radius| radius.resolve(short_side).min(short_side / 2))
+    }
+
+    pub(crate) fn blend_width(&self, (width, height): (u32, u32)) -> u32 {
+        let short_side = width.min(height);
+        self.blend_width
+            .map_or(0, |blend| blend.resolve(short_side).min(short_side / 2))
+    }
+}
+
+/// A size in pixels, or in percent of the shorter side of the placed image
+/// unless documented otherwise.
+///
+/// Written as a number of pixels, `"12px"` or `"5%"` in the job list.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(try_from = "LengthValue", into = "LengthValue")]
+pub enum Length {
+    Pixels(u32),
+    Percent(f32),
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum LengthValue {
+    Pixels(u32),
+    Text(String),
+}
+
+impl TryFrom<LengthValue> for Length {
+    type Error = String;
+
+    fn try_from(value: LengthValue) -> Result<Self, Self::Error> {
+        let text = match value {
+            LengthValue::Pixels(pixels) => return Ok(Length::Pixels(pixels)),
+            LengthValue::Text(text) => text,
+        };
+        let invalid = || format!("invalid length {:?}, expected pixels or a percentage", text);
+        let trimmed = text.trim();
+        if let Some(percent) = trimmed.strip_suffix('%') {
+            let percent: f32 = percent.trim().parse().map_err(|_| invalid())?;
+            if !percent.is_finite() || percent < 0.0 {
+                return Err(invalid());
+            }
+            return Ok(Length::Percent(percent));
+        }
+        let pixels = trimmed.strip_suffix("px").unwrap_or(trimmed).trim();
+        pixels.parse().map(Length::Pixels).map_err(|_| invalid())
+    }
+}
+
+impl From<Length> for LengthValue {
+    fn from(length: Length) -> Self {
+        match length {
+            Length::Pixels(pixels) => LengthValue::Pixels(pixels),
+            Length::Percent(percent) => LengthValue::Text(format!("{}%", percent)),
+        }
+    }
+}
+
+impl Length {
+    /// The length in pixels for a placed image whose shorter side is `base`.
+    pub fn resolve(self, base: u32) -> u32 {
+        match self {
+            Length::Pixels(pixels) => pixels,
+            Length::Percent(percent) => (base as f32 * percent / 100.0).round() as u32,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Border {
+    pub width: Length,
+    #[serde(default)]
+    pub color: BorderColor,
+}
+
+/// `"auto"` or a hex color.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(try_from = "String", into = "String")]
+pub enum BorderColor {
+    /// The background under the border, darkened.
+    #[default]
+    Auto,
+    Color(Color),
+}
+
+impl TryFrom<String> for BorderColor {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        if value == "auto" {
+            Ok(BorderColor::Auto)
+        } else {
+            value.parse().map(BorderColor::Color)
+        }
+    }
+}
+
+impl From<BorderColor> for String {
+    fn from(color: BorderColor) -> Self {
+        match color {
+            BorderColor::Auto => "auto".to_string(),
+            BorderColor::Color(color) => color.to_string(),
+        }
+    }
+}
+
+/// How much of the background an `auto` border keeps.
+const AUTO_BORDER_BRIGHTNESS: f32 = 0.6;
+
+/// How much of the pixel at `(px, py)` lies within the rectangle at `(x, y)`
+/// of the given size with corners rounded by `radius`, antialiased over one
+/// pixel.
+fn coverage(
+    (px, py): (u32, u32),
+    (x, y): (f32, f32),
+    (width, height): (f32, f32),
+    radius: f32,
+) -> f32 {
+    // Signed distance from the pixel center to the rounded rectangle
+    let (half_width, half_height) = (width / 2.0, height / 2.0);
+    let qx = (px as f32 + 0.5 - x - half_width).abs() - (half_width - radius);
+    let qy = (py as f32 + 0.5 - y - half_height).abs() - (half_height - radius);
+    let outside = qx.max(0.0).hypot(qy.max(0.0));
+    let distance = outside + qx.max(qy).min(0.0) - radius;
+    (0.5 - distance).clamp(0.0, 1.0)
+}
+
+/// `from` moved towards `to` by `amount` between 0 and 1.
+pub(crate) fn mix(from: image::Rgba<u8>, to: image::Rgba<u8>, amount: f32) -> image::Rgba<u8> {
+    let mut mixed = from;
+    for (channel, to) in mixed.0.iter_mut().zip(to.0.iter()) {
+        *channel = (*channel as f32 + (*to as f32 - *channel as f32) * amount).round() as u8;
+    }
+    mixed
+}
+
+/// Draws `border` on the canvas just outside the image placed at `(x, y)`
+/// with the given size, clipped to the canvas. The border follows corners
+/// rounded by `radius`.
+pub(crate) fn draw_border(
+    canvas: &mut image::RgbaImage,
+    (x, y): (u32, u32),
+    (width, height): (u32, u32),
+    border: &Border,
+    radius: u32,
+) {
+    let stroke = border.width.resolve(width.min(height));
+    if stroke == 0 {
+        return;
+    }
+    let (canvas_width, canvas_height) = canvas.dimensions();
+    let (left, top) = (x.saturating_sub(stroke), y.saturating_sub(stroke));
+    let right = x
+        .saturating_add(width)
+        .saturating_add(stroke)
+        .min(canvas_width);
+    let bottom = y
+        .saturating_add(height)
+        .saturating_add(stroke)
+        .min(canvas_height);
+    let outer = (
+        (x as f32 - stroke as f32, y as f32 - stroke as f32),
+        (
+            width as f32 + 2.0 * stroke as f32,
+            height as f32 + 2.0 * stroke as f32,
+        ),
+        (radius + stroke) as f32,
+    );
+    let inner = (
+        (x as f32, y as f32),
+        (width as f32, height as f32),
+        radius as f32,
+    );
+    for py in top..bottom {
+        for px in left..right {
+            let amount = if radius == 0 {
+                if (x..x + width).contains(&px) && (y..y + height).contains(&py) {
+                    continue;
+                }
+                1.0
+            } else {
+                coverage((px, py), outer.0, outer.1, outer.2)
+                    - coverage((px, py), inner.0, inner.1, inner.2)
+            };
+            if amount <= 0.0 {
+                continue;
+            }
+            let pixel = canvas.get_pixel_mut(px, py);
+            let stroked = match border.color {
+                BorderColor::Auto => color::scale(*pixel, AUTO_BORDER_BRIGHTNESS),
+                BorderColor::Color(color) => {
+                    let mut stroked = *pixel;
+                    stroked.blend(&color.0);
+                    stroked
+                }
+            };
+            *pixel = if amount >= 1.0 {
+                stroked
+            } else {
+                mix(*pixel, stroked, amount)
+            };
+        }
+    }
+}
+
+/// Whether any pixel of `img` lets the background show through.
+fn has_transparency(img: &DynamicImage) -> bool {
+    img.color().has_alpha() && img.pixels().any(|(_, _, pixel)| pixel.0[3] < 255)
+}
+
+/// Puts `img` onto the canvas at `(x, y)`, blending it over the background
+/// where it is transparent. Opaque images are copied, which is faster.
+pub(crate) fn composite(
+    canvas: &mut image::RgbaImage,
+    img: &DynamicImage,
+    (x, y): (u32, u32),
+) -> Result<(), ImageError> {
+    let (width, height) = img.dimensions();
+    if x + width > canvas.width() || y + height > canvas.height() || !has_transparency(img) {
+        // Also where it does not fit, to fail the way it always has
+        return canvas.copy_from(img, x, y);
+    }
+    let img = img.to_rgba8();
+    for (px, py, pixel) in img.enumerate_pixels() {
+        let under = canvas.get_pixel_mut(x + px, y + py);
+        *under = over(*under, *pixel);
+    }
+    Ok(())
+}
+
+/// `top` laid over `bottom`, rounded rather than cut off as `Pixel::blend`
+/// does, so that an opaque background stays opaque under it.
+pub(crate) fn over<T: Channel>(bottom: image::Rgba<T>, top: image::Rgba<T>) -> image::Rgba<T> {
+    let top_alpha = top.0[3].into() / T::MAX;
+    if top_alpha == 1.0 {
+        return top;
+    } else if top_alpha == 0.0 {
+        return bottom;
+    }
+    let bottom_alpha = bottom.0[3].into() / T::MAX * (1.0 - top_alpha);
+    let alpha = top_alpha + bottom_alpha;
+    let mut blended = top.0;
+    for (channel, &bottom) in blended.iter_mut().zip(&bottom.0) {
+        *channel =
+            T::from_f32(((*channel).into() * top_alpha + bottom.into() * bottom_alpha) / alpha);
+    }
+    blended[3] = T::from_f32(alpha * T::MAX);
+    image::Rgba(blended)
+}
+
+/// Copies `img` onto the canvas at `(x, y)` with its corners rounded by
+/// `radius`, blending the antialiased edge into the background.
+pub(crate) fn place_rounded(
+    canvas: &mut image::RgbaImage,
+    img: &DynamicImage,
+    (x, y): (u32, u32),
+    radius: u32,
+) -> Result<(), ImageError> {
+    let (width, height) = img.dimensions();
+    if x + width > canvas.width() || y + height > canvas.height() {
+        // Fails the same way as without rounding
+        return canvas.copy_from(img, x, y);
+    }
+    let in_corner = |px: u32, py: u32| {
+        (px < radius || px >= width - radius) && (py < radius || py >= height - radius)
+    };
+    // The background under the corners, which `composite` replaces
+    let mut corners = Vec::new();
+    for py in 0..height {
+        for px in 0..width {
+            if in_corner(px, py) {
+                corners.push((px, py, *canvas.get_pixel(x + px, y + py)));
+            }
+        }
+    }
+    composite(canvas, img, (x, y))?;
+    let size = (width as f32, height as f32);
+    for (px, py, background) in corners {
+        let amount = coverage((px, py), (0.0, 0.0), size, radius as f32);
+        if amount >= 1.0 {
+            continue;
+        }
+        let mut pixel = img.get_pixel(px, py);
+        pixel.0[3] = (pixel.0[3] as f32 * amount).round() as u8;
+        canvas.put_pixel(x + px, y + py, over(background, pixel));
+    }
+    Ok(())
+}
+
+/// Runs `place` to put an image of `(width, height)` on the canvas at
+/// `(x, y)`, then fades it into the background it covered over `blend`
+/// pixels along each side that is not at the edge of the canvas.
+pub(crate) fn place_feathered(
+    canvas: &mut image::RgbaImage,
+    (x, y): (u32, u32),
+    (width, height): (u32, u32),
+    blend: u32,
+    place: impl FnOnce(&mut image::RgbaImage) -> Result<(), ImageError>,
+) -> Result<(), ImageError> {
+    if x + width > canvas.width() || y + height > canvas.height() {
+        // Fails the same way as without feathering
+        return place(canvas);
+    }
+    let (left, top) = (x > 0, y > 0);
+    let (right, bottom) = (x + width < canvas.width(), y + height < canvas.height());
+    // How much of the image shows `at` pixels in from a faded side
+    let fade = |faded: bool, at: u32| {
+        if faded {
+            ((at as f32 + 0.5) / blend as f32).min(1.0)
+        } else {
+            1.0
+        }
+    };
+    let mut band = Vec::new();
+    for py in 0..height {
+        let vertical = fade(top, py).min(fade(bottom, height - 1 - py));
+        for px in 0..width {
+            let amount = vertical
+                .min(fade(left, px))
+                .min(fade(right, width - 1 - px));
+            if amount < 1.0 {
+                band.push((px, py, amount, *canvas.get_pixel(x + px, y + py)));
+            }
+        }
+    }
+    place(canvas)?;
+    for (px, py, amount, background) in band {
+        let placed = canvas.get_pixel_mut(x + px, y + py);
+        for (channel, below) in placed.0.iter_mut().zip(background.0) {
+            let above = *channel as f32;
+            *channel = (below as f32 + (above - below as f32) * amount).round() as u8;
+        }
+    }
+    Ok(())
+}
+
+/// A logo or other image stamped into a corner of the canvas.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Watermark {
+    /// Image file to stamp, usually with an alpha channel.
+    pub path: String,
+    #[serde(default)]
+    pub anchor: Anchor,
+    /// Distance from the canvas edges, in percent of the shorter side of the
+    /// canvas rather than of the placed image.
+    #[serde(default = "no_margin")]
+    pub margin: Length,
+    /// From 0 to 1, multiplied with the alpha of the watermark.
+    #[serde(default = "opaque")]
+    pub opacity: f32,
+    /// Width as a fraction of the canvas width. The watermark keeps its own
+    /// size when unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scale: Option<f32>,
+    /// Keeps the watermark to the extended area on this side of the image,
+    /// anchored within it and scaled down to fit, its `scale` being of the
+    /// width of the area. Left out when there is no such area, as with
+    /// `mode: crop` and `seamCarve`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub area: Option<CaptionArea>,
+}
+
+fn no_margin() -> Length {
+    Length::Pixels(0)
+}
+
+fn opaque() -> f32 {
+    1.0
+}
+
+/// Corner of the canvas a watermark is anchored to.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum Anchor {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    #[default]
+    BottomRight,
+}
+
+/// Text rendered into one of the extended areas of the canvas.
+///
+/// Long text is wrapped and, if it still does not fit, shrunk until it does,
+/// so it never runs onto the placed image.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Caption {
+    pub text: String,
+    /// TrueType or OpenType font file.
+    pub font: String,
+    /// Largest font size, in percent of the shorter side of the canvas
+    /// rather than of the placed image.
+    #[serde(default = "caption_size")]
+    pub size: Length,
+    #[serde(default = "white")]
+    pub color: Color,
+    #[serde(default)]
+    pub align: Align,
+    #[serde(default)]
+    pub area: CaptionArea,
+}
+
+fn caption_size() -> Length {
+    Length::Percent(6.0)
+}
+
+fn white() -> Color {
+    Color(image::Rgba([255, 255, 255, 255]))
+}
+
+/// Horizontal alignment of the lines of a caption.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum Align {
+    Left,
+    #[default]
+    Center,
+    Right,
+}
+
+/// Which extended area a caption or watermark goes into.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum CaptionArea {
+    /// Above the image, or left of it when the canvas was extended sideways.
+    Before,
+    /// Below the image, or right of it.
+    #[default]
+    After,
+}
+
+/// Radial darkening of the background, leaving the placed image alone.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "camelCase", default)]
+pub struct Vignette {
+    /// How much light is taken away in the corners, from 0 to 1.
+    pub strength: f32,
+    /// Exponent of the distance from the center. Higher values keep more of
+    /// the middle untouched.
+    pub falloff: f32,
+}
+
+impl Default for Vignette {
+    fn default() -> Self {
+        Vignette {
+            strength: 0.5,
+            falloff: 2.0,
+        }
+    }
+}
+
+/// Darkens the canvas outside the image placed at `(x, y)` with the given
+/// size, in linear light so the hues stay the same.
+pub(crate) fn draw_vignette(
+    canvas: &mut image::RgbaImage,
+    (x, y): (u32, u32),
+    (width, height): (u32, u32),
+    vignette: &Vignette,
+) {
+    let strength = vignette.strength.clamp(0.0, 1.0);
+    if strength == 0.0 {
+        return;
+    }
+    let linear: Vec<f32> = (0..=255).map(color::to_linear).collect();
+    let (center_x, center_y) = (canvas.width() as f32 / 2.0, canvas.height() as f32 / 2.0);
+    let corner = center_x.hypot(center_y).max(f32::EPSILON);
+    for (px, py, pixel) in canvas.enumerate_pixels_mut() {
+        if (x..x + width).contains(&px) && (y..y + height).contains(&py) {
+            continue;
+        }
+        let distance = (px as f32 + 0.5 - center_x).hypot(py as f32 + 0.5 - center_y) / corner;
+        let light = 1.0 - strength * distance.min(1.0).powf(vignette.falloff);
+        for channel in pixel.0.iter_mut().take(3) {
+            *channel = color::from_linear(linear[*channel as usize] * light);
+        }
+    }
+}
+
+/// `backgroundAdjust`, in percent of the way to black or white and of the
+/// chroma of the sampled colors.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "camelCase", default, deny_unknown_fields)]
+pub struct BackgroundAdjust {
+    /// From -100 for black to 100 for white.
+    pub brightness: f32,
+    /// From -100 for gray, above 0 for more vivid colors.
+    pub saturation: f32,
+}
+
+/// Monochrome noise, the same for the three channels of a pixel so it
+/// shifts lightness and never hue.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "camelCase", default)]
+pub struct Grain {
+    /// Largest change of a pixel, in 8-bit levels.
+    pub amount: f32,
+    /// Different seeds give different noise, the same seed the same.
+    pub seed: u64,
+}
+
+impl Default for Grain {
+    fn default() -> Self {
+        Grain {
+            amount: 3.0,
+            seed: 0,
+        }
+    }
+}
+
+/// Adds `grain` to the canvas outside the image placed at `(x, y)` with
+/// the given size. The noise of each pixel only depends on the seed and
+/// where the pixel is, and is triangular so small changes are likelier.
+pub(crate) fn draw_grain(
+    canvas: &mut image::RgbaImage,
+    (x, y): (u32, u32),
+    (width, height): (u32, u32),
+    grain: &Grain,
+) {
+    if grain.amount <= 0.0 {
+        return;
+    }
+    for (px, py, pixel) in canvas.enumerate_pixels_mut() {
+        if (x..x + width).contains(&px) && (y..y + height).contains(&py) {
+            continue;
+        }
+        let mut rng = Rng(grain.seed ^ (u64::from(py) << 32 | u64::from(px)));
+        let noise = (rng.signed() + rng.signed()) / 2.0 * grain.amount;
+        for channel in pixel.0.iter_mut().take(3) {
+            *channel = (*channel as f32 + noise).round().clamp(0.0, 255.0) as u8;
+        }
+    }
+}
+
+/// A soft shadow cast by the placed image onto the background.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "camelCase", default)]
+pub struct Shadow {
+    /// Pixels to the right and down, negative to the left or up.
+    pub offset: (i32, i32),
+    /// Blur radius in pixels, 0 for a hard edge.
+    pub blur: u32,
+    pub color: Color,
+    /// From 0 to 1, multiplied with the alpha of `color`.
+    pub opacity: f32,
+}
+
+impl Default for Shadow {
+    fn default() -> Self {
+        Shadow {
+            offset: (6, 6),
+            blur: 12,
+            color: Color(image::Rgba([0, 0, 0, 255])),
+            opacity: 0.5,
+        }
+    }
+}
+
+/// Coverage along one axis of a shadow spanning `start..end`, blurred with a
+/// Gaussian of `radius`. Positions outside the canvas still count, so a
+/// shadow reaching over an edge fades out the same as anywhere else.
+fn shadow_profile(len: u32, start: i64, end: i64, radius: u32) -> Vec<f32> {
+    let radius = radius as i64;
+    // Running sum of the kernel from -radius up to each offset
+    let mut cumulative = Vec::with_capacity(2 * radius as usize + 1);
+    let sigma = (radius as f32 / 2.0).max(f32::EPSILON);
+    let mut sum = 0.0;
+    for k in -radius..=radius {
+        sum += (-((k * k) as f32) / (2.0 * sigma * sigma)).exp();
+        cumulative.push(sum);
+    }
+    let below = |k: i64| -> f32 {
+        if k < -radius {
+            0.0
+        } else if k >= radius {
+            1.0
+        } else {
+            cumulative[(k + radius) as usize] / sum
+        }
+    };
+    (0..len as i64)
+        .map(|x| below(x - start) - below(x - end))
+        .collect()
+}
+
+/// Draws `shadow` under the image placed at `(x, y)` with the given size
+/// and corners rounded by `radius`.
+pub(crate) fn draw_shadow(
+    canvas: &mut image::RgbaImage,
+    (x, y): (u32, u32),
+    (width, height): (u32, u32),
+    shadow: &Shadow,
+    radius: u32,
+) {
+    let left = x as i64 + shadow.offset.0 as i64;
+    let top = y as i64 + shadow.offset.1 as i64;
+    let columns = shadow_profile(canvas.width(), left, left + width as i64, shadow.blur);
+    let rows = shadow_profile(canvas.height(), top, top + height as i64, shadow.blur);
+    let alpha = shadow.color.0[3] as f32 * shadow.opacity.clamp(0.0, 1.0);
+    // Rounded corners cut into the shadow by as much as they could blur
+    let grown = (
+        (
+            left as f32 - shadow.blur as f32,
+            top as f32 - shadow.blur as f32,
+        ),
+        (
+            width as f32 + 2.0 * shadow.blur as f32,
+            height as f32 + 2.0 * shadow.blur as f32,
+        ),
+        (radius + shadow.blur) as f32,
+    );
+    for (py, row) in rows.iter().enumerate() {
+        if *row <= 0.0 {
+            continue;
+        }
+        for (px, column) in columns.iter().enumerate() {
+            let mut amount = row * column;
+            if radius > 0 {
+                amount *= coverage((px as u32, py as u32), grown.0, grown.1, grown.2);
+            }
+            if amount <= 0.0 {
+                continue;
+            }
+            let mut color = shadow.color.0;
+            color[3] = (alpha * amount).round() as u8;
+            canvas.get_pixel_mut(px as u32, py as u32).blend(&color);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::Oklab;
+
+    /// An sRGB color of Oklab `hue` in degrees, well inside the gamut.
+    fn of_hue(hue: f32) -> image::Rgba<u8> {
+        let (sin, cos) = hue.to_radians().sin_cos();
+        Oklab {
+            l: 0.65,
+            a: 0.1 * cos,
+            b: 0.1 * sin,
+        }
+        .to_rgb(255)
+    }
+
+    /// Oklab hue of `color` in degrees, from 0 to 360.
+    fn hue(color: image::Rgba<u8>) -> f32 {
+        let lab = Oklab::from_rgb(color);
+        lab.b.atan2(lab.a).to_degrees().rem_euclid(360.0)
+    }
+
+    /// Degrees between two hues, the short way round.
+    fn apart(a: f32, b: f32) -> f32 {
+        let difference = (a - b).rem_euclid(360.0);
+        difference.min(360.0 - difference)
+    }
+
+    #[test]
+    fn complement_turns_the_hue_half_way() {
+        let style = Style::default();
+        for &edge in &[0.0, 1.0, 90.0, 179.0, 181.0, 270.0, 359.0] {
+            let color = of_hue(edge);
+            let complement = style.complement(color, color);
+            let found = hue(complement);
+            assert!(
+                apart(found, hue(color) + 180.0) < 3.0,
+                "complement of hue {} has hue {}",
+                edge,
+                found
+            );
+        }
+    }
+
+    #[test]
+    fn complement_of_edges_across_zero_is_near_180() {
+        // Averaging 350 and 10 degrees as numbers would give 180, and a
+        // complement near 0
+        let style = Style::default();
+        for &(first, second) in &[(350.0, 10.0), (359.0, 1.0), (5.0, 355.0)] {
+            let complement = style.complement(of_hue(first), of_hue(second));
+            let found = hue(complement);
+            assert!(
+                apart(found, 180.0) < 5.0,
+                "complement of hues {} and {} has hue {}",
+                first,
+                second,
+                found
+            );
+        }
+    }
+
+    #[test]
+    fn complement_of_gray_is_the_neutral_for_its_lightness() {
+        let dark = image::Rgba([40, 40, 40, 255]);
+        let light = image::Rgba([220, 222, 220, 255]);
+        let style = Style::default();
+        assert_eq!(style.complement(dark, dark), DEFAULT_COMPLEMENT_LIGHT.0);
+        assert_eq!(style.complement(light, light), DEFAULT_COMPLEMENT_DARK.0);
+
+        let style = Style {
+            complement_light: Some(Color(image::Rgba([255, 250, 240, 255]))),
+            complement_dark: Some(Color(image::Rgba([10, 20, 30, 255]))),
+            ..Style::default()
+        };
+        assert_eq!(style.complement(dark, dark), [255, 250, 240, 255].into());
+        assert_eq!(style.complement(light, light), [10, 20, 30, 255].into());
+    }
+
+    #[test]
+    fn complement_of_opposite_hues_is_gray() {
+        // Opposite edges average out to no chroma, which has no complement
+        let style = Style::default();
+        let complement = style.complement(of_hue(40.0), of_hue(220.0));
+        assert!(
+            complement == DEFAULT_COMPLEMENT_LIGHT.0 || complement == DEFAULT_COMPLEMENT_DARK.0,
+            "found {:?}",
+            complement
+        );
+    }
+}
@@ -0,0 +1,301 @@
+//! Synthetic sources and assertions about extended outputs, for tests
+//! written against the crate, with the `test-util` feature.
+//!
+//! Sources are drawn in memory and `encode` turns them into the bytes
+//! `extend_bytes` takes, or a file for a job list; `decode` reads an output
+//! back. The assertions panic with what was expected and what was found, as
+//! `assert_eq!` does.
+//!
+//! Bands and seams are named by the side of the canvas they are measured
+//! from: the background added above a landscape image is the `Top` band,
+//! and the seam is the first line of the image counted from that side.
+
+use std::io::Cursor;
+
+use image::{ColorType, DynamicImage, GenericImageView, ImageFormat, Rgba, RgbaImage};
+
+/// A side of an image.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Side {
+    Top,
+    Bottom,
+    Left,
+    Right,
+}
+
+/// `from` on the left blending into `to` on the right.
+pub fn horizontal_gradient(width: u32, height: u32, from: Rgba<u8>, to: Rgba<u8>) -> RgbaImage {
+    RgbaImage::from_fn(width, height, |x, _| blend(from, to, x, width))
+}
+
+/// `from` at the top blending into `to` at the bottom.
+pub fn vertical_gradient(width: u32, height: u32, from: Rgba<u8>, to: Rgba<u8>) -> RgbaImage {
+    RgbaImage::from_fn(width, height, |_, y| blend(from, to, y, height))
+}
+
+/// Squares of `cell` pixels, alternately `even` and `odd`, `even` in the
+/// top left corner.
+pub fn checkerboard(
+    width: u32,
+    height: u32,
+    cell: u32,
+    even: Rgba<u8>,
+    odd: Rgba<u8>,
+) -> RgbaImage {
+    let cell = cell.max(1);
+    RgbaImage::from_fn(width, height, |x, y| match (x / cell + y / cell) % 2 {
+        0 => even,
+        _ => odd,
+    })
+}
+
+/// The color `at` of `length` steps from `from` to `to`.
+fn blend(from: Rgba<u8>, to: Rgba<u8>, at: u32, length: u32) -> Rgba<u8> {
+    let t = match length {
+        0 | 1 => 0.0,
+        _ => at as f32 / (length - 1) as f32,
+    };
+    let mut color = from;
+    for (channel, (&from, &to)) in color.0.iter_mut().zip(from.0.iter().zip(&to.0)) {
+        *channel = (from as f32 + (to as f32 - from as f32) * t).round() as u8;
+    }
+    color
+}
+
+/// `img` encoded as `format`.
+///
+/// Panics when the format is not compiled in or cannot hold the image.
+pub fn encode(img: &DynamicImage, format: ImageFormat) -> Vec<u8> {
+    // 16-bit PNGs through the encoder of outputs, as `write_to` gets their
+    // byte order wrong
+    #[cfg(feature = "png")]
+    if format == ImageFormat::Png && crate::depth::is_deep(img.color()) {
+        return crate::format::encode(img, format, Default::default())
+            .unwrap_or_else(|e| panic!("cannot encode the image as {:?}: {}", format, e));
+    }
+    let mut data = Cursor::new(Vec::new());
+    if let Err(e) = img.write_to(&mut data, format) {
+        panic!("cannot encode the image as {:?}: {}", format, e);
+    }
+    data.into_inner()
+}
+
+/// Decodes an output.
+///
+/// Panics when it does not decode.
+pub fn decode(data: &[u8]) -> DynamicImage {
+    match image::load_from_memory(data) {
+        Ok(img) => img,
+        Err(e) => panic!("output does not decode: {}", e),
+    }
+}
+
+/// Asserts that `img` is `width` by `height`.
+#[track_caller]
+pub fn assert_dimensions(img: &DynamicImage, (width, height): (u32, u32)) {
+    let (found_width, found_height) = img.dimensions();
+    assert!(
+        (found_width, found_height) == (width, height),
+        "expected a {}x{} image, found {}x{}",
+        width,
+        height,
+        found_width,
+        found_height
+    );
+}
+
+/// Asserts that `img` decoded as `color`, as 16-bit sources do in PNG and
+/// TIFF outputs.
+#[track_caller]
+pub fn assert_color_type(img: &DynamicImage, color: ColorType) {
+    assert!(
+        img.color() == color,
+        "expected a {:?} image, found {:?}",
+        color,
+        img.color()
+    );
+}
+
+/// Pixels of the line `at` lines in from `side`.
+fn line(img: &RgbaImage, side: Side, at: u32) -> Vec<Rgba<u8>> {
+    let (width, height) = img.dimensions();
+    match side {
+        Side::Top => (0..width).map(|x| *img.get_pixel(x, at)).collect(),
+        Side::Bottom => (0..width)
+            .map(|x| *img.get_pixel(x, height - 1 - at))
+            .collect(),
+        Side::Left => (0..height).map(|y| *img.get_pixel(at, y)).collect(),
+        Side::Right => (0..height)
+            .map(|y| *img.get_pixel(width - 1 - at, y))
+            .collect(),
+    }
+}
+
+/// How many lines there are from `side` to the opposite side.
+fn depth(img: &RgbaImage, side: Side) -> u32 {
+    match side {
+        Side::Top | Side::Bottom => img.height(),
+        Side::Left | Side::Right => img.width(),
+    }
+}
+
+/// The largest difference between two channels of `a` and `b`.
+fn distance(a: Rgba<u8>, b: Rgba<u8>) -> u8 {
+    a.0.iter()
+        .zip(&b.0)
+        .map(|(&a, &b)| a.abs_diff(b))
+        .max()
+        .unwrap_or(0)
+}
+
+/// Mean over every channel of the difference between two lines.
+fn step(a: &[Rgba<u8>], b: &[Rgba<u8>]) -> f32 {
+    let total: u32 = a
+        .iter()
+        .zip(b)
+        .flat_map(|(a, b)| a.0.iter().zip(&b.0).map(|(&a, &b)| a.abs_diff(b) as u32))
+        .sum();
+    total as f32 / (a.len() * 4).max(1) as f32
+}
+
+/// Asserts that every pixel of the `lines` deep band along `side` is within
+/// `tolerance` of `color` in each channel.
+#[track_caller]
+pub fn assert_band_color(img: &RgbaImage, side: Side, lines: u32, color: Rgba<u8>, tolerance: u8) {
+    let available = depth(img, side);
+    assert!(
+        lines <= available,
+        "a band of {} lines does not fit in the {} lines from {:?}",
+        lines,
+        available,
+        side
+    );
+    for at in 0..lines {
+        if let Some((index, pixel)) = line(img, side, at)
+            .into_iter()
+            .enumerate()
+            .find(|&(_, pixel)| distance(pixel, color) > tolerance)
+        {
+            panic!(
+                "expected {:?} within {} in the {:?} band, found {:?} at pixel {} of line {}",
+                color.0, tolerance, side, pixel.0, index, at
+            );
+        }
+    }
+}
+
+/// Asserts that the first line of the image is `at` lines in from `side`:
+/// the line before it and that one differ by more than `tolerance` on
+/// average, and no two lines of the band before them do.
+#[track_caller]
+pub fn assert_seam(img: &RgbaImage, side: Side, at: u32, tolerance: u8) {
+    let available = depth(img, side);
+    assert!(
+        at > 0 && at < available,
+        "a seam {} lines in from {:?} is outside the {} lines there are",
+        at,
+        side,
+        available
+    );
+    let steps: Vec<f32> = (1..=at)
+        .map(|line_at| step(&line(img, side, line_at - 1), &line(img, side, line_at)))
+        .collect();
+    if let Some(early) = steps[..steps.len() - 1]
+        .iter()
+        .position(|&step| step > tolerance as f32)
+    {
+        panic!(
+            "expected the seam {} lines in from {:?}, found one at {} already",
+            at,
+            side,
+            early + 1
+        );
+    }
+    let last = steps[steps.len() - 1];
+    assert!(
+        last > tolerance as f32,
+        "expected a seam {} lines in from {:?}, but the lines there differ by {:.1}, within {}",
+        at,
+        side,
+        last,
+        tolerance
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RED: Rgba<u8> = Rgba([255, 0, 0, 255]);
+    const BLUE: Rgba<u8> = Rgba([0, 0, 255, 255]);
+
+    /// 10 lines of red above 10 of blue, 4 wide.
+    fn bands() -> RgbaImage {
+        RgbaImage::from_fn(4, 20, |_, y| if y < 10 { RED } else { BLUE })
+    }
+
+    #[test]
+    fn gradients_run_from_one_color_to_the_other() {
+        let across = horizontal_gradient(5, 2, RED, BLUE);
+        assert_eq!(*across.get_pixel(0, 1), RED);
+        assert_eq!(*across.get_pixel(2, 0), Rgba([128, 0, 128, 255]));
+        assert_eq!(*across.get_pixel(4, 1), BLUE);
+        let down = vertical_gradient(2, 5, RED, BLUE);
+        assert_eq!(*down.get_pixel(1, 0), RED);
+        assert_eq!(*down.get_pixel(0, 4), BLUE);
+        // A single line is all the first color
+        assert_eq!(*horizontal_gradient(1, 1, RED, BLUE).get_pixel(0, 0), RED);
+    }
+
+    #[test]
+    fn checkerboard_starts_with_even() {
+        let board = checkerboard(4, 4, 2, RED, BLUE);
+        assert_eq!(*board.get_pixel(1, 1), RED);
+        assert_eq!(*board.get_pixel(2, 1), BLUE);
+        assert_eq!(*board.get_pixel(1, 2), BLUE);
+        assert_eq!(*board.get_pixel(3, 3), RED);
+    }
+
+    #[test]
+    fn encode_and_decode_round_trip() {
+        let img = DynamicImage::ImageRgba8(bands());
+        let decoded = decode(&encode(&img, ImageFormat::Png));
+        assert_dimensions(&decoded, (4, 20));
+        assert_color_type(&decoded, ColorType::Rgba8);
+        assert_eq!(decoded.to_rgba8(), bands());
+    }
+
+    #[test]
+    fn bands_and_seams_are_found() {
+        let img = bands();
+        assert_band_color(&img, Side::Top, 10, RED, 0);
+        assert_band_color(&img, Side::Bottom, 10, BLUE, 0);
+        assert_band_color(&img, Side::Left, 4, Rgba([128, 0, 128, 255]), 128);
+        assert_seam(&img, Side::Top, 10, 10);
+        assert_seam(&img, Side::Bottom, 10, 10);
+    }
+
+    #[test]
+    #[should_panic(expected = "expected a 4x21 image, found 4x20")]
+    fn wrong_dimensions_panic() {
+        assert_dimensions(&DynamicImage::ImageRgba8(bands()), (4, 21));
+    }
+
+    #[test]
+    #[should_panic(expected = "found [0, 0, 255, 255] at pixel 0 of line 10")]
+    fn a_band_too_deep_panics() {
+        assert_band_color(&bands(), Side::Top, 11, RED, 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "found one at 10 already")]
+    fn a_seam_further_in_panics() {
+        assert_seam(&bands(), Side::Top, 12, 10);
+    }
+
+    #[test]
+    #[should_panic(expected = "but the lines there differ by 0.0")]
+    fn a_seam_that_is_not_there_panics() {
+        assert_seam(&bands(), Side::Top, 5, 10);
+    }
+}
@@ -0,0 +1,291 @@
+//! Per-entry timeouts, and the cancelling of entries nobody waits for.
+//!
+//! An entry with a timeout runs on a worker thread. The caller stops waiting
+//! at the deadline, removes the temporary output the worker started and
+//! reports the entry as timed out, while the worker gives up at its next
+//! checkpoint. Every commit is a checkpoint as well. A worker run
+//! `cancellable` gives up at its next checkpoint once its flag is set, its
+//! temporary output going as it is dropped.
+//!
+//! An abandoned worker may never reach another checkpoint, as one stuck
+//! reading a hung network share. The stage permits it holds are given back
+//! at the deadline, so the rest of a pipeline carries on without it. At
+//! most `MAX_ABANDONED` of them are left running, each perhaps holding a
+//! decoded source: past that an entry with a timeout waits for one of them
+//! to end before it starts, within its own deadline.
+
+use std::cell::{Cell, RefCell};
+use std::fs;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::error::{Context, ExtendError, Stage, TimedOut};
+use crate::stages::{self, Holder};
+
+/// Workers abandoned at their deadline that may still be running, at most.
+pub(crate) const MAX_ABANDONED: usize = 16;
+
+/// Workers abandoned and still running, with `ENDED` told as one ends.
+static ABANDONED: Mutex<usize> = Mutex::new(0);
+static ENDED: Condvar = Condvar::new();
+
+struct Watch {
+    deadline: Instant,
+    limit: Duration,
+    /// Stage the worker is in and the path it concerns.
+    current: Mutex<(Stage, String)>,
+    temp_files: Mutex<Vec<String>>,
+    holder: Arc<Holder>,
+    /// Both only change with `ABANDONED` locked.
+    finished: AtomicBool,
+    abandoned: AtomicBool,
+}
+
+/// Marks the worker of `Watch` finished as it goes, whichever way it does.
+struct Finished(Arc<Watch>);
+
+impl Drop for Finished {
+    fn drop(&mut self) {
+        let mut abandoned = lock(&ABANDONED);
+        self.0.finished.store(true, Ordering::SeqCst);
+        if self.0.abandoned.load(Ordering::SeqCst) {
+            *abandoned -= 1;
+            ENDED.notify_one();
+        }
+    }
+}
+
+impl Watch {
+    fn timed_out(&self) -> ExtendError {
+        let current = lock(&self.current);
+        ExtendError::new(current.0, Some(&current.1), TimedOut { limit: self.limit })
+    }
+}
+
+thread_local! {
+    static WATCH: RefCell<Option<Arc<Watch>>> = const { RefCell::new(None) };
+    /// Stage of the last checkpoint on this thread, with or without a timeout.
+    static STAGE: Cell<Stage> = const { Cell::new(Stage::Read) };
+    /// Set once the result of the entry on this thread is no longer wanted.
+    static CANCELLED: RefCell<Option<Arc<AtomicBool>>> = const { RefCell::new(None) };
+}
+
+/// Runs `process` on this thread, its checkpoints failing once `cancelled`
+/// is set.
+#[cfg(feature = "tokio")]
+pub(crate) fn cancellable<T>(cancelled: Arc<AtomicBool>, process: impl FnOnce() -> T) -> T {
+    let before = CANCELLED.with(|current| current.replace(Some(cancelled)));
+    let result = process();
+    CANCELLED.with(|current| *current.borrow_mut() = before);
+    result
+}
+
+fn cancelled() -> Option<Arc<AtomicBool>> {
+    CANCELLED.with(|current| current.borrow().clone())
+}
+
+/// Records that the worker moved on to `stage` of `path`, and fails once the
+/// deadline passed or the entry was cancelled. Outside of `run` and
+/// `cancellable` only the stage is recorded.
+pub(crate) fn checkpoint(stage: Stage, path: &str) -> Result<(), ExtendError> {
+    STAGE.with(|current| current.set(stage));
+    if cancelled().is_some_and(|cancelled| cancelled.load(Ordering::Relaxed)) {
+        return Err(ExtendError::new(stage, Some(path), "cancelled"));
+    }
+    WATCH.with(|watch| match &*watch.borrow() {
+        Some(watch) => {
+            *lock(&watch.current) = (stage, path.to_string());
+            if Instant::now() >= watch.deadline {
+                Err(watch.timed_out())
+            } else {
+                Ok(())
+            }
+        }
+        None => Ok(()),
+    })
+}
+
+/// Stage this thread last reached a checkpoint of, set back to reading by
+/// `reset_stage` before each entry.
+pub(crate) fn stage() -> Stage {
+    STAGE.with(Cell::get)
+}
+
+pub(crate) fn reset_stage() {
+    STAGE.with(|current| current.set(Stage::Read));
+}
+
+/// Registers a temporary file for removal once the worker is abandoned.
+pub(crate) fn track(temp: &str) {
+    WATCH.with(|watch| {
+        if let Some(watch) = &*watch.borrow() {
+            lock(&watch.temp_files).push(temp.to_string());
+        }
+    })
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Runs `process` for the entry reading `src`, giving up on it after `limit`.
+///
+/// A worker that finished its last write right at the deadline may still be
+/// reported as timed out, but never leaves partial output behind.
+pub(crate) fn run<T: Send + 'static>(
+    limit: Duration,
+    src: &str,
+    process: impl FnOnce() -> Result<T, ExtendError> + Send + 'static,
+) -> Result<T, ExtendError> {
+    let watch = Arc::new(Watch {
+        deadline: Instant::now() + limit,
+        limit,
+        current: Mutex::new((Stage::Read, src.to_string())),
+        temp_files: Mutex::new(Vec::new()),
+        holder: Arc::new(Holder::default()),
+        finished: AtomicBool::new(false),
+        abandoned: AtomicBool::new(false),
+    });
+    let mut abandoned = lock(&ABANDONED);
+    while *abandoned >= MAX_ABANDONED {
+        let left = watch.deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            return Err(watch.timed_out());
+        }
+        abandoned = ENDED
+            .wait_timeout(abandoned, left)
+            .unwrap_or_else(|e| e.into_inner())
+            .0;
+    }
+    drop(abandoned);
+
+    let (sender, receiver) = mpsc::channel();
+    let worker = Arc::clone(&watch);
+    let gates = stages::current();
+    let cancelled = cancelled();
+    thread::Builder::new()
+        .name("image-bg-extender-entry".into())
+        .spawn(move || {
+            let finished = Finished(Arc::clone(&worker));
+            stages::enter_held(gates, Arc::clone(&worker.holder));
+            WATCH.with(|watch| *watch.borrow_mut() = Some(worker));
+            CANCELLED.with(|current| *current.borrow_mut() = cancelled);
+            // Nobody listens anymore once the caller gave up
+            let _ = sender.send(process());
+            drop(finished);
+        })
+        .context(Stage::Read, src)?;
+
+    let left = watch.deadline.saturating_duration_since(Instant::now());
+    match receiver.recv_timeout(left) {
+        Ok(result) => result,
+        Err(RecvTimeoutError::Timeout) => {
+            {
+                let mut abandoned = lock(&ABANDONED);
+                if !watch.finished.load(Ordering::SeqCst) {
+                    watch.abandoned.store(true, Ordering::SeqCst);
+                    *abandoned += 1;
+                }
+            }
+            watch.holder.abandon();
+            // Committed ones were renamed away already
+            for temp in lock(&watch.temp_files).iter() {
+                let _ = fs::remove_file(temp);
+            }
+            Err(watch.timed_out())
+        }
+        Err(RecvTimeoutError::Disconnected) => {
+            let current = lock(&watch.current);
+            Err(ExtendError::new(
+                current.0,
+                Some(&current.1),
+                "processing panicked",
+            ))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::ErrorKind;
+    use crate::stages::{Gates, StageLimits};
+
+    #[test]
+    fn finishes_within_the_limit() {
+        let result = run(Duration::from_secs(5), "a.png", || Ok(7));
+        assert_eq!(result.unwrap(), 7);
+    }
+
+    #[test]
+    fn gives_up_at_the_deadline() {
+        let started = Instant::now();
+        let result = run(Duration::from_millis(50), "slow.png", || {
+            thread::sleep(Duration::from_millis(500));
+            Ok(())
+        });
+        assert!(started.elapsed() < Duration::from_millis(400));
+        let e = result.unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::Timeout);
+        assert_eq!(e.path(), Some("slow.png"));
+    }
+
+    #[test]
+    fn gives_back_the_permits_of_an_abandoned_worker() {
+        let gates = Gates::new(StageLimits::uniform(1));
+        stages::enter(Some(Arc::clone(&gates)));
+        let (hang, hung) = mpsc::channel::<()>();
+        let result = run(Duration::from_millis(50), "hung.png", move || {
+            let _permit = stages::acquire(Stage::Read);
+            // Until the test ends
+            let _ = hung.recv();
+            Ok(())
+        });
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::Timeout);
+
+        // Another worker of the same pipeline gets the only read permit
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            stages::enter(Some(gates));
+            let permit = stages::acquire(Stage::Read);
+            let _ = sender.send(permit.is_some());
+        });
+        assert_eq!(receiver.recv_timeout(Duration::from_secs(5)), Ok(true));
+        stages::enter(None);
+        drop(hang);
+    }
+
+    #[test]
+    fn bounds_the_abandoned_workers() {
+        let (hang, hung) = mpsc::channel::<()>();
+        let hung = Arc::new(Mutex::new(hung));
+        for _ in 0..MAX_ABANDONED {
+            let hung = Arc::clone(&hung);
+            let result = run(Duration::from_millis(10), "hung.png", move || {
+                let _ = lock(&hung).recv();
+                Ok(())
+            });
+            assert_eq!(result.unwrap_err().kind(), ErrorKind::Timeout);
+        }
+        // No room left, so this one times out without starting
+        let started = AtomicBool::new(false);
+        let started = Arc::new(started);
+        let result = run(Duration::from_millis(100), "next.png", {
+            let started = Arc::clone(&started);
+            move || {
+                started.store(true, Ordering::SeqCst);
+                Ok(())
+            }
+        });
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::Timeout);
+        assert!(!started.load(Ordering::SeqCst));
+
+        // The hung workers end, and make room again
+        drop(hang);
+        let result = run(Duration::from_secs(5), "next.png", || Ok(()));
+        assert!(result.is_ok());
+    }
+}
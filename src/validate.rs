@@ -0,0 +1,627 @@
+//! Checks of an entry that need no image work, shared by processing and
+//! `--check`.
+
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use crate::error::Stage;
+use crate::format;
+use crate::{
+    archive, atomic, fill, storage, style, AspectRatio, ImageInfo, Mode, Options, Overwrite,
+    MODE_PLACEHOLDER,
+};
+
+/// A field of an entry, or one of its paths, that cannot work.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Invalid {
+    /// Stage that would fail on it.
+    pub stage: Stage,
+    /// Field of the entry in the job list.
+    pub field: &'static str,
+    pub message: String,
+}
+
+impl Invalid {
+    pub(crate) fn new(stage: Stage, field: &'static str, message: impl Into<String>) -> Self {
+        Invalid {
+            stage,
+            field,
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for Invalid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.field, self.message)
+    }
+}
+
+impl Error for Invalid {}
+
+/// Checks the settings of an entry, without touching the file system.
+pub fn fields(dest: &str, aspect_ratio: AspectRatio, options: &Options) -> Vec<Invalid> {
+    let mut problems = Vec::new();
+    if dest.is_empty() {
+        problems.push(Invalid::new(Stage::Write, "destination", "is missing"));
+    }
+    problems.extend(settings(aspect_ratio, options));
+    problems.extend(output_format(dest, options));
+    problems
+}
+
+/// `fields` but for the destination, for an image extended in memory.
+pub fn settings(aspect_ratio: AspectRatio, options: &Options) -> Vec<Invalid> {
+    let mut problems = Vec::new();
+    if let AspectRatio::Fixed((0, _)) | AspectRatio::Fixed((_, 0)) = aspect_ratio {
+        problems.push(Invalid::new(
+            Stage::Composite,
+            "aspectRatio",
+            "both sides must be nonzero",
+        ));
+    }
+    if let Some((0, _)) | Some((_, 0)) = options.target_size {
+        problems.push(Invalid::new(
+            Stage::Composite,
+            "targetSize",
+            "both sides must be nonzero",
+        ));
+    }
+    for (field, max) in [
+        ("maxWidth", options.max_width),
+        ("maxHeight", options.max_height),
+    ] {
+        if max == Some(0) {
+            problems.push(Invalid::new(Stage::Composite, field, "must be nonzero"));
+        }
+    }
+    match &options.allowed_ratios {
+        Some(ratios) if ratios.is_empty() => problems.push(Invalid::new(
+            Stage::Composite,
+            "allowedRatios",
+            "must not be empty",
+        )),
+        Some(ratios)
+            if ratios
+                .iter()
+                .any(|&(width, height)| width == 0 || height == 0) =>
+        {
+            problems.push(Invalid::new(
+                Stage::Composite,
+                "allowedRatios",
+                "both sides of every ratio must be nonzero",
+            ))
+        }
+        _ => {}
+    }
+    if options
+        .tolerance
+        .is_some_and(|tolerance| !(0.0..1.0).contains(&tolerance))
+    {
+        problems.push(Invalid::new(
+            Stage::Composite,
+            "tolerance",
+            "must be at least 0 and below 1",
+        ));
+    }
+    if options
+        .max_zoom
+        .is_some_and(|zoom| !(0.0..1.0).contains(&zoom))
+    {
+        problems.push(Invalid::new(
+            Stage::Composite,
+            "maxZoom",
+            "must be at least 0 and below 1",
+        ));
+    }
+    if options
+        .crop_threshold
+        .is_some_and(|threshold| !(0.0..=1.0).contains(&threshold))
+    {
+        problems.push(Invalid::new(
+            Stage::Composite,
+            "cropThreshold",
+            "must be between 0 and 1",
+        ));
+    }
+    if options.quality.is_some_and(|quality| quality > 100) {
+        problems.push(Invalid::new(
+            Stage::Write,
+            "quality",
+            "must be between 0 and 100",
+        ));
+    }
+    if options.avif_speed.is_some_and(|speed| speed > 10) {
+        problems.push(Invalid::new(
+            Stage::Write,
+            "avifSpeed",
+            "must be between 0 and 10",
+        ));
+    }
+    if options.jxl_effort.is_some_and(|effort| effort > 127) {
+        problems.push(Invalid::new(
+            Stage::Write,
+            "jxlEffort",
+            "must be between 0 and 127",
+        ));
+    }
+    if options.max_output_pixels == Some(0) {
+        problems.push(Invalid::new(
+            Stage::Composite,
+            "maxOutputPixels",
+            "must be nonzero",
+        ));
+    }
+    if options.max_source_pixels == Some(0) {
+        problems.push(Invalid::new(
+            Stage::Decode,
+            "maxSourcePixels",
+            "must be nonzero",
+        ));
+    }
+    if options.timeout == Some(0) {
+        problems.push(Invalid::new(
+            Stage::Read,
+            "timeout",
+            "must be at least one second",
+        ));
+    }
+    if options
+        .rotate
+        .is_some_and(|angle| ![0, 90, 180, 270].contains(&angle))
+    {
+        problems.push(Invalid::new(
+            Stage::Decode,
+            "rotate",
+            "must be 90, 180 or 270 degrees",
+        ));
+    }
+    if let Some(shadow) = &options.style.shadow {
+        if !(0.0..=1.0).contains(&shadow.opacity) {
+            problems.push(Invalid::new(
+                Stage::Composite,
+                "shadow",
+                "opacity must be between 0 and 1",
+            ));
+        }
+    }
+    if let Some(watermark) = &options.style.watermark {
+        if !(0.0..=1.0).contains(&watermark.opacity) {
+            problems.push(Invalid::new(
+                Stage::Composite,
+                "watermark",
+                "opacity must be between 0 and 1",
+            ));
+        }
+        if watermark
+            .scale
+            .is_some_and(|scale| !scale.is_finite() || scale <= 0.0)
+        {
+            problems.push(Invalid::new(
+                Stage::Composite,
+                "watermark",
+                "scale must be above 0",
+            ));
+        }
+    }
+    if options.preview_width == Some(0) {
+        problems.push(Invalid::new(
+            Stage::Write,
+            "previewWidth",
+            "must be at least 1",
+        ));
+    }
+    if options.preview_width.is_some() && !cfg!(feature = "jpeg") {
+        problems.push(Invalid::new(
+            Stage::Write,
+            "previewWidth",
+            format::FormatNotCompiled {
+                format: "JPEG",
+                feature: "jpeg",
+            }
+            .to_string(),
+        ));
+    }
+    if options.style.caption.is_some() && !cfg!(feature = "text") {
+        problems.push(Invalid::new(
+            Stage::Composite,
+            "caption",
+            format::FormatNotCompiled {
+                format: "Caption",
+                feature: "text",
+            }
+            .to_string(),
+        ));
+    }
+    if let Some(vignette) = &options.style.vignette {
+        if !(0.0..=1.0).contains(&vignette.strength) {
+            problems.push(Invalid::new(
+                Stage::Composite,
+                "vignette",
+                "strength must be between 0 and 1",
+            ));
+        }
+        if !vignette.falloff.is_finite() || vignette.falloff <= 0.0 {
+            problems.push(Invalid::new(
+                Stage::Composite,
+                "vignette",
+                "falloff must be above 0",
+            ));
+        }
+    }
+    if options
+        .style
+        .grain
+        .is_some_and(|grain| !grain.amount.is_finite() || grain.amount < 0.0)
+    {
+        problems.push(Invalid::new(
+            Stage::Composite,
+            "grain",
+            "amount must not be negative",
+        ));
+    }
+    if options
+        .style
+        .background_lightness
+        .is_some_and(|lightness| !(-1.0..=1.0).contains(&lightness))
+    {
+        problems.push(Invalid::new(
+            Stage::Composite,
+            "backgroundLightness",
+            "must be between -1 and 1",
+        ));
+    }
+    if options
+        .style
+        .background_saturation
+        .is_some_and(|saturation| !saturation.is_finite() || saturation < 0.0)
+    {
+        problems.push(Invalid::new(
+            Stage::Composite,
+            "backgroundSaturation",
+            "must not be negative",
+        ));
+    }
+    if let Some(adjust) = options.style.background_adjust {
+        if !(-100.0..=100.0).contains(&adjust.brightness) {
+            problems.push(Invalid::new(
+                Stage::Composite,
+                "backgroundAdjust",
+                "brightness must be between -100 and 100",
+            ));
+        }
+        if !adjust.saturation.is_finite() || adjust.saturation < -100.0 {
+            problems.push(Invalid::new(
+                Stage::Composite,
+                "backgroundAdjust",
+                "saturation must not be below -100",
+            ));
+        }
+    }
+    if options
+        .style
+        .min_contrast
+        .is_some_and(|contrast| !(0.0..=1.0).contains(&contrast))
+    {
+        problems.push(Invalid::new(
+            Stage::Composite,
+            "minContrast",
+            "must be between 0 and 1",
+        ));
+    }
+    if options
+        .style
+        .blur_sigma
+        .is_some_and(|sigma| !sigma.is_finite() || sigma <= 0.0)
+    {
+        problems.push(Invalid::new(
+            Stage::Composite,
+            "blurSigma",
+            "must be above 0",
+        ));
+    }
+    if options
+        .style
+        .blur_downscale
+        .is_some_and(|downscale| !downscale.is_finite() || downscale < 1.0)
+    {
+        problems.push(Invalid::new(
+            Stage::Composite,
+            "blurDownscale",
+            "must be at least 1",
+        ));
+    }
+    if options
+        .style
+        .uniform_weight
+        .is_some_and(|weight| !(0.0..=1.0).contains(&weight))
+    {
+        problems.push(Invalid::new(
+            Stage::Composite,
+            "uniformWeight",
+            "must be between 0 and 1",
+        ));
+    }
+    if let Some(name) = &options.style.fill {
+        if fill::registered(name).is_none() {
+            problems.push(Invalid::new(
+                Stage::Composite,
+                "fill",
+                format!("no fill is registered as {}", name),
+            ));
+        }
+    }
+    if options
+        .style
+        .palette_size
+        .is_some_and(|size| !style::PALETTE_SIZES.contains(&size))
+    {
+        problems.push(Invalid::new(
+            Stage::Composite,
+            "paletteSize",
+            format!(
+                "must be between {} and {}",
+                style::PALETTE_SIZES.start(),
+                style::PALETTE_SIZES.end()
+            ),
+        ));
+    }
+    if options
+        .style
+        .gradient_angle
+        .is_some_and(|angle| !angle.is_finite())
+    {
+        problems.push(Invalid::new(
+            Stage::Composite,
+            "gradientAngle",
+            "must be a number of degrees",
+        ));
+    }
+    if options
+        .style
+        .inset_scale
+        .is_some_and(|scale| !(scale > 0.0 && scale <= 1.0))
+    {
+        problems.push(Invalid::new(
+            Stage::Composite,
+            "insetScale",
+            "must be above 0 and at most 1",
+        ));
+    }
+    problems
+}
+
+/// Checks that the format `dest` is written in has an encoder in this build,
+/// suggesting the nearest ones that have when it has not.
+///
+/// Destinations without an extension are left alone, as an unchanged image
+/// is copied through without encoding it.
+fn output_format(dest: &str, options: &Options) -> Option<Invalid> {
+    let alternatives = |extension: &str| {
+        let nearest = format::nearest_extensions(extension);
+        if nearest.is_empty() {
+            return String::new();
+        }
+        let nearest: Vec<String> = nearest.iter().map(|ext| format!(".{}", ext)).collect();
+        format!(", nearest supported: {}", nearest.join(", "))
+    };
+    let (encoder, field, extension) = match options.output_format {
+        Some(format) => (
+            format.encoder(),
+            "outputFormat",
+            format.encoder().extensions[0],
+        ),
+        None => {
+            let extension = Path::new(storage::name(dest)).extension()?.to_str()?;
+            match format::encoder_for_extension(extension) {
+                Some(encoder) => (encoder, "destination", extension),
+                None => {
+                    let message = match format::read_only(extension) {
+                        Some(name) => format!("{} images can be read but not written", name),
+                        None => format!("no format is written for .{} files", extension),
+                    };
+                    return Some(Invalid::new(
+                        Stage::Write,
+                        "destination",
+                        message + &alternatives(extension),
+                    ));
+                }
+            }
+        }
+    };
+    if encoder.compiled {
+        return None;
+    }
+    Some(Invalid::new(
+        Stage::Write,
+        field,
+        encoder.not_compiled().to_string() + &alternatives(extension),
+    ))
+}
+
+/// Checks that every variant of `info` has a destination of its own.
+pub fn variants(info: &ImageInfo) -> Vec<Invalid> {
+    let mut problems = Vec::new();
+    let template = info.destination();
+    let mut seen: Vec<String> = Vec::new();
+    let mode = match info.options.mode {
+        Mode::Extend => None,
+        Mode::Crop => Some("crop"),
+        Mode::SeamCarve => Some("seamCarve"),
+        Mode::ExtendOrCrop => Some("extendOrCrop"),
+        Mode::ZoomToFill => None,
+    };
+    if let (Some(mode), false) = (mode, info.variants.is_empty()) {
+        problems.push(Invalid::new(
+            Stage::Composite,
+            "variants",
+            format!("{} mode draws no background to vary", mode),
+        ));
+    }
+    if info.options.preview_width.is_some() && !info.variants.is_empty() {
+        problems.push(Invalid::new(
+            Stage::Write,
+            "previewWidth",
+            "is not written for variants",
+        ));
+    }
+    for (index, variant) in info.variants().iter().enumerate() {
+        if variant.destination.is_none() && !template.contains(MODE_PLACEHOLDER) {
+            problems.push(Invalid::new(
+                Stage::Write,
+                "variants",
+                format!(
+                    "variant {} needs a destination, or the entry one with {}",
+                    index, MODE_PLACEHOLDER
+                ),
+            ));
+            continue;
+        }
+        let dest = variant.destination(template);
+        match seen.iter().position(|earlier| *earlier == dest) {
+            Some(earlier) => problems.push(Invalid::new(
+                Stage::Write,
+                "variants",
+                format!("variants {} and {} both write {}", earlier, index, dest),
+            )),
+            None if variant.destination.is_some() => {
+                problems.extend(output_format(&dest, info.options()))
+            }
+            None => {}
+        }
+        seen.push(dest);
+    }
+    problems
+}
+
+/// Checks the settings of every output of `info`, and that each has a
+/// destination of its own.
+pub fn outputs(info: &ImageInfo) -> Vec<Invalid> {
+    let mut problems = Vec::new();
+    if !info.variants.is_empty() {
+        problems.push(Invalid::new(
+            Stage::Composite,
+            "outputs",
+            "cannot be set along with variants",
+        ));
+    }
+    let mut seen: Vec<&str> = Vec::new();
+    for (index, output) in info.outputs.iter().enumerate() {
+        problems.extend(
+            fields(&output.destination, output.aspect_ratio, &output.options)
+                .into_iter()
+                .map(|problem| {
+                    Invalid::new(
+                        problem.stage,
+                        "outputs",
+                        format!("output {}: {}", index, problem),
+                    )
+                }),
+        );
+        let dest = output.destination.as_str();
+        if dest.is_empty() {
+            continue;
+        }
+        if let Some(earlier) = seen.iter().position(|earlier| *earlier == dest) {
+            problems.push(Invalid::new(
+                Stage::Write,
+                "outputs",
+                format!("outputs {} and {} both write {}", earlier, index, dest),
+            ));
+        }
+        seen.push(dest);
+    }
+    problems
+}
+
+/// Checks that the source of `info` can be read and its destination
+/// written, without reading or writing either.
+pub fn paths(info: &ImageInfo) -> Vec<Invalid> {
+    let mut problems = Vec::new();
+    if archive::split(&info.source).is_some() {
+        if let Err(e) = archive::check(&info.source) {
+            problems.push(Invalid::new(Stage::Read, "source", e.to_string()));
+        }
+    } else if let Err(e) = storage::source(&info.source).check(&info.source) {
+        problems.push(Invalid::new(Stage::Read, "source", e.to_string()));
+    }
+
+    if let Some(watermark) = &info.options.style.watermark {
+        if let Err(e) = fs::File::open(&watermark.path) {
+            problems.push(Invalid::new(
+                Stage::Composite,
+                "watermark",
+                format!("{}: {}", watermark.path, e),
+            ));
+        }
+    }
+
+    if let Some(caption) = &info.options.style.caption {
+        if let Err(e) = fs::File::open(&caption.font) {
+            problems.push(Invalid::new(
+                Stage::Composite,
+                "caption",
+                format!("{}: {}", caption.font, e),
+            ));
+        }
+    }
+
+    let mut dirs: Vec<&Path> = Vec::new();
+    let outputs = info.outputs();
+    // Remote destinations are only known to be writable once uploaded
+    for dest in outputs.iter().filter(|dest| !storage::is_remote(dest)) {
+        // Destinations in an archive are written next to it
+        let dest = archive::split(dest).map_or(dest.as_str(), |(archive, _)| archive);
+        let dir = match Path::new(dest).parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        if dirs.contains(&dir) {
+            continue;
+        }
+        dirs.push(dir);
+        match fs::metadata(dir) {
+            Ok(meta) if !meta.is_dir() => problems.push(Invalid::new(
+                Stage::Write,
+                "destination",
+                format!("{} is not a directory", dir.display()),
+            )),
+            Ok(meta) if meta.permissions().readonly() => problems.push(Invalid::new(
+                Stage::Write,
+                "destination",
+                format!("{} is not writable", dir.display()),
+            )),
+            Ok(_) => {}
+            Err(e) => problems.push(Invalid::new(
+                Stage::Write,
+                "destination",
+                format!("{}: {}", dir.display(), e),
+            )),
+        }
+    }
+
+    if !info.options.in_place
+        && outputs
+            .iter()
+            .any(|dest| atomic::same_file(&info.source, dest).unwrap_or(false))
+    {
+        problems.push(Invalid::new(
+            Stage::Write,
+            "destination",
+            "is the source file, set inPlace to overwrite it",
+        ));
+    }
+
+    if info.options.overwrite() == Overwrite::Error {
+        if let Some(dest) = info.existing_output() {
+            problems.push(Invalid::new(
+                Stage::Write,
+                "destination",
+                format!("{} exists, and overwrite is error", dest),
+            ));
+        }
+    }
+    problems
+}
@@ -0,0 +1,32 @@
+//! Browser bindings, enabled with the `wasm` feature and built for
+//! `wasm32-unknown-unknown` with the default features off, as threads and
+//! files are not there:
+//!
+//! ```sh
+//! cargo build --lib --release --target wasm32-unknown-unknown \
+//!     --no-default-features --features wasm,png,jpeg,webp
+//! wasm-bindgen --target web --out-dir pkg \
+//!     target/wasm32-unknown-unknown/release/image_bg_extender.wasm
+//! ```
+//!
+//! Only the in-memory path is bound, which never touches the filesystem.
+
+use wasm_bindgen::prelude::*;
+
+use crate::Options;
+
+/// extendBytes(data, ratio, options?) -> Uint8Array
+///
+/// Extends an encoded image to `ratio`, as `"W:H"`, and returns the encoded
+/// result. `options` is a JSON object of the settings a job entry takes,
+/// such as `{"background": "blur", "outputFormat": "png"}`.
+#[wasm_bindgen(js_name = extendBytes)]
+pub fn extend_bytes(data: &[u8], ratio: &str, options: Option<String>) -> Result<Vec<u8>, JsError> {
+    let aspect_ratio = crate::parse_ratio(ratio).map_err(|e| JsError::new(&e))?;
+    let options: Options = match options {
+        Some(json) => serde_json::from_str(&json)?,
+        None => Options::default(),
+    };
+    crate::extend_bytes_with(data, aspect_ratio, None, &options)
+        .map_err(|e| JsError::new(&e.to_string()))
+}
@@ -0,0 +1,106 @@
+//! Images dropped into a directory, as they arrive, enabled with the `watch`
+//! feature. Subdirectories are not watched, and images already there when
+//! the watch starts are left alone.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::time::{Duration, Instant};
+
+use notify::event::{AccessKind, AccessMode};
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+
+use crate::archive;
+
+/// How long a new file must go untouched before it is taken, so that one
+/// still being copied in is not read half written.
+pub const SETTLE: Duration = Duration::from_millis(500);
+
+/// The images arriving in a watched directory, each once it has settled.
+/// Never ends while the directory is watched.
+pub struct NewImages {
+    // Stops watching when dropped
+    _watcher: RecommendedWatcher,
+    events: Receiver<notify::Result<Event>>,
+    /// Files written to lately, by when they were last touched.
+    pending: HashMap<PathBuf, Instant>,
+}
+
+/// Starts watching `dir` for new images.
+pub fn watch(dir: &Path) -> notify::Result<NewImages> {
+    let (sender, events) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(move |event| {
+        // The receiver only goes away with the watcher
+        let _ = sender.send(event);
+    })?;
+    watcher.watch(dir, RecursiveMode::NonRecursive)?;
+    Ok(NewImages {
+        _watcher: watcher,
+        events,
+        pending: HashMap::new(),
+    })
+}
+
+impl NewImages {
+    fn record(&mut self, event: Event) {
+        let now = Instant::now();
+        for path in event.paths {
+            match event.kind {
+                EventKind::Remove(_) => {
+                    self.pending.remove(&path);
+                }
+                EventKind::Create(_)
+                | EventKind::Modify(_)
+                | EventKind::Access(AccessKind::Close(AccessMode::Write))
+                    if archive::is_image(&path.to_string_lossy()) =>
+                {
+                    self.pending.insert(path, now);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// A pending file that has settled, if any is still there.
+    fn settled(&mut self) -> Option<PathBuf> {
+        let now = Instant::now();
+        let path = self
+            .pending
+            .iter()
+            .filter(|(_, &touched)| now.duration_since(touched) >= SETTLE)
+            .min_by_key(|(_, &touched)| touched)
+            .map(|(path, _)| path.clone())?;
+        self.pending.remove(&path);
+        Some(path)
+    }
+}
+
+impl Iterator for NewImages {
+    type Item = notify::Result<PathBuf>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            while let Some(path) = self.settled() {
+                // Renamed away or replaced by a directory meanwhile
+                if path.is_file() {
+                    return Some(Ok(path));
+                }
+            }
+            let event = match self.pending.values().min() {
+                Some(&touched) => {
+                    let wait = SETTLE.saturating_sub(touched.elapsed());
+                    match self.events.recv_timeout(wait) {
+                        Ok(event) => event,
+                        Err(RecvTimeoutError::Timeout) => continue,
+                        Err(RecvTimeoutError::Disconnected) => return None,
+                    }
+                }
+                None => self.events.recv().ok()?,
+            };
+            match event {
+                Ok(event) => self.record(event),
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
@@ -0,0 +1,152 @@
+//! Watermarks stamped over finished canvases.
+//!
+//! Decoded watermarks are kept for the rest of the process, so a batch
+//! decodes each file once however many entries use it. The latest scaled
+//! copy of each is kept as well, which covers the frames of an animation and
+//! runs of entries with the same canvas size.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, OnceLock};
+
+use image::{imageops, Pixel, RgbaImage};
+
+use crate::error::WatermarkError;
+use crate::style::{Anchor, Style, Watermark};
+use crate::Layout;
+
+#[derive(Default)]
+struct Cached {
+    original: Option<Arc<RgbaImage>>,
+    scaled: Option<(imageops::FilterType, Arc<RgbaImage>)>,
+}
+
+static CACHE: OnceLock<Mutex<HashMap<String, Cached>>> = OnceLock::new();
+
+/// The watermark at `path`, scaled to `width` with `filter` when given.
+fn load(
+    path: &str,
+    width: Option<u32>,
+    filter: imageops::FilterType,
+) -> Result<Arc<RgbaImage>, WatermarkError> {
+    let cache = CACHE.get_or_init(Mutex::default);
+    let cached = |select: fn(&Cached) -> Option<&Arc<RgbaImage>>| {
+        let cache = cache.lock().unwrap_or_else(|e| e.into_inner());
+        cache.get(path).and_then(select).cloned()
+    };
+
+    let original = match cached(|cached| cached.original.as_ref()) {
+        Some(original) => original,
+        None => {
+            // Decoded without holding the lock, so other workers are not held up
+            let decoded = Arc::new(
+                image::open(path)
+                    .map_err(|e| WatermarkError::new(path, e))?
+                    .into_rgba8(),
+            );
+            let mut cache = cache.lock().unwrap_or_else(|e| e.into_inner());
+            cache.entry(path.to_string()).or_default().original = Some(Arc::clone(&decoded));
+            decoded
+        }
+    };
+    let width = match width {
+        Some(width) if width != original.width() => width,
+        _ => return Ok(original),
+    };
+    {
+        let cache = cache.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some((used, scaled)) = cache.get(path).and_then(|cached| cached.scaled.as_ref()) {
+            if *used == filter && scaled.width() == width {
+                return Ok(Arc::clone(scaled));
+            }
+        }
+    }
+
+    let height = (original.height() as u64 * width as u64 / original.width().max(1) as u64).max(1);
+    let scaled = Arc::new(imageops::resize(&*original, width, height as u32, filter));
+    let mut cache = cache.lock().unwrap_or_else(|e| e.into_inner());
+    cache.entry(path.to_string()).or_default().scaled = Some((filter, Arc::clone(&scaled)));
+    Ok(scaled)
+}
+
+/// Stamps `watermark` into `(left, top, width, height)` of the canvas,
+/// scaled down to fit inside its margins when `fit` is set.
+fn stamp(
+    canvas: &mut RgbaImage,
+    (left, top, area_width, area_height): (u32, u32, u32, u32),
+    fit: bool,
+    watermark: &Watermark,
+    filter: imageops::FilterType,
+) -> Result<(), WatermarkError> {
+    let (canvas_width, canvas_height) = canvas.dimensions();
+    let margin = watermark.margin.resolve(canvas_width.min(canvas_height));
+    let mut width = watermark
+        .scale
+        .map(|scale| ((area_width as f32 * scale).round() as u32).max(1));
+    if fit {
+        let room = (
+            area_width.saturating_sub(2 * margin),
+            area_height.saturating_sub(2 * margin),
+        );
+        if room.0 == 0 || room.1 == 0 {
+            return Ok(());
+        }
+        let original = load(&watermark.path, None, filter)?;
+        let (original_width, original_height) = (
+            original.width().max(1) as u64,
+            original.height().max(1) as u64,
+        );
+        let wanted = width.unwrap_or(original.width()) as u64;
+        let tallest = room.1 as u64 * original_width / original_height;
+        width = Some((wanted.min(tallest) as u32).clamp(1, room.0));
+    }
+    let image = load(&watermark.path, width, filter)?;
+    let (left, top, margin) = (left as i64, top as i64, margin as i64);
+    let (right, bottom) = (left + area_width as i64, top + area_height as i64);
+    let (width, height) = (image.width() as i64, image.height() as i64);
+    let x = match watermark.anchor {
+        Anchor::TopLeft | Anchor::BottomLeft => left + margin,
+        Anchor::TopRight | Anchor::BottomRight => right - width - margin,
+    };
+    let y = match watermark.anchor {
+        Anchor::TopLeft | Anchor::TopRight => top + margin,
+        Anchor::BottomLeft | Anchor::BottomRight => bottom - height - margin,
+    };
+
+    let opacity = watermark.opacity.clamp(0.0, 1.0);
+    for (px, py, pixel) in image.enumerate_pixels() {
+        let (cx, cy) = (x + px as i64, y + py as i64);
+        if cx < left || cy < top || cx >= right || cy >= bottom {
+            continue;
+        }
+        let mut pixel = *pixel;
+        pixel.0[3] = (pixel.0[3] as f32 * opacity).round() as u8;
+        canvas.get_pixel_mut(cx as u32, cy as u32).blend(&pixel);
+    }
+    Ok(())
+}
+
+/// Stamps the watermark of `style`, if any, over the finished canvas laid
+/// out as `layout`, which canvases without a background around the image
+/// leave out.
+pub(crate) fn apply(
+    canvas: &mut RgbaImage,
+    layout: Option<&Layout>,
+    style: &Style,
+) -> Result<(), WatermarkError> {
+    let watermark = match &style.watermark {
+        Some(watermark) => watermark,
+        None => return Ok(()),
+    };
+    let region = match (watermark.area, layout) {
+        (None, _) => (0, 0, canvas.width(), canvas.height()),
+        (Some(area), Some(layout)) => layout.area(area),
+        (Some(_), None) => return Ok(()),
+    };
+    stamp(
+        canvas,
+        region,
+        watermark.area.is_some(),
+        watermark,
+        style.filter(imageops::FilterType::Triangle),
+    )
+}
@@ -0,0 +1,165 @@
+//! The backgrounds, drawn through `extend_image` on sources from
+//! `test_util`.
+
+mod common;
+
+use common::{BLUE, RED};
+use image_bg_extender::color::Color;
+use image_bg_extender::image::{DynamicImage, Rgba, RgbaImage};
+use image_bg_extender::test_util::{self, Side};
+use image_bg_extender::{extend_image, Background, Options};
+
+/// A 200 by 100 red to blue gradient extended to a 200 by 200 square, with
+/// 50 lines of background above and below it.
+fn extend(options: &Options) -> RgbaImage {
+    let source = DynamicImage::ImageRgba8(test_util::horizontal_gradient(200, 100, RED, BLUE));
+    let output = extend_image(&source, (1, 1), options).unwrap();
+    test_util::assert_dimensions(&output, (200, 200));
+    output.to_rgba8()
+}
+
+/// Asserts that every pixel of the background is opaque and drawn from the
+/// source, which has no green and is never dark.
+#[track_caller]
+fn assert_painted(img: &RgbaImage) {
+    for (x, y, pixel) in img.enumerate_pixels() {
+        let [r, g, b, a] = pixel.0;
+        assert!(
+            a == 255 && g <= 2 && r as u32 + b as u32 >= 240,
+            "pixel {},{} is {:?}, which is not from the source",
+            x,
+            y,
+            pixel.0
+        );
+    }
+}
+
+fn blur(sigma: f32, downscale: f32) -> Options {
+    let mut options = Options::default();
+    options.style.background = Background::Blur;
+    options.style.blur_sigma = Some(sigma);
+    options.style.blur_downscale = Some(downscale);
+    options
+}
+
+#[test]
+fn blur_with_a_tiny_sigma_keeps_the_source() {
+    for downscale in [1.0, 4.0] {
+        let img = extend(&blur(0.1, downscale));
+        assert_painted(&img);
+        // The background covers the canvas from the middle half of the
+        // source, so the band still runs from red to blue
+        let [left_r, _, left_b, _] = img.get_pixel(0, 25).0;
+        let [right_r, _, right_b, _] = img.get_pixel(199, 25).0;
+        assert!(
+            left_r > right_r + 100 && right_b > left_b + 100,
+            "the band runs from {:?} to {:?}",
+            (left_r, left_b),
+            (right_r, right_b)
+        );
+    }
+}
+
+#[test]
+fn blur_wider_than_the_canvas_evens_it_out() {
+    for downscale in [1.0, 4.0] {
+        let img = extend(&blur(200.0, downscale));
+        assert_painted(&img);
+        // Across and down the band, the colors are nearly the same
+        let left = *img.get_pixel(0, 0);
+        test_util::assert_band_color(&img, Side::Top, 50, left, 48);
+        test_util::assert_band_color(&img, Side::Bottom, 50, left, 48);
+    }
+}
+
+const GREEN: Rgba<u8> = Rgba([0, 160, 0, 255]);
+const ORANGE: Rgba<u8> = Rgba([250, 140, 20, 255]);
+
+/// A 100 by 50 orange logo with its corners cut off, fully transparent
+/// there with black stored under them as many files do, and a half
+/// transparent orange stripe along the bottom.
+fn logo() -> DynamicImage {
+    DynamicImage::ImageRgba8(RgbaImage::from_fn(100, 50, |x, y| {
+        let corner = !(10..90).contains(&x) && !(10..40).contains(&y);
+        if corner {
+            Rgba([0, 0, 0, 0])
+        } else if y >= 45 {
+            Rgba([250, 140, 20, 128])
+        } else {
+            ORANGE
+        }
+    }))
+}
+
+#[test]
+fn the_background_shows_through_a_transparent_logo() {
+    let mut options = Options::default();
+    options.style.background_color = Some(Color(GREEN));
+    let output = extend_image(&logo(), (1, 1), &options).unwrap();
+    test_util::assert_dimensions(&output, (100, 100));
+    let output = output.to_rgba8();
+
+    // The logo sits 25 lines down, with the background in its corners
+    for &(x, y) in &[(0, 25), (99, 25), (0, 74), (99, 74), (5, 30)] {
+        assert_eq!(*output.get_pixel(x, y), GREEN, "corner at {},{}", x, y);
+    }
+    assert_eq!(*output.get_pixel(50, 50), ORANGE);
+    // Half of the stripe is the background
+    let [r, g, b, a] = output.get_pixel(50, 72).0;
+    assert_eq!(a, 255);
+    for (found, expected) in [(r, 125), (g, 150), (b, 10)] {
+        assert!(found.abs_diff(expected) <= 3, "stripe is {:?}", (r, g, b));
+    }
+}
+
+/// Red above blue, 60 by 40, extended to a square with 10 lines above and
+/// below it.
+fn red_over_blue(options: &Options) -> RgbaImage {
+    let source = DynamicImage::ImageRgba8(RgbaImage::from_fn(60, 40, |_, y| match y {
+        0..=19 => RED,
+        _ => BLUE,
+    }));
+    let output = extend_image(&source, (1, 1), options).unwrap();
+    test_util::assert_dimensions(&output, (60, 60));
+    output.to_rgba8()
+}
+
+#[test]
+fn uniform_fills_both_bands_with_the_blend() {
+    // `weight` of red blended with the rest of blue in linear light
+    for (weight, blend) in [
+        (None, Rgba([188, 0, 188, 255])),
+        (Some(0.75), Rgba([225, 0, 137, 255])),
+        (Some(0.0), BLUE),
+    ] {
+        let mut options = Options::default();
+        options.style.background = Background::Uniform;
+        options.style.uniform_weight = weight;
+        let img = red_over_blue(&options);
+        test_util::assert_band_color(&img, Side::Top, 10, blend, 1);
+        test_util::assert_band_color(&img, Side::Bottom, 10, blend, 1);
+        // One color, even if rounded differently from the one above
+        let background: Vec<_> = img
+            .enumerate_pixels()
+            .filter(|&(_, y, _)| !(10..50).contains(&y))
+            .map(|(_, _, pixel)| *pixel)
+            .collect();
+        assert!(
+            background.iter().all(|&pixel| pixel == background[0]),
+            "more than one color"
+        );
+    }
+}
+
+#[test]
+fn complement_stands_out_from_the_image() {
+    let mut options = Options::default();
+    options.style.background = Background::Complement;
+    let img = red_over_blue(&options);
+    // No step within the band, and a clear one onto the image
+    test_util::assert_seam(&img, Side::Top, 10, 40);
+    test_util::assert_seam(&img, Side::Bottom, 10, 40);
+    let fill = *img.get_pixel(0, 0);
+    test_util::assert_band_color(&img, Side::Bottom, 10, fill, 0);
+    assert!(fill.0[1] > fill.0[0] && fill.0[1] > fill.0[2], "{:?}", fill);
+}
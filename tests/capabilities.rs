@@ -0,0 +1,243 @@
+//! The table of the encoders compiled in, which each destination is checked
+//! against before its source is read. Written to hold with codecs left out
+//! too, as in `cargo test --no-default-features --features png --test
+//! capabilities`.
+
+mod common;
+
+use image_bg_extender::error::{ErrorKind, Stage};
+use image_bg_extender::format::{encoder_for_extension, nearest_extensions, ENCODERS};
+use image_bg_extender::image::{DynamicImage, ImageFormat, Rgba};
+use image_bg_extender::{
+    compile_image, extend_bytes_with, test_util, ImageInfo, Options, OutputFormat,
+};
+use serde_json::json;
+
+/// Extensions with the feature of their encoder, and whether this build has it.
+const CODECS: &[(&str, &str, bool)] = &[
+    ("png", "png", cfg!(feature = "png")),
+    ("jpg", "jpeg", cfg!(feature = "jpeg")),
+    ("gif", "gif", cfg!(feature = "gif")),
+    ("bmp", "bmp", cfg!(feature = "bmp")),
+    ("tif", "tiff", cfg!(feature = "tiff")),
+    ("ico", "ico", cfg!(feature = "ico")),
+    ("tga", "tga", cfg!(feature = "tga")),
+    ("ppm", "pnm", cfg!(feature = "pnm")),
+    ("ff", "farbfeld", cfg!(feature = "farbfeld")),
+    ("jxl", "jxl", cfg!(feature = "jxl")),
+    ("avif", "avif", cfg!(feature = "avif")),
+];
+
+/// The error of extending a source that does not exist into `destination`,
+/// which is the destination's when it is checked first.
+fn missing_source_into(destination: &str) -> image_bg_extender::ExtendError {
+    let dir = tempfile::tempdir().unwrap();
+    let info: ImageInfo = serde_json::from_value(json!({
+        "source": common::path(dir.path(), "missing.png"),
+        "destination": common::path(dir.path(), destination),
+        "aspectRatio": "1:1",
+    }))
+    .unwrap();
+    match compile_image(&info) {
+        Ok(_) => panic!("a missing source was extended"),
+        Err(e) => e,
+    }
+}
+
+#[test]
+fn the_table_follows_the_features() {
+    for &(extension, feature, compiled) in CODECS {
+        let encoder = encoder_for_extension(extension).unwrap();
+        assert_eq!(encoder.feature, feature, "{}", extension);
+        assert_eq!(encoder.compiled, compiled, "{}", extension);
+    }
+    for encoder in ENCODERS {
+        for extension in encoder.extensions {
+            let suggested = nearest_extensions(extension);
+            assert_eq!(
+                suggested.first() == Some(extension),
+                encoder.compiled && encoder.stills,
+                "{} is suggested as {:?}",
+                extension,
+                suggested
+            );
+        }
+    }
+}
+
+#[test]
+fn a_format_left_out_fails_before_the_source_is_read() {
+    for &(extension, feature, compiled) in CODECS {
+        let e = missing_source_into(&format!("out.{}", extension));
+        if compiled {
+            assert_eq!(e.stage(), Stage::Read, "{}: {}", extension, e);
+        } else {
+            assert_eq!(e.stage(), Stage::Write, "{}: {}", extension, e);
+            assert_eq!(e.kind(), ErrorKind::Invalid, "{}: {}", extension, e);
+            let message = e.to_string();
+            assert!(
+                message.contains(&format!("enable the `{}` feature", feature)),
+                "{}",
+                message
+            );
+        }
+    }
+}
+
+/// AVIF is left out of the default features, so the usual `cargo test`
+/// covers a codec that is not compiled in.
+#[cfg(not(feature = "avif"))]
+#[test]
+fn avif_is_reported_as_not_compiled_in() {
+    let encoder = encoder_for_extension("avif").unwrap();
+    assert_eq!((encoder.format, encoder.feature), ("AVIF", "avif"));
+    assert!(!encoder.compiled);
+    assert!(!nearest_extensions("avif").contains(&"avif"));
+
+    let expected = "AVIF support is not compiled in, enable the `avif` feature";
+    let e = missing_source_into("out.avif");
+    assert_eq!(e.stage(), Stage::Write, "{}", e);
+    assert_eq!(e.kind(), ErrorKind::Invalid, "{}", e);
+    assert!(e.to_string().contains(expected), "{}", e);
+
+    // Asked for by `outputFormat`, whatever the destination says
+    let dir = tempfile::tempdir().unwrap();
+    let info: ImageInfo = serde_json::from_value(json!({
+        "source": common::gradient(dir.path(), "in.png", 40, 20),
+        "destination": common::path(dir.path(), "out.png"),
+        "aspectRatio": "1:1",
+        "outputFormat": "avif",
+    }))
+    .unwrap();
+    let e = compile_image(&info).unwrap_err();
+    assert_eq!(e.stage(), Stage::Write, "{}", e);
+    assert!(e.to_string().contains(expected), "{}", e);
+    assert!(!dir.path().join("out.png").exists());
+}
+
+#[test]
+fn a_typo_names_the_nearest_formats_compiled_in() {
+    let e = missing_source_into("out.bmp2");
+    assert_eq!(e.stage(), Stage::Write, "{}", e);
+    let message = e.to_string();
+    assert!(
+        message.contains("no format is written for .bmp2 files"),
+        "{}",
+        message
+    );
+    let nearest = nearest_extensions("bmp2");
+    assert!(!nearest.is_empty());
+    let listed: Vec<String> = nearest.iter().map(|ext| format!(".{}", ext)).collect();
+    assert!(
+        message.contains(&format!("nearest supported: {}", listed.join(", "))),
+        "{}",
+        message
+    );
+    assert_eq!(nearest[0] == "bmp", cfg!(feature = "bmp"), "{:?}", nearest);
+}
+
+#[test]
+fn check_uses_the_same_table() {
+    let dir = tempfile::tempdir().unwrap();
+    let source = common::gradient(dir.path(), "in.png", 40, 20);
+    let mut jobs = vec![json!({
+        "source": source,
+        "destination": common::path(dir.path(), "out.bmp2"),
+        "aspectRatio": "1:1",
+    })];
+    let left_out = CODECS.iter().find(|&&(_, _, compiled)| !compiled);
+    if let Some((extension, _, _)) = left_out {
+        jobs.push(json!({
+            "source": source,
+            "destination": common::path(dir.path(), &format!("out.{}", extension)),
+            "aspectRatio": "1:1",
+        }));
+    }
+    let output = common::run_jobs(dir.path(), &json!(jobs), &["--check"]);
+    let stderr = common::stderr(&output);
+    assert_eq!(common::code(&output), 3, "{}", stderr);
+    assert!(
+        stderr.contains("destination: no format is written for .bmp2 files"),
+        "{}",
+        stderr
+    );
+    if let Some((_, feature, _)) = left_out {
+        assert!(
+            stderr.contains(&format!("enable the `{}` feature", feature)),
+            "{}",
+            stderr
+        );
+    }
+    assert!(!dir.path().join("out.bmp2").exists());
+}
+
+#[test]
+fn jpeg_xl_is_taken_by_name() {
+    let entry = |extra: serde_json::Value| {
+        let mut entry = json!({
+            "source": "in.png",
+            "destination": "out.png",
+            "aspectRatio": "1:1",
+        });
+        entry
+            .as_object_mut()
+            .unwrap()
+            .extend(extra.as_object().unwrap().clone());
+        serde_json::from_value::<ImageInfo>(entry)
+            .err()
+            .map(|e| e.to_string())
+    };
+    assert!(entry(json!({ "outputFormat": "jxl" })).is_none());
+    // Other names still list the known ones
+    let e = entry(json!({ "outputFormat": "jpgx" })).expect("jpgx was taken");
+    assert!(
+        e.contains("unknown variant `jpgx`") && e.contains("`jxl`"),
+        "{}",
+        e
+    );
+    assert_eq!("JXL".parse::<OutputFormat>(), Ok(OutputFormat::Jxl));
+}
+
+/// `img` extended to 1:1 as JPEG XL.
+fn extended_jxl(img: &DynamicImage) -> Result<Vec<u8>, image_bg_extender::ExtendError> {
+    let options = Options {
+        output_format: Some(OutputFormat::Jxl),
+        ..Options::default()
+    };
+    let png = test_util::encode(img, ImageFormat::Png);
+    extend_bytes_with(&png, (1, 1), None, &options)
+}
+
+#[cfg(feature = "jxl")]
+#[test]
+fn jpeg_xl_is_written_in_8_and_16_bits() {
+    use image_bg_extender::image::{ImageBuffer, Rgb};
+
+    let narrow = DynamicImage::ImageRgba8(test_util::horizontal_gradient(
+        40,
+        20,
+        Rgba([255, 0, 0, 255]),
+        Rgba([0, 0, 255, 255]),
+    ));
+    let deep = DynamicImage::ImageRgb16(ImageBuffer::from_fn(40, 20, |x, _| {
+        Rgb([x as u16 * 1000, 1, 65535])
+    }));
+    for img in [narrow, deep] {
+        let data = extended_jxl(&img).unwrap();
+        assert_eq!(data[..2], [0xFF, 0x0A], "not a JPEG XL codestream");
+    }
+}
+
+#[cfg(not(feature = "jxl"))]
+#[test]
+fn jpeg_xl_needs_the_jxl_feature() {
+    let img = DynamicImage::ImageRgba8(test_util::horizontal_gradient(
+        40,
+        20,
+        Rgba([255, 0, 0, 255]),
+        Rgba([0, 0, 255, 255]),
+    ));
+    let e = extended_jxl(&img).unwrap_err();
+    assert_eq!(e.stage(), Stage::Write, "{}", e);
+    assert!(e.to_string().contains("enable the `jxl` feature"), "{}", e);
+}
@@ -0,0 +1,75 @@
+//! Helpers shared by the integration tests, most of which drive the binary
+//! on sources drawn by `test_util` into a temporary directory.
+
+#![allow(dead_code)]
+
+use std::path::Path;
+use std::process::{Command, Output, Stdio};
+
+use image_bg_extender::image::{DynamicImage, ImageFormat, Rgba};
+use image_bg_extender::test_util;
+
+pub const RED: Rgba<u8> = Rgba([255, 0, 0, 255]);
+pub const BLUE: Rgba<u8> = Rgba([0, 0, 255, 255]);
+
+/// The binary, with nothing on stdin.
+pub fn bin() -> Command {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_image_bg_extender"));
+    command.stdin(Stdio::null());
+    command
+}
+
+/// Runs the binary with `args` and the job list `jobs`, written to
+/// `jobs.json` in `dir`.
+pub fn run_jobs(dir: &Path, jobs: &serde_json::Value, args: &[&str]) -> Output {
+    let list = dir.join("jobs.json");
+    std::fs::write(&list, jobs.to_string()).unwrap();
+    bin().args(args).arg(&list).output().unwrap()
+}
+
+/// A red to blue gradient of `width` by `height` written to `name` in
+/// `dir`, in the format its extension names. Returns its path.
+pub fn gradient(dir: &Path, name: &str, width: u32, height: u32) -> String {
+    let img = test_util::horizontal_gradient(width, height, RED, BLUE);
+    let path = dir.join(name);
+    let format = ImageFormat::from_path(&path).unwrap();
+    std::fs::write(
+        &path,
+        test_util::encode(&DynamicImage::ImageRgba8(img), format),
+    )
+    .unwrap();
+    path.to_str().unwrap().to_string()
+}
+
+/// `name` in `dir`, as a string for a job list.
+pub fn path(dir: &Path, name: &str) -> String {
+    dir.join(name).to_str().unwrap().to_string()
+}
+
+/// The exit code, which a signal leaves out.
+pub fn code(output: &Output) -> i32 {
+    output
+        .status
+        .code()
+        .expect("the binary was killed by a signal")
+}
+
+pub fn stderr(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+/// CRC of a PNG chunk, over its type and data, which decoders check.
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
@@ -0,0 +1,179 @@
+//! A corpus of broken sources, drawn from good ones in `test_util` and
+//! damaged, each of which has to fail with an error and never panic.
+
+mod common;
+
+use std::panic::{self, AssertUnwindSafe};
+
+use image_bg_extender::error::{ErrorKind, ExtendError};
+use image_bg_extender::image::{DynamicImage, ImageFormat, Rgba};
+use image_bg_extender::{compile_image, extend_bytes, test_util, ImageInfo};
+
+fn good(format: ImageFormat) -> Vec<u8> {
+    let img =
+        test_util::vertical_gradient(32, 24, Rgba([200, 30, 30, 255]), Rgba([30, 30, 200, 255]));
+    test_util::encode(&DynamicImage::ImageRgba8(img), format)
+}
+
+/// `data` without its last `cut` bytes.
+fn truncated(mut data: Vec<u8>, cut: usize) -> Vec<u8> {
+    data.truncate(data.len() - cut);
+    data
+}
+
+/// A PNG with a width of zero in its header, and the header's CRC to match.
+fn png_zero_width() -> Vec<u8> {
+    let mut data = good(ImageFormat::Png);
+    // Signature, chunk length, then "IHDR" and the width
+    data[16..20].copy_from_slice(&0u32.to_be_bytes());
+    let crc = common::crc32(&data[12..29]);
+    data[29..33].copy_from_slice(&crc.to_be_bytes());
+    data
+}
+
+/// A BMP with a width of zero in its info header.
+fn bmp_zero_width() -> Vec<u8> {
+    let mut data = good(ImageFormat::Bmp);
+    data[18..22].copy_from_slice(&0i32.to_le_bytes());
+    data
+}
+
+/// A GIF with a screen and frame of zero width.
+fn gif_zero_width() -> Vec<u8> {
+    let mut data = good(ImageFormat::Gif);
+    data[6..8].copy_from_slice(&0u16.to_le_bytes());
+    let frame = data.iter().position(|&byte| byte == 0x2c).unwrap();
+    data[frame + 5..frame + 7].copy_from_slice(&0u16.to_le_bytes());
+    data
+}
+
+/// Every broken source, named by its file, with whether it counts as
+/// corrupt or is refused by the decoder as an image it cannot read.
+fn corpus() -> Vec<(&'static str, Vec<u8>, ErrorKind)> {
+    let png = good(ImageFormat::Png);
+    let jpeg = good(ImageFormat::Jpeg);
+    let gif = good(ImageFormat::Gif);
+    let bmp = good(ImageFormat::Bmp);
+    vec![
+        ("empty.png", Vec::new(), ErrorKind::Corrupt),
+        ("empty.jpg", Vec::new(), ErrorKind::Corrupt),
+        ("signature.png", png[..8].to_vec(), ErrorKind::Corrupt),
+        ("header.png", png[..33].to_vec(), ErrorKind::Corrupt),
+        (
+            "half.png",
+            truncated(png.clone(), png.len() / 2),
+            ErrorKind::Corrupt,
+        ),
+        ("no-end.png", truncated(png, 12), ErrorKind::Corrupt),
+        (
+            "header.jpg",
+            jpeg[..jpeg.len() / 4].to_vec(),
+            ErrorKind::Corrupt,
+        ),
+        (
+            "half.jpg",
+            truncated(jpeg.clone(), jpeg.len() / 2),
+            ErrorKind::Corrupt,
+        ),
+        ("header.gif", gif[..13].to_vec(), ErrorKind::Corrupt),
+        (
+            "half.gif",
+            truncated(gif.clone(), gif.len() / 2),
+            ErrorKind::Corrupt,
+        ),
+        ("header.bmp", bmp[..30].to_vec(), ErrorKind::Corrupt),
+        (
+            "half.bmp",
+            truncated(bmp.clone(), bmp.len() / 2),
+            ErrorKind::Corrupt,
+        ),
+        ("zero-width.png", png_zero_width(), ErrorKind::Corrupt),
+        ("zero-width.gif", gif_zero_width(), ErrorKind::Corrupt),
+        // The BMP decoder turns down a width of zero before decoding
+        ("zero-width.bmp", bmp_zero_width(), ErrorKind::Image),
+    ]
+}
+
+/// Sources whose format is only told by the name of the file, and which
+/// are not images at all in memory.
+fn unnamed() -> Vec<(&'static str, Vec<u8>)> {
+    let jpeg = good(ImageFormat::Jpeg);
+    vec![
+        ("marker.jpg", jpeg[..2].to_vec()),
+        ("noise.png", (0..=255u8).cycle().take(4096).collect()),
+    ]
+}
+
+/// `run`, failing the test if it panics.
+fn no_panic<T>(name: &str, run: impl FnOnce() -> T) -> T {
+    match panic::catch_unwind(AssertUnwindSafe(run)) {
+        Ok(result) => result,
+        Err(_) => panic!("{} panicked", name),
+    }
+}
+
+fn assert_fails(name: &str, result: Result<impl Sized, ExtendError>) -> ExtendError {
+    match result {
+        Ok(_) => panic!("{} was extended", name),
+        Err(e) => e,
+    }
+}
+
+#[test]
+fn every_broken_file_fails() {
+    let dir = tempfile::tempdir().unwrap();
+    let named = unnamed()
+        .into_iter()
+        .map(|(name, data)| (name, data, ErrorKind::Corrupt));
+    for (name, data, kind) in corpus().into_iter().chain(named) {
+        let source = dir.path().join(name);
+        std::fs::write(&source, &data).unwrap();
+        let info: ImageInfo = serde_json::from_value(serde_json::json!({
+            "source": source,
+            "destination": dir.path().join(format!("out-{}", name)),
+            "aspectRatio": "1:1",
+        }))
+        .unwrap();
+        let e = assert_fails(name, no_panic(name, || compile_image(&info)));
+        assert_eq!(e.kind(), kind, "{}: {}", name, e);
+        assert_eq!(e.path(), source.to_str(), "{}: {}", name, e);
+        assert!(
+            !dir.path().join(format!("out-{}", name)).exists(),
+            "{} left an output",
+            name
+        );
+    }
+}
+
+#[test]
+fn every_broken_buffer_fails() {
+    for (name, data, kind) in corpus() {
+        let e = assert_fails(name, no_panic(name, || extend_bytes(&data, (1, 1), None)));
+        assert_eq!(e.kind(), kind, "{}: {}", name, e);
+    }
+    for (name, data) in unnamed() {
+        let e = assert_fails(name, no_panic(name, || extend_bytes(&data, (1, 1), None)));
+        assert_eq!(e.kind(), ErrorKind::Image, "{}: {}", name, e);
+    }
+}
+
+#[test]
+fn cut_short_sources_say_where_the_data_ends() {
+    let dir = tempfile::tempdir().unwrap();
+    let png = good(ImageFormat::Png);
+    let data = truncated(png, 40);
+    let source = dir.path().join("cut.png");
+    std::fs::write(&source, &data).unwrap();
+    let info: ImageInfo = serde_json::from_value(serde_json::json!({
+        "source": source,
+        "destination": dir.path().join("out.png"),
+        "aspectRatio": "1:1",
+    }))
+    .unwrap();
+    let e = compile_image(&info).unwrap_err().to_string();
+    assert!(
+        e.contains(&format!("data ends at byte {}", data.len())),
+        "{}",
+        e
+    );
+}
@@ -0,0 +1,143 @@
+//! The physical resolution of PNG and JPEG sources, carried over into the
+//! outputs and never made up for sources without one.
+
+mod common;
+
+use std::convert::TryInto;
+use std::path::Path;
+
+use image_bg_extender::image::ImageFormat;
+use image_bg_extender::test_util;
+use serde_json::json;
+
+/// 300 DPI in the pixels per meter of `pHYs`.
+const PNG_300_DPI: u32 = 11811;
+
+/// The `pHYs` chunk of a PNG as pixels per unit and the unit, 1 for meters.
+fn png_density(data: &[u8]) -> Option<(u32, u32, u8)> {
+    let be32 = |at: usize| u32::from_be_bytes(data[at..at + 4].try_into().unwrap());
+    let mut at = 8;
+    while at + 8 <= data.len() {
+        let len = be32(at) as usize;
+        match &data[at + 4..at + 8] {
+            b"pHYs" => return Some((be32(at + 8), be32(at + 12), data[at + 16])),
+            b"IDAT" => return None,
+            _ => at += 12 + len,
+        }
+    }
+    None
+}
+
+/// The unit and densities of the JFIF header that starts a JPEG, unit 0
+/// giving only the shape of the pixels.
+fn jpeg_density(data: &[u8]) -> (u8, u16, u16) {
+    assert_eq!(&data[6..11], b"JFIF\0", "no JFIF header");
+    let be16 = |at: usize| u16::from_be_bytes([data[at], data[at + 1]]);
+    (data[13], be16(14), be16(16))
+}
+
+/// A gradient written to `name` in `dir` at 300 DPI, or with no density.
+fn source(dir: &Path, name: &str, tagged: bool) -> String {
+    let path = common::gradient(dir, name, 40, 20);
+    let mut data = std::fs::read(&path).unwrap();
+    if tagged {
+        match ImageFormat::from_path(&path).unwrap() {
+            ImageFormat::Png => {
+                let mut chunk = 9u32.to_be_bytes().to_vec();
+                chunk.extend_from_slice(b"pHYs");
+                chunk.extend_from_slice(&PNG_300_DPI.to_be_bytes());
+                chunk.extend_from_slice(&PNG_300_DPI.to_be_bytes());
+                chunk.push(1);
+                let crc = common::crc32(&chunk[4..]);
+                chunk.extend_from_slice(&crc.to_be_bytes());
+                // Right after the signature and IHDR
+                data.splice(33..33, chunk);
+            }
+            ImageFormat::Jpeg => {
+                data[13] = 1;
+                data[14..16].copy_from_slice(&300u16.to_be_bytes());
+                data[16..18].copy_from_slice(&300u16.to_be_bytes());
+            }
+            format => panic!("no density for {:?}", format),
+        }
+        std::fs::write(&path, &data).unwrap();
+    }
+    path
+}
+
+/// Extends each of `sources` into the destination named with it.
+fn extend(dir: &Path, entries: &[(&str, &str)]) {
+    let jobs: Vec<_> = entries
+        .iter()
+        .map(|&(source, destination)| {
+            json!({
+                "source": common::path(dir, source),
+                "destination": common::path(dir, destination),
+                "aspectRatio": "1:1",
+            })
+        })
+        .collect();
+    let output = common::run_jobs(dir, &json!(jobs), &[]);
+    assert_eq!(common::code(&output), 0, "{}", common::stderr(&output));
+}
+
+fn read(dir: &Path, name: &str) -> Vec<u8> {
+    let data = std::fs::read(dir.join(name)).unwrap();
+    // Still an image the decoder takes
+    test_util::assert_dimensions(&test_util::decode(&data), (40, 40));
+    data
+}
+
+#[test]
+fn tagged_sources_keep_300_dpi() {
+    let dir = tempfile::tempdir().unwrap();
+    source(dir.path(), "in.png", true);
+    source(dir.path(), "in.jpg", true);
+    extend(
+        dir.path(),
+        &[
+            ("in.png", "png.png"),
+            ("in.png", "png.jpg"),
+            ("in.jpg", "jpg.jpg"),
+            ("in.jpg", "jpg.png"),
+        ],
+    );
+    let png = Some((PNG_300_DPI, PNG_300_DPI, 1));
+    assert_eq!(png_density(&read(dir.path(), "png.png")), png);
+    assert_eq!(png_density(&read(dir.path(), "jpg.png")), png);
+    assert_eq!(jpeg_density(&read(dir.path(), "jpg.jpg")), (1, 300, 300));
+    assert_eq!(jpeg_density(&read(dir.path(), "png.jpg")), (1, 300, 300));
+}
+
+#[test]
+fn untagged_sources_get_no_density() {
+    let dir = tempfile::tempdir().unwrap();
+    source(dir.path(), "in.png", false);
+    source(dir.path(), "in.jpg", false);
+    extend(
+        dir.path(),
+        &[
+            ("in.png", "png.png"),
+            ("in.png", "png.jpg"),
+            ("in.jpg", "jpg.jpg"),
+            ("in.jpg", "jpg.png"),
+        ],
+    );
+    assert_eq!(png_density(&read(dir.path(), "png.png")), None);
+    assert_eq!(png_density(&read(dir.path(), "jpg.png")), None);
+    // Not 72 DPI, nor any other unit
+    assert_eq!(jpeg_density(&read(dir.path(), "jpg.jpg")).0, 0);
+    assert_eq!(jpeg_density(&read(dir.path(), "png.jpg")).0, 0);
+}
+
+#[test]
+fn a_tagged_source_is_decoded_as_before() {
+    let dir = tempfile::tempdir().unwrap();
+    let tagged = std::fs::read(source(dir.path(), "in.png", true)).unwrap();
+    assert_eq!(png_density(&tagged), Some((PNG_300_DPI, PNG_300_DPI, 1)));
+    let plain = std::fs::read(common::gradient(dir.path(), "plain.png", 40, 20)).unwrap();
+    assert_eq!(
+        test_util::decode(&tagged).to_rgba8(),
+        test_util::decode(&plain).to_rgba8()
+    );
+}
@@ -0,0 +1,112 @@
+//! The color type of outputs, which keeps 16 bits a channel where the
+//! destination can hold them.
+
+use image_bg_extender::image::{ColorType, DynamicImage, ImageBuffer, ImageFormat, Rgb, Rgba};
+use image_bg_extender::style::Background;
+use image_bg_extender::{extend_bytes, extend_bytes_with, test_util, Options};
+
+type Rgb16Image = ImageBuffer<Rgb<u16>, Vec<u16>>;
+
+/// A 16-bit red to blue gradient of 40 by 20, as a PNG.
+fn deep_png() -> Vec<u8> {
+    let img: ImageBuffer<Rgba<u16>, Vec<u16>> = ImageBuffer::from_fn(40, 20, |x, _| {
+        let blue = (x * 65535 / 39) as u16;
+        Rgba([65535 - blue, 0, blue, 65535])
+    });
+    test_util::encode(&DynamicImage::ImageRgba16(img), ImageFormat::Png)
+}
+
+#[test]
+fn png_keeps_16_bits() {
+    let output = test_util::decode(&extend_bytes(&deep_png(), (1, 1), None).unwrap());
+    test_util::assert_dimensions(&output, (40, 40));
+    test_util::assert_color_type(&output, ColorType::Rgba16);
+}
+
+#[test]
+fn jpeg_gets_8_bits() {
+    let data = extend_bytes(&deep_png(), (1, 1), Some(ImageFormat::Jpeg)).unwrap();
+    let output = test_util::decode(&data);
+    test_util::assert_dimensions(&output, (40, 40));
+    test_util::assert_color_type(&output, ColorType::Rgb8);
+}
+
+#[test]
+fn eight_bit_sources_stay_8_bits() {
+    let img =
+        test_util::horizontal_gradient(40, 20, Rgba([255, 0, 0, 255]), Rgba([0, 0, 255, 255]));
+    let data = test_util::encode(&DynamicImage::ImageRgba8(img), ImageFormat::Png);
+    let output = test_util::decode(&extend_bytes(&data, (1, 1), None).unwrap());
+    test_util::assert_color_type(&output, ColorType::Rgba8);
+}
+
+/// An opaque 40 by 20 gradient whose red and green only change in the low
+/// 8 bits: all of it is one color in 8 bits.
+fn low_bit_gradient() -> Rgb16Image {
+    ImageBuffer::from_fn(40, 20, |x, y| {
+        Rgb([1000 + x as u16 * 3, 20000 + y as u16, 512])
+    })
+}
+
+/// `source` extended to 1:1 on a `background`, and `maxWidth` when given.
+fn extended(source: &Rgb16Image, background: Background, max_width: Option<u32>) -> Rgb16Image {
+    let data = test_util::encode(&DynamicImage::ImageRgb16(source.clone()), ImageFormat::Png);
+    let mut options = Options {
+        max_width,
+        ..Options::default()
+    };
+    options.style.background = background;
+    let output = test_util::decode(&extend_bytes_with(&data, (1, 1), None, &options).unwrap());
+    test_util::assert_color_type(&output, ColorType::Rgb16);
+    output.into_rgb16()
+}
+
+fn assert_source_kept(output: &Rgb16Image, source: &Rgb16Image) {
+    for (x, y, pixel) in source.enumerate_pixels() {
+        assert_eq!(output.get_pixel(x, y + 10), pixel, "at {},{}", x, y);
+    }
+}
+
+#[test]
+fn mirror_reflects_every_bit() {
+    let source = low_bit_gradient();
+    let output = extended(&source, Background::Mirror, None);
+    assert_eq!(output.dimensions(), (40, 40));
+    assert_source_kept(&output, &source);
+    for x in 0..40 {
+        for row in 0..10 {
+            assert_eq!(output.get_pixel(x, 9 - row), source.get_pixel(x, row));
+            assert_eq!(output.get_pixel(x, 30 + row), source.get_pixel(x, 19 - row));
+        }
+    }
+}
+
+#[test]
+fn blur_is_worked_out_in_16_bits() {
+    let source = low_bit_gradient();
+    let output = extended(&source, Background::Blur, None);
+    assert_source_kept(&output, &source);
+    // In 8 bits the whole band would be the one value of 4 * 257
+    let mut reds: Vec<u16> = (0..40).map(|x| output.get_pixel(x, 0).0[0]).collect();
+    assert!(
+        reds.iter().all(|red| (1000..=1117).contains(red)),
+        "{:?}",
+        reds
+    );
+    reds.dedup();
+    assert!(reds.len() >= 10, "{:?}", reds);
+    let greens: Vec<u16> = (0..10).map(|y| output.get_pixel(20, y).0[1]).collect();
+    assert!(
+        greens.iter().all(|green| (20000..=20019).contains(green)),
+        "{:?}",
+        greens
+    );
+}
+
+#[test]
+fn shrinking_keeps_16_bits() {
+    let output = extended(&low_bit_gradient(), Background::Replicate, Some(20));
+    assert_eq!(output.dimensions(), (20, 20));
+    assert!(output.pixels().any(|pixel| pixel.0[0] % 257 != 0));
+    assert!(output.pixels().any(|pixel| pixel.0[1] % 257 != 0));
+}
@@ -0,0 +1,172 @@
+//! Exit codes and the closing summary, driven with good and bad job lists.
+
+mod common;
+
+use std::path::Path;
+
+use serde_json::json;
+
+fn entry(dir: &Path, source: &str, dest: &str) -> serde_json::Value {
+    json!({
+        "source": source,
+        "destination": common::path(dir, dest),
+        "aspectRatio": "1:1",
+    })
+}
+
+#[test]
+fn succeeds_when_every_entry_does() {
+    let dir = tempfile::tempdir().unwrap();
+    let a = common::gradient(dir.path(), "a.png", 40, 20);
+    let b = common::gradient(dir.path(), "b.png", 20, 40);
+    let jobs = json!([
+        entry(dir.path(), &a, "a-out.png"),
+        entry(dir.path(), &b, "b-out.png")
+    ]);
+    let output = common::run_jobs(dir.path(), &jobs, &[]);
+    assert_eq!(common::code(&output), 0);
+    assert!(common::stderr(&output).contains("2 ok, 0 skipped, 0 failed"));
+}
+
+#[test]
+fn partial_failure_when_some_fail() {
+    let dir = tempfile::tempdir().unwrap();
+    let a = common::gradient(dir.path(), "a.png", 40, 20);
+    let missing = common::path(dir.path(), "missing.png");
+    let jobs = json!([
+        entry(dir.path(), &a, "a-out.png"),
+        entry(dir.path(), &missing, "b-out.png"),
+    ]);
+    let output = common::run_jobs(dir.path(), &jobs, &[]);
+    assert_eq!(common::code(&output), 2);
+    assert!(common::stderr(&output).contains("1 ok, 0 skipped, 1 failed"));
+}
+
+#[test]
+fn failure_when_all_fail() {
+    let dir = tempfile::tempdir().unwrap();
+    let missing = common::path(dir.path(), "missing.png");
+    let jobs = json!([entry(dir.path(), &missing, "out.png")]);
+    let output = common::run_jobs(dir.path(), &jobs, &[]);
+    assert_eq!(common::code(&output), 1);
+    assert!(common::stderr(&output).contains("0 ok, 0 skipped, 1 failed"));
+}
+
+#[test]
+fn invalid_input_for_a_malformed_job_list() {
+    let dir = tempfile::tempdir().unwrap();
+    let list = dir.path().join("jobs.json");
+    std::fs::write(&list, "[{\"source\": ").unwrap();
+    let output = common::bin().arg(&list).output().unwrap();
+    assert_eq!(common::code(&output), 3);
+
+    let output = common::run_jobs(dir.path(), &json!([{ "source": "a.png" }]), &[]);
+    assert_eq!(common::code(&output), 3);
+}
+
+#[test]
+fn invalid_input_for_a_misspelt_setting() {
+    let dir = tempfile::tempdir().unwrap();
+    let a = common::gradient(dir.path(), "a.png", 40, 20);
+    let mut misspelt = entry(dir.path(), &a, "out.png");
+    misspelt["backgroundColour"] = json!("#00ff00");
+    let output = common::run_jobs(dir.path(), &json!([misspelt]), &[]);
+    assert_eq!(common::code(&output), 3);
+    assert!(
+        common::stderr(&output).contains("unknown field `backgroundColour`"),
+        "{}",
+        common::stderr(&output)
+    );
+
+    // In an output as well
+    let mut misspelt = entry(dir.path(), &a, "out.png");
+    misspelt["outputs"] =
+        json!([{ "destination": common::path(dir.path(), "b.png"), "qualty": 80 }]);
+    let output = common::run_jobs(dir.path(), &json!([misspelt]), &[]);
+    assert_eq!(common::code(&output), 3);
+    assert!(common::stderr(&output).contains("unknown field `qualty`"));
+    assert!(!dir.path().join("out.png").exists());
+}
+
+#[test]
+fn format_is_read_as_the_output_format() {
+    let dir = tempfile::tempdir().unwrap();
+    let a = common::gradient(dir.path(), "a.png", 40, 20);
+    let mut jpeg = entry(dir.path(), &a, "out.png");
+    jpeg["format"] = json!("jpeg");
+    let output = common::run_jobs(dir.path(), &json!([jpeg]), &[]);
+    assert_eq!(common::code(&output), 0, "{}", common::stderr(&output));
+    let written = std::fs::read(dir.path().join("out.png")).unwrap();
+    assert!(written.starts_with(&[0xff, 0xd8, 0xff]));
+}
+
+#[test]
+fn no_entries_for_an_empty_job_list() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = common::run_jobs(dir.path(), &json!([]), &[]);
+    assert_eq!(common::code(&output), 4);
+}
+
+/// A job list whose second of three entries has no source.
+fn missing_in_the_middle(dir: &Path) -> serde_json::Value {
+    let a = common::gradient(dir, "a.png", 40, 20);
+    let c = common::gradient(dir, "c.png", 20, 40);
+    let missing = common::path(dir, "missing.png");
+    json!([
+        entry(dir, &a, "a-out.png"),
+        entry(dir, &missing, "b-out.png"),
+        entry(dir, &c, "c-out.png"),
+    ])
+}
+
+#[test]
+fn carries_on_past_a_failure_by_default() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = common::run_jobs(dir.path(), &missing_in_the_middle(dir.path()), &[]);
+    let stderr = common::stderr(&output);
+    assert_eq!(common::code(&output), 2, "{}", stderr);
+    assert!(stderr.contains("2 ok, 0 skipped, 1 failed"), "{}", stderr);
+    assert!(stderr.contains(&format!(
+        "Failed sources:\n  {}",
+        common::path(dir.path(), "missing.png")
+    )));
+    assert!(dir.path().join("a-out.png").exists());
+    assert!(dir.path().join("c-out.png").exists());
+}
+
+#[test]
+fn fail_fast_stops_at_the_first_failure() {
+    let dir = tempfile::tempdir().unwrap();
+    let jobs = missing_in_the_middle(dir.path());
+    let output = common::run_jobs(dir.path(), &jobs, &["--fail-fast"]);
+    let stderr = common::stderr(&output);
+    assert_eq!(common::code(&output), 5, "{}", stderr);
+    assert!(stderr.contains("1 ok, 0 skipped, 1 failed"), "{}", stderr);
+    assert!(
+        stderr.contains("Stopped at the first failure, 1 entries not attempted"),
+        "{}",
+        stderr
+    );
+    assert!(dir.path().join("a-out.png").exists());
+    assert!(!dir.path().join("c-out.png").exists());
+}
+
+#[test]
+fn fail_fast_stops_a_parallel_batch_too() {
+    let dir = tempfile::tempdir().unwrap();
+    let source = common::gradient(dir.path(), "a.png", 40, 20);
+    let missing = common::path(dir.path(), "missing.png");
+    let mut jobs = vec![entry(dir.path(), &source, "0.png")];
+    jobs.push(entry(dir.path(), &missing, "1.png"));
+    for n in 2..40 {
+        jobs.push(entry(dir.path(), &source, &format!("{}.png", n)));
+    }
+    let output = common::run_jobs(dir.path(), &jobs.into(), &["--fail-fast", "--jobs", "2"]);
+    let stderr = common::stderr(&output);
+    assert_eq!(common::code(&output), 5, "{}", stderr);
+    // Entries the other worker already started are still reported, but
+    // nothing after the failure is reported as failed
+    assert!(stderr.contains(", 1 failed"), "{}", stderr);
+    assert!(stderr.contains("entries not attempted"), "{}", stderr);
+    assert!(!dir.path().join("39.png").exists());
+}
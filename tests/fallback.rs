@@ -0,0 +1,104 @@
+//! `fallbackColor` for edges with nothing visible to sample, and the
+//! `colorSources` that say it was used.
+
+mod common;
+
+use std::path::Path;
+
+use image_bg_extender::image::{DynamicImage, ImageFormat, Rgba, RgbaImage};
+use image_bg_extender::report::{ColorSource, ImageReport};
+use image_bg_extender::test_util::{self, Side};
+use image_bg_extender::{compile_image, ImageInfo};
+use serde_json::json;
+
+const ORANGE: Rgba<u8> = Rgba([250, 140, 20, 255]);
+const GREEN: [u8; 4] = [0, 160, 0, 255];
+
+/// A 60 by 30 orange sticker inside a 5 pixel border that is fully
+/// transparent, with black stored under it.
+fn sticker(dir: &Path) -> String {
+    let img = RgbaImage::from_fn(60, 30, |x, y| {
+        if (5..55).contains(&x) && (5..25).contains(&y) {
+            ORANGE
+        } else {
+            Rgba([0, 0, 0, 0])
+        }
+    });
+    let path = dir.join("sticker.png");
+    let data = test_util::encode(&DynamicImage::ImageRgba8(img), ImageFormat::Png);
+    std::fs::write(&path, data).unwrap();
+    path.to_str().unwrap().to_string()
+}
+
+/// Extends `source` to a square, 15 lines of background above and below,
+/// with the settings of `style` added to the entry.
+fn extend(dir: &Path, source: &str, style: serde_json::Value) -> (ImageReport, RgbaImage) {
+    let destination = common::path(dir, "out.png");
+    let mut entry = json!({
+        "source": source,
+        "destination": destination,
+        "aspectRatio": "1:1",
+    });
+    entry
+        .as_object_mut()
+        .unwrap()
+        .extend(style.as_object().unwrap().clone());
+    let info: ImageInfo = serde_json::from_value(entry).unwrap();
+    let report = compile_image(&info).unwrap();
+    let output = test_util::decode(&std::fs::read(destination).unwrap());
+    test_util::assert_dimensions(&output, (60, 60));
+    (report, output.to_rgba8())
+}
+
+#[test]
+fn a_transparent_border_takes_the_fallback_color() {
+    let dir = tempfile::tempdir().unwrap();
+    let source = sticker(dir.path());
+    let (report, img) = extend(dir.path(), &source, json!({ "fallbackColor": "#00a000" }));
+    assert_eq!(report.colors, [GREEN, GREEN]);
+    assert_eq!(
+        report.color_sources,
+        [ColorSource::Fallback, ColorSource::Fallback]
+    );
+    test_util::assert_band_color(&img, Side::Top, 15, Rgba(GREEN), 0);
+    test_util::assert_band_color(&img, Side::Bottom, 15, Rgba(GREEN), 0);
+}
+
+#[test]
+fn without_a_fallback_color_the_opaque_pixels_stand_in() {
+    let dir = tempfile::tempdir().unwrap();
+    let source = sticker(dir.path());
+    let (report, img) = extend(dir.path(), &source, json!({}));
+    // The sticker, rather than the black under its border
+    assert_eq!(report.colors, [ORANGE.0, ORANGE.0]);
+    assert_eq!(
+        report.color_sources,
+        [ColorSource::Fallback, ColorSource::Fallback]
+    );
+    test_util::assert_band_color(&img, Side::Top, 15, ORANGE, 0);
+}
+
+#[test]
+fn a_forced_color_is_not_a_fallback() {
+    let dir = tempfile::tempdir().unwrap();
+    let source = sticker(dir.path());
+    let (report, _) = extend(
+        dir.path(),
+        &source,
+        json!({ "firstColor": "#0000ff", "fallbackColor": "#00a000" }),
+    );
+    assert_eq!(report.colors, [[0, 0, 255, 255], GREEN]);
+    assert_eq!(
+        report.color_sources,
+        [ColorSource::Forced, ColorSource::Fallback]
+    );
+}
+
+#[test]
+fn visible_edges_are_sampled() {
+    let dir = tempfile::tempdir().unwrap();
+    let source = common::gradient(dir.path(), "wide.png", 60, 30);
+    let (report, _) = extend(dir.path(), &source, json!({ "fallbackColor": "#00a000" }));
+    assert!(report.colors.iter().all(|&color| color != GREEN));
+    assert!(report.color_sources.is_empty());
+}
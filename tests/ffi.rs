@@ -0,0 +1,106 @@
+//! The C interface: the committed header against the one generated from
+//! `src/ffi.rs`, and the smoke test of `ffi/` built against the static
+//! library and run.
+
+#![cfg(all(feature = "ffi", unix))]
+
+mod common;
+
+use std::path::Path;
+use std::process::Command;
+
+fn committed_header() -> std::path::PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("include/image_bg_extender.h")
+}
+
+#[test]
+fn the_committed_header_is_up_to_date() {
+    let generated = std::fs::read_to_string(env!("IBE_GENERATED_HEADER")).unwrap();
+    if std::env::var_os("IMAGE_BG_EXTENDER_UPDATE_HEADER").is_some() {
+        std::fs::write(committed_header(), &generated).unwrap();
+        return;
+    }
+    let committed = std::fs::read_to_string(committed_header()).unwrap();
+    assert!(
+        committed == generated,
+        "include/image_bg_extender.h is out of date with src/ffi.rs, run `make -C ffi header`"
+    );
+}
+
+#[test]
+fn the_smoke_test_passes() {
+    // The static library of this build, beside the test; the one next to
+    // the binary is from the last `cargo build`, with whatever features it had
+    let library = Path::new(env!("CARGO_BIN_EXE_image_bg_extender"))
+        .parent()
+        .unwrap()
+        .join("deps/libimage_bg_extender.a");
+    assert!(library.exists(), "no static library at {:?}", library);
+
+    let dir = tempfile::tempdir().unwrap();
+    let smoke = dir.path().join("smoke");
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let compiler = std::env::var("CC").unwrap_or_else(|_| "cc".to_string());
+    let built = Command::new(&compiler)
+        .args(["-Wall", "-Wextra", "-Werror", "-I"])
+        .arg(root.join("include"))
+        .arg("-o")
+        .arg(&smoke)
+        .arg(root.join("ffi/smoke.c"))
+        .arg(&library)
+        .args(["-lpthread", "-ldl", "-lm"])
+        .output()
+        .unwrap_or_else(|e| panic!("cannot run the C compiler {}: {}", compiler, e));
+    assert!(
+        built.status.success(),
+        "smoke.c does not build:\n{}",
+        String::from_utf8_lossy(&built.stderr)
+    );
+
+    let destination = dir.path().join("out.png");
+    let run = Command::new(&smoke).arg(&destination).output().unwrap();
+    let stdout = String::from_utf8_lossy(&run.stdout);
+    assert!(
+        run.status.success(),
+        "the smoke test failed:\n{}{}",
+        stdout,
+        String::from_utf8_lossy(&run.stderr)
+    );
+    assert!(stdout.ends_with("ok\n"), "{}", stdout);
+    assert!(destination.exists());
+    // The source it drew is cleaned up
+    assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 2);
+}
+
+#[test]
+fn errors_come_back_with_the_code_of_their_kind() {
+    use image_bg_extender::ffi::{ibe_buffer_free, ibe_extend_bytes, IbeBuffer, IbeErrorCode};
+    use std::ptr;
+
+    let dir = tempfile::tempdir().unwrap();
+    let png = std::fs::read(common::gradient(dir.path(), "source.png", 40, 20)).unwrap();
+    let extend = |data: &[u8], ratio: (u32, u32)| {
+        let mut out = IbeBuffer {
+            data: ptr::null_mut(),
+            len: 0,
+        };
+        unsafe {
+            let code = ibe_extend_bytes(
+                data.as_ptr(),
+                data.len(),
+                ratio.0,
+                ratio.1,
+                ptr::null(),
+                &mut out,
+                ptr::null_mut(),
+            );
+            ibe_buffer_free(&mut out);
+            code
+        }
+    };
+    assert_eq!(extend(&png, (1, 1)), IbeErrorCode::Ok);
+    assert_eq!(extend(b"not an image", (1, 1)), IbeErrorCode::Image);
+    assert_eq!(extend(&png[..33], (1, 1)), IbeErrorCode::Corrupt);
+    assert_eq!(extend(b"", (1, 1)), IbeErrorCode::Corrupt);
+    assert_eq!(extend(&png, (0, 1)), IbeErrorCode::InvalidArgument);
+}
@@ -0,0 +1,73 @@
+//! Entries whose destination is their source, by the same path, through
+//! `.` components or through a symlink.
+
+mod common;
+
+use image_bg_extender::error::Stage;
+use image_bg_extender::test_util;
+use image_bg_extender::{compile_image, ImageInfo};
+use serde_json::json;
+
+fn entry(source: &str, destination: &str, in_place: bool) -> ImageInfo {
+    serde_json::from_value(json!({
+        "source": source,
+        "destination": destination,
+        "aspectRatio": "1:1",
+        "inPlace": in_place,
+    }))
+    .unwrap()
+}
+
+#[test]
+fn refuses_to_overwrite_the_source() {
+    let dir = tempfile::tempdir().unwrap();
+    let source = common::gradient(dir.path(), "a.png", 40, 20);
+    let before = std::fs::read(&source).unwrap();
+    let dotted = dir.path().join(".").join("a.png");
+    for dest in [source.clone(), dotted.to_str().unwrap().to_string()] {
+        let e = compile_image(&entry(&source, &dest, false)).unwrap_err();
+        assert_eq!(e.stage(), Stage::Write);
+        assert!(e.to_string().contains("set inPlace"), "{}", e);
+    }
+    assert_eq!(std::fs::read(&source).unwrap(), before);
+}
+
+#[cfg(unix)]
+#[test]
+fn refuses_a_symlink_to_the_source() {
+    let dir = tempfile::tempdir().unwrap();
+    let source = common::gradient(dir.path(), "a.png", 40, 20);
+    let before = std::fs::read(&source).unwrap();
+    let link = dir.path().join("link.png");
+    std::os::unix::fs::symlink(&source, &link).unwrap();
+    let e = compile_image(&entry(&source, link.to_str().unwrap(), false)).unwrap_err();
+    assert!(e.to_string().contains("set inPlace"), "{}", e);
+    assert_eq!(std::fs::read(&source).unwrap(), before);
+}
+
+#[test]
+fn in_place_replaces_the_source_whole() {
+    let dir = tempfile::tempdir().unwrap();
+    let source = common::gradient(dir.path(), "a.png", 40, 20);
+    let report = compile_image(&entry(&source, &source, true)).unwrap();
+    assert_eq!((report.output_width, report.output_height), (40, 40));
+    let written = test_util::decode(&std::fs::read(&source).unwrap());
+    test_util::assert_dimensions(&written, (40, 40));
+    assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+}
+
+#[cfg(unix)]
+#[test]
+fn in_place_through_a_symlink_keeps_the_link() {
+    let dir = tempfile::tempdir().unwrap();
+    let source = common::gradient(dir.path(), "a.png", 40, 20);
+    let link = dir.path().join("link.png");
+    std::os::unix::fs::symlink(&source, &link).unwrap();
+    compile_image(&entry(link.to_str().unwrap(), link.to_str().unwrap(), true)).unwrap();
+    assert!(std::fs::symlink_metadata(&link)
+        .unwrap()
+        .file_type()
+        .is_symlink());
+    let written = test_util::decode(&std::fs::read(&source).unwrap());
+    test_util::assert_dimensions(&written, (40, 40));
+}
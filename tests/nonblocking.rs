@@ -0,0 +1,100 @@
+//! `compile_image_async`, reading and writing still images with `tokio::fs`
+//! and handing the rest to `compile_image`.
+
+#![cfg(feature = "tokio")]
+
+mod common;
+
+use image_bg_extender::error::Stage;
+use image_bg_extender::image::{self, GenericImageView};
+use image_bg_extender::{compile_image_async, ExtendError, ImageInfo, ImageReport};
+use serde_json::json;
+
+fn run(entry: serde_json::Value) -> Result<ImageReport, ExtendError> {
+    let info: ImageInfo = serde_json::from_value(entry).unwrap();
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    runtime.block_on(compile_image_async(info))
+}
+
+/// The names of the files in `dir`, sorted.
+fn files(dir: &std::path::Path) -> Vec<String> {
+    let mut names: Vec<String> = std::fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+        .collect();
+    names.sort();
+    names
+}
+
+#[test]
+fn a_still_is_extended_and_put_in_place() {
+    let dir = tempfile::tempdir().unwrap();
+    let source = common::gradient(dir.path(), "in.png", 40, 20);
+    let dest = common::path(dir.path(), "out.png");
+    let report = run(json!({
+        "source": source,
+        "destination": dest,
+        "aspectRatio": "1:1",
+    }))
+    .unwrap();
+    assert_eq!((report.output_width, report.output_height), (40, 40));
+    assert_eq!(image::open(&dest).unwrap().dimensions(), (40, 40));
+    // No temporary output is left behind
+    assert_eq!(files(dir.path()), ["in.png", "out.png"]);
+}
+
+#[test]
+fn a_source_that_fits_is_copied_through() {
+    let dir = tempfile::tempdir().unwrap();
+    let source = common::gradient(dir.path(), "in.png", 20, 20);
+    let dest = common::path(dir.path(), "out.png");
+    let report = run(json!({
+        "source": source,
+        "destination": dest,
+        "aspectRatio": "1:1",
+    }))
+    .unwrap();
+    assert_eq!((report.output_width, report.output_height), (20, 20));
+    assert_eq!(
+        std::fs::read(&dest).unwrap(),
+        std::fs::read(&source).unwrap()
+    );
+}
+
+#[test]
+fn failures_say_their_stage() {
+    let dir = tempfile::tempdir().unwrap();
+    let e = run(json!({
+        "source": common::path(dir.path(), "missing.png"),
+        "destination": common::path(dir.path(), "out.png"),
+        "aspectRatio": "1:1",
+    }))
+    .unwrap_err();
+    assert_eq!(e.stage(), Stage::Read, "{}", e);
+
+    std::fs::write(dir.path().join("broken.png"), b"not a PNG").unwrap();
+    let e = run(json!({
+        "source": common::path(dir.path(), "broken.png"),
+        "destination": common::path(dir.path(), "out.png"),
+        "aspectRatio": "1:1",
+    }))
+    .unwrap_err();
+    assert_eq!(e.stage(), Stage::Decode, "{}", e);
+    assert!(!dir.path().join("out.png").exists());
+}
+
+#[test]
+fn entries_that_write_more_go_through_compile_image() {
+    let dir = tempfile::tempdir().unwrap();
+    let source = common::gradient(dir.path(), "in.png", 40, 20);
+    let dest = common::path(dir.path(), "out.png");
+    run(json!({
+        "source": source,
+        "destination": dest,
+        "aspectRatio": "1:1",
+        "sidecar": true,
+    }))
+    .unwrap();
+    assert_eq!(image::open(&dest).unwrap().dimensions(), (40, 40));
+    assert!(dir.path().join("out.png.json").exists());
+}
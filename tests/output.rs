@@ -0,0 +1,80 @@
+//! `--output json`: one `EntryResult` a line on stdout, read back with
+//! serde as a wrapping script would.
+
+mod common;
+
+use image_bg_extender::error::Stage;
+use image_bg_extender::report::{EntryResult, Status};
+use serde_json::json;
+
+fn results(stdout: &[u8]) -> Vec<EntryResult> {
+    String::from_utf8(stdout.to_vec())
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect()
+}
+
+#[test]
+fn one_result_a_line_in_job_list_order() {
+    let dir = tempfile::tempdir().unwrap();
+    let wide = common::gradient(dir.path(), "wide.png", 40, 20);
+    let missing = common::path(dir.path(), "missing.png");
+    let jobs = json!([
+        { "source": wide, "destination": common::path(dir.path(), "a.png"), "aspectRatio": "1:1" },
+        { "source": missing, "destination": common::path(dir.path(), "b.png"), "aspectRatio": "1:1" },
+    ]);
+    let output = common::run_jobs(dir.path(), &jobs, &["--output", "json"]);
+    assert_eq!(common::code(&output), 2, "{}", common::stderr(&output));
+
+    let results = results(&output.stdout);
+    assert_eq!(results.len(), 2);
+    let ok = &results[0];
+    assert_eq!(ok.status, Status::Ok);
+    assert_eq!(ok.source, wide);
+    assert_eq!(ok.destination, common::path(dir.path(), "a.png"));
+    assert_eq!((ok.output_width, ok.output_height), (Some(40), Some(40)));
+    assert_eq!(ok.colors.len(), 2);
+    assert!(ok.error.is_none());
+
+    let failed = &results[1];
+    assert_eq!(failed.status, Status::Error);
+    assert_eq!(failed.source, missing);
+    let error = failed.error.as_ref().unwrap();
+    assert_eq!(error.kind, "io");
+    assert_eq!(error.stage, Stage::Read);
+    assert!(error.message.contains(&missing));
+    // Nothing else reaches stdout
+    assert!(!String::from_utf8_lossy(&output.stdout).contains("Image saved"));
+}
+
+#[test]
+fn skipped_entries_say_why() {
+    let dir = tempfile::tempdir().unwrap();
+    let wide = common::gradient(dir.path(), "wide.png", 40, 20);
+    let jobs = json!([
+        { "source": wide, "destination": common::path(dir.path(), "a.png"), "aspectRatio": "1:1" },
+    ]);
+    let first = common::run_jobs(dir.path(), &jobs, &["--output", "json", "--incremental"]);
+    assert_eq!(results(&first.stdout)[0].status, Status::Ok);
+
+    let again = common::run_jobs(dir.path(), &jobs, &["--output", "json", "--incremental"]);
+    assert_eq!(common::code(&again), 0, "{}", common::stderr(&again));
+    let skipped = &results(&again.stdout)[0];
+    assert_eq!(skipped.status, Status::Skipped);
+    assert!(skipped.skip_reason.is_some());
+}
+
+#[test]
+fn the_schema_round_trips() {
+    let dir = tempfile::tempdir().unwrap();
+    let wide = common::gradient(dir.path(), "wide.png", 40, 20);
+    let jobs = json!([
+        { "source": wide, "destination": common::path(dir.path(), "a.png"), "aspectRatio": "1:1" },
+    ]);
+    let output = common::run_jobs(dir.path(), &jobs, &["--output", "json"]);
+    let line = String::from_utf8(output.stdout).unwrap();
+    let written: serde_json::Value = serde_json::from_str(line.trim()).unwrap();
+    let result: EntryResult = serde_json::from_value(written.clone()).unwrap();
+    assert_eq!(serde_json::to_value(&result).unwrap(), written);
+}
@@ -0,0 +1,110 @@
+//! Panics in one entry, injected through `IMAGE_BG_EXTENDER_INJECT_PANIC`
+//! of `test-util`, are contained to that entry in serial and parallel runs.
+
+mod common;
+
+use std::path::Path;
+use std::process::Output;
+
+use serde_json::json;
+
+/// Runs the binary with `args` on five entries, the third of which panics
+/// and the fourth of which has no source.
+fn run(dir: &Path, args: &[&str]) -> Output {
+    let jobs: Vec<_> = ["a", "b", "boom", "missing", "e"]
+        .iter()
+        .map(|&name| {
+            let source = match name {
+                "missing" => common::path(dir, "missing.png"),
+                _ => common::gradient(dir, &format!("{}.png", name), 40, 20),
+            };
+            json!({
+                "source": source,
+                "destination": common::path(dir, &format!("out-{}.png", name)),
+                "aspectRatio": "1:1",
+            })
+        })
+        .collect();
+    let list = dir.join("jobs.json");
+    std::fs::write(&list, json!(jobs).to_string()).unwrap();
+    common::bin()
+        .env("IMAGE_BG_EXTENDER_INJECT_PANIC", "boom")
+        .args(args)
+        .arg(&list)
+        .output()
+        .unwrap()
+}
+
+#[track_caller]
+fn assert_contained(dir: &Path, output: &Output) {
+    let stderr = common::stderr(output);
+    // Panics win over failures
+    assert_eq!(common::code(output), 6, "{}", stderr);
+    assert!(
+        stderr.contains("3 ok, 0 skipped, 1 failed, 1 panicked"),
+        "{}",
+        stderr
+    );
+    assert!(
+        stderr.contains(&format!(
+            "Failed sources:\n  {}\n",
+            common::path(dir, "missing.png")
+        )),
+        "{}",
+        stderr
+    );
+    assert!(
+        stderr.contains(&format!(
+            "Panicked sources:\n  {}\n",
+            common::path(dir, "boom.png")
+        )),
+        "{}",
+        stderr
+    );
+    assert!(
+        stderr.contains("internal error, processing panicked: injected panic for"),
+        "{}",
+        stderr
+    );
+    for name in ["a", "b", "e"] {
+        assert!(dir.join(format!("out-{}.png", name)).exists(), "{}", name);
+    }
+    assert!(!dir.join("out-boom.png").exists());
+}
+
+#[test]
+fn a_panic_is_contained_in_a_serial_run() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = run(dir.path(), &["--jobs", "1"]);
+    assert_contained(dir.path(), &output);
+}
+
+#[test]
+fn a_panic_is_contained_in_a_parallel_run() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = run(dir.path(), &["--jobs", "4"]);
+    assert_contained(dir.path(), &output);
+}
+
+#[test]
+fn a_panic_alone_exits_as_an_internal_error() {
+    let dir = tempfile::tempdir().unwrap();
+    let source = common::gradient(dir.path(), "boom.png", 40, 20);
+    let jobs = json!([{
+        "source": source,
+        "destination": common::path(dir.path(), "out.png"),
+        "aspectRatio": "1:1",
+    }]);
+    let list = dir.path().join("jobs.json");
+    std::fs::write(&list, jobs.to_string()).unwrap();
+    let output = common::bin()
+        .env("IMAGE_BG_EXTENDER_INJECT_PANIC", "boom")
+        .args(["--output", "json"])
+        .arg(&list)
+        .output()
+        .unwrap();
+    assert_eq!(common::code(&output), 6, "{}", common::stderr(&output));
+    let result: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(result["status"], "error", "{}", result);
+    assert_eq!(result["error"]["kind"], "panic", "{}", result);
+}
@@ -0,0 +1,84 @@
+//! Single images written to stdout, from `--src`/`--dest` and from a job
+//! list of one entry, and `--src`/`--dest` files, which run as an entry of
+//! a job list does.
+
+mod common;
+
+use image_bg_extender::image::Rgba;
+use image_bg_extender::test_util::{self, Side};
+use serde_json::json;
+
+const GREEN: Rgba<u8> = Rgba([0, 255, 0, 255]);
+
+#[test]
+fn single_entry_to_stdout_keeps_its_options() {
+    let dir = tempfile::tempdir().unwrap();
+    let source = common::gradient(dir.path(), "wide.png", 40, 20);
+    let jobs = json!([{
+        "source": source,
+        "destination": "-",
+        "aspectRatio": "1:1",
+        "backgroundColor": "#00ff00",
+    }]);
+    let output = common::run_jobs(dir.path(), &jobs, &[]);
+    assert_eq!(common::code(&output), 0, "{}", common::stderr(&output));
+
+    let img = test_util::decode(&output.stdout);
+    test_util::assert_dimensions(&img, (40, 40));
+    let img = img.to_rgba8();
+    test_util::assert_band_color(&img, Side::Top, 10, GREEN, 0);
+    test_util::assert_band_color(&img, Side::Bottom, 10, GREEN, 0);
+}
+
+#[test]
+fn pipe_mode_writes_stdout_in_the_input_format() {
+    let dir = tempfile::tempdir().unwrap();
+    let source = common::gradient(dir.path(), "tall.png", 20, 40);
+    let output = common::bin()
+        .args(["--src", &source, "--dest", "-", "--ratio", "1:1"])
+        .output()
+        .unwrap();
+    assert_eq!(common::code(&output), 0, "{}", common::stderr(&output));
+    assert!(output.stdout.starts_with(b"\x89PNG"));
+    test_util::assert_dimensions(&test_util::decode(&output.stdout), (40, 40));
+}
+
+#[test]
+fn files_of_src_and_dest_run_as_an_entry() {
+    let dir = tempfile::tempdir().unwrap();
+    let source = common::gradient(dir.path(), "wide.png", 40, 20);
+    let dest = common::path(dir.path(), "out.png");
+    let run = |dest: &str, flags: &[&str]| {
+        common::bin()
+            .args(["--src", &source, "--dest", dest, "--ratio", "1:1"])
+            .args(flags)
+            .output()
+            .unwrap()
+    };
+
+    // Nothing is written by a check or a dry run
+    for flag in ["--check", "--dry-run"] {
+        let output = run(&dest, &[flag]);
+        assert_eq!(common::code(&output), 0, "{}", common::stderr(&output));
+        assert!(!std::path::Path::new(&dest).exists(), "{}", flag);
+    }
+
+    let output = run(&dest, &["--json"]);
+    assert_eq!(common::code(&output), 0, "{}", common::stderr(&output));
+    let report: serde_json::Value =
+        serde_json::from_slice(output.stdout.split(|&b| b == b'\n').next().unwrap()).unwrap();
+    assert_eq!(report["status"], "ok");
+    assert_eq!(report["outputWidth"], 40);
+    test_util::assert_dimensions(&test_util::decode(&std::fs::read(&dest).unwrap()), (40, 40));
+
+    let output = run(&dest, &["--overwrite", "error"]);
+    assert_eq!(common::code(&output), 1);
+    assert!(common::stderr(&output).contains("destination exists"));
+
+    // The source is only replaced with `inPlace`
+    let before = std::fs::read(&source).unwrap();
+    let output = run(&source, &[]);
+    assert_eq!(common::code(&output), 1);
+    assert!(common::stderr(&output).contains("set inPlace"));
+    assert_eq!(std::fs::read(&source).unwrap(), before);
+}
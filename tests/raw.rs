@@ -0,0 +1,68 @@
+//! `extend_raw` on pixels that were never encoded.
+
+mod common;
+
+use common::{BLUE, RED};
+use image_bg_extender::image::{DynamicImage, RgbaImage};
+use image_bg_extender::test_util::{self, Side};
+use image_bg_extender::{extend_raw, Options};
+
+/// Red at the top to blue at the bottom, 60 by 30.
+fn gradient() -> RgbaImage {
+    test_util::vertical_gradient(60, 30, RED, BLUE)
+}
+
+#[test]
+fn extends_a_gradient_buffer() {
+    let source = gradient();
+    let output = extend_raw(source.as_raw(), 60, 30, (1, 1), &Options::default()).unwrap();
+    assert_eq!((output.width, output.height), (60, 60));
+    assert_eq!(output.data.len(), 60 * 60 * 4);
+
+    let img = RgbaImage::from_raw(output.width, output.height, output.data).unwrap();
+    test_util::assert_dimensions(&DynamicImage::ImageRgba8(img.clone()), (60, 60));
+    // The edges of the gradient above and below it
+    test_util::assert_band_color(&img, Side::Top, 15, RED, 10);
+    test_util::assert_band_color(&img, Side::Bottom, 15, BLUE, 10);
+    for (x, y, &pixel) in source.enumerate_pixels() {
+        assert_eq!(*img.get_pixel(x, y + 15), pixel, "at {},{}", x, y);
+    }
+}
+
+#[test]
+fn refuses_a_buffer_of_the_wrong_length() {
+    let source = gradient();
+    let short = &source.as_raw()[..source.as_raw().len() - 4];
+    let e = extend_raw(short, 60, 30, (1, 1), &Options::default())
+        .err()
+        .unwrap()
+        .to_string();
+    assert!(
+        e.contains("7196 bytes") && e.contains("7200 bytes"),
+        "{}",
+        e
+    );
+
+    // Rows padded to a stride of 64 pixels
+    let padded: Vec<u8> = source
+        .as_raw()
+        .chunks(60 * 4)
+        .flat_map(|row| row.iter().copied().chain([0; 16]))
+        .collect();
+    let e = extend_raw(&padded, 60, 30, (1, 1), &Options::default())
+        .err()
+        .unwrap()
+        .to_string();
+    assert!(e.contains("tightly packed"), "{}", e);
+}
+
+#[test]
+fn refuses_no_pixels() {
+    for (width, height) in [(0, 30), (60, 0)] {
+        let e = extend_raw(&[], width, height, (1, 1), &Options::default())
+            .err()
+            .unwrap()
+            .to_string();
+        assert!(e.contains("is no image"), "{}", e);
+    }
+}
@@ -0,0 +1,89 @@
+//! `resizeFilter`, from the job list to the scaled image.
+
+use image_bg_extender::image::{DynamicImage, Rgba};
+use image_bg_extender::style::Filter;
+use image_bg_extender::{extend_image, test_util, ImageInfo, Options};
+
+const NAMES: [&str; 5] = ["nearest", "triangle", "catmullRom", "gaussian", "lanczos3"];
+
+fn entry(filter: &str) -> Result<ImageInfo, serde_json::Error> {
+    serde_json::from_value(serde_json::json!({
+        "source": "in.png",
+        "destination": "out.png",
+        "aspectRatio": "1:1",
+        "resizeFilter": filter,
+    }))
+}
+
+#[test]
+fn every_filter_name_is_known() {
+    for name in NAMES {
+        let info = entry(name).unwrap();
+        assert!(info.options().style.resize_filter.is_some(), "{}", name);
+        assert!(name.parse::<Filter>().is_ok(), "{}", name);
+    }
+}
+
+#[test]
+fn an_unknown_filter_lists_the_known_ones() {
+    let e = match entry("bicubic") {
+        Ok(_) => panic!("bicubic was taken for a filter"),
+        Err(e) => e.to_string(),
+    };
+    assert!(e.contains("bicubic"), "{}", e);
+    for name in NAMES {
+        assert!(e.contains(name), "{} is not listed in: {}", name, e);
+    }
+
+    // And so does the `--resize-filter` of the command line
+    let e = "bicubic".parse::<Filter>().unwrap_err();
+    for name in NAMES {
+        assert!(e.contains(name), "{} is not listed in: {}", name, e);
+    }
+}
+
+const DARK: Rgba<u8> = Rgba([16, 16, 16, 255]);
+const LIGHT: Rgba<u8> = Rgba([240, 240, 240, 255]);
+
+/// A 16 by 8 checkerboard of 4 pixel squares scaled up four times into a
+/// 64 by 64 square, returning the 64 by 32 placed in its middle.
+fn upscale(filter: Option<Filter>) -> Vec<Rgba<u8>> {
+    let art = DynamicImage::ImageRgba8(test_util::checkerboard(16, 8, 4, DARK, LIGHT));
+    let mut options = Options {
+        target_size: Some((64, 64)),
+        allow_upscale: true,
+        ..Options::default()
+    };
+    options.style.resize_filter = filter;
+    let output = extend_image(&art, (1, 1), &options).unwrap();
+    test_util::assert_dimensions(&output, (64, 64));
+    let output = output.to_rgba8();
+    (16..48)
+        .flat_map(|y| (0..64).map(move |x| (x, y)))
+        .map(|(x, y)| *output.get_pixel(x, y))
+        .collect()
+}
+
+#[test]
+fn nearest_keeps_the_hard_edges_of_pixel_art() {
+    let pixels = upscale(Some(Filter::Nearest));
+    assert!(
+        pixels.iter().all(|&pixel| pixel == DARK || pixel == LIGHT),
+        "nearest blended the squares"
+    );
+    // Each square is now 16 pixels across
+    let expected = test_util::checkerboard(64, 32, 16, DARK, LIGHT);
+    assert!(pixels.iter().eq(expected.pixels()));
+}
+
+#[test]
+fn smoother_filters_blend_pixel_art() {
+    for filter in [None, Some(Filter::Triangle), Some(Filter::Lanczos3)] {
+        let pixels = upscale(filter);
+        assert!(
+            pixels.iter().any(|&pixel| pixel != DARK && pixel != LIGHT),
+            "{:?} kept the hard edges",
+            filter
+        );
+    }
+}
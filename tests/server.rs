@@ -0,0 +1,256 @@
+//! Status codes of `POST /extend`, with requests handed to the router
+//! without a socket.
+
+#![cfg(feature = "server")]
+
+mod common;
+
+use std::time::Duration;
+
+use axum::body::{Body, Bytes};
+use axum::http::{Request, StatusCode};
+use futures_util::stream;
+use image_bg_extender::image::{DynamicImage, ImageFormat, Rgba};
+use image_bg_extender::server::{self, ServerConfig};
+use image_bg_extender::test_util::{self, Side};
+use serde_json::json;
+use tower::ServiceExt;
+
+const GREEN: Rgba<u8> = Rgba([0, 255, 0, 255]);
+
+/// The status, content type and body `request` is answered with.
+fn send(config: ServerConfig, request: Request<Body>) -> (StatusCode, String, Vec<u8>) {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    runtime.block_on(async {
+        let response = server::router(&config).oneshot(request).await.unwrap();
+        let status = response.status();
+        let content_type = response
+            .headers()
+            .get("content-type")
+            .map_or_else(String::new, |value| value.to_str().unwrap().to_string());
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, content_type, body.to_vec())
+    })
+}
+
+/// `POST /extend?{query}` of `body`, the image itself.
+fn post(config: ServerConfig, query: &str, body: Vec<u8>) -> (StatusCode, String, Vec<u8>) {
+    let request = Request::post(format!("/extend?{}", query))
+        .body(Body::from(body))
+        .unwrap();
+    send(config, request)
+}
+
+/// `POST /extend` of a form with `source` as its image and `options` as
+/// its JSON options.
+fn post_form(
+    config: ServerConfig,
+    source: Vec<u8>,
+    options: serde_json::Value,
+) -> (StatusCode, String, Vec<u8>) {
+    const BOUNDARY: &str = "boundary-of-the-test";
+    let mut body = Vec::new();
+    for (name, content) in [
+        ("image", source),
+        ("options", options.to_string().into_bytes()),
+    ] {
+        body.extend_from_slice(
+            format!(
+                "--{}\r\nContent-Disposition: form-data; name=\"{}\"\r\n\r\n",
+                BOUNDARY, name
+            )
+            .as_bytes(),
+        );
+        body.extend_from_slice(&content);
+        body.extend_from_slice(b"\r\n");
+    }
+    body.extend_from_slice(format!("--{}--\r\n", BOUNDARY).as_bytes());
+    let request = Request::post("/extend")
+        .header(
+            "content-type",
+            format!("multipart/form-data; boundary={}", BOUNDARY),
+        )
+        .body(Body::from(body))
+        .unwrap();
+    send(config, request)
+}
+
+fn config() -> ServerConfig {
+    ServerConfig::new("127.0.0.1:0".parse().unwrap())
+}
+
+/// The `kind` of a JSON error body.
+fn kind(body: &[u8]) -> String {
+    let error: serde_json::Value = serde_json::from_slice(body).unwrap();
+    error["error"]["kind"].as_str().unwrap().to_string()
+}
+
+/// The `message` of a JSON error body.
+fn message(body: &[u8]) -> String {
+    let error: serde_json::Value = serde_json::from_slice(body).unwrap();
+    error["error"]["message"].as_str().unwrap().to_string()
+}
+
+fn source(format: ImageFormat) -> Vec<u8> {
+    let img = test_util::horizontal_gradient(40, 20, common::RED, common::BLUE);
+    test_util::encode(&DynamicImage::ImageRgba8(img), format)
+}
+
+#[test]
+fn extends_an_upload() {
+    let (status, content_type, body) = post(config(), "ratio=1:1", source(ImageFormat::Png));
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(content_type, "image/png");
+    test_util::assert_dimensions(&test_util::decode(&body), (40, 40));
+}
+
+#[test]
+fn truncated_uploads_are_unprocessable() {
+    for format in [ImageFormat::Bmp, ImageFormat::Png, ImageFormat::Jpeg] {
+        let mut data = source(format);
+        data.truncate(data.len() / 2);
+        let (status, _, body) = post(config(), "ratio=1:1", data);
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY, "{:?}", format);
+        assert_eq!(kind(&body), "decode", "{:?}", format);
+    }
+}
+
+#[test]
+fn invalid_parameters_are_bad_requests() {
+    let (status, _, body) = post(config(), "ratio=0:1", source(ImageFormat::Png));
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(kind(&body), "invalidParameter");
+
+    let (status, _, body) = post(config(), "", source(ImageFormat::Png));
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(kind(&body), "invalidParameter");
+}
+
+#[test]
+fn oversized_bodies_are_refused() {
+    let config = ServerConfig {
+        max_upload_bytes: 64,
+        ..config()
+    };
+    let (status, _, _) = post(config, "ratio=1:1", source(ImageFormat::Png));
+    assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+}
+
+#[test]
+fn the_limits_of_the_config_apply_to_uploads() {
+    let limited = |max_output_pixels, max_source_pixels| ServerConfig {
+        max_output_pixels,
+        max_source_pixels,
+        ..config()
+    };
+    // The 40 by 20 source extends to 40 by 40
+    for config in [limited(Some(1599), None), limited(None, Some(799))] {
+        let (status, _, body) = post(config, "ratio=1:1", source(ImageFormat::Png));
+        assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(kind(&body), "limits");
+    }
+    let (status, _, _) = post(
+        limited(Some(1600), Some(800)),
+        "ratio=1:1",
+        source(ImageFormat::Png),
+    );
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[test]
+fn requests_cannot_raise_the_limits_of_the_config() {
+    let limited = ServerConfig {
+        max_output_pixels: Some(1599),
+        ..config()
+    };
+    let options = json!({ "aspectRatio": "1:1", "maxOutputPixels": 100_000_000 });
+    let (status, _, body) = post_form(limited, source(ImageFormat::Png), options);
+    assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+    assert_eq!(kind(&body), "limits");
+
+    // Lowering them is up to the request
+    let options = json!({ "aspectRatio": [1, 1], "maxOutputPixels": 1599 });
+    let (status, _, body) = post_form(config(), source(ImageFormat::Png), options);
+    assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+    assert_eq!(kind(&body), "limits");
+}
+
+#[test]
+fn form_options_take_the_settings_of_an_entry() {
+    for ratio in [json!("1:1"), json!([1, 1])] {
+        let options = json!({ "aspectRatio": ratio, "backgroundColor": "#00ff00" });
+        let (status, content_type, body) = post_form(config(), source(ImageFormat::Png), options);
+        assert_eq!(status, StatusCode::OK, "{}", String::from_utf8_lossy(&body));
+        assert_eq!(content_type, "image/png");
+        let img = test_util::decode(&body);
+        test_util::assert_dimensions(&img, (40, 40));
+        test_util::assert_band_color(&img.to_rgba8(), Side::Top, 10, GREEN, 0);
+    }
+
+    let options = json!({ "aspectRatio": "1:1", "format": "jpeg", "quality": 90 });
+    let (status, content_type, _) = post_form(config(), source(ImageFormat::Png), options);
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(content_type, "image/jpeg");
+}
+
+#[test]
+fn form_options_that_cannot_work_are_bad_requests() {
+    for (options, field) in [
+        (
+            json!({ "aspectRatio": "1:1", "backgroundColour": "#00ff00" }),
+            "backgroundColour",
+        ),
+        (
+            json!({ "aspectRatio": "1:1", "blurSigma": -1 }),
+            "blurSigma",
+        ),
+        (json!({ "aspectRatio": "auto" }), "aspectRatio"),
+        (json!({ "aspectRatio": "1:1", "format": "jxl2" }), "jxl2"),
+    ] {
+        let (status, _, body) = post_form(config(), source(ImageFormat::Png), options);
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{}", field);
+        assert_eq!(kind(&body), "invalidParameter");
+        assert!(message(&body).contains(field), "{}", message(&body));
+    }
+}
+
+#[test]
+fn stalled_uploads_do_not_hold_a_slot() {
+    let config = ServerConfig {
+        max_concurrent: 1,
+        ..config()
+    };
+    let router = server::router(&config);
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    runtime.block_on(async {
+        let request = |body| Request::post("/extend?ratio=1:1").body(body).unwrap();
+        // Never finishes sending
+        let stalled = Body::from_stream(stream::pending::<Result<Bytes, std::io::Error>>());
+        let first = tokio::spawn(router.clone().oneshot(request(stalled)));
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let body = Body::from(source(ImageFormat::Png));
+        let response = tokio::time::timeout(Duration::from_secs(10), router.oneshot(request(body)))
+            .await
+            .expect("the upload waited for the stalled one")
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(!first.is_finished());
+        first.abort();
+    });
+}
+
+#[test]
+fn uploads_that_do_not_arrive_in_time_are_refused() {
+    let config = ServerConfig {
+        read_timeout: Duration::from_millis(100),
+        ..config()
+    };
+    let stalled = Body::from_stream(stream::pending::<Result<Bytes, std::io::Error>>());
+    let request = Request::post("/extend?ratio=1:1").body(stalled).unwrap();
+    let (status, _, body) = send(config, request);
+    assert_eq!(status, StatusCode::REQUEST_TIMEOUT);
+    assert_eq!(kind(&body), "timeout");
+}
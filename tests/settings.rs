@@ -0,0 +1,83 @@
+//! Settings that cannot work, refused by `extend_image` and `extend_raw`
+//! before anything is drawn, as the job list refuses them.
+
+mod common;
+
+use common::{BLUE, RED};
+use image_bg_extender::image::DynamicImage;
+use image_bg_extender::test_util;
+use image_bg_extender::{extend_image, extend_raw, AspectRatio, ErrorKind, Options};
+
+/// Options and ratios that would divide by zero, clamp backwards or draw
+/// nonsense, with the field each is refused for.
+fn cases() -> Vec<((u32, u32), Options, &'static str)> {
+    let mut cases = vec![
+        ((0, 1), Options::default(), "aspectRatio"),
+        ((16, 0), Options::default(), "aspectRatio"),
+        (
+            (1, 1),
+            Options {
+                target_size: Some((0, 10)),
+                ..Options::default()
+            },
+            "targetSize",
+        ),
+        (
+            (1, 1),
+            Options {
+                rotate: Some(45),
+                ..Options::default()
+            },
+            "rotate",
+        ),
+    ];
+    let mut blur = Options::default();
+    blur.style.blur_sigma = Some(-1.0);
+    cases.push(((1, 1), blur, "blurSigma"));
+    let mut inset = Options::default();
+    inset.style.inset_scale = Some(0.0);
+    cases.push(((1, 1), inset, "insetScale"));
+    cases
+}
+
+#[test]
+fn extend_image_refuses_settings_that_cannot_work() {
+    let source = DynamicImage::ImageRgba8(test_util::horizontal_gradient(40, 20, RED, BLUE));
+    for (ratio, options, field) in cases() {
+        match extend_image(&source, ratio, &options) {
+            Ok(_) => panic!("{} was not refused", field),
+            Err(e) => {
+                assert_eq!(e.kind(), ErrorKind::Invalid, "{}", e);
+                assert!(e.to_string().contains(field), "{}", e);
+            }
+        }
+    }
+}
+
+#[test]
+fn extend_raw_refuses_settings_that_cannot_work() {
+    let source = test_util::horizontal_gradient(40, 20, RED, BLUE);
+    for (ratio, options, field) in cases() {
+        match extend_raw(source.as_raw(), 40, 20, ratio, &options) {
+            Ok(_) => panic!("{} was not refused", field),
+            Err(e) => {
+                assert_eq!(e.kind(), ErrorKind::Invalid, "{}", e);
+                assert!(e.to_string().contains(field), "{}", e);
+            }
+        }
+    }
+}
+
+#[test]
+fn auto_needs_a_ratio_to_pick_from() {
+    assert_eq!(
+        AspectRatio::Auto.resolve((40, 20), &[(1, 1), (2, 1)]),
+        Ok((2, 1))
+    );
+    let e = AspectRatio::Auto.resolve((40, 20), &[]).unwrap_err();
+    assert_eq!(e.to_string(), "allowedRatios: must not be empty");
+    assert_eq!(
+        AspectRatio::Fixed((3, 2)).resolve((40, 20), &[]),
+        Ok((3, 2))
+    );
+}
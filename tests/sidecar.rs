@@ -0,0 +1,95 @@
+//! `sidecar: true`: the `Analysis` written next to an output, read back
+//! with serde as downstream tools do.
+
+mod common;
+
+use image_bg_extender::report::{Analysis, Placement, ANALYSIS_VERSION};
+use image_bg_extender::Background;
+use serde_json::{json, Value};
+
+/// Extends a 40 by 20 gradient to a square with a sidecar, returning the
+/// sidecar as written.
+fn sidecar(dir: &std::path::Path) -> Value {
+    let source = common::gradient(dir, "wide.png", 40, 20);
+    let destination = common::path(dir, "out.png");
+    let jobs = json!([{
+        "source": source,
+        "destination": destination,
+        "aspectRatio": "1:1",
+        "sidecar": true,
+    }]);
+    let output = common::run_jobs(dir, &jobs, &[]);
+    assert_eq!(common::code(&output), 0, "{}", common::stderr(&output));
+    let written = std::fs::read(format!("{}.json", destination)).unwrap();
+    serde_json::from_slice(&written).unwrap()
+}
+
+#[test]
+fn describes_the_output() {
+    let dir = tempfile::tempdir().unwrap();
+    let analysis: Analysis = serde_json::from_value(sidecar(dir.path())).unwrap();
+    assert_eq!(analysis.version, ANALYSIS_VERSION);
+    assert_eq!(analysis.source, common::path(dir.path(), "wide.png"));
+    assert_eq!(analysis.destination, common::path(dir.path(), "out.png"));
+    assert_eq!((analysis.source_width, analysis.source_height), (40, 20));
+    assert_eq!(analysis.crop, (0, 0));
+    assert_eq!((analysis.output_width, analysis.output_height), (40, 40));
+    assert_eq!(
+        analysis.placement,
+        Placement {
+            x: 0,
+            y: 10,
+            width: 40,
+            height: 20,
+        }
+    );
+    assert_eq!(analysis.background, Background::Split);
+    assert_eq!(analysis.colors.len(), 2);
+}
+
+#[test]
+fn round_trips_without_losing_fields() {
+    let dir = tempfile::tempdir().unwrap();
+    let written = sidecar(dir.path());
+    let analysis: Analysis = serde_json::from_value(written.clone()).unwrap();
+    assert_eq!(serde_json::to_value(&analysis).unwrap(), written);
+    let again: Analysis = serde_json::from_str(&serde_json::to_string(&analysis).unwrap()).unwrap();
+    assert_eq!(again, analysis);
+}
+
+/// Renaming or dropping any of these breaks readers of version 1, and has
+/// to bump `ANALYSIS_VERSION`.
+#[test]
+fn keeps_the_field_names_of_its_version() {
+    assert_eq!(ANALYSIS_VERSION, 1);
+    let dir = tempfile::tempdir().unwrap();
+    let written = sidecar(dir.path());
+    let mut names: Vec<&str> = written
+        .as_object()
+        .unwrap()
+        .keys()
+        .map(String::as_str)
+        .collect();
+    names.sort_unstable();
+    assert_eq!(
+        names,
+        [
+            "background",
+            "colors",
+            "crop",
+            "destination",
+            "outputHeight",
+            "outputWidth",
+            "placement",
+            "source",
+            "sourceHeight",
+            "sourceWidth",
+            "version",
+        ]
+    );
+    let placement: Vec<&String> = written["placement"].as_object().unwrap().keys().collect();
+    assert_eq!(placement.len(), 4);
+    for name in ["x", "y", "width", "height"] {
+        assert!(written["placement"].get(name).is_some(), "{}", name);
+    }
+}
@@ -0,0 +1,186 @@
+//! Sources and destinations at `http://` URLs and `s3://` paths, against a
+//! server on the loopback interface that records what it is sent.
+
+mod common;
+
+#[cfg(feature = "http")]
+mod remote {
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::sync::mpsc::{self, Receiver};
+    use std::thread;
+
+    use image_bg_extender::image::{self, GenericImageView, ImageFormat};
+    use image_bg_extender::storage;
+    use serde_json::json;
+
+    use super::common;
+
+    struct Request {
+        method: String,
+        path: String,
+        headers: Vec<(String, String)>,
+        body: Vec<u8>,
+    }
+
+    impl Request {
+        fn header(&self, name: &str) -> Option<&str> {
+            self.headers
+                .iter()
+                .find(|(header, _)| header.eq_ignore_ascii_case(name))
+                .map(|(_, value)| value.as_str())
+        }
+    }
+
+    /// Serves every connection with the status and body `respond` gives for
+    /// its request, one request a connection. Returns the address and the
+    /// requests as they come in.
+    fn serve(respond: fn(&Request) -> (u16, Vec<u8>)) -> (String, Receiver<Request>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = format!("http://{}", listener.local_addr().unwrap());
+        let (sender, requests) = mpsc::channel();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                let mut words = line.split_whitespace();
+                let method = words.next().unwrap().to_string();
+                let path = words.next().unwrap().to_string();
+                let mut headers = Vec::new();
+                loop {
+                    line.clear();
+                    reader.read_line(&mut line).unwrap();
+                    match line.trim_end().split_once(": ") {
+                        Some((name, value)) => headers.push((name.to_string(), value.to_string())),
+                        None => break,
+                    }
+                }
+                let mut request = Request {
+                    method,
+                    path,
+                    headers,
+                    body: Vec::new(),
+                };
+                let length = request
+                    .header("content-length")
+                    .map_or(0, |length| length.parse().unwrap());
+                request.body = vec![0; length];
+                reader.read_exact(&mut request.body).unwrap();
+
+                let (status, body) = respond(&request);
+                write!(
+                    stream,
+                    "HTTP/1.1 {} Status\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                    status,
+                    body.len()
+                )
+                .unwrap();
+                if request.method != "HEAD" {
+                    stream.write_all(&body).unwrap();
+                }
+                if sender.send(request).is_err() {
+                    break;
+                }
+            }
+        });
+        (address, requests)
+    }
+
+    /// A 40 by 20 gradient as a PNG at `/source.png`, and uploads taken
+    /// anywhere.
+    fn respond(request: &Request) -> (u16, Vec<u8>) {
+        match (request.method.as_str(), request.path.as_str()) {
+            ("GET" | "HEAD", "/source.png") => {
+                let dir = tempfile::tempdir().unwrap();
+                let source = common::gradient(dir.path(), "source.png", 40, 20);
+                (200, std::fs::read(source).unwrap())
+            }
+            ("PUT", _) => (201, Vec::new()),
+            (_, _) => (
+                404,
+                b"<Error><Message>No such object</Message></Error>".to_vec(),
+            ),
+        }
+    }
+
+    #[test]
+    fn an_http_source_is_fetched_and_the_output_uploaded() {
+        let (address, requests) = serve(respond);
+        let dir = tempfile::tempdir().unwrap();
+        let jobs = json!([{
+            "source": format!("{}/source.png", address),
+            "destination": format!("{}/out/extended.png?version=2", address),
+            "aspectRatio": "1:1",
+        }]);
+        let output = common::run_jobs(dir.path(), &jobs, &[]);
+        assert_eq!(common::code(&output), 0, "{}", common::stderr(&output));
+
+        let upload = requests
+            .try_iter()
+            .find(|request| request.method == "PUT")
+            .expect("the output was not uploaded");
+        assert_eq!(upload.path, "/out/extended.png?version=2");
+        let img = image::load_from_memory_with_format(&upload.body, ImageFormat::Png).unwrap();
+        assert_eq!(img.dimensions(), (40, 40));
+    }
+
+    #[test]
+    fn http_errors_say_the_status_and_message() {
+        let (address, _requests) = serve(respond);
+        let url = format!("{}/missing.png", address);
+        let error = storage::source(&url).read(&url).unwrap_err().to_string();
+        assert_eq!(error, "HTTP 404 Status: No such object");
+        assert!(storage::source(&url).check(&url).is_err());
+        let url = format!("{}/source.png", address);
+        assert!(storage::source(&url).check(&url).is_ok());
+    }
+
+    #[cfg(feature = "s3")]
+    #[test]
+    fn s3_requests_are_signed_and_addressed_by_path() {
+        let (address, requests) = serve(respond);
+        std::env::set_var("AWS_ENDPOINT_URL", &address);
+        std::env::set_var("AWS_ACCESS_KEY_ID", "AKIDEXAMPLE");
+        std::env::set_var("AWS_SECRET_ACCESS_KEY", "secret");
+        std::env::set_var("AWS_REGION", "eu-west-1");
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("upload.png");
+        std::fs::write(&file, b"not really a PNG").unwrap();
+
+        let dest = "s3://bucket/out/a b.png";
+        storage::sink(dest).put(dest, &file).unwrap();
+        let upload = requests.recv().unwrap();
+        assert_eq!(upload.method, "PUT");
+        assert_eq!(upload.path, "/bucket/out/a%20b.png");
+        assert_eq!(upload.body, b"not really a PNG");
+        let authorization = upload.header("authorization").unwrap();
+        assert!(
+            authorization.starts_with("AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/"),
+            "{}",
+            authorization
+        );
+        assert!(authorization.contains("/eu-west-1/s3/aws4_request"));
+        let payload_hash = upload.header("x-amz-content-sha256").unwrap();
+        assert_eq!(payload_hash.len(), 64);
+        assert!(upload.header("x-amz-date").is_some());
+
+        let source = "s3://bucket/missing.png";
+        let error = storage::source(source).read(source).unwrap_err();
+        assert_eq!(error.to_string(), "HTTP 404 Status: No such object");
+    }
+}
+
+#[cfg(not(feature = "http"))]
+#[test]
+fn urls_need_the_http_feature() {
+    use image_bg_extender::storage;
+
+    let url = "https://example.com/source.png";
+    let error = storage::source(url).read(url).unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "HTTP support is not compiled in, enable the `http` feature"
+    );
+}
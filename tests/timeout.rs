@@ -0,0 +1,68 @@
+//! `--timeout` with a source that never finishes reading: a FIFO nobody
+//! writes to, whose open blocks for good.
+
+#![cfg(unix)]
+
+mod common;
+
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+use serde_json::json;
+
+#[test]
+fn a_hung_read_does_not_hold_up_the_batch() {
+    let dir = tempfile::tempdir().unwrap();
+    let hung = common::path(dir.path(), "hung.png");
+    let made = Command::new("mkfifo").arg(&hung).status().unwrap();
+    assert!(made.success());
+    let mut jobs = vec![json!({
+        "source": hung,
+        "destination": common::path(dir.path(), "hung-out.png"),
+        "aspectRatio": "1:1",
+    })];
+    for n in 0..4 {
+        let source = common::gradient(dir.path(), &format!("{}.png", n), 40, 20);
+        jobs.push(json!({
+            "source": source,
+            "destination": common::path(dir.path(), &format!("{}-out.png", n)),
+            "aspectRatio": "1:1",
+        }));
+    }
+    let list = dir.path().join("jobs.json");
+    std::fs::write(&list, serde_json::Value::from(jobs).to_string()).unwrap();
+
+    // With one read at a time, the hung entry holds the only read permit
+    let mut child = common::bin()
+        .args(["--jobs", "2", "--read-jobs", "1", "--timeout", "1"])
+        .arg(&list)
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let started = Instant::now();
+    let status = loop {
+        if let Some(status) = child.try_wait().unwrap() {
+            break status;
+        }
+        if started.elapsed() > Duration::from_secs(30) {
+            child.kill().unwrap();
+            panic!("the batch did not finish");
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    };
+    let output = child.wait_with_output().unwrap();
+    let stderr = common::stderr(&output);
+    // Some failed, others succeeded
+    assert_eq!(status.code(), Some(2), "{}", stderr);
+    assert!(
+        stderr.contains(&format!("{:?}: timed out after 1s", hung)),
+        "{}",
+        stderr
+    );
+    // The entry started next to the hung one may have waited out its own
+    // second for the read permit, but the ones after it went through
+    for n in 1..4 {
+        assert!(dir.path().join(format!("{}-out.png", n)).exists());
+    }
+}